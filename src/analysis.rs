@@ -0,0 +1,227 @@
+//! Annotate finished games with engine analysis.
+//!
+//! Every move gets a comment with the engine's evaluation, the best move and the principal variation,
+//! and moves that lose a lot of winning probability are marked as mistakes (`?`) or blunders (`??`).
+//! The annotated game can be written with [`Game::game_to_ptn`], and loaded in any PTN viewer.
+//! Use [`annotate_game_with_callback`] to show each move as soon as it has been analyzed.
+
+use std::time;
+
+use board_game_traits::{Color, GameResult, Position as PositionTrait};
+use pgn_traits::PgnPosition;
+
+use crate::position::{Move, Position};
+use crate::ptn::{Game, PtnMove};
+use crate::search::{MctsSetting, MonteCarloTree, Score};
+
+/// Maximum number of moves shown in the principal variation
+const PV_LENGTH: usize = 8;
+
+/// How much the engine searches each position
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnalysisBudget {
+    Nodes(u64),
+    /// Maximum search time. The search usually stops earlier if the best move is obvious
+    Time(time::Duration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalysisSettings<const S: usize> {
+    budget: AnalysisBudget,
+    mcts_settings: MctsSetting<S>,
+    mistake_threshold: Score,
+    blunder_threshold: Score,
+}
+
+impl<const S: usize> AnalysisSettings<S> {
+    /// Search each position for a fixed number of nodes
+    pub fn nodes(nodes: u64) -> Self {
+        AnalysisSettings {
+            budget: AnalysisBudget::Nodes(nodes),
+            mcts_settings: MctsSetting::default().arena_size_for_nodes(nodes as u32),
+            mistake_threshold: 0.1,
+            blunder_threshold: 0.25,
+        }
+    }
+
+    /// Search each position for at most `max_time`
+    pub fn time(max_time: time::Duration) -> Self {
+        AnalysisSettings {
+            budget: AnalysisBudget::Time(max_time),
+            mcts_settings: MctsSetting::default(),
+            mistake_threshold: 0.1,
+            blunder_threshold: 0.25,
+        }
+    }
+
+    pub fn mcts_settings(mut self, mcts_settings: MctsSetting<S>) -> Self {
+        self.mcts_settings = mcts_settings;
+        self
+    }
+
+    /// Set how much winning probability a move must lose to be marked as a mistake or a blunder.
+    /// Defaults to 0.1 and 0.25.
+    pub fn thresholds(mut self, mistake_threshold: Score, blunder_threshold: Score) -> Self {
+        self.mistake_threshold = mistake_threshold;
+        self.blunder_threshold = blunder_threshold;
+        self
+    }
+}
+
+/// A game with engine annotations, as returned by [`annotate_game`]
+#[derive(Debug, Clone)]
+pub struct AnnotatedGame<const S: usize> {
+    pub game: Game<Position<S>>,
+    /// True if at least one search ran out of memory before using its full budget,
+    /// in which case some annotations come from shallower searches than requested
+    pub out_of_memory: bool,
+}
+
+/// The engine's view of a single position
+#[derive(Debug, Clone)]
+struct PositionAnalysis<const S: usize> {
+    best_move: Move<S>,
+    /// Winning probability for the side to move
+    score: Score,
+    pv: Vec<Move<S>>,
+    out_of_memory: bool,
+}
+
+fn analyze_position<const S: usize>(
+    position: &Position<S>,
+    settings: &AnalysisSettings<S>,
+) -> PositionAnalysis<S> {
+    let mut tree = MonteCarloTree::with_settings(position.clone(), settings.mcts_settings.clone());
+    let mut out_of_memory = false;
+    match settings.budget {
+        AnalysisBudget::Nodes(nodes) => {
+            for _ in 0..nodes.max(2) {
+                if tree.select().is_none() {
+                    out_of_memory = true;
+                    break;
+                }
+            }
+        }
+        AnalysisBudget::Time(max_time) => tree.search_for_time(max_time, |_| {}),
+    }
+    let (best_move, score) = tree.best_move();
+    PositionAnalysis {
+        best_move,
+        score,
+        pv: tree.pv().take(PV_LENGTH).collect(),
+        out_of_memory,
+    }
+}
+
+/// Analyze every position in the game, and return a copy of the game with annotated moves.
+///
+/// The comment on each move is replaced by the engine's evaluation of the move,
+/// along with the best move and principal variation in the position before it.
+/// Mistakes and blunders get `?` and `??` annotations, and the engine's line is added as a variation.
+/// Existing variations and other annotations are kept.
+pub fn annotate_game<const S: usize>(
+    game: &Game<Position<S>>,
+    settings: &AnalysisSettings<S>,
+) -> AnnotatedGame<S> {
+    annotate_game_with_callback(game, settings, |_, _| {})
+}
+
+/// Like [`annotate_game`], but calls `callback` with each annotated move as soon as it is finished,
+/// along with the position before the move.
+pub fn annotate_game_with_callback<const S: usize, F>(
+    game: &Game<Position<S>>,
+    settings: &AnalysisSettings<S>,
+    mut callback: F,
+) -> AnnotatedGame<S>
+where
+    F: FnMut(&Position<S>, &PtnMove<Move<S>>),
+{
+    let mut position = game.start_position.clone();
+    let mut annotated_moves = Vec::with_capacity(game.moves.len());
+    let mut analysis = analyze_position(&position, settings);
+    let mut out_of_memory = analysis.out_of_memory;
+
+    for ptn_move in game.moves.iter() {
+        let mover = position.side_to_move();
+        let before = position.clone();
+        position.do_move(ptn_move.mv);
+
+        // Winning probability of the move that was played, for the side that played it
+        let (score, next_analysis) = match position.game_result() {
+            Some(game_result) => (result_score(game_result, mover), None),
+            None => {
+                let next_analysis = analyze_position(&position, settings);
+                out_of_memory |= next_analysis.out_of_memory;
+                (1.0 - next_analysis.score, Some(next_analysis))
+            }
+        };
+
+        let mut annotated_move = ptn_move.clone();
+        annotated_move
+            .annotations
+            .retain(|annotation| *annotation != "?");
+
+        let score_loss = analysis.score - score;
+        if ptn_move.mv != analysis.best_move && score_loss >= settings.mistake_threshold {
+            annotated_move.annotations.push("?");
+            if score_loss >= settings.blunder_threshold {
+                annotated_move.annotations.push("?");
+            }
+            annotated_move.variations.push(
+                analysis
+                    .pv
+                    .iter()
+                    .map(|mv| PtnMove {
+                        mv: *mv,
+                        annotations: vec![],
+                        comment: String::new(),
+                        variations: vec![],
+                    })
+                    .collect(),
+            );
+        }
+
+        annotated_move.comment = format!(
+            "{:.1}%, best {} {:.1}%, pv {}",
+            score * 100.0,
+            before.move_to_san(&analysis.best_move),
+            analysis.score * 100.0,
+            pv_to_san(&before, &analysis.pv)
+        );
+        callback(&before, &annotated_move);
+        annotated_moves.push(annotated_move);
+
+        match next_analysis {
+            Some(next_analysis) => analysis = next_analysis,
+            None => break,
+        }
+    }
+
+    AnnotatedGame {
+        game: Game {
+            start_position: game.start_position.clone(),
+            moves: annotated_moves,
+            game_result_str: game.game_result_str,
+            tags: game.tags.clone(),
+        },
+        out_of_memory,
+    }
+}
+
+fn result_score(game_result: GameResult, color: Color) -> Score {
+    match (game_result, color) {
+        (GameResult::Draw, _) => 0.5,
+        (GameResult::WhiteWin, Color::White) | (GameResult::BlackWin, Color::Black) => 1.0,
+        (GameResult::WhiteWin, Color::Black) | (GameResult::BlackWin, Color::White) => 0.0,
+    }
+}
+
+fn pv_to_san<const S: usize>(position: &Position<S>, pv: &[Move<S>]) -> String {
+    let mut position = position.clone();
+    let mut move_strings = Vec::with_capacity(pv.len());
+    for mv in pv {
+        move_strings.push(position.move_to_san(mv));
+        position.do_move(*mv);
+    }
+    move_strings.join(" ")
+}
@@ -1,12 +1,12 @@
 #[cfg(feature = "constant-tuning")]
 use std::collections::HashSet;
 use std::convert::TryFrom;
-use std::io::{Read, Write};
+use std::io::Write;
 #[cfg(feature = "constant-tuning")]
 use std::str::FromStr;
 #[cfg(feature = "constant-tuning")]
 use std::sync::atomic::{self, AtomicU64};
-use std::{io, time};
+use std::{env, io, time};
 
 use board_game_traits::Position as PositionTrait;
 use board_game_traits::{Color, GameResult};
@@ -15,16 +15,20 @@ use pgn_traits::PgnPosition;
 #[cfg(feature = "constant-tuning")]
 use rayon::prelude::*;
 
-use tiltak::evaluation::{parameters, value_eval};
-use tiltak::minmax;
+use tiltak::analysis::{self, AnalysisSettings};
 #[cfg(feature = "sqlite")]
-use tiltak::policy_sqlite;
-#[cfg(feature = "constant-tuning")]
-use tiltak::position::Role;
+use tiltak::book::OpeningBook;
+use tiltak::evaluation::parameter_file::{load_policy_params, load_value_params};
+use tiltak::evaluation::{explain, parameters};
+use tiltak::minmax;
 use tiltak::position::{AbstractBoard, Direction, Komi, Move, Square, SquareCacheEntry};
-use tiltak::position::{Position, Stack};
+use tiltak::position::{Piece, Position, PositionBuilder, Role, Stack};
+use tiltak::ptn::playtak::PlaytakGameRecord;
+use tiltak::ptn::ptn_parser::PtnReader;
 use tiltak::ptn::{Game, PtnMove};
 use tiltak::search::MctsSetting;
+#[cfg(feature = "sqlite")]
+use tiltak::sqlite::{self, DatabasePosition, GameDatabase, GameFilter};
 use tiltak::{position, search};
 
 #[cfg(test)]
@@ -34,16 +38,25 @@ pub mod playtak;
 pub mod tei;
 
 fn main() {
+    println!("Start with --value-params <file> and --policy-params <file> to analyze with parameters from files");
     println!("play: Play against the engine through the command line");
     println!("aimatch: Watch the engine play against a very simple minmax implementation");
     println!("analyze <size>: Analyze a given position, provided from a PTN or a simple move list");
     println!("tps <size>: Analyze a given position, provided from a tps string");
     println!("game <size>: Analyze a whole game, provided from a PTN or a simple move list");
     println!(
-        "perft <size>: Generate perft numbers of a given position, provided from a tps string"
+        "perft <size> [bulk|hashed|parallel|divide]: Generate perft numbers of a given position, provided from a tps string"
     );
+    println!("edit <size>: Set up a position piece by piece, starting from a tps string");
+    println!("playtak_to_ptn: Convert Playtak game records to PTN, provided as text on stdin");
+    #[cfg(feature = "sqlite")]
+    println!("playtak_to_ptn <database>: Convert all games in a Playtak game database to PTN");
+    #[cfg(feature = "sqlite")]
+    println!("test_policy [size] [database]: Test how well policy scores find immediate wins in real games");
     #[cfg(feature = "sqlite")]
-    println!("test_policy: Test how well policy scores find immediate wins in real games");
+    println!("db_report <size> <database> [player]: Measure policy accuracy and value calibration on a game database");
+    #[cfg(feature = "sqlite")]
+    println!("db_book <size> <database> <book.txt> [max_ply]: Build an opening book from the games in a database");
     loop {
         let mut input = String::new();
         let bytes_read = io::stdin().read_line(&mut input).unwrap();
@@ -82,15 +95,28 @@ fn main() {
                 Some(s) => println!("Unsupported size {}", s),
                 None => analyze_position_from_tps::<5>(),
             },
-            "perft" => match words.get(1) {
-                Some(&"3") => perft_from_tps::<3>(),
-                Some(&"4") => perft_from_tps::<4>(),
-                Some(&"5") => perft_from_tps::<5>(),
-                Some(&"6") => perft_from_tps::<6>(),
-                Some(&"7") => perft_from_tps::<7>(),
-                Some(&"8") => perft_from_tps::<8>(),
+            "perft" => {
+                let mode = words.get(2).copied().unwrap_or("bulk");
+                match words.get(1) {
+                    Some(&"3") => perft_from_tps::<3>(mode),
+                    Some(&"4") => perft_from_tps::<4>(mode),
+                    Some(&"5") => perft_from_tps::<5>(mode),
+                    Some(&"6") => perft_from_tps::<6>(mode),
+                    Some(&"7") => perft_from_tps::<7>(mode),
+                    Some(&"8") => perft_from_tps::<8>(mode),
+                    Some(s) => println!("Unsupported size {}", s),
+                    None => perft_from_tps::<5>(mode),
+                }
+            }
+            "edit" => match words.get(1) {
+                Some(&"3") => edit_position::<3>(),
+                Some(&"4") => edit_position::<4>(),
+                Some(&"5") => edit_position::<5>(),
+                Some(&"6") => edit_position::<6>(),
+                Some(&"7") => edit_position::<7>(),
+                Some(&"8") => edit_position::<8>(),
                 Some(s) => println!("Unsupported size {}", s),
-                None => perft_from_tps::<5>(),
+                None => edit_position::<5>(),
             },
             #[cfg(feature = "constant-tuning")]
             "openings" => {
@@ -144,8 +170,73 @@ fn main() {
             }
             #[cfg(feature = "constant-tuning")]
             "analyze_openings" => analyze_openings::<6>(Komi::default(), 500_000),
+            "playtak_to_ptn" => {
+                match words.get(1) {
+                    #[cfg(feature = "sqlite")]
+                    Some(database) => {
+                        match GameDatabase::open(database)
+                            .and_then(|db| db.records(&GameFilter::default()))
+                        {
+                            Ok(records) => records.iter().for_each(print_playtak_game),
+                            Err(err) => println!("Couldn't read database {}: {}", database, err),
+                        }
+                    }
+                    _ => {
+                        println!("Enter one Playtak game record per line, then press enter followed by CTRL+D");
+                        for line in io::stdin().lines() {
+                            let line = line.unwrap();
+                            if line.trim().is_empty() {
+                                continue;
+                            }
+                            match line.parse() {
+                                Ok(record) => print_playtak_game(&record),
+                                Err(err) => eprintln!("{}", err),
+                            }
+                        }
+                    }
+                }
+                return;
+            }
+            #[cfg(feature = "sqlite")]
+            "test_policy" => {
+                let database = words.get(2).unwrap_or(&"puzzles.db");
+                match words.get(1) {
+                    Some(&"4") => test_policy::<4>(database),
+                    Some(&"5") | None => test_policy::<5>(database),
+                    Some(&"6") => test_policy::<6>(database),
+                    Some(s) => println!("Unsupported size {}", s),
+                }
+            }
+            #[cfg(feature = "sqlite")]
+            "db_book" => {
+                let (Some(size), Some(database), Some(book_path)) =
+                    (words.get(1), words.get(2), words.get(3))
+                else {
+                    println!("Usage: db_book <size> <database> <book.txt> [max_ply]");
+                    continue;
+                };
+                let max_ply = words.get(4).and_then(|ply| ply.parse().ok()).unwrap_or(8);
+                match *size {
+                    "4" => database_book::<4>(database, book_path, max_ply),
+                    "5" => database_book::<5>(database, book_path, max_ply),
+                    "6" => database_book::<6>(database, book_path, max_ply),
+                    s => println!("Unsupported size {}", s),
+                }
+            }
             #[cfg(feature = "sqlite")]
-            "test_policy" => policy_sqlite::check_all_games(),
+            "db_report" => {
+                let (Some(size), Some(database)) = (words.get(1), words.get(2)) else {
+                    println!("Usage: db_report <size> <database> [player]");
+                    continue;
+                };
+                let player = words.get(3).copied();
+                match *size {
+                    "4" => database_report::<4>(database, player),
+                    "5" => database_report::<5>(database, player),
+                    "6" => database_report::<6>(database, player),
+                    s => println!("Unsupported size {}", s),
+                }
+            }
             "value_features" => match words.get(1) {
                 Some(&"4") => print_value_features::<4>(Komi::from_half_komi(4).unwrap()), // TODO: Bad default komi
                 Some(&"5") => print_value_features::<5>(Komi::from_half_komi(4).unwrap()),
@@ -162,29 +253,23 @@ fn main() {
             },
             "game" => {
                 println!("Enter move list or a full PTN, then press enter followed by CTRL+D");
-                let mut input = String::new();
 
                 match words.get(1) {
                     Some(&"6") => {
-                        io::stdin().read_to_string(&mut input).unwrap();
-                        let games = tiltak::ptn::ptn_parser::parse_ptn(&input).unwrap();
-                        if games.is_empty() {
+                        let Some(game) = read_game_from_stdin::<6>() else {
                             continue;
-                        }
+                        };
                         println!("Analyzing 1 game: ");
 
-                        analyze_game::<6>(games[0].clone());
+                        analyze_game::<6>(game);
                     }
                     None | Some(&"5") => {
-                        io::stdin().read_to_string(&mut input).unwrap();
-                        let games = tiltak::ptn::ptn_parser::parse_ptn(&input).unwrap();
-                        if games.is_empty() {
-                            println!("Couldn't parse any games");
+                        let Some(game) = read_game_from_stdin::<5>() else {
                             continue;
-                        }
+                        };
                         println!("Analyzing 1 game: ");
 
-                        analyze_game::<5>(games[0].clone());
+                        analyze_game::<5>(game);
                     }
                     Some(s) => println!("Game analysis at size {} not available", s),
                 }
@@ -200,6 +285,8 @@ fn main() {
 
 #[cfg(feature = "constant-tuning")]
 fn analyze_openings<const S: usize>(komi: Komi, nodes: u32) {
+    use std::io::Read;
+
     let mut input = String::new();
     io::stdin().read_to_string(&mut input).unwrap();
     input
@@ -417,20 +504,111 @@ fn print_policy_features<const S: usize>(komi: Komi) {
     }
 }
 
+/// Print a Playtak game as PTN, or an error if it couldn't be converted
+fn print_playtak_game(record: &PlaytakGameRecord) {
+    match record.to_ptn() {
+        Ok(ptn) => print!("{}", ptn),
+        Err(err) => eprintln!("Couldn't convert Playtak game #{}: {}", record.id, err),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn read_database_positions<const S: usize>(
+    database: &str,
+    filter: &GameFilter,
+) -> Option<Vec<DatabasePosition<S>>> {
+    match GameDatabase::open(database).and_then(|db| Ok(db.positions::<S>(filter)?.collect())) {
+        Ok(positions) => Some(positions),
+        Err(err) => {
+            println!("Couldn't read database {}: {}", database, err);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn test_policy<const S: usize>(database: &str) {
+    if let Some(positions) = read_database_positions::<S>(database, &GameFilter::default()) {
+        sqlite::reports::check_immediate_wins(&positions);
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn database_report<const S: usize>(database: &str, player: Option<&str>) {
+    // Only use games with komi that the engine has parameters for
+    let komi = if S == 4 {
+        Komi::default()
+    } else {
+        Komi::from_half_komi(4).unwrap()
+    };
+    let mut filter = GameFilter::default().komi(komi);
+    if let Some(player) = player {
+        filter = filter.player(player);
+    }
+    if let Some(positions) = read_database_positions::<S>(database, &filter) {
+        println!("Read {} positions from {}", positions.len(), database);
+        println!("{}", sqlite::reports::policy_accuracy(&positions));
+        println!("{}", sqlite::reports::value_calibration(&positions));
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn database_book<const S: usize>(database: &str, book_path: &str, max_ply: usize) {
+    let komi = if S == 4 {
+        Komi::default()
+    } else {
+        Komi::from_half_komi(4).unwrap()
+    };
+    let games: Vec<Game<Position<S>>> = match GameDatabase::open(database).and_then(|db| {
+        Ok(db
+            .games::<S>(&GameFilter::default().komi(komi))?
+            .filter_map(Result::ok)
+            .collect())
+    }) {
+        Ok(games) => games,
+        Err(err) => {
+            println!("Couldn't read database {}: {}", database, err);
+            return;
+        }
+    };
+    let book = OpeningBook::from_games(&games, komi, max_ply, 5);
+    match book.to_file(book_path) {
+        Ok(()) => println!(
+            "Wrote {} positions from {} games to {}",
+            book.len(),
+            games.len(),
+            book_path
+        ),
+        Err(err) => println!("Couldn't write {}: {}", book_path, err),
+    }
+}
+
+/// Read the first game from a PTN on stdin
+fn read_game_from_stdin<const S: usize>() -> Option<Game<Position<S>>> {
+    let mut reader = PtnReader::new(io::stdin().lock());
+    match reader.next() {
+        Some(Ok(game)) => Some(game),
+        Some(Err(err)) => {
+            println!("Couldn't parse game: {}", err);
+            None
+        }
+        None => {
+            println!("Couldn't parse any games");
+            None
+        }
+    }
+}
+
 fn analyze_position_from_ptn<const S: usize>() {
     println!("Enter move list or a full PTN, then press enter followed by CTRL+D");
 
-    let mut input = String::new();
-    io::stdin().read_to_string(&mut input).unwrap();
-    let games: Vec<Game<Position<S>>> = tiltak::ptn::ptn_parser::parse_ptn(&input).unwrap();
-    if games.is_empty() {
-        println!("Couldn't parse any games");
+    let Some(game) = read_game_from_stdin::<S>() else {
         return;
-    }
+    };
 
-    let mut position: Position<S> = games[0].start_position.clone();
+    let mut position: Position<S> = game.start_position.clone();
 
-    for PtnMove { mv, .. } in games[0].moves.clone() {
+    for PtnMove { mv, .. } in game.moves {
         position.do_move(mv);
     }
     analyze_position(&position)
@@ -455,117 +633,27 @@ fn analyze_position<const S: usize>(position: &Position<S>) {
 
     assert_eq!(position.game_result(), None, "Cannot analyze finished game");
 
-    let group_data = position.group_data();
-
-    let mut coefficients = vec![f16::ZERO; parameters::num_value_features::<S>()];
-    let coefficients_mid_index = coefficients.len() / 2;
-
-    let (white_coefficients, black_coefficients) =
-        coefficients.split_at_mut(coefficients_mid_index);
-
-    {
-        let mut white_value_features = parameters::ValueFeatures::new::<S>(white_coefficients);
-        let mut black_value_features = parameters::ValueFeatures::new::<S>(black_coefficients);
-        value_eval::static_eval_game_phase::<S>(
-            position,
-            &group_data,
-            &mut white_value_features,
-            &mut black_value_features,
-        );
-    }
-    for (feature, param) in white_coefficients.iter_mut().zip(
-        <Position<S>>::value_params(eval_komi)
-            .iter()
-            .take(coefficients_mid_index),
-    ) {
-        *feature *= f16::from_f32(*param);
-    }
-
-    for (feature, param) in black_coefficients.iter_mut().zip(
-        <Position<S>>::value_params(eval_komi)
-            .iter()
-            .skip(coefficients_mid_index),
-    ) {
-        *feature *= f16::from_f32(*param);
-    }
-
-    let mut mixed_coefficients: Vec<f16> = white_coefficients
-        .iter()
-        .zip(black_coefficients.iter())
-        .map(|(white, black)| *white - *black)
-        .collect();
-
-    let white_value_features = parameters::ValueFeatures::new::<S>(white_coefficients);
-    let white_value_features_string = format!("{:?}", white_value_features);
-
-    let black_value_features = parameters::ValueFeatures::new::<S>(black_coefficients);
-    let black_value_features_string = format!("{:?}", black_value_features);
-
-    let mixed_value_features = parameters::ValueFeatures::new::<S>(&mut mixed_coefficients);
-    let mixed_value_features_string = format!("{:?}", mixed_value_features);
-
-    println!("White features:");
-    for line in white_value_features_string.split("],") {
-        let (name, values) = line.split_once(": ").unwrap();
-        println!("{:32}: {}],", name, values);
-    }
-    println!();
-    println!("Black features:");
-    for line in black_value_features_string.split("],") {
-        let (name, values) = line.split_once(": ").unwrap();
-        println!("{:32}: {}],", name, values);
-    }
-    println!();
-    println!("Mixed features:");
-    for line in mixed_value_features_string.split("],") {
-        let (name, values) = line.split_once(": ").unwrap();
-        println!("{:32}: {}],", name, values);
-    }
-
-    let mut simple_moves = vec![];
-    let mut moves = vec![];
-    let mut fcd_per_move = vec![];
-
-    position.generate_moves_with_probabilities(
-        &position.group_data(),
-        &mut simple_moves,
-        &mut moves,
-        &mut fcd_per_move,
-        &mut vec![],
-        <Position<S>>::policy_params(eval_komi),
-        &mut Some(vec![]),
-    );
-    moves.sort_by(|(_mv, score1), (_, score2)| score1.partial_cmp(score2).unwrap().reverse());
-
-    let mut feature_sets =
-        vec![vec![f16::ZERO; parameters::num_policy_features::<S>()]; moves.len()];
-    let mut policy_feature_sets: Vec<_> = feature_sets
-        .iter_mut()
-        .map(|feature_set| parameters::PolicyFeatures::new::<S>(feature_set))
-        .collect();
-
-    let simple_moves: Vec<Move<S>> = moves.iter().map(|(mv, _)| *mv).collect();
-
-    position.features_for_moves(
-        &mut policy_feature_sets,
-        &simple_moves,
-        &mut fcd_per_move,
-        &group_data,
+    print!(
+        "{}",
+        explain::explain_value(position, <Position<S>>::value_params(eval_komi))
     );
+    println!();
 
     println!("Top 10 heuristic moves:");
-    for ((mv, score), features) in moves.iter().zip(feature_sets).take(10) {
-        println!("{}: {:.3}%", mv, score.to_f32() * 100.0);
-        for feature in features {
-            print!("{:.1}, ", feature);
-        }
-        println!();
+    for explanation in explain::explain_policy(position, <Position<S>>::policy_params(eval_komi))
+        .iter()
+        .take(10)
+    {
+        print!("{}", explanation);
     }
-    let settings: MctsSetting<S> = search::MctsSetting::default()
-        .arena_size(2_u32.pow(31))
-        .exclude_moves(vec![])
-        .add_value_params(<Position<S>>::value_params_2komi().into())
-        .add_policy_params(<Position<S>>::policy_params_2komi().into());
+    let settings: MctsSetting<S> = add_params_from_args(
+        search::MctsSetting::default()
+            .arena_size(2_u32.pow(31))
+            .exclude_moves(vec![])
+            .add_value_params(<Position<S>>::value_params_2komi().into())
+            .add_policy_params(<Position<S>>::policy_params_2komi().into()),
+        position.komi(),
+    );
     let start_time = time::Instant::now();
 
     let mut tree = search::MonteCarloTree::with_settings(position.clone(), settings);
@@ -601,7 +689,106 @@ fn analyze_position<const S: usize>(position: &Position<S>) {
     }
 }
 
-fn perft_from_tps<const S: usize>() {
+fn edit_position<const S: usize>() {
+    println!("Enter TPS (or leave empty for initial)");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let mut builder = if input.trim().is_empty() {
+        PositionBuilder::new()
+    } else {
+        match <Position<S>>::from_fen(&input) {
+            Ok(position) => PositionBuilder::from_position(&position),
+            Err(err) => {
+                println!("Couldn't parse TPS: {}", err);
+                return;
+            }
+        }
+    };
+    println!(
+        "set <square> <stack>: Replace a stack, e.g. \"set c3 12S\", or \"set c3 x\" to clear it"
+    );
+    println!("side <1|2>: Set the side to move");
+    println!("move <number>: Set the move number");
+    println!("komi <komi>: Set the komi");
+    println!("reserves <1|2> <stones> <capstones>: Set a player's reserves");
+    println!("done: Print the final tps and exit the editor");
+
+    loop {
+        input.clear();
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            break;
+        }
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let result: Result<PositionBuilder<S>, String> = match words.as_slice() {
+            ["set", square, stack] => Square::parse_square(square)
+                .map_err(|err| err.to_string())
+                .and_then(|square| Ok(builder.clone().stack(square, &parse_stack(stack)?))),
+            ["side", "1"] => Ok(builder.clone().side_to_move(Color::White)),
+            ["side", "2"] => Ok(builder.clone().side_to_move(Color::Black)),
+            ["move", move_number] => move_number
+                .parse()
+                .map(|move_number| builder.clone().move_number(move_number))
+                .map_err(|err| format!("Bad move number \"{}\": {}", move_number, err)),
+            ["komi", komi] => komi
+                .parse()
+                .map(|komi| builder.clone().komi(komi))
+                .map_err(|err| format!("Bad komi \"{}\": {}", komi, err)),
+            ["reserves", color @ ("1" | "2"), stones, caps] => {
+                let color = if *color == "1" {
+                    Color::White
+                } else {
+                    Color::Black
+                };
+                match (stones.parse(), caps.parse()) {
+                    (Ok(stones), Ok(caps)) => Ok(builder.clone().reserves(color, stones, caps)),
+                    _ => Err(format!("Bad reserves \"{} {}\"", stones, caps)),
+                }
+            }
+            ["done"] => match builder.build() {
+                Ok(position) => {
+                    println!("TPS {}", position.to_fen());
+                    break;
+                }
+                Err(err) => Err(err.to_string()),
+            },
+            _ => Err(format!("Unknown command \"{}\"", input.trim())),
+        };
+        match result {
+            Ok(new_builder) => {
+                builder = new_builder;
+                // Show the position after every edit, or explain why it is invalid
+                match builder.build() {
+                    Ok(position) => {
+                        println!("{:?}", position);
+                        println!("TPS {}", position.to_fen());
+                    }
+                    Err(err) => println!("Invalid position: {}", err),
+                }
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+/// Parse a single stack in tps notation, e.g. "12S", or "x" for an empty square
+fn parse_stack(input: &str) -> Result<Vec<Piece>, String> {
+    if input == "x" {
+        return Ok(vec![]);
+    }
+    let mut pieces = vec![];
+    for ch in input.chars() {
+        match (ch, pieces.last_mut()) {
+            ('1', _) => pieces.push(Piece::WhiteFlat),
+            ('2', _) => pieces.push(Piece::BlackFlat),
+            ('S', Some(piece)) => *piece = Piece::from_role_color(Role::Wall, piece.color()),
+            ('C', Some(piece)) => *piece = Piece::from_role_color(Role::Cap, piece.color()),
+            _ => return Err(format!("Couldn't parse stack \"{}\"", input)),
+        }
+    }
+    Ok(pieces)
+}
+
+fn perft_from_tps<const S: usize>(mode: &str) {
     println!("Enter TPS (or leave empty for initial)");
     let mut input = String::new();
     io::stdin().read_line(&mut input).unwrap();
@@ -610,13 +797,27 @@ fn perft_from_tps<const S: usize>() {
     } else {
         <Position<S>>::from_fen(&input).unwrap()
     };
-    perft(&mut position);
+    match mode {
+        "bulk" => perft(&mut position, |position, depth| position.bulk_perft(depth)),
+        "hashed" => perft(&mut position, |position, depth| {
+            position.hashed_perft(depth, 1 << 20)
+        }),
+        #[cfg(feature = "rayon")]
+        "parallel" => perft(&mut position, |position, depth| {
+            position.parallel_perft(depth)
+        }),
+        "divide" => perft_divide(&mut position),
+        s => println!("Unknown perft mode \"{}\"", s),
+    }
 }
 
-fn perft<const S: usize>(position: &mut Position<S>) {
+fn perft<const S: usize, F: Fn(&mut Position<S>, u16) -> u64>(
+    position: &mut Position<S>,
+    perft_fn: F,
+) {
     for depth in 0.. {
         let start_time = time::Instant::now();
-        let result = position.bulk_perft(depth);
+        let result = perft_fn(position, depth);
         println!(
             "{}: {}, {:.2}s, {:.1} Mnps",
             depth,
@@ -627,46 +828,82 @@ fn perft<const S: usize>(position: &mut Position<S>) {
     }
 }
 
+fn perft_divide<const S: usize>(position: &mut Position<S>) {
+    println!("Enter depth");
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let depth: u16 = match input.trim().parse() {
+        Ok(depth) => depth,
+        Err(err) => {
+            println!("Couldn't parse depth \"{}\": {}", input.trim(), err);
+            return;
+        }
+    };
+
+    let start_time = time::Instant::now();
+    #[cfg(feature = "rayon")]
+    let move_counts = position.parallel_perft_divide(depth);
+    #[cfg(not(feature = "rayon"))]
+    let move_counts = position.perft_divide(depth);
+
+    for (mv, result) in move_counts.iter() {
+        println!("{}: {}", position.move_to_san(mv), result);
+    }
+    let total: u64 = move_counts.iter().map(|(_, result)| result).sum();
+    println!(
+        "{} moves, {} nodes, {:.2}s",
+        move_counts.len(),
+        total,
+        start_time.elapsed().as_secs_f32()
+    );
+}
+
+/// Use the value and policy parameters from the files given with `--value-params` and `--policy-params`, if any
+fn add_params_from_args<const S: usize>(
+    mut settings: MctsSetting<S>,
+    komi: Komi,
+) -> MctsSetting<S> {
+    let arg_value = |name: &str| env::args().skip_while(|arg| arg != name).nth(1);
+    if let Some(path) = arg_value("--value-params") {
+        let file = load_value_params::<S>(&path, komi).unwrap_or_else(|err| panic!("{}", err));
+        settings = settings.add_value_params(file.params.into_boxed_slice());
+    }
+    if let Some(path) = arg_value("--policy-params") {
+        let file = load_policy_params::<S>(&path, komi).unwrap_or_else(|err| panic!("{}", err));
+        settings = settings.add_policy_params(file.params.into_boxed_slice());
+    }
+    settings
+}
+
+/// Print the engine's evaluation of every move as soon as it is analyzed,
+/// followed by an annotated PTN of the whole game
 fn analyze_game<const S: usize>(game: Game<Position<S>>) {
-    let mut position = game.start_position.clone();
-    let mut ply_number = 2;
-    for PtnMove { mv, .. } in game.moves {
-        position.do_move(mv);
-        if let Some(game_result) = position.game_result() {
-            let result_string = match game_result {
-                GameResult::WhiteWin => "1-0",
-                GameResult::BlackWin => "0-1",
-                GameResult::Draw => "1/2-1/2",
-            };
-            if ply_number % 2 == 0 {
-                print!("{}. {} {}", ply_number / 2, mv, result_string);
-                io::stdout().flush().unwrap();
-            } else {
-                println!("{}... {} {}", ply_number / 2, mv, result_string);
-            }
-        } else {
-            let (best_move, score) = search::mcts(position.clone(), 1_000_000);
-            if ply_number % 2 == 0 {
-                print!(
-                    "{}. {} {{{:.2}%, best reply {}}} ",
-                    ply_number / 2,
-                    position.move_to_san(&mv),
-                    (1.0 - score) * 100.0,
-                    best_move
-                );
+    let settings = AnalysisSettings::nodes(1_000_000).mcts_settings(
+        add_params_from_args(MctsSetting::default(), game.start_position.komi())
+            .arena_size_for_nodes(1_000_000),
+    );
+    let annotated_game =
+        analysis::annotate_game_with_callback(&game, &settings, |position, ptn_move| {
+            let move_number = position.half_moves_played() / 2 + 1;
+            let move_string = format!(
+                "{}{} {{{}}}",
+                position.move_to_san(&ptn_move.mv),
+                ptn_move.annotations.concat(),
+                ptn_move.comment
+            );
+            if position.side_to_move() == Color::White {
+                print!("{}. {} ", move_number, move_string);
                 io::stdout().flush().unwrap();
             } else {
-                println!(
-                    "{}... {} {{{:.2}%, best reply {}}}",
-                    ply_number / 2,
-                    position.move_to_san(&mv),
-                    (1.0 - score) * 100.0,
-                    best_move
-                );
+                println!("{}... {}", move_number, move_string);
             }
-        }
-        ply_number += 1;
+        });
+    println!();
+    if annotated_game.out_of_memory {
+        eprintln!("Warning: Some searches stopped early due to OOM");
     }
+    println!();
+    annotated_game.game.game_to_ptn(&mut io::stdout()).unwrap();
 }
 
 /// Play a game against the engine through stdin
@@ -727,7 +964,9 @@ fn bench() {
     let start_time = time::Instant::now();
 
     let position = <Position<6>>::default();
-    let settings = search::MctsSetting::default().arena_size_for_nodes(NODES);
+    let settings = search::MctsSetting::default()
+        .arena_size_for_nodes(NODES)
+        .eval_cache_size(1 << 16);
     let mut tree = search::MonteCarloTree::with_settings(position, settings);
     let mut last_iteration_start_time = time::Instant::now();
     for n in 1..=NODES {
@@ -748,11 +987,12 @@ fn bench() {
     let knps = 5000.0 / start_time.elapsed().as_secs_f32();
 
     println!(
-        "{}: {:.2}%, {:.2}s, {:.1} knps",
+        "{}: {:.2}%, {:.2}s, {:.1} knps, {:.1}% eval cache hits",
         mv,
         score * 100.0,
         start_time.elapsed().as_secs_f32(),
         knps,
+        tree.eval_cache_hit_rate().unwrap_or_default() * 100.0,
     );
 }
 
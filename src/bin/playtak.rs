@@ -7,7 +7,7 @@ use std::time::Duration;
 use std::time::Instant;
 use std::{io, net, thread};
 
-use board_game_traits::{Color, GameResult, Position as PositionTrait};
+use board_game_traits::{Color, Position as PositionTrait};
 use bufstream::BufStream;
 use chrono::{Datelike, Local};
 use clap::{Arg, ArgAction, Command};
@@ -19,12 +19,14 @@ use rand::seq::SliceRandom;
 use rand::Rng;
 #[cfg(feature = "aws-lambda-client")]
 use tiltak::aws;
+use tiltak::book::{BookSelection, BookSettings, OpeningBook};
+use tiltak::evaluation::parameter_file::{load_policy_params, load_value_params};
 use tiltak::position;
 use tiltak::position::{squares_iterator, Move, Role, Square};
 use tiltak::position::{Komi, Position};
 use tiltak::ptn::{Game, PtnMove};
 use tiltak::search;
-use tiltak::search::MctsSetting;
+use tiltak::search::{MctsSetting, TimeManagement};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct PlaytakSettings {
@@ -41,19 +43,29 @@ pub struct PlaytakSettings {
     seek_unrated: bool,
     target_move_time: Option<Duration>,
     komi: Komi,
+    book_ply: usize,
+    book_selection: BookSelection,
 }
 
 impl PlaytakSettings {
     pub fn to_mcts_setting<const S: usize>(&self) -> MctsSetting<S> {
+        // Use a smaller share of the time left than the engine default,
+        // to leave a margin for network lag on playtak.com
+        let time_management = TimeManagement {
+            time_left_share: 1.0 / 6.0,
+            ..TimeManagement::default()
+        };
         if let Some(dirichlet) = self.dirichlet_noise {
             MctsSetting::default()
                 .add_dirichlet(dirichlet)
                 .add_rollout_depth(self.rollout_depth)
                 .add_rollout_temperature(self.rollout_temperature)
+                .add_time_management(time_management)
         } else {
             MctsSetting::default()
                 .add_rollout_depth(self.rollout_depth)
                 .add_rollout_temperature(self.rollout_temperature)
+                .add_time_management(time_management)
         }
     }
 }
@@ -178,6 +190,35 @@ pub fn main() -> Result<()> {
             .help("Seek games with komi")
             .num_args(1)
             .default_value("0"))
+        .arg(Arg::new("book")
+            .long("book")
+            .env("BOOK")
+            .help("Play moves from this opening book in the opening. The book must be for the size of the game")
+            .num_args(1))
+        .arg(Arg::new("bookPly")
+            .long("book-ply")
+            .env("BOOK_PLY")
+            .help("Play book moves for at most this many plies")
+            .num_args(1)
+            .default_value("12")
+            .value_parser(clap::value_parser!(u64)))
+        .arg(Arg::new("bookSelection")
+            .long("book-selection")
+            .env("BOOK_SELECTION")
+            .help("Pick book moves at random by their weight, or always pick the highest-scoring move")
+            .num_args(1)
+            .value_parser(["weighted", "best"])
+            .default_value("weighted"))
+        .arg(Arg::new("valueParams")
+            .long("value-params")
+            .env("VALUE_PARAMS")
+            .help("Read the engine's value parameters from this file, instead of using the built-in ones. The file must be for the size of the game")
+            .num_args(1))
+        .arg(Arg::new("policyParams")
+            .long("policy-params")
+            .env("POLICY_PARAMS")
+            .help("Read the engine's policy parameters from this file, instead of using the built-in ones. The file must be for the size of the game")
+            .num_args(1))
         .arg(Arg::new("seekUnrated")
             .long("seek-unrated")
             .env("SEEK_UNRATED")
@@ -290,6 +331,13 @@ pub fn main() -> Result<()> {
 
     let seek_unrated = matches.get_flag("seekUnrated");
 
+    let book_ply = *matches.get_one::<u64>("bookPly").unwrap() as usize;
+    let book_selection = matches
+        .get_one::<String>("bookSelection")
+        .unwrap()
+        .parse()
+        .unwrap();
+
     let playtak_base_url = matches.get_one::<String>("playtakBaseUrl").unwrap();
     let playtak_port = *matches.get_one::<u16>("playtakPort").unwrap();
 
@@ -309,6 +357,8 @@ pub fn main() -> Result<()> {
         seek_unrated,
         target_move_time,
         komi,
+        book_ply,
+        book_selection,
     };
 
     loop {
@@ -341,6 +391,9 @@ pub fn main() -> Result<()> {
             warn!("No username/password provided, logging in as guest");
             session.login_guest()?;
         }
+        session.book_path = matches.get_one::<String>("book").cloned();
+        session.value_params_path = matches.get_one::<String>("valueParams").cloned();
+        session.policy_params_path = matches.get_one::<String>("policyParams").cloned();
 
         // Re-connect if we get disconnected from the server
         let error = match matches.get_one::<String>("playBot") {
@@ -480,6 +533,9 @@ struct PlaytakSession {
     username: Option<String>,
     #[cfg(feature = "aws-lambda-client")]
     aws_function_name: Option<String>,
+    book_path: Option<String>,
+    value_params_path: Option<String>,
+    policy_params_path: Option<String>,
     connection: BufStream<TcpStream>,
     // The server requires regular pings, to not kick the user
     // This thread does nothing but provide those pings
@@ -534,6 +590,9 @@ impl PlaytakSession {
             username: None,
             #[cfg(feature = "aws-lambda-client")]
             aws_function_name: None,
+            book_path: None,
+            value_params_path: None,
+            policy_params_path: None,
             connection,
             ping_thread,
         })
@@ -761,6 +820,29 @@ impl PlaytakSession {
         }
     }
 
+    /// Search settings for a game of size `S`, with parameters from the parameter files, if any.
+    /// If a file can't be read, the built-in parameters are used instead.
+    fn mcts_settings<const S: usize>(&self, playtak_settings: PlaytakSettings) -> MctsSetting<S> {
+        let mut mcts_settings = playtak_settings.to_mcts_setting();
+        if let Some(path) = &self.value_params_path {
+            match load_value_params::<S>(path, playtak_settings.komi) {
+                Ok(file) => {
+                    mcts_settings = mcts_settings.add_value_params(file.params.into_boxed_slice())
+                }
+                Err(err) => warn!("{}, playing with the built-in value parameters", err),
+            }
+        }
+        if let Some(path) = &self.policy_params_path {
+            match load_policy_params::<S>(path, playtak_settings.komi) {
+                Ok(file) => {
+                    mcts_settings = mcts_settings.add_policy_params(file.params.into_boxed_slice())
+                }
+                Err(err) => warn!("{}, playing with the built-in policy parameters", err),
+            }
+        }
+        mcts_settings
+    }
+
     /// The main game loop of a playtak game.
     /// Mutually recursive with `seek_game`, which places a new seek as soon as the game finishes.
     fn play_game<const S: usize>(
@@ -784,6 +866,24 @@ impl PlaytakSession {
         let mut position = <Position<S>>::start_position_with_komi(game.komi);
         let mut moves = vec![];
         let mut our_time_left = game.time_left;
+        let book =
+            self.book_path
+                .as_ref()
+                .and_then(|path| match OpeningBook::<S>::from_file(path) {
+                    Ok(book) => Some(
+                        BookSettings::new(book)
+                            .selection(playtak_settings.book_selection)
+                            .max_ply(playtak_settings.book_ply),
+                    ),
+                    Err(err) => {
+                        warn!(
+                            "Couldn't read opening book {}, playing without it: {}",
+                            path, err
+                        );
+                        None
+                    }
+                });
+        let mcts_settings = self.mcts_settings::<S>(playtak_settings);
         'gameloop: loop {
             if position.game_result().is_some() {
                 // Double check that the game is still over, if we remove information about move repetitions
@@ -796,96 +896,117 @@ impl PlaytakSession {
                 }
             }
             if position.side_to_move() == game.our_color && !restoring_previous_session {
-                let (best_move, score) =
-                    // On the very first move, always place instantly in a random corner
-                    if squares_iterator::<S>().all(|square| position[square].is_empty()) {
-                        let mut rng = rand::thread_rng();
-                        let corner_placements: Vec<Move<S>> = Square::corners().into_iter().map(|square| Move::placement(Role::Flat, square)).collect();
-
-                        (*corner_placements.choose(&mut rng).unwrap(), 0.0)
-                    } else if let Some(fixed_nodes) = playtak_settings.fixed_nodes {
-                        let settings =
-                            playtak_settings.to_mcts_setting()
-                            .arena_size_for_nodes(fixed_nodes as u32);
-                        let mut tree = search::MonteCarloTree::with_settings(position.clone(), settings);
-                        for _ in 0..fixed_nodes {
-                            if tree.select().is_none() {
-                                eprintln!("Warning: Search stopped early due to OOM");
-                                break;
-                            };
-                        }
-
-                        // Wait for a bit
-                        let mut rng = rand::thread_rng();
-                        let sleep_duration = Duration::from_millis(rng.gen_range(1000..2500));
-                        thread::sleep(sleep_duration);
-
-                        tree.best_move()
-                    } else {
-                        #[cfg(feature = "aws-lambda-client")]
-                        {
-                            let aws_function_name = self.aws_function_name.as_ref().unwrap();
-                            let start_time = Instant::now();
-                            let event = aws::Event {
-                                size: S,
-                                tps: None,
-                                moves: moves
-                                    .iter()
-                                    .map(|PtnMove { mv, .. }: &PtnMove<Move<S>>| mv.to_string())
-                                    .collect(),
-                                time_control: search::TimeControl::Time(our_time_left, game.increment),
-                                komi: position.komi().into(),
-                                eval_komi: None,
-                                dirichlet_noise: playtak_settings.dirichlet_noise,
-                                rollout_depth: playtak_settings.rollout_depth,
-                                rollout_temperature: playtak_settings.rollout_temperature,
-                            };
-                            let aws::Output { pv, score, nodes, mem_usage, time_taken } =
-                                aws::client::best_move_aws(aws_function_name, &event)?;
+                let (best_move, score) = if let Some(book_move) = book
+                    .as_ref()
+                    .and_then(|book| book.choose_move(&position, &mut rand::thread_rng()))
+                {
+                    info!("Playing book move {}", position.move_to_san(&book_move.mv));
+                    (book_move.mv, book_move.score)
+                }
+                // On the very first move, always place instantly in a random corner
+                else if squares_iterator::<S>().all(|square| position[square].is_empty()) {
+                    let mut rng = rand::thread_rng();
+                    let corner_placements: Vec<Move<S>> = Square::corners()
+                        .into_iter()
+                        .map(|square| Move::placement(Role::Flat, square))
+                        .collect();
+
+                    (*corner_placements.choose(&mut rng).unwrap(), 0.0)
+                } else if let Some(fixed_nodes) = playtak_settings.fixed_nodes {
+                    let settings = mcts_settings
+                        .clone()
+                        .arena_size_for_nodes(fixed_nodes as u32);
+                    let mut tree =
+                        search::MonteCarloTree::with_settings(position.clone(), settings);
+                    for _ in 0..fixed_nodes {
+                        if tree.select().is_none() {
+                            eprintln!("Warning: Search stopped early due to OOM");
+                            break;
+                        };
+                    }
 
-                            debug!("{} nodes, {}MB, {:.1}s taken, {}ms overhead",
-                                nodes,
-                                mem_usage / (1024 * 1024),
-                                time_taken.as_secs_f32(),
-                                (start_time.elapsed() - time_taken).as_millis()
-                            );
+                    // Wait for a bit
+                    let mut rng = rand::thread_rng();
+                    let sleep_duration = Duration::from_millis(rng.gen_range(1000..2500));
+                    thread::sleep(sleep_duration);
 
-                            (position.move_from_san(&pv[0]).unwrap(), score)
-                        }
+                    tree.best_move()
+                } else {
+                    #[cfg(feature = "aws-lambda-client")]
+                    {
+                        let aws_function_name = self.aws_function_name.as_ref().unwrap();
+                        let start_time = Instant::now();
+                        let event = aws::Event {
+                            size: S,
+                            tps: None,
+                            moves: moves
+                                .iter()
+                                .map(|PtnMove { mv, .. }: &PtnMove<Move<S>>| mv.to_string())
+                                .collect(),
+                            time_control: search::TimeControl::Time(our_time_left, game.increment),
+                            komi: position.komi().into(),
+                            eval_komi: None,
+                            dirichlet_noise: playtak_settings.dirichlet_noise,
+                            rollout_depth: playtak_settings.rollout_depth,
+                            rollout_temperature: playtak_settings.rollout_temperature,
+                        };
+                        let aws::Output {
+                            pv,
+                            score,
+                            nodes,
+                            mem_usage,
+                            time_taken,
+                        } = aws::client::best_move_aws(aws_function_name, &event)?;
+
+                        debug!(
+                            "{} nodes, {}MB, {:.1}s taken, {}ms overhead",
+                            nodes,
+                            mem_usage / (1024 * 1024),
+                            time_taken.as_secs_f32(),
+                            (start_time.elapsed() - time_taken).as_millis()
+                        );
+
+                        (position.move_from_san(&pv[0]).unwrap(), score)
+                    }
 
-                        #[cfg(not(feature = "aws-lambda-client"))]
-                        {
-                            let maximum_time = if let Some(target_move_time) =  playtak_settings.target_move_time {
-                                (our_time_left / 6 + game.increment / 2).min(2 * target_move_time)
+                    #[cfg(not(feature = "aws-lambda-client"))]
+                    {
+                        let maximum_time = mcts_settings
+                            .time_management()
+                            .max_time(our_time_left, game.increment);
+                        let maximum_time =
+                            if let Some(target_move_time) = playtak_settings.target_move_time {
+                                maximum_time.min(2 * target_move_time)
                             } else {
-                                our_time_left / 6 + game.increment / 2
+                                maximum_time
                             };
 
-                            // Give enough memory for a CPU calculating at roughly 200K nps.
-                            let max_nodes = (maximum_time.as_secs() as u32).saturating_mul(200_000);
+                        // Give enough memory for a CPU calculating at roughly 200K nps.
+                        let max_nodes = (maximum_time.as_secs() as u32).saturating_mul(200_000);
 
-                            // For 6s, the toughest position I've found required 40 elements/node searched
-                            // This formula gives 72, which is hopefully plenty
-                            let max_arena_size = if playtak_settings.rollout_depth < 10 {
-                                max_nodes.saturating_mul((S * S) as u32 * 2)
-                            } else {
-                                // Give SlateBot a smaller tree size, because its nps is much lower
-                                max_nodes.saturating_mul(S as u32 * 2)
-                            };
+                        // For 6s, the toughest position I've found required 40 elements/node searched
+                        // This formula gives 72, which is hopefully plenty
+                        let max_arena_size = if playtak_settings.rollout_depth < 10 {
+                            max_nodes.saturating_mul((S * S) as u32 * 2)
+                        } else {
+                            // Give SlateBot a smaller tree size, because its nps is much lower
+                            max_nodes.saturating_mul(S as u32 * 2)
+                        };
 
-                            let settings =
-                                playtak_settings.to_mcts_setting()
-                                .arena_size(max_arena_size.min(2_u32.pow(31)));
+                        let settings = mcts_settings
+                            .clone()
+                            .arena_size(max_arena_size.min(2_u32.pow(31)));
 
-                            search::play_move_time(position.clone(), maximum_time, settings)
-                        }
-                    };
+                        search::play_move_time(position.clone(), maximum_time, settings)
+                    }
+                };
 
                 position.do_move(best_move);
                 moves.push(PtnMove {
                     mv: best_move,
                     annotations: vec![],
                     comment: score.to_string(),
+                    variations: vec![],
                 });
 
                 let output_string =
@@ -894,23 +1015,10 @@ impl PlaytakSession {
 
                 // Say "Tak" whenever there is a threat to win
                 // Only do this vs Shigewara
-                if game.white_player == "shigewara" || game.black_player == "shigewara" {
-                    let mut position_clone = position.clone();
-                    position_clone.null_move();
-                    let mut moves = vec![];
-                    position_clone.generate_moves(&mut moves);
-                    for mv in moves {
-                        let reverse_move = position_clone.do_move(mv);
-                        match (position_clone.side_to_move(), position_clone.game_result()) {
-                            (Color::White, Some(GameResult::BlackWin))
-                            | (Color::Black, Some(GameResult::WhiteWin)) => {
-                                self.send_line("Tell shigewara Tak!")?;
-                                break;
-                            }
-                            _ => (),
-                        }
-                        position_clone.reverse_move(reverse_move);
-                    }
+                if (game.white_player == "shigewara" || game.black_player == "shigewara")
+                    && !position.tak_threats().is_empty()
+                {
+                    self.send_line("Tell shigewara Tak!")?;
                 }
             } else {
                 // Wait for the opponent's move. The server may send other messages in the meantime
@@ -960,6 +1068,7 @@ impl PlaytakSession {
                                     mv: move_played,
                                     annotations: vec![],
                                     comment: "0.0".to_string(),
+                                    variations: vec![],
                                 });
                                 break;
                             }
@@ -7,11 +7,15 @@ use std::{env, io};
 use tiltak::position::{Komi, Position};
 
 use std::any::Any;
+use tiltak::book::{BookSelection, BookSettings, OpeningBook};
+use tiltak::evaluation::parameter_file::{load_policy_params, load_value_params};
 use tiltak::search::MctsSetting;
 use tiltak::search::{self, MonteCarloTree};
 
 pub fn main() {
     let is_slatebot = env::args().any(|arg| arg == "--slatebot");
+    let value_params_file = arg_value("--value-params");
+    let policy_params_file = arg_value("--policy-params");
 
     loop {
         let mut input = String::new();
@@ -24,12 +28,23 @@ pub fn main() {
     println!("id name Tiltak");
     println!("id author Morten Lohne");
     println!("option name HalfKomi type spin default 0 min -10 max 10");
+    println!("option name BookFile type string default");
+    println!("option name BookPly type spin default 12 min 0 max 1000");
+    println!("option name BookSelection type combo default weighted var weighted var best");
     println!("teiok");
 
     // Position stored in a `dyn Any` variable, because it can be any size
     let mut position: Option<Box<dyn Any>> = None;
     let mut size: Option<usize> = None;
     let mut komi = Komi::default();
+    let mut book_file: Option<String> = None;
+    let mut book_ply = 12;
+    let mut book_selection = BookSelection::Weighted;
+    // The book is loaded for the current size, so it is also stored as `dyn Any`
+    let mut book: Option<Box<dyn Any>> = None;
+    // Search settings for the current size, with the parameters from files.
+    // The files are only read once per game, not on every `go`
+    let mut mcts_settings: Option<Box<dyn Any>> = None;
 
     for line in BufReader::new(io::stdin()).lines().map(Result::unwrap) {
         let mut words = line.split_whitespace();
@@ -37,35 +52,54 @@ pub fn main() {
             "quit" => break,
             "isready" => println!("readyok"),
             "setoption" => {
-                if [
-                    words.next().unwrap_or_default(),
-                    words.next().unwrap_or_default(),
-                    words.next().unwrap_or_default(),
-                ]
-                .join(" ")
-                    == "name HalfKomi value"
-                {
-                    if let Some(k) = words
-                        .next()
-                        .and_then(|komi_string| komi_string.parse::<i8>().ok())
-                        .and_then(Komi::from_half_komi)
-                    {
-                        komi = k;
-                    } else {
-                        panic!("Invalid komi setting \"{}\"", line);
+                let rest: Vec<&str> = words.collect();
+                let (name, value) = match rest.iter().position(|word| *word == "value") {
+                    Some(i) if rest.first() == Some(&"name") => {
+                        (rest[1..i].join(" "), rest[i + 1..].join(" "))
                     }
-                } else {
-                    panic!("Invalid setoption string \"{}\"", line);
+                    _ => panic!("Invalid setoption string \"{}\"", line),
+                };
+                match name.as_str() {
+                    "HalfKomi" => {
+                        if let Some(k) = value.parse::<i8>().ok().and_then(Komi::from_half_komi) {
+                            komi = k;
+                        } else {
+                            panic!("Invalid komi setting \"{}\"", line);
+                        }
+                    }
+                    "BookFile" => book_file = if value.is_empty() { None } else { Some(value) },
+                    "BookPly" => {
+                        book_ply = value
+                            .parse()
+                            .unwrap_or_else(|_| panic!("Invalid book ply setting \"{}\"", line))
+                    }
+                    "BookSelection" => {
+                        book_selection = value
+                            .parse()
+                            .unwrap_or_else(|_| panic!("Invalid book selection \"{}\"", line))
+                    }
+                    _ => panic!("Invalid setoption string \"{}\"", line),
                 }
+                book = None;
+                // The parameter files are checked against the komi
+                mcts_settings = None;
             }
             "teinewgame" => {
                 let size_string = words.next();
                 size = size_string.and_then(|s| usize::from_str(s).ok());
                 position = None;
 
-                match size {
-                    Some(4) | Some(5) | Some(6) => (),
-                    _ => panic!("Error: Unsupported size {}", size.unwrap_or_default()),
+                book = None;
+
+                mcts_settings = load_mcts_settings_for_size(
+                    size,
+                    is_slatebot,
+                    komi,
+                    &value_params_file,
+                    &policy_params_file,
+                );
+                if mcts_settings.is_none() {
+                    panic!("Error: Unsupported size {}", size.unwrap_or_default());
                 }
             }
             "position" => {
@@ -77,38 +111,162 @@ pub fn main() {
                     Some(s) => panic!("Unsupported size {}", s),
                 }
             }
-            "go" => match size {
-                Some(4) => parse_go_string::<4>(
-                    &line,
-                    position.as_ref().and_then(|p| p.downcast_ref()).unwrap(),
-                    is_slatebot,
-                ),
-                Some(5) => parse_go_string::<5>(
-                    &line,
-                    position.as_ref().and_then(|p| p.downcast_ref()).unwrap(),
-                    is_slatebot,
-                ),
-                Some(6) => parse_go_string::<6>(
-                    &line,
-                    position.as_ref().and_then(|p| p.downcast_ref()).unwrap(),
-                    is_slatebot,
-                ),
-                Some(s) => panic!("Error: Unsupported size {}", s),
-                None => panic!("Error: Received go without receiving teinewgame string"),
-            },
+            "go" => {
+                if book.is_none() {
+                    book = match size {
+                        Some(4) => Some(load_book::<4>(&book_file, book_ply, book_selection)),
+                        Some(5) => Some(load_book::<5>(&book_file, book_ply, book_selection)),
+                        Some(6) => Some(load_book::<6>(&book_file, book_ply, book_selection)),
+                        _ => None,
+                    };
+                }
+                if mcts_settings.is_none() {
+                    mcts_settings = load_mcts_settings_for_size(
+                        size,
+                        is_slatebot,
+                        komi,
+                        &value_params_file,
+                        &policy_params_file,
+                    );
+                }
+                match size {
+                    Some(4) => go::<4>(&line, &position, &book, &mcts_settings),
+                    Some(5) => go::<5>(&line, &position, &book, &mcts_settings),
+                    Some(6) => go::<6>(&line, &position, &book, &mcts_settings),
+                    Some(s) => panic!("Error: Unsupported size {}", s),
+                    None => panic!("Error: Received go without receiving teinewgame string"),
+                }
+            }
             s => panic!("Unknown command \"{}\"", s),
         }
     }
 }
 
+/// Load the book for the current size. The result is an `Option<BookSettings<S>>`,
+/// which is `None` if no book is set
+fn load_book<const S: usize>(
+    book_file: &Option<String>,
+    book_ply: usize,
+    book_selection: BookSelection,
+) -> Box<dyn Any> {
+    let book_settings: Option<BookSettings<S>> = book_file.as_ref().map(|path| {
+        let book = OpeningBook::from_file(path)
+            .unwrap_or_else(|err| panic!("Couldn't read opening book {}: {}", path, err));
+        BookSettings::new(book)
+            .selection(book_selection)
+            .max_ply(book_ply)
+    });
+    Box::new(book_settings)
+}
+
+/// The value of a command line option like `--value-params <file>`
+fn arg_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+/// Load the search settings for the current size, see `load_mcts_settings`.
+/// The result is an `MctsSetting<S>`, or `None` if the size is not supported
+fn load_mcts_settings_for_size(
+    size: Option<usize>,
+    is_slatebot: bool,
+    komi: Komi,
+    value_params_file: &Option<String>,
+    policy_params_file: &Option<String>,
+) -> Option<Box<dyn Any>> {
+    match size {
+        Some(4) => Some(Box::new(load_mcts_settings::<4>(
+            is_slatebot,
+            komi,
+            value_params_file,
+            policy_params_file,
+        ))),
+        Some(5) => Some(Box::new(load_mcts_settings::<5>(
+            is_slatebot,
+            komi,
+            value_params_file,
+            policy_params_file,
+        ))),
+        Some(6) => Some(Box::new(load_mcts_settings::<6>(
+            is_slatebot,
+            komi,
+            value_params_file,
+            policy_params_file,
+        ))),
+        _ => None,
+    }
+}
+
+/// Search settings for size `S`, with the value and policy parameters from files, if given.
+/// If a file can't be used, the error is reported to the GUI, and the built-in parameters are used instead
+fn load_mcts_settings<const S: usize>(
+    is_slatebot: bool,
+    komi: Komi,
+    value_params_file: &Option<String>,
+    policy_params_file: &Option<String>,
+) -> MctsSetting<S> {
+    let mut mcts_settings: MctsSetting<S> = if is_slatebot {
+        MctsSetting::default().add_rollout_depth(200)
+    } else {
+        MctsSetting::default()
+    };
+    if let Some(path) = value_params_file {
+        match load_value_params::<S>(path, komi) {
+            Ok(file) => {
+                mcts_settings = mcts_settings.add_value_params(file.params.into_boxed_slice())
+            }
+            Err(err) => println!(
+                "info string {}, playing with the built-in value parameters",
+                err
+            ),
+        }
+    }
+    if let Some(path) = policy_params_file {
+        match load_policy_params::<S>(path, komi) {
+            Ok(file) => {
+                mcts_settings = mcts_settings.add_policy_params(file.params.into_boxed_slice())
+            }
+            Err(err) => println!(
+                "info string {}, playing with the built-in policy parameters",
+                err
+            ),
+        }
+    }
+    mcts_settings
+}
+
+/// Handle a `go` command, with the position, book and search settings stored for size `S`
+fn go<const S: usize>(
+    line: &str,
+    position: &Option<Box<dyn Any>>,
+    book: &Option<Box<dyn Any>>,
+    mcts_settings: &Option<Box<dyn Any>>,
+) {
+    parse_go_string::<S>(
+        line,
+        position.as_ref().and_then(|p| p.downcast_ref()).unwrap(),
+        book.as_ref()
+            .and_then(|b| b.downcast_ref::<Option<BookSettings<S>>>())
+            .and_then(Option::as_ref),
+        mcts_settings
+            .as_ref()
+            .and_then(|settings| settings.downcast_ref::<MctsSetting<S>>())
+            .unwrap()
+            .clone(),
+    )
+}
+
 fn parse_position_string<const S: usize>(line: &str, komi: Komi) -> Position<S> {
-    let mut words_iter = line.split_whitespace();
+    let mut words_iter = line.split_whitespace().peekable();
     words_iter.next(); // position
     let mut position = match words_iter.next() {
         Some("startpos") => Position::start_position_with_komi(komi),
         Some("tps") => {
-            let tps: String = (&mut words_iter).take(3).collect::<Vec<_>>().join(" ");
-            <Position<S>>::from_fen_with_komi(&tps, komi).unwrap()
+            // The TPS may be extended with more fields, which go until the move list
+            let mut tps_words = vec![];
+            while let Some(word) = words_iter.next_if(|word| *word != "moves") {
+                tps_words.push(word);
+            }
+            <Position<S>>::from_fen_with_komi(&tps_words.join(" "), komi).unwrap()
         }
         _ => panic!("Expected \"startpos\" or \"tps\" to specify position."),
     };
@@ -125,16 +283,26 @@ fn parse_position_string<const S: usize>(line: &str, komi: Komi) -> Position<S>
     position
 }
 
-fn parse_go_string<const S: usize>(line: &str, position: &Position<S>, is_slatebot: bool) {
+fn parse_go_string<const S: usize>(
+    line: &str,
+    position: &Position<S>,
+    book: Option<&BookSettings<S>>,
+    mcts_settings: MctsSetting<S>,
+) {
+    if let Some(book_move) =
+        book.and_then(|book| book.choose_move(position, &mut rand::thread_rng()))
+    {
+        println!(
+            "info string book move, score cp {}",
+            (book_move.score * 200.0 - 100.0) as i64
+        );
+        println!("bestmove {}", position.move_to_san(&book_move.mv));
+        return;
+    }
+
     let mut words = line.split_whitespace();
     words.next(); // go
 
-    let mcts_settings = if is_slatebot {
-        MctsSetting::default().add_rollout_depth(200)
-    } else {
-        MctsSetting::default()
-    };
-
     match words.next() {
         Some("movetime") => {
             let msecs = words.next().unwrap();
@@ -196,9 +364,10 @@ fn parse_go_string<const S: usize>(line: &str, position: &Position<S>, is_slateb
 
             println!("{:?}, {:?}", white_time, black_time);
 
+            let time_management = mcts_settings.time_management();
             let max_time = match position.side_to_move() {
-                Color::White => white_time / 5 + white_inc / 2,
-                Color::Black => black_time / 5 + black_inc / 2,
+                Color::White => time_management.max_time(white_time, white_inc),
+                Color::Black => time_management.max_time(black_time, black_inc),
             };
 
             let start_time = Instant::now();
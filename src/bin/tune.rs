@@ -1,14 +1,30 @@
-use std::path::Path;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgMatches, Command};
 
+#[cfg(feature = "mlp")]
+use rand::{rngs::StdRng, SeedableRng};
+use tiltak::book::{BookSelection, BookSettings, OpeningBook};
+#[cfg(feature = "mlp")]
+use tiltak::evaluation::mlp::{load_policy_mlp, load_value_mlp, Mlp};
+use tiltak::evaluation::parameter_file::{
+    load_policy_params, load_value_params, ParameterFile, ParameterKind,
+};
 use tiltak::evaluation::parameters::{
     self, NUM_POLICY_FEATURES_4S, NUM_POLICY_FEATURES_5S, NUM_POLICY_FEATURES_6S,
     NUM_VALUE_FEATURES_4S, NUM_VALUE_FEATURES_5S, NUM_VALUE_FEATURES_6S,
 };
 use tiltak::position::Komi;
-use tiltak::tune::{spsa, training};
+use tiltak::search::{MctsSetting, TimeControl};
+use tiltak::tune::distributed::{self, Coordinator};
+use tiltak::tune::gradient_descent::{GradientDescentSettings, Optimizer};
+#[cfg(feature = "mlp")]
+use tiltak::tune::mlp_training;
+use tiltak::tune::sprt::{Sprt, SprtResult};
+use tiltak::tune::training::Augmentation;
+use tiltak::tune::training_run::{Manifest, TrainingConfig};
+use tiltak::tune::{play_match, spsa, training, training_data};
 
 fn main() {
     let app = Command::new("Tiltak variable tuning")
@@ -24,257 +40,547 @@ fn main() {
                 .default_value("5")
                 .value_parser(clap::value_parser!(u64).range(4..=6)),
         )
+        .arg(
+            Arg::new("komi")
+                .global(true)
+                .long("komi")
+                .help("Komi for self-play games and tuning")
+                .num_args(1)
+                .default_value("2")
+                .value_parser(|komi: &str| komi.parse::<Komi>()),
+        )
+        .arg(
+            Arg::new("batch-size")
+                .global(true)
+                .long("batch-size")
+                .help("Number of self-play games played between each training")
+                .num_args(1)
+                .default_value("500")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("batches-for-training")
+                .global(true)
+                .long("batches-for-training")
+                .help("Train from the games in this many recent self-play batches")
+                .num_args(1)
+                .default_value("25")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("nodes")
+                .global(true)
+                .long("nodes")
+                .help("Nodes to search for each move in self-play games and matches")
+                .num_args(1)
+                .default_value("50000")
+                .value_parser(clap::value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("dirichlet-alpha")
+                .global(true)
+                .long("dirichlet-alpha")
+                .help("Alpha for the dirichlet noise added to the root in self-play games")
+                .num_args(1)
+                .default_value("0.2")
+                .value_parser(clap::value_parser!(f32)),
+        )
+        .arg(
+            Arg::new("opening-book")
+                .global(true)
+                .long("opening-book")
+                .help("Start self-play games and match game pairs with moves from this opening book")
+                .num_args(1)
+                .value_name("book.txt"),
+        )
+        .arg(
+            Arg::new("book-ply")
+                .global(true)
+                .long("book-ply")
+                .help("Play book moves for at most this many plies")
+                .num_args(1)
+                .default_value("12")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("book-selection")
+                .global(true)
+                .long("book-selection")
+                .help("Pick book moves at random by their weight, or always pick the highest-scoring move")
+                .num_args(1)
+                .default_value("weighted")
+                .value_parser(["weighted", "best"]),
+        )
+        .arg(
+            Arg::new("coordinator")
+                .global(true)
+                .long("coordinator")
+                .help("Listen for workers started with `tune worker` on this address, like 0.0.0.0:5050, and let them play the self-play games instead of playing them locally")
+                .num_args(1)
+                .value_name("address:port"),
+        )
+        .arg(
+            Arg::new("value-params")
+                .global(true)
+                .long("value-params")
+                .help("Start self-play training from the value parameters in this file, instead of the built-in ones")
+                .num_args(1)
+                .value_name("value_params.txt"),
+        )
+        .arg(
+            Arg::new("policy-params")
+                .global(true)
+                .long("policy-params")
+                .help("Start self-play training from the policy parameters in this file, instead of the built-in ones")
+                .num_args(1)
+                .value_name("policy_params.txt"),
+        )
         .subcommand(Command::new("selfplay")
-            .about("Tune value and policy constants by playing against itself. Will write the games to text files in the working directory."))
+            .about("Tune value and policy constants by playing against itself. Will write the games, parameters and a manifest to a new run directory in the working directory."))
         .subcommand(Command::new("selfplay-from-scratch")
-            .about("Tune value and policy constants from randomly initialized values by playing against itself. Will write the games to text files in the working directory."))
-        .subcommand(Command::new("continue-selfplay")
-            .about("Continue selfplay training")
-            .arg(Arg::new("training-id")
-                .long("training-id")
+            .about("Tune value and policy constants from randomly initialized values by playing against itself. Will write the games, parameters and a manifest to a new run directory in the working directory."))
+        .subcommand(Command::new("worker")
+            .about("Play self-play games for a coordinator, started with `tune selfplay --coordinator`, until it stops. The board size must match the coordinator's.")
+            .arg(Arg::new("coordinator-address")
+                .help("Address of the coordinator, like 192.168.0.10:5050")
+                .value_name("address:port")
+                .required(true))
+            .arg(Arg::new("threads")
+                .long("threads")
+                .help("Number of games to play at the same time. Defaults to the number of CPU cores")
                 .num_args(1)
+                .value_parser(clap::value_parser!(u64).range(1..))))
+        .subcommand(Command::new("continue-selfplay")
+            .about("Continue selfplay training from the manifest in a run directory. The size and training settings are read from the manifest.")
+            .arg(Arg::new("run-directory")
+                .index(1)
                 .required(true)
-                .value_parser(clap::value_parser!(u64)),
-            ))
+                .value_name("selfplay0_5s")))
         .subcommand(Command::new("value-from-file")
-                .about("Tune value constants from randomly initialized values, using the given ptn file. Note that the ptn parser is completely broken, and will probably fail on any files not generated by this program itself.")
+                .about("Tune value constants from randomly initialized values, using the given ptn file.")
                 .arg(Arg::new("file-name")
                     .index(1)
                     .required(true)
-                    .value_name("games.ptn")))
+                    .value_name("games.ptn"))
+                .arg(Arg::new("value-output")
+                    .long("value-output")
+                    .help("File to write the value parameters to")
+                    .num_args(1)
+                    .default_value("value_params.txt"))
+                .arg(Arg::new("value-learning-rate")
+                    .long("value-learning-rate")
+                    .help("Initial learning rate for the value parameters. Defaults to a value that suits the optimizer")
+                    .num_args(1)
+                    .value_parser(clap::value_parser!(f32)))
+                .arg(Arg::new("value-log")
+                    .long("value-log")
+                    .help("Write the training and validation error of the value parameters to this CSV file")
+                    .num_args(1)
+                    .value_name("value_training.csv"))
+                .args(tuning_args()))
         .subcommand(
             Command::new("both-from-file")
-                .about("Tune value and policy constants from randomly initialized values, using the given text file")
+                .about("Tune value and policy constants from randomly initialized values, using a ptn file and its move scores, or a binary training data file")
                 .arg(Arg::new("value-file-name")
                     .index(1)
                     .required(true)
+                    .help("Games to train from, or a training data file if no move scores are given")
                     .value_name("games.ptn"))
                 .arg(Arg::new("policy-file-name")
+                    .index(2)
+                    .value_name("move_scores.txt"))
+                .arg(Arg::new("value-output")
+                    .long("value-output")
+                    .help("File to write the value parameters to")
+                    .num_args(1)
+                    .default_value("value_params.txt"))
+                .arg(Arg::new("policy-output")
+                    .long("policy-output")
+                    .help("File to write the policy parameters to")
+                    .num_args(1)
+                    .default_value("policy_params.txt"))
+                .arg(Arg::new("value-learning-rate")
+                    .long("value-learning-rate")
+                    .help("Initial learning rate for the value parameters. Defaults to a value that suits the optimizer")
+                    .num_args(1)
+                    .value_parser(clap::value_parser!(f32)))
+                .arg(Arg::new("policy-learning-rate")
+                    .long("policy-learning-rate")
+                    .help("Initial learning rate for the policy parameters. Defaults to a value that suits the optimizer")
+                    .num_args(1)
+                    .value_parser(clap::value_parser!(f32)))
+                .arg(Arg::new("value-log")
+                    .long("value-log")
+                    .help("Write the training and validation error of the value parameters to this CSV file")
+                    .num_args(1)
+                    .value_name("value_training.csv"))
+                .arg(Arg::new("policy-log")
+                    .long("policy-log")
+                    .help("Write the training and validation error of the policy parameters to this CSV file")
+                    .num_args(1)
+                    .value_name("policy_training.csv"))
+                .args(tuning_args())
+        )
+        .subcommand(
+            Command::new("convert-training-data")
+                .about("Convert a ptn file and its move scores to the binary training data format, merging duplicate positions")
+                .arg(Arg::new("games-file-name")
+                    .index(1)
+                    .required(true)
+                    .value_name("games.ptn"))
+                .arg(Arg::new("move-scores-file-name")
                     .index(2)
                     .required(true)
                     .value_name("move_scores.txt"))
+                .arg(Arg::new("output-file-name")
+                    .index(3)
+                    .required(true)
+                    .value_name("training_data.bin"))
+        )
+        .subcommand(
+            Command::new("build-book")
+                .about("Build an opening book, by searching from the start position, or from the games in a ptn file")
+                .arg(Arg::new("output-file-name")
+                    .index(1)
+                    .required(true)
+                    .value_name("book.txt"))
+                .arg(Arg::new("games")
+                    .long("games")
+                    .help("Build the book from the games in this file, instead of searching")
+                    .num_args(1)
+                    .value_name("games.ptn"))
+                .arg(Arg::new("max-ply")
+                    .long("max-ply")
+                    .help("Number of plies to include in the book")
+                    .num_args(1)
+                    .default_value("6")
+                    .value_parser(clap::value_parser!(u64)))
+                .arg(Arg::new("nodes")
+                    .long("nodes")
+                    .help("Nodes to search in each position")
+                    .num_args(1)
+                    .default_value("100000")
+                    .value_parser(clap::value_parser!(u64)))
+                .arg(Arg::new("min-visit-share")
+                    .long("min-visit-share")
+                    .help("Only include moves that get at least this share of the search's visits")
+                    .num_args(1)
+                    .default_value("0.1")
+                    .value_parser(clap::value_parser!(f32)))
+                .arg(Arg::new("min-games")
+                    .long("min-games")
+                    .help("When building from games, only include moves played in at least this many games")
+                    .num_args(1)
+                    .default_value("5")
+                    .value_parser(clap::value_parser!(u32)))
         )
         .subcommand(Command::new("spsa")
-            .about("Tune exploration parameters using SPSA. Starting values are hard-coded.")
+            .about("Tune search and time management parameters using SPSA. Reads the parameters to tune, with their starting values, from a file, and writes them back to a checkpoint file after every game.")
+            .arg(Arg::new("variables-file")
+                .help("File with one parameter per line: name, value, delta, apply factor, min and max. Give a checkpoint file to resume tuning")
+                .value_name("variables.txt")
+                .required(true))
             .arg(Arg::new("book")
                 .num_args(1)
                 .long("book")
                 .help("Opening book for the games.")
                 .value_name("book.txt")
-            )).arg_required_else_help(true);
+            )
+            .arg(Arg::new("tc")
+                .long("tc")
+                .help("Time control for the games, as seconds plus increment")
+                .num_args(1)
+                .default_value("20+0.2")
+                .value_parser(parse_tc))
+            .arg(Arg::new("fixed-nodes")
+                .long("fixed-nodes")
+                .help("Search a fixed number of nodes per move, instead of using a time control")
+                .num_args(1)
+                .conflicts_with("tc")
+                .value_parser(clap::value_parser!(u64).range(1..)))
+            .arg(Arg::new("checkpoint")
+                .long("checkpoint")
+                .help("Write the current values and iteration to this file after every game")
+                .num_args(1)
+                .default_value("spsa_checkpoint.txt"))
+            .arg(Arg::new("log")
+                .long("log")
+                .help("Append the result and the values of every game to this CSV file")
+                .num_args(1)
+                .default_value("spsa_log.csv"))
+            .arg(Arg::new("iterations")
+                .long("iterations")
+                .help("Stop after this many games in total, including those before resuming. Runs indefinitely by default")
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64)))
+        )
+        .subcommand(Command::new("match")
+            .about("Play two players against each other in game pairs with the same opening, until a sequential probability ratio test (SPRT) decides whether player 1 is stronger. Players use the built-in parameters, unless parameter files are given.")
+            .arg(Arg::new("player1-value-params")
+                .long("player1-value-params")
+                .help("Value parameters for player1")
+                .num_args(1)
+                .value_name("value_params.txt"))
+            .arg(Arg::new("player1-policy-params")
+                .long("player1-policy-params")
+                .help("Policy parameters for player1")
+                .num_args(1)
+                .value_name("policy_params.txt"))
+            .arg(Arg::new("player1-search-params")
+                .long("player1-search-params")
+                .help("Comma-separated search parameters for player1, like 1.43,2800,0.61")
+                .num_args(1))
+            .arg(Arg::new("player2-value-params")
+                .long("player2-value-params")
+                .help("Value parameters for player2")
+                .num_args(1)
+                .value_name("value_params.txt"))
+            .arg(Arg::new("player2-policy-params")
+                .long("player2-policy-params")
+                .help("Policy parameters for player2")
+                .num_args(1)
+                .value_name("policy_params.txt"))
+            .arg(Arg::new("player2-search-params")
+                .long("player2-search-params")
+                .help("Comma-separated search parameters for player2, like 1.43,2800,0.61")
+                .num_args(1))
+            .arg(Arg::new("elo0")
+                .long("elo0")
+                .help("Elo difference of the SPRT's null hypothesis")
+                .num_args(1)
+                .default_value("0")
+                .value_parser(clap::value_parser!(f64)))
+            .arg(Arg::new("elo1")
+                .long("elo1")
+                .help("Elo difference of the SPRT's alternative hypothesis")
+                .num_args(1)
+                .default_value("10")
+                .value_parser(clap::value_parser!(f64)))
+            .arg(Arg::new("alpha")
+                .long("alpha")
+                .help("Probability of accepting elo1 when elo0 is true")
+                .num_args(1)
+                .default_value("0.05")
+                .value_parser(clap::value_parser!(f64)))
+            .arg(Arg::new("beta")
+                .long("beta")
+                .help("Probability of accepting elo0 when elo1 is true")
+                .num_args(1)
+                .default_value("0.05")
+                .value_parser(clap::value_parser!(f64)))
+            .arg(Arg::new("max-games")
+                .long("max-games")
+                .help("Stop the match after this many games, even if the SPRT hasn't finished")
+                .num_args(1)
+                .default_value("20000")
+                .value_parser(clap::value_parser!(u64).range(2..)))
+        ).arg_required_else_help(true);
+
+    #[cfg(feature = "mlp")]
+    let app = app.subcommand(
+        Command::new("selfplay-mlp")
+            .about("Train value and policy networks by playing against itself, instead of linear constants. Will write the games, networks and a manifest to a new run directory in the working directory.")
+            .arg(Arg::new("hidden-layers")
+                .long("hidden-layers")
+                .help("Comma-separated sizes of the hidden layers, for networks trained from scratch")
+                .num_args(1)
+                .default_value("32"))
+            .arg(Arg::new("value-mlp")
+                .long("value-mlp")
+                .help("Start training from the value network in this file")
+                .num_args(1)
+                .value_name("value_mlp.txt"))
+            .arg(Arg::new("policy-mlp")
+                .long("policy-mlp")
+                .help("Start training from the policy network in this file")
+                .num_args(1)
+                .value_name("policy_mlp.txt")),
+    );
 
     let matches = app.get_matches();
     let size: usize = *matches.get_one::<u64>("size").unwrap() as usize;
-    let komi = Komi::from_str("2.0").unwrap();
+    let komi = *matches.get_one::<Komi>("komi").unwrap();
 
     match matches.subcommand() {
         Some(("selfplay", _)) => {
-            for i in 0.. {
-                let file_name = format!("games{}_s{}_batch0.ptn", i, size);
-                if !Path::new(&file_name).exists() {
-                    match size {
-                        4 => training::train_perpetually::<
-                            4,
-                            NUM_VALUE_FEATURES_4S,
-                            NUM_POLICY_FEATURES_4S,
-                        >(
-                            i,
-                            komi,
-                            parameters::value_features_4s(komi),
-                            parameters::policy_features_4s(komi),
-                            vec![],
-                            vec![],
-                            0,
-                        )
-                        .unwrap(),
-                        5 => training::train_perpetually::<
-                            5,
-                            NUM_VALUE_FEATURES_5S,
-                            NUM_POLICY_FEATURES_5S,
-                        >(
-                            i,
-                            komi,
-                            parameters::value_features_5s(komi),
-                            parameters::policy_features_5s(komi),
-                            vec![],
-                            vec![],
-                            0,
-                        )
-                        .unwrap(),
-                        6 => training::train_perpetually::<
-                            6,
-                            NUM_VALUE_FEATURES_6S,
-                            NUM_POLICY_FEATURES_6S,
-                        >(
-                            i,
-                            komi,
-                            parameters::value_features_6s(komi),
-                            parameters::policy_features_6s(komi),
-                            vec![],
-                            vec![],
-                            0,
-                        )
-                        .unwrap(),
-                        _ => panic!("Size {} not supported.", size),
-                    }
-                    break;
-                } else {
-                    println!("File {} already exists, trying next.", file_name);
-                }
+            let run_directory = new_run_directory(size);
+            match size {
+                4 => selfplay::<4, NUM_VALUE_FEATURES_4S, NUM_POLICY_FEATURES_4S>(
+                    &run_directory,
+                    &matches,
+                ),
+                5 => selfplay::<5, NUM_VALUE_FEATURES_5S, NUM_POLICY_FEATURES_5S>(
+                    &run_directory,
+                    &matches,
+                ),
+                6 => selfplay::<6, NUM_VALUE_FEATURES_6S, NUM_POLICY_FEATURES_6S>(
+                    &run_directory,
+                    &matches,
+                ),
+                _ => panic!("Size {} not supported.", size),
             }
         }
         Some(("selfplay-from-scratch", _)) => {
-            for i in 0.. {
-                let file_name = format!("games{}_{}s_batch0.ptn", i, size);
-                if !Path::new(&file_name).exists() {
-                    match size {
-                        4 => training::train_from_scratch::<
-                            4,
-                            NUM_VALUE_FEATURES_4S,
-                            NUM_POLICY_FEATURES_4S,
-                        >(i, komi)
-                        .unwrap(),
-                        5 => training::train_from_scratch::<
-                            5,
-                            NUM_VALUE_FEATURES_5S,
-                            NUM_POLICY_FEATURES_5S,
-                        >(i, komi)
-                        .unwrap(),
-                        6 => training::train_from_scratch::<
-                            6,
-                            NUM_VALUE_FEATURES_6S,
-                            NUM_POLICY_FEATURES_6S,
-                        >(i, komi)
-                        .unwrap(),
-                        _ => panic!("Size {} not supported.", size),
-                    }
-                    break;
-                } else {
-                    println!("File {} already exists, trying next.", file_name);
-                }
-            }
-        }
-        Some(("continue-selfplay", arg)) => {
-            let training_id: usize = *arg.get_one::<u64>("training-id").unwrap() as usize;
+            let run_directory = new_run_directory(size);
+            let config = training_config(&matches);
             match size {
-                4 => {
-                    training::continue_training::<4, NUM_VALUE_FEATURES_4S, NUM_POLICY_FEATURES_4S>(
-                        training_id,
-                        komi,
-                    )
-                    .unwrap()
-                }
-                5 => {
-                    training::continue_training::<5, NUM_VALUE_FEATURES_5S, NUM_POLICY_FEATURES_5S>(
-                        training_id,
-                        komi,
-                    )
-                    .unwrap()
-                }
-                6 => {
-                    training::continue_training::<6, NUM_VALUE_FEATURES_6S, NUM_POLICY_FEATURES_6S>(
-                        training_id,
-                        komi,
-                    )
-                    .unwrap()
-                }
+                4 => training::train_from_scratch::<
+                    4,
+                    NUM_VALUE_FEATURES_4S,
+                    NUM_POLICY_FEATURES_4S,
+                >(
+                    &run_directory,
+                    &config,
+                    book_settings(&matches).as_ref(),
+                    coordinator(&matches).as_ref(),
+                ),
+                5 => training::train_from_scratch::<
+                    5,
+                    NUM_VALUE_FEATURES_5S,
+                    NUM_POLICY_FEATURES_5S,
+                >(
+                    &run_directory,
+                    &config,
+                    book_settings(&matches).as_ref(),
+                    coordinator(&matches).as_ref(),
+                ),
+                6 => training::train_from_scratch::<
+                    6,
+                    NUM_VALUE_FEATURES_6S,
+                    NUM_POLICY_FEATURES_6S,
+                >(
+                    &run_directory,
+                    &config,
+                    book_settings(&matches).as_ref(),
+                    coordinator(&matches).as_ref(),
+                ),
                 _ => panic!("Size {} not supported.", size),
             }
+            .unwrap()
         }
-        Some(("value-from-file", arg)) => {
-            let file_name = arg.get_one::<String>("file-name").unwrap();
-            match size {
-                4 => {
-                    let value_params =
-                        training::tune_value_from_file::<4, NUM_VALUE_FEATURES_4S>(file_name, komi)
-                            .unwrap();
-                    println!("{:?}", value_params);
-                }
-                5 => {
-                    let value_params =
-                        training::tune_value_from_file::<5, NUM_VALUE_FEATURES_5S>(file_name, komi)
-                            .unwrap();
-                    println!("{:?}", value_params);
-                }
-                6 => {
-                    let value_params =
-                        training::tune_value_from_file::<6, NUM_VALUE_FEATURES_6S>(file_name, komi)
-                            .unwrap();
-                    println!("{:?}", value_params);
-                }
+        Some(("worker", arg)) => {
+            let address = arg.get_one::<String>("coordinator-address").unwrap();
+            let threads = arg
+                .get_one::<u64>("threads")
+                .map(|threads| *threads as usize)
+                .unwrap_or_else(|| {
+                    std::thread::available_parallelism()
+                        .map(|threads| threads.get())
+                        .unwrap_or(1)
+                });
+            let num_games = match size {
+                4 => distributed::run_worker::<4>(address, threads),
+                5 => distributed::run_worker::<5>(address, threads),
+                6 => distributed::run_worker::<6>(address, threads),
                 _ => panic!("Size {} not supported.", size),
             }
+            .unwrap_or_else(|err| panic!("Worker failed: {}", err));
+            println!("Coordinator stopped after {} games", num_games);
         }
-        Some(("both-from-file", arg)) => {
-            let value_file_name = arg.get_one::<String>("value-file-name").unwrap();
-            let policy_file_name = arg.get_one::<String>("policy-file-name").unwrap();
-            match size {
-                4 => {
-                    let (value_params, policy_params) =
-                        training::tune_value_and_policy_from_file::<
-                            4,
-                            NUM_VALUE_FEATURES_4S,
-                            NUM_POLICY_FEATURES_4S,
-                        >(value_file_name, policy_file_name, komi)
-                        .unwrap();
-                    println!("Value: {:?}", value_params);
-                    println!("Policy: {:?}", policy_params);
-                }
-                5 => {
-                    let (value_params, policy_params) =
-                        training::tune_value_and_policy_from_file::<
-                            5,
-                            NUM_VALUE_FEATURES_5S,
-                            NUM_POLICY_FEATURES_5S,
-                        >(value_file_name, policy_file_name, komi)
-                        .unwrap();
-                    println!("Value: {:?}", value_params);
-                    println!("Policy: {:?}", policy_params);
-                }
-                6 => {
-                    let (value_params, policy_params) =
-                        training::tune_value_and_policy_from_file::<
-                            6,
-                            NUM_VALUE_FEATURES_6S,
-                            NUM_POLICY_FEATURES_6S,
-                        >(value_file_name, policy_file_name, komi)
-                        .unwrap();
-                    println!("Value: {:?}", value_params);
-                    println!("Policy: {:?}", policy_params);
-                }
+        Some(("continue-selfplay", arg)) => {
+            let run_directory = Path::new(arg.get_one::<String>("run-directory").unwrap());
+            let manifest = Manifest::from_dir(run_directory).unwrap_or_else(|err| {
+                panic!(
+                    "Couldn't read manifest in {}: {}",
+                    run_directory.display(),
+                    err
+                )
+            });
+            match manifest.size {
+                4 => continue_selfplay::<4, NUM_VALUE_FEATURES_4S, NUM_POLICY_FEATURES_4S>(
+                    run_directory,
+                    &manifest,
+                    arg,
+                ),
+                5 => continue_selfplay::<5, NUM_VALUE_FEATURES_5S, NUM_POLICY_FEATURES_5S>(
+                    run_directory,
+                    &manifest,
+                    arg,
+                ),
+                6 => continue_selfplay::<6, NUM_VALUE_FEATURES_6S, NUM_POLICY_FEATURES_6S>(
+                    run_directory,
+                    &manifest,
+                    arg,
+                ),
+                size => panic!("Size {} not supported.", size),
+            }
+        }
+        Some(("value-from-file", arg)) => match size {
+            4 => value_from_file::<4, NUM_VALUE_FEATURES_4S>(arg, komi),
+            5 => value_from_file::<5, NUM_VALUE_FEATURES_5S>(arg, komi),
+            6 => value_from_file::<6, NUM_VALUE_FEATURES_6S>(arg, komi),
+            _ => panic!("Size {} not supported.", size),
+        },
+        Some(("both-from-file", arg)) => match size {
+            4 => both_from_file::<4, NUM_VALUE_FEATURES_4S, NUM_POLICY_FEATURES_4S>(arg, komi),
+            5 => both_from_file::<5, NUM_VALUE_FEATURES_5S, NUM_POLICY_FEATURES_5S>(arg, komi),
+            6 => both_from_file::<6, NUM_VALUE_FEATURES_6S, NUM_POLICY_FEATURES_6S>(arg, komi),
+            _ => panic!("Size {} not supported.", size),
+        },
+        Some(("convert-training-data", arg)) => {
+            let games_file_name = arg.get_one::<String>("games-file-name").unwrap();
+            let move_scores_file_name = arg.get_one::<String>("move-scores-file-name").unwrap();
+            let output_file_name = arg.get_one::<String>("output-file-name").unwrap();
+            let (num_records, num_unique_records) = match size {
+                4 => training_data::convert_text_training_data::<4>(
+                    games_file_name,
+                    move_scores_file_name,
+                    output_file_name,
+                    komi,
+                ),
+                5 => training_data::convert_text_training_data::<5>(
+                    games_file_name,
+                    move_scores_file_name,
+                    output_file_name,
+                    komi,
+                ),
+                6 => training_data::convert_text_training_data::<6>(
+                    games_file_name,
+                    move_scores_file_name,
+                    output_file_name,
+                    komi,
+                ),
                 _ => panic!("Size {} not supported.", size),
             }
+            .unwrap();
+            println!(
+                "Wrote {} unique positions out of {} to {}",
+                num_unique_records, num_records, output_file_name
+            );
         }
-        Some(("spsa", arg)) => {
-            let mut variables = vec![
-                spsa::Variable {
-                    value: 1.43,
-                    delta: 0.20,
-                    apply_factor: 0.005,
-                },
-                spsa::Variable {
-                    value: 2800.0,
-                    delta: 1000.0,
-                    apply_factor: 0.005,
-                },
-                spsa::Variable {
-                    value: 0.61,
-                    delta: 0.05,
-                    apply_factor: 0.005,
-                },
-            ];
+        Some(("build-book", arg)) => match size {
+            4 => build_book::<4>(arg, komi),
+            5 => build_book::<5>(arg, komi),
+            6 => build_book::<6>(arg, komi),
+            _ => panic!("Size {} not supported.", size),
+        },
+        Some(("spsa", arg)) => match size {
+            4 => run_spsa::<4>(arg, komi),
+            5 => run_spsa::<5>(arg, komi),
+            6 => run_spsa::<6>(arg, komi),
+            _ => panic!("Size {} not supported.", size),
+        },
+        Some(("match", arg)) => match size {
+            4 => run_match::<4>(arg, komi),
+            5 => run_match::<5>(arg, komi),
+            6 => run_match::<6>(arg, komi),
+            _ => panic!("Size {} not supported.", size),
+        },
+        #[cfg(feature = "mlp")]
+        Some(("selfplay-mlp", arg)) => {
+            let run_directory = new_run_directory(size);
             match size {
-                4 => spsa::tune::<4>(
-                    &mut variables,
-                    arg.get_one::<String>("book").map(|s| s.as_ref()),
+                4 => selfplay_mlp::<4, NUM_VALUE_FEATURES_4S, NUM_POLICY_FEATURES_4S>(
+                    &run_directory,
+                    arg,
                 ),
-                5 => spsa::tune::<5>(
-                    &mut variables,
-                    arg.get_one::<String>("book").map(|s| s.as_ref()),
+                5 => selfplay_mlp::<5, NUM_VALUE_FEATURES_5S, NUM_POLICY_FEATURES_5S>(
+                    &run_directory,
+                    arg,
                 ),
-                6 => spsa::tune::<6>(
-                    &mut variables,
-                    arg.get_one::<String>("book").map(|s| s.as_ref()),
+                6 => selfplay_mlp::<6, NUM_VALUE_FEATURES_6S, NUM_POLICY_FEATURES_6S>(
+                    &run_directory,
+                    arg,
                 ),
                 _ => panic!("Size {} not supported.", size),
             }
@@ -285,3 +591,443 @@ fn main() {
         }
     }
 }
+
+/// The first unused run directory for self-play training, like `selfplay0_5s`
+fn new_run_directory(size: usize) -> PathBuf {
+    for i in 0.. {
+        let run_directory = PathBuf::from(format!("selfplay{}_{}s", i, size));
+        if !run_directory.exists() {
+            return run_directory;
+        } else {
+            println!(
+                "Directory {} already exists, trying next.",
+                run_directory.display()
+            );
+        }
+    }
+    unreachable!()
+}
+
+fn training_config(matches: &ArgMatches) -> TrainingConfig {
+    TrainingConfig {
+        komi: *matches.get_one::<Komi>("komi").unwrap(),
+        batch_size: *matches.get_one::<u64>("batch-size").unwrap() as usize,
+        batches_for_training: *matches.get_one::<u64>("batches-for-training").unwrap() as usize,
+        nodes: *matches.get_one::<u64>("nodes").unwrap(),
+        dirichlet_alpha: *matches.get_one::<f32>("dirichlet-alpha").unwrap(),
+    }
+}
+
+fn selfplay<const S: usize, const N: usize, const M: usize>(
+    run_directory: &Path,
+    matches: &ArgMatches,
+) {
+    let config = training_config(matches);
+    let (value_params, policy_params) = initial_params::<S, N, M>(matches, config.komi);
+    training::train_perpetually::<S, N, M>(
+        run_directory,
+        &config,
+        &value_params,
+        &policy_params,
+        book_settings(matches).as_ref(),
+        coordinator(matches).as_ref(),
+    )
+    .unwrap()
+}
+
+/// Resume training from a run directory, with the kind of parameters given in its manifest
+fn continue_selfplay<const S: usize, const N: usize, const M: usize>(
+    run_directory: &Path,
+    manifest: &Manifest,
+    matches: &ArgMatches,
+) {
+    let book = book_settings::<S>(matches);
+    match manifest.kind.as_str() {
+        "linear" => training::continue_training::<S, N, M>(
+            run_directory,
+            book.as_ref(),
+            coordinator(matches).as_ref(),
+        ),
+        #[cfg(feature = "mlp")]
+        "mlp" if matches.contains_id("coordinator") => {
+            panic!("Self-play workers can't play with mlp parameters")
+        }
+        #[cfg(feature = "mlp")]
+        "mlp" => mlp_training::continue_mlp_training::<S, N, M>(run_directory, book.as_ref()),
+        kind => panic!("Can't continue training of {} parameters", kind),
+    }
+    .unwrap()
+}
+
+/// Train networks in self-play, starting from the networks given on the command line, if any,
+/// otherwise from randomly initialized networks
+#[cfg(feature = "mlp")]
+fn selfplay_mlp<const S: usize, const N: usize, const M: usize>(
+    run_directory: &Path,
+    arg: &ArgMatches,
+) {
+    if arg.contains_id("coordinator") {
+        panic!("Self-play workers can't play with mlp parameters")
+    }
+    let config = training_config(arg);
+    let komi = config.komi;
+    let hidden_layers: Vec<usize> = arg
+        .get_one::<String>("hidden-layers")
+        .unwrap()
+        .split(',')
+        .map(|layer_size| {
+            layer_size
+                .parse()
+                .unwrap_or_else(|_| panic!("Bad hidden layer size \"{}\"", layer_size))
+        })
+        .collect();
+    let mut rng = StdRng::from_seed([0; 32]);
+    let value_mlp = match arg.get_one::<String>("value-mlp") {
+        Some(path) => load_value_mlp::<S>(path, komi).unwrap_or_else(|err| panic!("{}", err)),
+        None => Mlp::new_random::<S, _>(ParameterKind::Value, komi, &hidden_layers, &mut rng),
+    };
+    let policy_mlp = match arg.get_one::<String>("policy-mlp") {
+        Some(path) => load_policy_mlp::<S>(path, komi).unwrap_or_else(|err| panic!("{}", err)),
+        None => Mlp::new_random::<S, _>(ParameterKind::Policy, komi, &hidden_layers, &mut rng),
+    };
+    mlp_training::train_mlp_perpetually::<S, N, M>(
+        run_directory,
+        &config,
+        value_mlp,
+        policy_mlp,
+        book_settings(arg).as_ref(),
+    )
+    .unwrap()
+}
+
+fn value_from_file<const S: usize, const N: usize>(arg: &ArgMatches, komi: Komi) {
+    let file_name = arg.get_one::<String>("file-name").unwrap();
+    let value_params = training::tune_value_from_file::<S, N>(
+        file_name,
+        komi,
+        augmentation(arg),
+        &gradient_descent_settings(arg, ParameterKind::Value),
+    )
+    .unwrap();
+    println!("{:?}", value_params);
+    write_params::<S>(
+        ParameterKind::Value,
+        komi,
+        &value_params,
+        arg.get_one::<String>("value-output").unwrap(),
+    );
+}
+
+fn both_from_file<const S: usize, const N: usize, const M: usize>(arg: &ArgMatches, komi: Komi) {
+    let value_file_name = arg.get_one::<String>("value-file-name").unwrap();
+    let value_settings = gradient_descent_settings(arg, ParameterKind::Value);
+    let policy_settings = gradient_descent_settings(arg, ParameterKind::Policy);
+    let (value_params, policy_params) = match arg.get_one::<String>("policy-file-name") {
+        Some(policy_file_name) => training::tune_value_and_policy_from_file::<S, N, M>(
+            value_file_name,
+            policy_file_name,
+            komi,
+            augmentation(arg),
+            &value_settings,
+            &policy_settings,
+        ),
+        None => training::tune_value_and_policy_from_training_data_file::<S, N, M>(
+            value_file_name,
+            komi,
+            augmentation(arg),
+            &value_settings,
+            &policy_settings,
+        ),
+    }
+    .unwrap();
+    println!("Value: {:?}", value_params);
+    println!("Policy: {:?}", policy_params);
+    write_params::<S>(
+        ParameterKind::Value,
+        komi,
+        &value_params,
+        arg.get_one::<String>("value-output").unwrap(),
+    );
+    write_params::<S>(
+        ParameterKind::Policy,
+        komi,
+        &policy_params,
+        arg.get_one::<String>("policy-output").unwrap(),
+    );
+}
+
+/// Options for tuning parameters with gradient descent, shared by the subcommands that tune from files
+fn tuning_args() -> Vec<Arg> {
+    vec![
+        Arg::new("augmentation")
+            .long("augmentation")
+            .help("Also train on the rotations and reflections of each position, and optionally with the colors swapped. Swapping colors requires 0 komi")
+            .num_args(1)
+            .default_value("none")
+            .value_parser(["none", "symmetries", "swapped-colors"]),
+        Arg::new("optimizer")
+            .long("optimizer")
+            .help("Optimizer for gradient descent")
+            .num_args(1)
+            .default_value("momentum")
+            .value_parser(["momentum", "adam"]),
+        Arg::new("l1-weight-decay")
+            .long("l1-weight-decay")
+            .help("L1 regularization strength")
+            .num_args(1)
+            .default_value("0")
+            .value_parser(clap::value_parser!(f32)),
+        Arg::new("l2-weight-decay")
+            .long("l2-weight-decay")
+            .help("L2 regularization strength")
+            .num_args(1)
+            .default_value("0")
+            .value_parser(clap::value_parser!(f32)),
+        Arg::new("validation-fraction")
+            .long("validation-fraction")
+            .help("Fraction of the games to hold out from training. Training stops when the error on their positions stops improving")
+            .num_args(1)
+            .default_value("0.1")
+            .value_parser(|fraction: &str| match fraction.parse::<f32>() {
+                Ok(fraction) if (0.0..1.0).contains(&fraction) => Ok(fraction),
+                _ => Err(format!("Expected a number between 0 and 1, got {}", fraction)),
+            }),
+    ]
+}
+
+/// Gradient descent settings for tuning one kind of parameters, from the options in `tuning_args`
+fn gradient_descent_settings(arg: &ArgMatches, kind: ParameterKind) -> GradientDescentSettings {
+    let optimizer: Optimizer = arg.get_one::<String>("optimizer").unwrap().parse().unwrap();
+    let learning_rate = arg
+        .get_one::<f32>(&format!("{}-learning-rate", kind))
+        .copied()
+        .unwrap_or_else(|| optimizer.default_learning_rate(kind));
+    let settings = GradientDescentSettings::new(learning_rate)
+        .optimizer(optimizer)
+        .l1_weight_decay(*arg.get_one::<f32>("l1-weight-decay").unwrap())
+        .l2_weight_decay(*arg.get_one::<f32>("l2-weight-decay").unwrap())
+        .validation_fraction(*arg.get_one::<f32>("validation-fraction").unwrap());
+    match arg.get_one::<String>(&format!("{}-log", kind)) {
+        Some(path) => settings.log_file(path),
+        None => settings,
+    }
+}
+
+fn augmentation(arg: &ArgMatches) -> Augmentation {
+    arg.get_one::<String>("augmentation")
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+fn write_params<const S: usize>(kind: ParameterKind, komi: Komi, params: &[f32], path: &str) {
+    ParameterFile::new::<S>(kind, komi, params)
+        .to_file(path)
+        .unwrap_or_else(|err| panic!("Couldn't write {} parameters to {}: {}", kind, path, err));
+    println!("Wrote {} parameters to {}", kind, path);
+}
+
+/// The parameters to start training from. Read from the files given on the command line, if any,
+/// otherwise the built-in parameters for the komi
+fn initial_params<const S: usize, const N: usize, const M: usize>(
+    matches: &ArgMatches,
+    komi: Komi,
+) -> ([f32; N], [f32; M]) {
+    let value_params: &[f32] = match matches.get_one::<String>("value-params") {
+        Some(path) => {
+            &load_value_params::<S>(path, komi)
+                .unwrap_or_else(|err| panic!("{}", err))
+                .params
+        }
+        None => match S {
+            4 => parameters::value_features_4s(komi),
+            5 => parameters::value_features_5s(komi),
+            6 => parameters::value_features_6s(komi),
+            _ => panic!("Size {} not supported.", S),
+        },
+    };
+    let policy_params: &[f32] = match matches.get_one::<String>("policy-params") {
+        Some(path) => {
+            &load_policy_params::<S>(path, komi)
+                .unwrap_or_else(|err| panic!("{}", err))
+                .params
+        }
+        None => match S {
+            4 => parameters::policy_features_4s(komi),
+            5 => parameters::policy_features_5s(komi),
+            6 => parameters::policy_features_6s(komi),
+            _ => panic!("Size {} not supported.", S),
+        },
+    };
+    (
+        value_params.try_into().unwrap(),
+        policy_params.try_into().unwrap(),
+    )
+}
+
+fn run_spsa<const S: usize>(arg: &ArgMatches, komi: Komi) {
+    let variables_file = arg.get_one::<String>("variables-file").unwrap();
+    let mut state = spsa::SpsaState::from_file(variables_file)
+        .unwrap_or_else(|err| panic!("Couldn't read SPSA variables: {}", err));
+    let time_control = match arg.get_one::<u64>("fixed-nodes") {
+        Some(nodes) => TimeControl::FixedNodes(*nodes),
+        None => {
+            let (time, increment) = *arg.get_one::<(Duration, Duration)>("tc").unwrap();
+            TimeControl::Time(time, increment)
+        }
+    };
+    let mut settings = spsa::SpsaSettings::<S>::new(time_control)
+        .komi(komi)
+        .checkpoint_file(arg.get_one::<String>("checkpoint").unwrap())
+        .log_file(arg.get_one::<String>("log").unwrap());
+    if let Some(book) = arg.get_one::<String>("book") {
+        settings = settings.book(book);
+    }
+    if let Some(iterations) = arg.get_one::<u64>("iterations") {
+        settings = settings.max_iterations(*iterations);
+    }
+    if state.iteration > 0 {
+        println!("Resuming SPSA from iteration {}", state.iteration);
+    }
+    spsa::tune::<S>(&mut state, &settings).unwrap();
+    println!(
+        "Finished after {} iterations: {:?}",
+        state.iteration,
+        state
+            .variables
+            .iter()
+            .map(|variable| (variable.parameter.name(), variable.value))
+            .collect::<Vec<_>>()
+    );
+}
+
+/// Parse a time control like `20+0.2`, in seconds
+fn parse_tc(input: &str) -> Result<(Duration, Duration), String> {
+    let parse_seconds = |seconds: &str| {
+        seconds
+            .parse::<f64>()
+            .ok()
+            .filter(|seconds| *seconds >= 0.0)
+            .map(Duration::from_secs_f64)
+            .ok_or_else(|| format!("Bad time control \"{}\"", input))
+    };
+    match input.split_once('+') {
+        Some((time, increment)) => Ok((parse_seconds(time)?, parse_seconds(increment)?)),
+        None => Ok((parse_seconds(input)?, Duration::ZERO)),
+    }
+}
+
+/// Search settings for one of the players in `tune match`
+fn match_player<const S: usize>(
+    arg: &ArgMatches,
+    player: &str,
+    nodes: u64,
+    komi: Komi,
+) -> MctsSetting<S> {
+    let mut settings = MctsSetting::default().arena_size_for_nodes(nodes as u32);
+    if let Some(path) = arg.get_one::<String>(&format!("{}-value-params", player)) {
+        let file = load_value_params::<S>(path, komi).unwrap_or_else(|err| panic!("{}", err));
+        settings = settings.add_value_params(file.params.into_boxed_slice());
+    }
+    if let Some(path) = arg.get_one::<String>(&format!("{}-policy-params", player)) {
+        let file = load_policy_params::<S>(path, komi).unwrap_or_else(|err| panic!("{}", err));
+        settings = settings.add_policy_params(file.params.into_boxed_slice());
+    }
+    if let Some(search_params) = arg.get_one::<String>(&format!("{}-search-params", player)) {
+        let search_params: Box<[f32]> = search_params
+            .split(',')
+            .map(|param| {
+                param
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Bad search parameter \"{}\"", param))
+            })
+            .collect();
+        settings = settings.add_search_params(search_params);
+    }
+    settings
+}
+
+fn run_match<const S: usize>(arg: &ArgMatches, komi: Komi) {
+    let nodes = *arg.get_one::<u64>("nodes").unwrap();
+    let sprt = Sprt {
+        elo0: *arg.get_one::<f64>("elo0").unwrap(),
+        elo1: *arg.get_one::<f64>("elo1").unwrap(),
+        alpha: *arg.get_one::<f64>("alpha").unwrap(),
+        beta: *arg.get_one::<f64>("beta").unwrap(),
+    };
+    let (stats, result) = play_match::play_match(
+        &match_player::<S>(arg, "player1", nodes, komi),
+        &match_player::<S>(arg, "player2", nodes, komi),
+        komi,
+        &TimeControl::FixedNodes(nodes),
+        book_settings(arg).as_ref(),
+        &sprt,
+        *arg.get_one::<u64>("max-games").unwrap() / 2,
+    );
+    println!("Final result: {}", stats);
+    match result {
+        Some(SprtResult::H0) => println!(
+            "SPRT accepted H0: player1 is at most {} Elo stronger",
+            sprt.elo0
+        ),
+        Some(SprtResult::H1) => println!(
+            "SPRT accepted H1: player1 is at least {} Elo stronger",
+            sprt.elo1
+        ),
+        None => println!("SPRT was inconclusive"),
+    }
+}
+
+fn build_book<const S: usize>(arg: &ArgMatches, komi: Komi) {
+    let output_file_name = arg.get_one::<String>("output-file-name").unwrap();
+    let max_ply = *arg.get_one::<u64>("max-ply").unwrap() as usize;
+    let book = if let Some(games_file_name) = arg.get_one::<String>("games") {
+        let games = training::read_games_from_file::<S>(games_file_name, komi).unwrap();
+        OpeningBook::from_games(
+            &games,
+            komi,
+            max_ply,
+            *arg.get_one::<u32>("min-games").unwrap(),
+        )
+    } else {
+        OpeningBook::from_search(
+            komi,
+            *arg.get_one::<u64>("nodes").unwrap(),
+            max_ply,
+            *arg.get_one::<f32>("min-visit-share").unwrap(),
+            &MctsSetting::default(),
+        )
+    };
+    book.to_file(output_file_name).unwrap();
+    println!("Wrote {} positions to {}", book.len(), output_file_name);
+}
+
+/// Start listening for self-play workers, if a coordinator address was given on the command line
+fn coordinator<const S: usize>(matches: &ArgMatches) -> Option<Coordinator<S>> {
+    let address = matches.get_one::<String>("coordinator")?;
+    let coordinator = Coordinator::bind(address)
+        .unwrap_or_else(|err| panic!("Couldn't listen for workers on {}: {}", address, err));
+    println!(
+        "Listening for {}s workers on {}",
+        S,
+        coordinator.local_addr()
+    );
+    Some(coordinator)
+}
+
+/// Load the opening book given on the command line, if any
+fn book_settings<const S: usize>(matches: &ArgMatches) -> Option<BookSettings<S>> {
+    let path = matches.get_one::<String>("opening-book")?;
+    let book = OpeningBook::from_file(path)
+        .unwrap_or_else(|err| panic!("Couldn't read opening book {}: {}", path, err));
+    let selection: BookSelection = matches
+        .get_one::<String>("book-selection")
+        .unwrap()
+        .parse()
+        .unwrap();
+    Some(
+        BookSettings::new(book)
+            .selection(selection)
+            .max_ply(*matches.get_one::<u64>("book-ply").unwrap() as usize),
+    )
+}
@@ -0,0 +1,515 @@
+//! Opening books, and playing from them.
+//!
+//! A book maps positions to candidate moves, each with a weight and a score.
+//! Positions are stored in their canonical orientation (see [`Position::canonical_tps`]),
+//! so a single entry covers all 8 rotations and reflections of the board.
+//!
+//! Books are stored as text. The first line is a header, followed by one line per position:
+//!
+//! ```text
+//! tiltak-book version 1 size 5 komi 2
+//! x5/x5/x5/x5/x5 1 1: a1 0.6 0.52, a2 0.4 0.5
+//! ```
+//!
+//! Each move is written in PTN for the canonical position, followed by its weight and its score,
+//! the winning probability for the side to move.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write as FmtWrite;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::{fs, str};
+
+use board_game_traits::{Color, GameResult, Position as PositionTrait};
+use pgn_traits::{Error, ErrorKind, PgnPosition};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::position::{Komi, Move, Position};
+use crate::ptn::{Game, PtnMove};
+use crate::search::{MctsSetting, MonteCarloTree, Score};
+
+pub const BOOK_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BookMove<const S: usize> {
+    pub mv: Move<S>,
+    /// How often the move should be played, relative to the other moves in the position
+    pub weight: f32,
+    /// Winning probability for the side to move, after playing the move
+    pub score: Score,
+}
+
+#[derive(Debug, Clone)]
+struct BookEntry<const S: usize> {
+    /// The position in its canonical orientation
+    position: Position<S>,
+    moves: Vec<BookMove<S>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OpeningBook<const S: usize> {
+    komi: Komi,
+    entries: BTreeMap<String, BookEntry<S>>,
+}
+
+impl<const S: usize> OpeningBook<S> {
+    /// An empty book
+    pub fn new(komi: Komi) -> Self {
+        OpeningBook {
+            komi,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn komi(&self) -> Komi {
+        self.komi
+    }
+
+    /// Number of positions in the book
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add a position to the book, replacing any moves that were already stored for it or its symmetries.
+    /// The moves must be legal in `position`, and are stored in the canonical orientation.
+    pub fn insert(&mut self, position: &Position<S>, moves: &[BookMove<S>]) {
+        assert_eq!(position.komi(), self.komi);
+        let key = position.canonical_tps();
+        let canonical_position = <Position<S>>::from_fen_with_komi(&key, self.komi).unwrap();
+        let canonical_moves = moves
+            .iter()
+            .map(|book_move| BookMove {
+                mv: equivalent_move(position, book_move.mv, &canonical_position)
+                    .expect("Book move must be legal in the position"),
+                ..*book_move
+            })
+            .collect();
+        self.entries.insert(
+            key,
+            BookEntry {
+                position: canonical_position,
+                moves: canonical_moves,
+            },
+        );
+    }
+
+    /// The book moves for the position, in the position's orientation.
+    /// Empty if the position is not in the book, or has different komi than the book.
+    pub fn moves(&self, position: &Position<S>) -> Vec<BookMove<S>> {
+        if position.komi() != self.komi {
+            return vec![];
+        }
+        let Some(entry) = self.entries.get(&position.canonical_tps()) else {
+            return vec![];
+        };
+        entry
+            .moves
+            .iter()
+            .filter_map(|book_move| {
+                Some(BookMove {
+                    mv: equivalent_move(&entry.position, book_move.mv, position)?,
+                    ..*book_move
+                })
+            })
+            .collect()
+    }
+
+    /// Build a book by searching each position with MCTS, starting from the start position.
+    /// Symmetric moves are merged, and every move that gets at least `min_visit_share` of the visits is added with its visit share as weight,
+    /// and the positions after those moves are searched in turn, up to `max_ply` plies into the game.
+    pub fn from_search(
+        komi: Komi,
+        nodes: u64,
+        max_ply: usize,
+        min_visit_share: f32,
+        mcts_settings: &MctsSetting<S>,
+    ) -> Self {
+        let mut book = Self::new(komi);
+        let mut queue = VecDeque::from([<Position<S>>::start_position_with_komi(komi)]);
+
+        while let Some(position) = queue.pop_front() {
+            if position.half_moves_played() >= max_ply
+                || position.game_result().is_some()
+                || book.entries.contains_key(&position.canonical_tps())
+            {
+                continue;
+            }
+            let mut tree = MonteCarloTree::with_settings(
+                position.clone(),
+                mcts_settings.clone().arena_size_for_nodes(nodes as u32),
+            );
+            for _ in 0..nodes.max(2) {
+                if tree.select().is_none() {
+                    eprintln!("Warning: Search stopped early due to OOM");
+                    break;
+                }
+            }
+            let root_moves = tree.root_moves();
+            let total_visits: u32 = root_moves.iter().map(|(_, visits, _)| visits).sum();
+
+            // Symmetric moves split the visits between them, so merge them into one book move
+            let mut merged: Vec<(String, Move<S>, u32, f32)> = vec![];
+            for (mv, visits, score) in root_moves {
+                let mut child = position.clone();
+                child.do_move(mv);
+                let child_key = child.canonical_tps();
+                match merged.iter_mut().find(|(key, _, _, _)| *key == child_key) {
+                    Some((_, _, merged_visits, score_sum)) => {
+                        *merged_visits += visits;
+                        *score_sum += score * visits as f32;
+                    }
+                    None => merged.push((child_key, mv, visits, score * visits as f32)),
+                }
+            }
+            let moves: Vec<BookMove<S>> = merged
+                .into_iter()
+                .map(|(_, mv, visits, score_sum)| BookMove {
+                    mv,
+                    weight: visits as f32 / total_visits.max(1) as f32,
+                    score: score_sum / visits.max(1) as f32,
+                })
+                .filter(|book_move| book_move.weight >= min_visit_share)
+                .collect();
+
+            for book_move in moves.iter() {
+                let mut child = position.clone();
+                child.do_move(book_move.mv);
+                queue.push_back(child);
+            }
+            if !moves.is_empty() {
+                book.insert(&position, &moves);
+            }
+        }
+        book
+    }
+
+    /// Build a book from the first `max_ply` plies of finished games, for example from a game database.
+    /// Each move's weight is the share of games it was played in, and its score is the average result of those games.
+    /// Moves played in fewer than `min_games` games are skipped.
+    /// Games with different komi than `komi`, or that don't start from the start position, are ignored.
+    pub fn from_games<'a>(
+        games: impl IntoIterator<Item = &'a Game<Position<S>>>,
+        komi: Komi,
+        max_ply: usize,
+        min_games: u32,
+    ) -> Self {
+        // For each canonical position, the number of games and the total score of each move,
+        // keyed by the canonical position after the move, so that symmetric moves are counted together
+        let mut move_stats: HashMap<String, HashMap<String, (u32, f32)>> = HashMap::new();
+        let start_position = <Position<S>>::start_position_with_komi(komi);
+
+        for game in games {
+            let Some(game_result) = game.game_result() else {
+                continue;
+            };
+            if game.start_position != start_position {
+                continue;
+            }
+            let mut position = game.start_position.clone();
+            for PtnMove { mv, .. } in game.moves.iter().take(max_ply) {
+                let key = position.canonical_tps();
+                let mover = position.side_to_move();
+                position.do_move(*mv);
+                let stats = move_stats
+                    .entry(key)
+                    .or_default()
+                    .entry(position.canonical_tps())
+                    .or_default();
+                stats.0 += 1;
+                stats.1 += result_score(game_result, mover);
+            }
+        }
+
+        let mut book = Self::new(komi);
+        for (key, stats_by_child) in move_stats {
+            let position = <Position<S>>::from_fen_with_komi(&key, komi).unwrap();
+            let total_games: u32 = stats_by_child.values().map(|(games, _)| games).sum();
+            let mut moves: Vec<BookMove<S>> = vec![];
+            let mut seen_children = vec![];
+            for (mv, child_key) in moves_with_children(&position) {
+                // If the position is symmetric, several moves lead to the same child. Only keep the first
+                if seen_children.contains(&child_key) {
+                    continue;
+                }
+                if let Some((games, total_score)) = stats_by_child.get(&child_key) {
+                    if *games >= min_games {
+                        moves.push(BookMove {
+                            mv,
+                            weight: *games as f32 / total_games as f32,
+                            score: total_score / *games as f32,
+                        });
+                    }
+                }
+                seen_children.push(child_key);
+            }
+            if !moves.is_empty() {
+                book.insert(&position, &moves);
+            }
+        }
+        book
+    }
+
+    /// Read a book in the text format
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut lines = reader.lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| Error::new_parse_error("Empty opening book".to_string()))?
+            .map_err(|err| Error::new(ErrorKind::IoError, err))?;
+        let komi = parse_header(&header, S)?;
+        let mut book = Self::new(komi);
+
+        for (line_number, line) in lines.enumerate() {
+            let line = line.map_err(|err| Error::new(ErrorKind::IoError, err))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (position, moves) = parse_line::<S>(&line, komi).map_err(|err| {
+                Error::new_caused_by(
+                    ErrorKind::ParseError,
+                    format!("Bad opening book entry on line {}", line_number + 2),
+                    err,
+                )
+            })?;
+            book.insert(&position, &moves);
+        }
+        Ok(book)
+    }
+
+    /// Write the book in the text format
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "tiltak-book version {} size {} komi {}",
+            BOOK_VERSION, S, self.komi
+        )?;
+        for (key, entry) in self.entries.iter() {
+            let mut line = format!("{}:", key);
+            for (i, book_move) in entry.moves.iter().enumerate() {
+                write!(
+                    line,
+                    "{} {} {} {}",
+                    if i == 0 { "" } else { "," },
+                    book_move.mv,
+                    book_move.weight,
+                    book_move.score
+                )
+                .unwrap();
+            }
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let file = fs::File::open(path).map_err(|err| Error::new(ErrorKind::IoError, err))?;
+        Self::read(io::BufReader::new(file))
+    }
+
+    pub fn to_file(&self, path: &str) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+fn parse_header(header: &str, size: usize) -> Result<Komi, Error> {
+    let words: Vec<&str> = header.split_whitespace().collect();
+    let ["tiltak-book", "version", version, "size", book_size, "komi", komi] = words[..] else {
+        return Err(Error::new_parse_error(format!(
+            "Bad opening book header \"{}\"",
+            header
+        )));
+    };
+    if version.parse::<u32>() != Ok(BOOK_VERSION) {
+        return Err(Error::new_parse_error(format!(
+            "Unsupported opening book version {}, expected {}",
+            version, BOOK_VERSION
+        )));
+    }
+    if book_size.parse::<usize>() != Ok(size) {
+        return Err(Error::new(
+            ErrorKind::IllegalPosition,
+            format!("Expected a {}s opening book, got size {}", size, book_size),
+        ));
+    }
+    komi.parse()
+        .map_err(|err| Error::new_caused_by(ErrorKind::ParseError, "Bad komi", err))
+}
+
+fn parse_line<const S: usize>(
+    line: &str,
+    komi: Komi,
+) -> Result<(Position<S>, Vec<BookMove<S>>), Error> {
+    let (tps, moves_string) = line
+        .split_once(':')
+        .ok_or_else(|| Error::new_parse_error(format!("Missing ':' in \"{}\"", line)))?;
+    let position = <Position<S>>::from_fen_with_komi(tps.trim(), komi)?;
+    let mut legal_moves = vec![];
+    position.generate_moves(&mut legal_moves);
+
+    let mut moves = vec![];
+    for move_string in moves_string.split(',') {
+        let words: Vec<&str> = move_string.split_whitespace().collect();
+        let [mv, weight, score] = words[..] else {
+            return Err(Error::new_parse_error(format!(
+                "Expected a move, its weight and its score, got \"{}\"",
+                move_string.trim()
+            )));
+        };
+        let mv = Move::from_string(mv)?;
+        if !legal_moves.contains(&mv) {
+            return Err(Error::new(
+                ErrorKind::IllegalMove,
+                format!("Illegal move {} in {}", mv, tps.trim()),
+            ));
+        }
+        let parse_number = |number: &str| {
+            number.parse::<f32>().map_err(|_| {
+                Error::new_parse_error(format!("Bad number \"{}\" for {}", number, mv))
+            })
+        };
+        moves.push(BookMove {
+            mv,
+            weight: parse_number(weight)?,
+            score: parse_number(score)?,
+        });
+    }
+    Ok((position, moves))
+}
+
+/// Each legal move in the position, along with the canonical TPS of the position after the move
+fn moves_with_children<const S: usize>(position: &Position<S>) -> Vec<(Move<S>, String)> {
+    let mut moves = vec![];
+    position.generate_moves(&mut moves);
+    moves
+        .into_iter()
+        .map(|mv| {
+            let mut child = position.clone();
+            child.do_move(mv);
+            (mv, child.canonical_tps())
+        })
+        .collect()
+}
+
+/// Find the move in `to` that corresponds to `mv` in `from`, where the two positions are symmetries of each other.
+/// Returns `None` if `mv` is not legal in `from`.
+fn equivalent_move<const S: usize>(
+    from: &Position<S>,
+    mv: Move<S>,
+    to: &Position<S>,
+) -> Option<Move<S>> {
+    let mut legal_moves = vec![];
+    from.generate_moves(&mut legal_moves);
+    if !legal_moves.contains(&mv) {
+        return None;
+    }
+    if from.to_fen() == to.to_fen() {
+        return Some(mv);
+    }
+    let mut child = from.clone();
+    child.do_move(mv);
+    let child_key = child.canonical_tps();
+    moves_with_children(to)
+        .into_iter()
+        .find(|(_, key)| *key == child_key)
+        .map(|(mv, _)| mv)
+}
+
+fn result_score(game_result: GameResult, color: Color) -> f32 {
+    match (game_result, color) {
+        (GameResult::Draw, _) => 0.5,
+        (GameResult::WhiteWin, Color::White) | (GameResult::BlackWin, Color::Black) => 1.0,
+        (GameResult::WhiteWin, Color::Black) | (GameResult::BlackWin, Color::White) => 0.0,
+    }
+}
+
+/// How to choose between the moves in a book position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookSelection {
+    /// Pick a random move, with probability proportional to its weight
+    Weighted,
+    /// Always pick the move with the highest score
+    Best,
+}
+
+impl str::FromStr for BookSelection {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "weighted" => Ok(BookSelection::Weighted),
+            "best" => Ok(BookSelection::Best),
+            _ => Err(Error::new_parse_error(format!(
+                "Book selection must be \"weighted\" or \"best\", got \"{}\"",
+                input
+            ))),
+        }
+    }
+}
+
+/// An opening book, and how the engine plays from it
+#[derive(Debug, Clone)]
+pub struct BookSettings<const S: usize> {
+    book: Arc<OpeningBook<S>>,
+    selection: BookSelection,
+    max_ply: usize,
+}
+
+impl<const S: usize> BookSettings<S> {
+    /// Play weighted random moves from the book, for the first 12 plies
+    pub fn new(book: OpeningBook<S>) -> Self {
+        BookSettings {
+            book: Arc::new(book),
+            selection: BookSelection::Weighted,
+            max_ply: 12,
+        }
+    }
+
+    pub fn selection(mut self, selection: BookSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Only play book moves in positions with fewer than `max_ply` plies played
+    pub fn max_ply(mut self, max_ply: usize) -> Self {
+        self.max_ply = max_ply;
+        self
+    }
+
+    pub fn book(&self) -> &OpeningBook<S> {
+        &self.book
+    }
+
+    /// Choose a book move for the position, or `None` if the engine should search instead
+    pub fn choose_move<R: Rng>(&self, position: &Position<S>, rng: &mut R) -> Option<BookMove<S>> {
+        if position.half_moves_played() >= self.max_ply || position.game_result().is_some() {
+            return None;
+        }
+        let moves = self.book.moves(position);
+        match self.selection {
+            BookSelection::Weighted => moves
+                .choose_weighted(rng, |book_move| book_move.weight.max(0.0))
+                .ok()
+                .copied(),
+            BookSelection::Best => moves
+                .into_iter()
+                .max_by(|move1, move2| move1.score.total_cmp(&move2.score)),
+        }
+    }
+
+    /// Play book moves from the position until the book runs out, and return them
+    pub fn choose_line<R: Rng>(&self, mut position: Position<S>, rng: &mut R) -> Vec<Move<S>> {
+        let mut line = vec![];
+        while let Some(book_move) = self.choose_move(&position, rng) {
+            position.do_move(book_move.mv);
+            line.push(book_move.mv);
+        }
+        line
+    }
+}
@@ -0,0 +1,262 @@
+//! Break down a position's static evaluation and its move priors into named feature groups,
+//! to see why the engine likes a position, or why a candidate move got a low prior.
+//!
+//! Contributions are given in the evaluation's own units, which are logits of the winning probability.
+//! For display, one unit is shown as 100 centipawns.
+
+use std::fmt;
+
+use board_game_traits::Color;
+use half::f16;
+
+use crate::evaluation::parameters::{self, PolicyFeatures, ValueFeatures};
+use crate::evaluation::policy_eval::{inverse_sigmoid, sigmoid};
+use crate::evaluation::value_eval;
+use crate::position::{Move, Position};
+
+/// The values of one feature group, like `flat_psqt`, and how much they add to the evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureContribution {
+    pub name: &'static str,
+    pub values: Vec<f32>,
+    pub params: Vec<f32>,
+    /// The dot product of the values and the params
+    pub contribution: f32,
+    /// How much the group changes the winning probability, or the move's probability before normalization,
+    /// compared to the same evaluation without the group
+    pub probability_delta: f32,
+}
+
+impl FeatureContribution {
+    pub fn centipawns(&self) -> f32 {
+        self.contribution * 100.0
+    }
+
+    fn is_zero(&self) -> bool {
+        self.values.iter().all(|value| *value == 0.0)
+    }
+}
+
+impl fmt::Display for FeatureContribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let active_features: Vec<String> = self
+            .values
+            .iter()
+            .zip(self.params.iter())
+            .enumerate()
+            .filter(|(_, (value, _))| **value != 0.0)
+            .map(|(i, (value, param))| format!("{}: {}*{:.3}", i, value, param))
+            .collect();
+        write!(
+            f,
+            "{:48} {:+7.1}cp {:+6.2}%  [{}]",
+            self.name,
+            self.centipawns(),
+            self.probability_delta * 100.0,
+            active_features.join(", ")
+        )
+    }
+}
+
+/// Named breakdown of a position's static evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueExplanation {
+    /// The feature groups of the first half of the value features, which describe white's pieces
+    pub white: Vec<FeatureContribution>,
+    /// The feature groups of the second half of the value features, which describe black's pieces
+    pub black: Vec<FeatureContribution>,
+    /// The static evaluation, from white's perspective
+    pub eval: f32,
+}
+
+impl ValueExplanation {
+    pub fn centipawns(&self) -> f32 {
+        self.eval * 100.0
+    }
+
+    /// Static winning probability for `color`
+    pub fn win_probability(&self, color: Color) -> f32 {
+        match color {
+            Color::White => sigmoid(self.eval),
+            Color::Black => 1.0 - sigmoid(self.eval),
+        }
+    }
+
+    /// All feature groups that are present in the position, with the largest contributions first
+    pub fn largest_contributions(&self) -> Vec<(Color, &FeatureContribution)> {
+        let mut contributions: Vec<(Color, &FeatureContribution)> = self
+            .white
+            .iter()
+            .map(|contribution| (Color::White, contribution))
+            .chain(
+                self.black
+                    .iter()
+                    .map(|contribution| (Color::Black, contribution)),
+            )
+            .filter(|(_, contribution)| !contribution.is_zero())
+            .collect();
+        contributions.sort_by(|(_, a), (_, b)| {
+            b.contribution
+                .abs()
+                .partial_cmp(&a.contribution.abs())
+                .unwrap()
+        });
+        contributions
+    }
+}
+
+impl fmt::Display for ValueExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Static eval {:+.1}cp, {:.2}% for white",
+            self.centipawns(),
+            self.win_probability(Color::White) * 100.0
+        )?;
+        for (color, contribution) in self.largest_contributions() {
+            writeln!(f, "{} {}", color, contribution)?;
+        }
+        Ok(())
+    }
+}
+
+/// Named breakdown of a single move's prior
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveExplanation<const S: usize> {
+    pub mv: Move<S>,
+    pub features: Vec<FeatureContribution>,
+    /// Constant added to every move, which depends on the number of legal moves
+    pub offset: f32,
+    /// The sum of the contributions and the offset
+    pub logit: f32,
+    /// The move's final prior in the search, after normalization
+    pub probability: f32,
+}
+
+impl<const S: usize> fmt::Display for MoveExplanation<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {:.2}%, logit {:+.3}, offset {:+.3}",
+            self.mv,
+            self.probability * 100.0,
+            self.logit,
+            self.offset
+        )?;
+        for contribution in self.features.iter().filter(|c| !c.is_zero()) {
+            writeln!(f, "    {}", contribution)?;
+        }
+        Ok(())
+    }
+}
+
+/// Explain the static evaluation of the position with the given value parameters.
+/// The position must not be decided.
+pub fn explain_value<const S: usize>(position: &Position<S>, params: &[f32]) -> ValueExplanation {
+    assert_eq!(params.len(), parameters::num_value_features::<S>());
+    let mut features = vec![f16::ZERO; params.len()];
+    let (white_features, black_features) = features.split_at_mut(params.len() / 2);
+    let mut white_value_features = ValueFeatures::new::<S>(white_features);
+    let mut black_value_features = ValueFeatures::new::<S>(black_features);
+    value_eval::static_eval_game_phase(
+        position,
+        &position.group_data(),
+        &mut white_value_features,
+        &mut black_value_features,
+    );
+
+    let (white_params, black_params) = params.split_at(params.len() / 2);
+    let mut white = contributions(white_value_features.groups(), white_params);
+    let mut black = contributions(black_value_features.groups(), black_params);
+
+    let eval: f32 = white
+        .iter()
+        .chain(black.iter())
+        .map(|contribution| contribution.contribution)
+        .sum();
+    for contribution in white.iter_mut().chain(black.iter_mut()) {
+        contribution.probability_delta = sigmoid(eval) - sigmoid(eval - contribution.contribution);
+    }
+    ValueExplanation { white, black, eval }
+}
+
+/// Explain the prior of every legal move in the position with the given policy parameters,
+/// ordered from most to least likely. The position must not be decided.
+pub fn explain_policy<const S: usize>(
+    position: &Position<S>,
+    params: &[f32],
+) -> Vec<MoveExplanation<S>> {
+    assert_eq!(params.len(), parameters::num_policy_features::<S>());
+    let group_data = position.group_data();
+
+    let mut probabilities = vec![];
+    position.generate_moves_with_probabilities(
+        &group_data,
+        &mut vec![],
+        &mut probabilities,
+        &mut vec![],
+        &mut vec![],
+        params,
+        &mut Some(vec![]),
+    );
+    let moves: Vec<Move<S>> = probabilities.iter().map(|(mv, _)| *mv).collect();
+    // The same offset as in `generate_moves_with_probabilities`
+    let offset = inverse_sigmoid(1.0 / moves.len().max(2) as f32);
+
+    let mut feature_sets = vec![vec![f16::ZERO; params.len()]; moves.len()];
+    let mut policy_features: Vec<PolicyFeatures> = feature_sets
+        .iter_mut()
+        .map(|feature_set| PolicyFeatures::new::<S>(feature_set))
+        .collect();
+    position.features_for_moves(&mut policy_features, &moves, &mut vec![], &group_data);
+
+    let mut explanations: Vec<MoveExplanation<S>> = policy_features
+        .iter()
+        .zip(probabilities)
+        .map(|(features, (mv, probability))| {
+            let mut features = contributions(features.groups(), params);
+            let logit = features
+                .iter()
+                .map(|contribution| contribution.contribution)
+                .sum::<f32>()
+                + offset;
+            for contribution in features.iter_mut() {
+                contribution.probability_delta =
+                    sigmoid(logit) - sigmoid(logit - contribution.contribution);
+            }
+            MoveExplanation {
+                mv,
+                features,
+                offset,
+                logit,
+                probability: probability.to_f32(),
+            }
+        })
+        .collect();
+    explanations.sort_by(|a, b| b.probability.partial_cmp(&a.probability).unwrap());
+    explanations
+}
+
+/// Pair up the feature groups with their params, which are in the same order.
+/// The probability deltas are left at zero.
+fn contributions(groups: Vec<(&'static str, &[f16])>, params: &[f32]) -> Vec<FeatureContribution> {
+    let mut offset = 0;
+    let mut contributions = Vec::with_capacity(groups.len());
+    for (name, values) in groups {
+        let params = &params[offset..offset + values.len()];
+        offset += values.len();
+        if name == "padding" {
+            continue;
+        }
+        let values: Vec<f32> = values.iter().map(|value| value.to_f32()).collect();
+        contributions.push(FeatureContribution {
+            name,
+            contribution: values.iter().zip(params).map(|(a, b)| a * b).sum(),
+            values,
+            params: params.to_vec(),
+            probability_delta: 0.0,
+        });
+    }
+    assert_eq!(offset, params.len());
+    contributions
+}
@@ -0,0 +1,470 @@
+//! A small multi-layer perceptron, as an alternative to the linear value and policy evaluation.
+//!
+//! The network takes the same hand-crafted features as the linear evaluation as its input,
+//! and has any number of fully connected hidden layers with ReLU activations, followed by a single linear output.
+//! Like the linear evaluation, the value network's output is a logit of white's winning probability,
+//! and the policy network's output is a logit of the move being played, before the move count offset is added.
+//!
+//! Networks are stored as text files, with a single header line followed by one weight per line:
+//!
+//! ```text
+//! tiltak-mlp version 1 kind value size 5 komi 2 layout 8c3e2a6b0f1d4e57 layers 344,32,1
+//! 0.0123
+//! -0.0456
+//! ...
+//! ```
+//!
+//! The weights are listed layer by layer, each layer's weights followed by its biases.
+//! `layout` is the same feature layout hash as in parameter files.
+
+use std::io::{self, BufRead, Write};
+use std::{fs, iter, mem};
+
+use board_game_traits::Position as PositionTrait;
+use half::f16;
+use pgn_traits::{Error, ErrorKind};
+use rand::Rng;
+
+use crate::evaluation::parameter_file::ParameterKind;
+use crate::evaluation::parameters::{PolicyFeatures, ValueFeatures};
+use crate::evaluation::value_eval;
+use crate::position::{GroupData, Komi, Move, Position};
+
+pub const MLP_FILE_VERSION: u32 = 1;
+
+/// A fully connected layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// The weight from input `i` to output `j` is at `i * outputs + j`
+    pub weights: Vec<f32>,
+    pub biases: Vec<f32>,
+}
+
+impl Layer {
+    fn forward(&self, input: impl Iterator<Item = f32>, output: &mut [f32]) {
+        output.copy_from_slice(&self.biases);
+        for (x, weights) in input.zip(self.weights.chunks_exact(self.outputs)) {
+            // Most features are zero, so skip them early
+            if x != 0.0 {
+                for (out, weight) in output.iter_mut().zip(weights) {
+                    *out += x * weight;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mlp {
+    pub kind: ParameterKind,
+    pub size: usize,
+    /// The komi the network was trained for
+    pub komi: Komi,
+    pub layout_hash: u64,
+    pub layers: Vec<Layer>,
+}
+
+impl Mlp {
+    /// Create a network with the given hidden layer sizes and small random weights,
+    /// taking the value or policy features for size `S` as input
+    pub fn new_random<const S: usize, R: Rng>(
+        kind: ParameterKind,
+        komi: Komi,
+        hidden_layers: &[usize],
+        rng: &mut R,
+    ) -> Self {
+        let sizes: Vec<usize> = iter::once(kind.num_features::<S>())
+            .chain(hidden_layers.iter().copied())
+            .chain(iter::once(1))
+            .collect();
+        let layers = sizes
+            .windows(2)
+            .map(|pair| {
+                let (inputs, outputs) = (pair[0], pair[1]);
+                // He initialization, for the ReLU activations
+                let bound = f32::sqrt(6.0 / inputs as f32);
+                Layer {
+                    inputs,
+                    outputs,
+                    weights: (0..inputs * outputs)
+                        .map(|_| rng.gen_range(-bound..bound))
+                        .collect(),
+                    biases: vec![0.0; outputs],
+                }
+            })
+            .collect();
+        Mlp {
+            kind,
+            size: S,
+            komi,
+            layout_hash: kind.layout_hash::<S>(),
+            layers,
+        }
+    }
+
+    pub fn num_inputs(&self) -> usize {
+        self.layers[0].inputs
+    }
+
+    /// The total number of weights and biases
+    pub fn num_params(&self) -> usize {
+        self.layers
+            .iter()
+            .map(|layer| layer.weights.len() + layer.biases.len())
+            .sum()
+    }
+
+    /// All weights and biases, in the same order as in the file
+    pub fn params(&self) -> impl Iterator<Item = &f32> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.weights.iter().chain(layer.biases.iter()))
+    }
+
+    pub fn params_mut(&mut self) -> impl Iterator<Item = &mut f32> {
+        self.layers
+            .iter_mut()
+            .flat_map(|layer| layer.weights.iter_mut().chain(layer.biases.iter_mut()))
+    }
+
+    /// Evaluate the network on a set of features.
+    /// `activations` is overwritten with the output of every layer, and only serves to re-use allocated memory.
+    pub fn evaluate(&self, input: &[f16], activations: &mut Vec<f32>) -> f32 {
+        assert_eq!(input.len(), self.num_inputs());
+        activations.resize(self.layers.iter().map(|layer| layer.outputs).sum(), 0.0);
+
+        let mut offset = 0;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let (previous, rest) = activations.split_at_mut(offset);
+            let output = &mut rest[..layer.outputs];
+            if i == 0 {
+                layer.forward(input.iter().map(|x| x.to_f32()), output);
+            } else {
+                layer.forward(previous[offset - layer.inputs..].iter().copied(), output);
+            }
+            if i + 1 < self.layers.len() {
+                for x in output.iter_mut() {
+                    *x = x.max(0.0);
+                }
+            }
+            offset += layer.outputs;
+        }
+        activations[offset - 1]
+    }
+
+    /// Add the gradient of every weight and bias to `gradients`, in the order of `params`,
+    /// given the activations from `evaluate` and the gradient of the output.
+    /// `deltas` and `previous_deltas` only serve to re-use allocated memory.
+    pub fn add_gradients(
+        &self,
+        input: &[f16],
+        activations: &[f32],
+        output_gradient: f32,
+        gradients: &mut [f32],
+        deltas: &mut Vec<f32>,
+        previous_deltas: &mut Vec<f32>,
+    ) {
+        assert_eq!(gradients.len(), self.num_params());
+        deltas.clear();
+        deltas.push(output_gradient);
+
+        let mut activation_end = activations.len();
+        let mut gradient_end = gradients.len();
+
+        for (i, layer) in self.layers.iter().enumerate().rev() {
+            let layer_gradients = &mut gradients
+                [gradient_end - layer.weights.len() - layer.biases.len()..gradient_end];
+            gradient_end -= layer.weights.len() + layer.biases.len();
+            let (weight_gradients, bias_gradients) =
+                layer_gradients.split_at_mut(layer.weights.len());
+
+            let layer_input: &[f32] = if i == 0 {
+                &[]
+            } else {
+                &activations
+                    [activation_end - layer.outputs - layer.inputs..activation_end - layer.outputs]
+            };
+            activation_end -= layer.outputs;
+
+            for (gradient, delta) in bias_gradients.iter_mut().zip(deltas.iter()) {
+                *gradient += delta;
+            }
+            for (j, weight_gradients) in
+                weight_gradients.chunks_exact_mut(layer.outputs).enumerate()
+            {
+                let x = if i == 0 {
+                    input[j].to_f32()
+                } else {
+                    layer_input[j]
+                };
+                if x != 0.0 {
+                    for (gradient, delta) in weight_gradients.iter_mut().zip(deltas.iter()) {
+                        *gradient += x * delta;
+                    }
+                }
+            }
+
+            if i > 0 {
+                previous_deltas.clear();
+                previous_deltas.extend(
+                    layer
+                        .weights
+                        .chunks_exact(layer.outputs)
+                        .zip(layer_input)
+                        .map(|(weights, x)| {
+                            // Derivative of the ReLU activation
+                            if *x > 0.0 {
+                                weights.iter().zip(deltas.iter()).map(|(w, d)| w * d).sum()
+                            } else {
+                                0.0
+                            }
+                        }),
+                );
+                mem::swap(deltas, previous_deltas);
+            }
+        }
+    }
+
+    /// Check that the network is of the expected kind, fits the current features for size `S`,
+    /// and was trained for the game's komi
+    pub fn check<const S: usize>(&self, kind: ParameterKind, komi: Komi) -> Result<(), Error> {
+        if self.kind != kind {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("Expected {} network, got {} network", kind, self.kind),
+            ));
+        }
+        if self.size != S {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("Expected {}s network, got size {}", S, self.size),
+            ));
+        }
+        if self.num_inputs() != kind.num_features::<S>() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Expected {} inputs to {} network for {}s, got {}",
+                    kind.num_features::<S>(),
+                    kind,
+                    S,
+                    self.num_inputs()
+                ),
+            ));
+        }
+        if self.layout_hash != kind.layout_hash::<S>() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "The network was trained for a different {} feature layout",
+                    kind
+                ),
+            ));
+        }
+        if self.komi != komi {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "The {} network was trained for komi {}, but the game has komi {}",
+                    kind, self.komi, komi
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut lines = reader.lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| Error::new_parse_error("Empty network file"))?
+            .map_err(|err| Error::new(ErrorKind::IoError, err))?;
+
+        let words: Vec<&str> = header.split_whitespace().collect();
+        let ["tiltak-mlp", "version", version, "kind", kind, "size", size, "komi", komi, "layout", layout_hash, "layers", layer_sizes] =
+            words[..]
+        else {
+            return Err(Error::new_parse_error(format!(
+                "Bad network file header \"{}\"",
+                header
+            )));
+        };
+        if version.parse::<u32>() != Ok(MLP_FILE_VERSION) {
+            return Err(Error::new_parse_error(format!(
+                "Unsupported network file version {}, expected {}",
+                version, MLP_FILE_VERSION
+            )));
+        }
+        let parse_error = |name: &str, value: &str| {
+            Error::new_parse_error(format!("Bad {} \"{}\" in network file", name, value))
+        };
+        let layer_sizes: Vec<usize> = layer_sizes
+            .split(',')
+            .map(|layer_size| match layer_size.parse() {
+                Ok(0) | Err(_) => Err(parse_error("layers", layer_sizes)),
+                Ok(layer_size) => Ok(layer_size),
+            })
+            .collect::<Result<_, _>>()?;
+        if layer_sizes.len() < 2 || layer_sizes.last() != Some(&1) {
+            return Err(parse_error("layers", &header));
+        }
+
+        let mut params = vec![];
+        for line in lines {
+            let line = line.map_err(|err| Error::new(ErrorKind::IoError, err))?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            params.push(line.parse().map_err(|_| parse_error("weight", line))?);
+        }
+
+        let mut params = params.into_iter();
+        let layers: Vec<Layer> = layer_sizes
+            .windows(2)
+            .map(|pair| {
+                let (inputs, outputs) = (pair[0], pair[1]);
+                Layer {
+                    inputs,
+                    outputs,
+                    weights: params.by_ref().take(inputs * outputs).collect(),
+                    biases: params.by_ref().take(outputs).collect(),
+                }
+            })
+            .collect();
+        let expected_params: usize = layer_sizes
+            .windows(2)
+            .map(|pair| (pair[0] + 1) * pair[1])
+            .sum();
+        let num_read: usize = layers
+            .iter()
+            .map(|layer| layer.weights.len() + layer.biases.len())
+            .sum::<usize>()
+            + params.len();
+        if num_read != expected_params {
+            return Err(Error::new_parse_error(format!(
+                "Expected {} weights, found {}",
+                expected_params, num_read
+            )));
+        }
+
+        Ok(Mlp {
+            kind: kind.parse()?,
+            size: size.parse().map_err(|_| parse_error("size", size))?,
+            komi: komi.parse().map_err(|_| parse_error("komi", komi))?,
+            layout_hash: u64::from_str_radix(layout_hash, 16)
+                .map_err(|_| parse_error("layout", layout_hash))?,
+            layers,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let layer_sizes: Vec<String> = iter::once(self.num_inputs())
+            .chain(self.layers.iter().map(|layer| layer.outputs))
+            .map(|layer_size| layer_size.to_string())
+            .collect();
+        writeln!(
+            writer,
+            "tiltak-mlp version {} kind {} size {} komi {} layout {:016x} layers {}",
+            MLP_FILE_VERSION,
+            self.kind,
+            self.size,
+            self.komi,
+            self.layout_hash,
+            layer_sizes.join(",")
+        )?;
+        for param in self.params() {
+            writeln!(writer, "{}", param)?;
+        }
+        Ok(())
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let file = fs::File::open(path).map_err(|err| Error::new(ErrorKind::IoError, err))?;
+        Self::read(io::BufReader::new(file))
+    }
+
+    pub fn to_file(&self, path: &str) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+/// Read a value network for size `S` from a file,
+/// and check that it fits the current value features and was trained for `komi`
+pub fn load_value_mlp<const S: usize>(path: &str, komi: Komi) -> Result<Mlp, Error> {
+    load_mlp::<S>(path, ParameterKind::Value, komi)
+}
+
+/// Read a policy network for size `S` from a file,
+/// and check that it fits the current policy features and was trained for `komi`
+pub fn load_policy_mlp<const S: usize>(path: &str, komi: Komi) -> Result<Mlp, Error> {
+    load_mlp::<S>(path, ParameterKind::Policy, komi)
+}
+
+fn load_mlp<const S: usize>(path: &str, kind: ParameterKind, komi: Komi) -> Result<Mlp, Error> {
+    let mlp = Mlp::from_file(path).map_err(|err| {
+        Error::new_caused_by(
+            ErrorKind::Other,
+            format!("Couldn't read {} network from {}", kind, path),
+            err,
+        )
+    })?;
+    mlp.check::<S>(kind, komi)?;
+    Ok(mlp)
+}
+
+impl<const S: usize> Position<S> {
+    /// Static evaluation with a value network, from white's perspective.
+    /// `features` must be zeroed, and is zeroed again before returning.
+    pub fn static_eval_with_mlp(
+        &self,
+        group_data: &GroupData<S>,
+        mlp: &Mlp,
+        features: &mut [f16],
+        activations: &mut Vec<f32>,
+    ) -> f32 {
+        let (white_features, black_features) = features.split_at_mut(features.len() / 2);
+        let mut white_value_features = ValueFeatures::new::<S>(white_features);
+        let mut black_value_features = ValueFeatures::new::<S>(black_features);
+        value_eval::static_eval_game_phase(
+            self,
+            group_data,
+            &mut white_value_features,
+            &mut black_value_features,
+        );
+        let eval = mlp.evaluate(features, activations);
+        features.fill(f16::ZERO);
+        eval
+    }
+
+    /// Move generation that includes the probability of each move being played, from a policy network.
+    /// Takes the same arguments as `generate_moves_with_probabilities`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate_moves_with_mlp(
+        &self,
+        mlp: &Mlp,
+        group_data: &GroupData<S>,
+        simple_moves: &mut Vec<Move<S>>,
+        moves: &mut Vec<(Move<S>, f16)>,
+        fcd_per_move: &mut Vec<i8>,
+        features: &mut Vec<Box<[f16]>>,
+        policy_feature_sets: &mut Option<Vec<PolicyFeatures<'static>>>,
+        activations: &mut Vec<f32>,
+    ) {
+        debug_assert!(simple_moves.is_empty());
+        self.generate_moves(simple_moves);
+        self.generate_moves_with_logits(
+            |features| mlp.evaluate(features, activations),
+            group_data,
+            simple_moves,
+            fcd_per_move,
+            moves,
+            features,
+            policy_feature_sets,
+        )
+    }
+}
@@ -1,3 +1,7 @@
+pub mod explain;
+#[cfg(feature = "mlp")]
+pub mod mlp;
+pub mod parameter_file;
 pub mod parameters;
 pub mod policy_eval;
 pub mod value_eval;
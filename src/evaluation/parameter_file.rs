@@ -0,0 +1,282 @@
+//! Read and write evaluation parameters as files, so that newly tuned parameters can be used without recompiling.
+//!
+//! A parameter file is text, with a single header line followed by one parameter per line:
+//!
+//! ```text
+//! tiltak-params version 1 kind value size 5 komi 2 layout 8c3e2a6b0f1d4e57 count 344
+//! 1.6332405
+//! 2.1396146
+//! ...
+//! ```
+//!
+//! `layout` is a hash of the names and sizes of the feature groups in `parameters.rs`,
+//! so that files are rejected if the features have changed since the file was written.
+
+use std::io::{self, BufRead, Write};
+use std::{fmt, fs, str};
+
+use half::f16;
+use pgn_traits::{Error, ErrorKind};
+
+use crate::evaluation::parameters::{
+    num_policy_features, num_value_features, PolicyFeatures, ValueFeatures,
+};
+use crate::position::Komi;
+
+pub const PARAMETER_FILE_VERSION: u32 = 1;
+
+/// Which evaluation function the parameters are for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterKind {
+    Value,
+    Policy,
+}
+
+impl fmt::Display for ParameterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParameterKind::Value => write!(f, "value"),
+            ParameterKind::Policy => write!(f, "policy"),
+        }
+    }
+}
+
+impl str::FromStr for ParameterKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "value" => Ok(ParameterKind::Value),
+            "policy" => Ok(ParameterKind::Policy),
+            _ => Err(Error::new_parse_error(format!(
+                "Unknown parameter kind \"{}\"",
+                s
+            ))),
+        }
+    }
+}
+
+impl ParameterKind {
+    pub fn num_features<const S: usize>(self) -> usize {
+        match self {
+            ParameterKind::Value => num_value_features::<S>(),
+            ParameterKind::Policy => num_policy_features::<S>(),
+        }
+    }
+
+    /// Hash of the names and sizes of the feature groups for size `S`.
+    /// Changes whenever features are added, removed, renamed or resized.
+    pub fn layout_hash<const S: usize>(self) -> u64 {
+        let groups: Vec<(&str, usize)> = match self {
+            ParameterKind::Value => {
+                // The value features are split in two halves, one for each side
+                let mut coefficients = vec![f16::ZERO; self.num_features::<S>() / 2];
+                ValueFeatures::new::<S>(&mut coefficients)
+                    .groups()
+                    .into_iter()
+                    .map(|(name, group)| (name, group.len()))
+                    .collect()
+            }
+            ParameterKind::Policy => {
+                let mut coefficients = vec![f16::ZERO; self.num_features::<S>()];
+                PolicyFeatures::new::<S>(&mut coefficients)
+                    .groups()
+                    .into_iter()
+                    .map(|(name, group)| (name, group.len()))
+                    .collect()
+            }
+        };
+        // 64-bit FNV-1a, which unlike `DefaultHasher` is stable between Rust versions
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for (name, len) in groups {
+            // Each name is terminated by a zero byte, so that names can't run into the next group
+            for byte in name.bytes().chain([0]).chain((len as u32).to_le_bytes()) {
+                hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+}
+
+/// A set of value or policy parameters, along with what they were tuned for
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterFile {
+    pub kind: ParameterKind,
+    pub size: usize,
+    /// The komi the parameters were tuned for
+    pub komi: Komi,
+    pub layout_hash: u64,
+    pub params: Vec<f32>,
+}
+
+impl ParameterFile {
+    /// Panics if the number of parameters is wrong for the kind and size
+    pub fn new<const S: usize>(kind: ParameterKind, komi: Komi, params: &[f32]) -> Self {
+        assert_eq!(params.len(), kind.num_features::<S>());
+        ParameterFile {
+            kind,
+            size: S,
+            komi,
+            layout_hash: kind.layout_hash::<S>(),
+            params: params.to_vec(),
+        }
+    }
+
+    /// Check that the parameters are of the expected kind, fit the current features for size `S`,
+    /// and were tuned for the game's komi
+    pub fn check<const S: usize>(&self, kind: ParameterKind, komi: Komi) -> Result<(), Error> {
+        if self.kind != kind {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("Expected {} parameters, got {} parameters", kind, self.kind),
+            ));
+        }
+        if self.size != S {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("Expected {}s parameters, got size {}", S, self.size),
+            ));
+        }
+        if self.params.len() != kind.num_features::<S>() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "Expected {} {} parameters for {}s, got {}",
+                    kind.num_features::<S>(),
+                    kind,
+                    S,
+                    self.params.len()
+                ),
+            ));
+        }
+        if self.layout_hash != kind.layout_hash::<S>() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "The parameters were written for a different {} feature layout",
+                    kind
+                ),
+            ));
+        }
+        if self.komi != komi {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "The {} parameters were tuned for komi {}, but the game has komi {}",
+                    kind, self.komi, komi
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut lines = reader.lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| Error::new_parse_error("Empty parameter file"))?
+            .map_err(|err| Error::new(ErrorKind::IoError, err))?;
+
+        let words: Vec<&str> = header.split_whitespace().collect();
+        let ["tiltak-params", "version", version, "kind", kind, "size", size, "komi", komi, "layout", layout_hash, "count", count] =
+            words[..]
+        else {
+            return Err(Error::new_parse_error(format!(
+                "Bad parameter file header \"{}\"",
+                header
+            )));
+        };
+        if version.parse::<u32>() != Ok(PARAMETER_FILE_VERSION) {
+            return Err(Error::new_parse_error(format!(
+                "Unsupported parameter file version {}, expected {}",
+                version, PARAMETER_FILE_VERSION
+            )));
+        }
+        let parse_error = |name: &str, value: &str| {
+            Error::new_parse_error(format!("Bad {} \"{}\" in parameter file", name, value))
+        };
+        let count: usize = count.parse().map_err(|_| parse_error("count", count))?;
+
+        let mut params = Vec::with_capacity(count);
+        for line in lines {
+            let line = line.map_err(|err| Error::new(ErrorKind::IoError, err))?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            params.push(line.parse().map_err(|_| parse_error("parameter", line))?);
+        }
+        if params.len() != count {
+            return Err(Error::new_parse_error(format!(
+                "Expected {} parameters, found {}",
+                count,
+                params.len()
+            )));
+        }
+
+        Ok(ParameterFile {
+            kind: kind.parse()?,
+            size: size.parse().map_err(|_| parse_error("size", size))?,
+            komi: komi.parse().map_err(|_| parse_error("komi", komi))?,
+            layout_hash: u64::from_str_radix(layout_hash, 16)
+                .map_err(|_| parse_error("layout", layout_hash))?,
+            params,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "tiltak-params version {} kind {} size {} komi {} layout {:016x} count {}",
+            PARAMETER_FILE_VERSION,
+            self.kind,
+            self.size,
+            self.komi,
+            self.layout_hash,
+            self.params.len()
+        )?;
+        for param in self.params.iter() {
+            writeln!(writer, "{}", param)?;
+        }
+        Ok(())
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let file = fs::File::open(path).map_err(|err| Error::new(ErrorKind::IoError, err))?;
+        Self::read(io::BufReader::new(file))
+    }
+
+    pub fn to_file(&self, path: &str) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+/// Read value parameters for size `S` from a file,
+/// and check that they fit the current value features and were tuned for `komi`
+pub fn load_value_params<const S: usize>(path: &str, komi: Komi) -> Result<ParameterFile, Error> {
+    load_params::<S>(path, ParameterKind::Value, komi)
+}
+
+/// Read policy parameters for size `S` from a file,
+/// and check that they fit the current policy features and were tuned for `komi`
+pub fn load_policy_params<const S: usize>(path: &str, komi: Komi) -> Result<ParameterFile, Error> {
+    load_params::<S>(path, ParameterKind::Policy, komi)
+}
+
+fn load_params<const S: usize>(
+    path: &str,
+    kind: ParameterKind,
+    komi: Komi,
+) -> Result<ParameterFile, Error> {
+    let file = ParameterFile::from_file(path).map_err(|err| {
+        Error::new_caused_by(
+            ErrorKind::Other,
+            format!("Couldn't read {} parameters from {}", kind, path),
+            err,
+        )
+    })?;
+    file.check::<S>(kind, komi)?;
+    Ok(file)
+}
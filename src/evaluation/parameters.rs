@@ -139,6 +139,73 @@ impl<'a> ValueFeatures<'a> {
             padding,
         }
     }
+
+    /// The name and coefficients of each feature group, in the order they appear in the coefficient vector
+    pub fn groups(&self) -> Vec<(&'static str, &[f16])> {
+        vec![
+            ("first_ply", &*self.first_ply),
+            ("second_ply", &*self.second_ply),
+            ("flat_psqt", &*self.flat_psqt),
+            ("wall_psqt", &*self.wall_psqt),
+            ("cap_psqt", &*self.cap_psqt),
+            ("supports_psqt", &*self.supports_psqt),
+            ("captives_psqt", &*self.captives_psqt),
+            (
+                "shallow_supports_per_piece",
+                &*self.shallow_supports_per_piece,
+            ),
+            ("deep_supports_per_piece", &*self.deep_supports_per_piece),
+            (
+                "shallow_captives_per_piece",
+                &*self.shallow_captives_per_piece,
+            ),
+            ("deep_captives_per_piece", &*self.deep_captives_per_piece),
+            (
+                "us_to_move_opening_flatstone_lead",
+                &*self.us_to_move_opening_flatstone_lead,
+            ),
+            (
+                "them_to_move_opening_flatstone_lead",
+                &*self.them_to_move_opening_flatstone_lead,
+            ),
+            (
+                "us_to_move_middlegame_flatstone_lead",
+                &*self.us_to_move_middlegame_flatstone_lead,
+            ),
+            (
+                "them_to_move_middlegame_flatstone_lead",
+                &*self.them_to_move_middlegame_flatstone_lead,
+            ),
+            (
+                "us_to_move_endgame_flatstone_lead",
+                &*self.us_to_move_endgame_flatstone_lead,
+            ),
+            (
+                "them_to_move_endgame_flatstone_lead",
+                &*self.them_to_move_endgame_flatstone_lead,
+            ),
+            ("i_number_of_groups", &*self.i_number_of_groups),
+            ("critical_squares", &*self.critical_squares),
+            (
+                "critical_square_cap_attack",
+                &*self.critical_square_cap_attack,
+            ),
+            ("flat_next_to_our_stack", &*self.flat_next_to_our_stack),
+            ("wall_next_to_our_stack", &*self.wall_next_to_our_stack),
+            ("cap_next_to_our_stack", &*self.cap_next_to_our_stack),
+            ("num_lines_occupied", &*self.num_lines_occupied),
+            ("line_control_empty", &*self.line_control_empty),
+            (
+                "line_control_their_blocking_piece",
+                &*self.line_control_their_blocking_piece,
+            ),
+            ("line_control_other", &*self.line_control_other),
+            ("sidelined_cap", &*self.sidelined_cap),
+            ("fully_isolated_cap", &*self.fully_isolated_cap),
+            ("semi_isolated_cap", &*self.semi_isolated_cap),
+            ("padding", &*self.padding),
+        ]
+    }
 }
 
 #[derive(Debug)]
@@ -334,6 +401,129 @@ impl<'a> PolicyFeatures<'a> {
             padding,
         }
     }
+
+    /// The name and coefficients of each feature group, in the order they appear in the coefficient vector
+    pub fn groups(&self) -> Vec<(&'static str, &[f16])> {
+        vec![
+            ("flat_psqt_white", &*self.flat_psqt_white),
+            ("flat_psqt_black", &*self.flat_psqt_black),
+            ("wall_psqt_white", &*self.wall_psqt_white),
+            ("wall_psqt_black", &*self.wall_psqt_black),
+            ("cap_psqt_white", &*self.cap_psqt_white),
+            ("cap_psqt_black", &*self.cap_psqt_black),
+            ("move_role_bonus_white", &*self.move_role_bonus_white),
+            ("move_role_bonus_black", &*self.move_role_bonus_black),
+            ("decline_win", &*self.decline_win),
+            ("place_to_win", &*self.place_to_win),
+            ("place_to_draw", &*self.place_to_draw),
+            ("place_to_loss", &*self.place_to_loss),
+            (
+                "place_to_allow_opponent_to_end",
+                &*self.place_to_allow_opponent_to_end,
+            ),
+            ("two_flats_left", &*self.two_flats_left),
+            ("three_flats_left", &*self.three_flats_left),
+            ("our_road_stones_in_line", &*self.our_road_stones_in_line),
+            (
+                "their_road_stones_in_line",
+                &*self.their_road_stones_in_line,
+            ),
+            ("extend_single_group_base", &*self.extend_single_group_base),
+            (
+                "extend_single_group_linear",
+                &*self.extend_single_group_linear,
+            ),
+            (
+                "extend_single_group_to_new_line_base",
+                &*self.extend_single_group_to_new_line_base,
+            ),
+            (
+                "extend_single_group_to_new_line_linear",
+                &*self.extend_single_group_to_new_line_linear,
+            ),
+            ("merge_two_groups_base", &*self.merge_two_groups_base),
+            ("merge_two_groups_linear", &*self.merge_two_groups_linear),
+            ("block_merger_base", &*self.block_merger_base),
+            ("block_merger_linear", &*self.block_merger_linear),
+            (
+                "place_our_critical_square",
+                &*self.place_our_critical_square,
+            ),
+            (
+                "place_their_critical_square",
+                &*self.place_their_critical_square,
+            ),
+            (
+                "ignore_their_critical_square",
+                &*self.ignore_their_critical_square,
+            ),
+            ("next_to_our_last_stone", &*self.next_to_our_last_stone),
+            ("next_to_their_last_stone", &*self.next_to_their_last_stone),
+            (
+                "diagonal_to_our_last_stone",
+                &*self.diagonal_to_our_last_stone,
+            ),
+            (
+                "diagonal_to_their_last_stone",
+                &*self.diagonal_to_their_last_stone,
+            ),
+            ("attack_strong_flats", &*self.attack_strong_flats),
+            (
+                "blocking_stone_blocks_extensions_of_two_flats",
+                &*self.blocking_stone_blocks_extensions_of_two_flats,
+            ),
+            (
+                "attack_strong_stack_with_wall",
+                &*self.attack_strong_stack_with_wall,
+            ),
+            (
+                "attack_strong_stack_with_cap",
+                &*self.attack_strong_stack_with_cap,
+            ),
+            ("attack_last_movement", &*self.attack_last_movement),
+            ("place_last_movement", &*self.place_last_movement),
+            ("simple_movement", &*self.simple_movement),
+            ("simple_capture", &*self.simple_capture),
+            ("simple_self_capture", &*self.simple_self_capture),
+            ("pure_spread", &*self.pure_spread),
+            ("fcd_highest_board", &*self.fcd_highest_board),
+            ("fcd_highest_stack", &*self.fcd_highest_stack),
+            ("fcd_other", &*self.fcd_other),
+            (
+                "stack_captured_by_movement",
+                &*self.stack_captured_by_movement,
+            ),
+            (
+                "stack_capture_in_strong_line",
+                &*self.stack_capture_in_strong_line,
+            ),
+            (
+                "stack_capture_in_strong_line_cap",
+                &*self.stack_capture_in_strong_line_cap,
+            ),
+            (
+                "move_cap_onto_strong_line",
+                &*self.move_cap_onto_strong_line,
+            ),
+            (
+                "move_cap_onto_strong_line_with_critical_square",
+                &*self.move_cap_onto_strong_line_with_critical_square,
+            ),
+            ("recapture_stack_pure", &*self.recapture_stack_pure),
+            ("recapture_stack_impure", &*self.recapture_stack_impure),
+            ("move_last_placement", &*self.move_last_placement),
+            ("continue_spread", &*self.continue_spread),
+            (
+                "move_onto_critical_square",
+                &*self.move_onto_critical_square,
+            ),
+            (
+                "spread_that_connects_groups_to_win",
+                &*self.spread_that_connects_groups_to_win,
+            ),
+            ("padding", &*self.padding),
+        ]
+    }
 }
 
 pub fn num_value_features<const S: usize>() -> usize {
@@ -39,6 +39,43 @@ impl<const S: usize> Position<S> {
         moves: &mut Vec<(Move<S>, f16)>,
         feature_sets: &mut Vec<Box<[f16]>>,
         policy_feature_sets: &mut Option<Vec<PolicyFeatures<'static>>>,
+    ) {
+        self.generate_moves_with_logits(
+            |features| {
+                const SIMD_WIDTH: usize = 8;
+                assert_eq!(features.len() % SIMD_WIDTH, 0);
+                assert_eq!(features.len(), params_for_color.len());
+
+                let partial_sums: [f32; SIMD_WIDTH] = features
+                    .chunks_exact(SIMD_WIDTH)
+                    .zip(params_for_color.chunks_exact(SIMD_WIDTH))
+                    .fold([0.0; SIMD_WIDTH], |acc, (c, p)| {
+                        array::from_fn(|i| acc[i] + c[i].to_f32() * p[i])
+                    });
+
+                partial_sums.iter().sum::<f32>()
+            },
+            group_data,
+            simple_moves,
+            fcd_per_move,
+            moves,
+            feature_sets,
+            policy_feature_sets,
+        )
+    }
+
+    /// Generate the probability of each move in `simple_moves`,
+    /// with a logit computed by `move_logit` from each move's policy features
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn generate_moves_with_logits<F: FnMut(&[f16]) -> f32>(
+        &self,
+        mut move_logit: F,
+        group_data: &GroupData<S>,
+        simple_moves: &mut Vec<Move<S>>,
+        fcd_per_move: &mut Vec<i8>,
+        moves: &mut Vec<(Move<S>, f16)>,
+        feature_sets: &mut Vec<Box<[f16]>>,
+        policy_feature_sets: &mut Option<Vec<PolicyFeatures<'static>>>,
     ) {
         let num_moves = simple_moves.len();
 
@@ -83,20 +120,7 @@ impl<const S: usize> Position<S> {
                         );
                     }
                     let offset = inverse_sigmoid(1.0 / num_moves.max(2) as f32);
-                    assert_eq!(features.len(), params_for_color.len());
-
-                    const SIMD_WIDTH: usize = 8;
-                    assert_eq!(features.len() % SIMD_WIDTH, 0);
-                    assert_eq!(features.len(), params_for_color.len());
-
-                    let partial_sums: [f32; SIMD_WIDTH] = features
-                        .chunks_exact(SIMD_WIDTH)
-                        .zip(params_for_color.chunks_exact(SIMD_WIDTH))
-                        .fold([0.0; SIMD_WIDTH], |acc, (c, p)| {
-                            array::from_fn(|i| acc[i] + c[i].to_f32() * p[i])
-                        });
-
-                    let total_value = partial_sums.iter().sum::<f32>() + offset;
+                    let total_value = move_logit(features) + offset;
 
                     features.fill(f16::ZERO);
 
@@ -212,6 +236,26 @@ struct MovementSynopsis<const S: usize> {
     destination: Square<S>,
 }
 
+/// A summary of the move history that the policy depends on, which is the last placement or movement of each side.
+/// Positions with the same stones, side to move and key get the same policy.
+pub fn last_moves_key<const S: usize>(position: &Position<S>) -> u64 {
+    let placement_key = |placement: Option<(Role, Square<S>)>| {
+        placement.map_or(0, |(role, square)| {
+            1 << 16 | (role as u64) << 8 | square.into_inner() as u64
+        })
+    };
+    let movement_key = |movement: Option<MovementSynopsis<S>>| {
+        movement.map_or(0, |movement| {
+            2 << 16
+                | (movement.origin.into_inner() as u64) << 8
+                | movement.destination.into_inner() as u64
+        })
+    };
+    (placement_key(our_last_placement(position)) | movement_key(our_last_movement(position))) << 32
+        | placement_key(their_last_placement(position))
+        | movement_key(their_last_movement(position))
+}
+
 fn our_last_placement<const S: usize>(position: &Position<S>) -> Option<(Role, Square<S>)> {
     position
         .moves()
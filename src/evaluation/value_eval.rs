@@ -11,6 +11,9 @@ use crate::position::{
     Position, Role::*, Square,
 };
 
+/// The ply after which the opening, middlegame and endgame scale factors no longer change
+pub const MAX_SCALE_FACTOR_PLY: usize = 48;
+
 pub fn static_eval_game_phase<const S: usize>(
     position: &Position<S>,
     group_data: &GroupData<S>,
@@ -172,7 +175,10 @@ pub fn static_eval_game_phase<const S: usize>(
         1.0,
     ));
     let endgame_scale_factor = f16::from_f32(f32::min(
-        f32::max((position.half_moves_played() as f32 - 24.0) / 24.0, 0.0),
+        f32::max(
+            (position.half_moves_played() as f32 - 24.0) / (MAX_SCALE_FACTOR_PLY as f32 - 24.0),
+            0.0,
+        ),
         1.0,
     ));
     let middlegame_scale_factor = f16::ONE - opening_scale_factor - endgame_scale_factor;
@@ -32,14 +32,16 @@ extern crate pgn_traits;
 
 pub use search::mcts;
 
+pub mod analysis;
 #[cfg(any(feature = "aws-lambda-runtime", feature = "aws-lambda-client"))]
 pub mod aws;
+pub mod book;
 pub mod minmax;
 pub mod move_gen;
-#[cfg(feature = "sqlite")]
-pub mod policy_sqlite;
 pub mod position;
 pub mod search;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 #[cfg(test)]
 mod tests;
 #[cfg(feature = "constant-tuning")]
@@ -0,0 +1,286 @@
+use board_game_traits::{Color, Position as PositionTrait};
+use pgn_traits::{Error, ErrorKind};
+
+use super::{squares_iterator, starting_capstones, starting_stones, Komi, Piece, Position, Role};
+use super::{Square, Stack};
+
+/// Builds a `Position` piece by piece, and validates the result.
+///
+/// By default, the reserves are whatever is not on the board, and white is to move on move 1.
+///
+/// ```
+/// use board_game_traits::Color;
+/// use tiltak::position::{Piece, PositionBuilder, Square};
+///
+/// let position = PositionBuilder::<5>::new()
+///     .stack(Square::parse_square("c3").unwrap(), &[Piece::WhiteFlat, Piece::BlackCap])
+///     .side_to_move(Color::White)
+///     .move_number(3)
+///     .build()
+///     .unwrap();
+/// assert_eq!(position.black_caps_left(), 0);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PositionBuilder<const S: usize> {
+    stacks: Vec<Vec<Piece>>,
+    to_move: Color,
+    move_number: usize,
+    komi: Komi,
+    white_reserves: Option<(u8, u8)>,
+    black_reserves: Option<(u8, u8)>,
+}
+
+impl<const S: usize> Default for PositionBuilder<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const S: usize> PositionBuilder<S> {
+    /// An empty board with white to move
+    pub fn new() -> Self {
+        PositionBuilder {
+            stacks: vec![vec![]; S * S],
+            to_move: Color::White,
+            move_number: 1,
+            komi: Komi::default(),
+            white_reserves: None,
+            black_reserves: None,
+        }
+    }
+
+    /// Start from an existing position. The move history is not kept.
+    pub fn from_position(position: &Position<S>) -> Self {
+        let mut builder = Self::new()
+            .side_to_move(position.side_to_move())
+            .move_number(position.half_moves_played() / 2 + 1)
+            .komi(position.komi());
+        for square in squares_iterator::<S>() {
+            builder.stacks[square.into_inner() as usize] = position[square].into_iter().collect();
+        }
+        builder
+    }
+
+    /// Replace the stack on `square`. The first piece is the bottom of the stack.
+    pub fn stack(mut self, square: Square<S>, pieces: &[Piece]) -> Self {
+        self.stacks[square.into_inner() as usize] = pieces.to_vec();
+        self
+    }
+
+    /// Put a single piece on top of the stack on `square`
+    pub fn push_piece(mut self, square: Square<S>, piece: Piece) -> Self {
+        self.stacks[square.into_inner() as usize].push(piece);
+        self
+    }
+
+    pub fn clear_square(self, square: Square<S>) -> Self {
+        self.stack(square, &[])
+    }
+
+    pub fn side_to_move(mut self, color: Color) -> Self {
+        self.to_move = color;
+        self
+    }
+
+    /// The full move number, as in TPS. Starts at 1.
+    pub fn move_number(mut self, move_number: usize) -> Self {
+        self.move_number = move_number;
+        self
+    }
+
+    pub fn komi(mut self, komi: Komi) -> Self {
+        self.komi = komi;
+        self
+    }
+
+    /// Set the number of stones (flats and walls) and capstones that a player has left to place.
+    /// If not set, the reserves are whatever is not on the board.
+    pub fn reserves(mut self, color: Color, stones: u8, caps: u8) -> Self {
+        match color {
+            Color::White => self.white_reserves = Some((stones, caps)),
+            Color::Black => self.black_reserves = Some((stones, caps)),
+        }
+        self
+    }
+
+    /// Validate the position, and build it
+    pub fn build(&self) -> Result<Position<S>, Error> {
+        if self.move_number == 0 {
+            return Err(illegal_position("Move number must be at least 1"));
+        }
+
+        let mut position = Position::start_position_with_komi(self.komi);
+        let (mut white_stones, mut black_stones, mut white_caps, mut black_caps) = (0, 0, 0, 0);
+
+        for square in squares_iterator::<S>() {
+            let pieces = &self.stacks[square.into_inner() as usize];
+            if pieces.len() > u8::MAX as usize {
+                return Err(illegal_position(format!(
+                    "Stack on {} is too tall, with {} pieces",
+                    square,
+                    pieces.len()
+                )));
+            }
+            let mut stack = Stack::default();
+            for (i, piece) in pieces.iter().enumerate() {
+                if i + 1 < pieces.len() && piece.role() != Role::Flat {
+                    return Err(illegal_position(format!(
+                        "{} buried in the stack on {}, only the top piece can be a wall or a capstone",
+                        role_name(piece.role()),
+                        square
+                    )));
+                }
+                match (piece.color(), piece.role()) {
+                    (Color::White, Role::Cap) => white_caps += 1,
+                    (Color::White, _) => white_stones += 1,
+                    (Color::Black, Role::Cap) => black_caps += 1,
+                    (Color::Black, _) => black_stones += 1,
+                }
+                stack.push(*piece);
+            }
+            position[square] = stack;
+        }
+
+        position.white_stones_left = reserves_left::<S>(
+            Color::White,
+            "stones",
+            white_stones,
+            starting_stones(S),
+            self.white_reserves.map(|(stones, _)| stones),
+        )?;
+        position.white_caps_left = reserves_left::<S>(
+            Color::White,
+            "capstones",
+            white_caps,
+            starting_capstones(S),
+            self.white_reserves.map(|(_, caps)| caps),
+        )?;
+        position.black_stones_left = reserves_left::<S>(
+            Color::Black,
+            "stones",
+            black_stones,
+            starting_stones(S),
+            self.black_reserves.map(|(stones, _)| stones),
+        )?;
+        position.black_caps_left = reserves_left::<S>(
+            Color::Black,
+            "capstones",
+            black_caps,
+            starting_capstones(S),
+            self.black_reserves.map(|(_, caps)| caps),
+        )?;
+
+        position.to_move = self.to_move;
+        position.half_moves_played = match self.to_move {
+            Color::White => 2 * self.move_number - 2,
+            Color::Black => 2 * self.move_number - 1,
+        };
+        if position.half_moves_played < 2
+            && white_stones + black_stones + white_caps + black_caps > position.half_moves_played
+        {
+            return Err(illegal_position(format!(
+                "{} pieces on the board after only {} plies",
+                white_stones + black_stones + white_caps + black_caps,
+                position.half_moves_played
+            )));
+        }
+        if position.half_moves_played <= 2 {
+            self.check_opening_placements(position.half_moves_played)?;
+        }
+
+        position.hash = position.zobrist_hash_from_scratch();
+        Ok(position)
+    }
+
+    /// Under swap rules, each player's first move places a flat of the opponent's color.
+    /// Check that the board could be the result of the first `half_moves_played` plies.
+    fn check_opening_placements(&self, half_moves_played: usize) -> Result<(), Error> {
+        let (mut white_flats, mut black_flats) = (0, 0);
+        for square in squares_iterator::<S>() {
+            let pieces = &self.stacks[square.into_inner() as usize];
+            if pieces.len() > 1 {
+                return Err(illegal_position(format!(
+                    "Stack on {} after only {} plies, the first two moves must be placements",
+                    square, half_moves_played
+                )));
+            }
+            match pieces.first() {
+                Some(Piece::WhiteFlat) => white_flats += 1,
+                Some(Piece::BlackFlat) => black_flats += 1,
+                Some(piece) => {
+                    return Err(illegal_position(format!(
+                        "{} on {} after only {} plies, the first two moves must place flats",
+                        role_name(piece.role()),
+                        square,
+                        half_moves_played
+                    )))
+                }
+                None => (),
+            }
+        }
+        // White places a black flat on the first ply, and black places a white flat on the second
+        let max_black_flats = (half_moves_played >= 1) as usize;
+        let max_white_flats = (half_moves_played >= 2) as usize;
+        if black_flats > max_black_flats || white_flats > max_white_flats {
+            return Err(illegal_position(format!(
+                "{} white and {} black flats on the board after only {} plies, but the first two moves must place one flat of the opponent's color each",
+                white_flats, black_flats, half_moves_played
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Check the number of pieces on the board against the starting reserves,
+/// and return the number of pieces the player has left to place
+fn reserves_left<const S: usize>(
+    color: Color,
+    piece_name: &str,
+    on_board: usize,
+    starting: u8,
+    reserves: Option<u8>,
+) -> Result<u8, Error> {
+    if on_board > starting as usize {
+        return Err(illegal_position(format!(
+            "Too many {} {}: {} on the board, but {}s only has {}",
+            color_name(color),
+            piece_name,
+            on_board,
+            S,
+            starting
+        )));
+    }
+    let max_reserves = starting - on_board as u8;
+    match reserves {
+        Some(reserves) if reserves > max_reserves => Err(illegal_position(format!(
+            "Too many {} {} in reserve: {} on the board and {} in reserve, but {}s only has {}",
+            color_name(color),
+            piece_name,
+            on_board,
+            reserves,
+            S,
+            starting
+        ))),
+        Some(reserves) => Ok(reserves),
+        None => Ok(max_reserves),
+    }
+}
+
+fn illegal_position<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Error {
+    Error::new(ErrorKind::IllegalPosition, error)
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "white",
+        Color::Black => "black",
+    }
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Flat => "Flat",
+        Role::Wall => "Wall",
+        Role::Cap => "Capstone",
+    }
+}
@@ -12,7 +12,10 @@ use board_game_traits::{EvalPosition as EvalPositionTrait, Position as PositionT
 use half::f16;
 use lazy_static::lazy_static;
 use pgn_traits::PgnPosition;
-use rand::{Rng, SeedableRng};
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
@@ -27,11 +30,14 @@ pub use utils::AbstractBoard;
 
 pub use mv::{ExpMove, Move, ReverseMove};
 
+pub use builder::PositionBuilder;
+
 use crate::evaluation::parameters::{self, PolicyFeatures, ValueFeatures};
 use crate::evaluation::value_eval;
 use crate::position::color_trait::ColorTr;
 
 pub(crate) mod bitboard;
+mod builder;
 pub(crate) mod color_trait;
 mod mv;
 mod square;
@@ -341,15 +347,20 @@ pub fn zobrist_to_move<const S: usize>(color: Color) -> u64 {
 }
 
 impl<const S: usize> ZobristKeys<S> {
+    /// The keys are drawn from a ChaCha8 generator seeded with 32 zero bytes, which, unlike `StdRng`,
+    /// produces the same numbers in every version of `rand_chacha`.
+    /// This keeps the hashes in extended TPS strings valid across builds.
     pub(crate) fn new() -> Box<Self> {
-        let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
+        let mut rng = ChaCha8Rng::from_seed([0; 32]);
 
         Box::new(ZobristKeys {
-            top_stones: AbstractBoard::new_from_fn(|| array::from_fn(|_| rng.gen())),
+            top_stones: AbstractBoard::new_from_fn(|| array::from_fn(|_| rng.next_u64())),
             stones_in_stack: array::from_fn(|_| {
-                Box::new(AbstractBoard::new_from_fn(|| array::from_fn(|_| rng.gen())))
+                Box::new(AbstractBoard::new_from_fn(|| {
+                    array::from_fn(|_| rng.next_u64())
+                }))
             }),
-            to_move: array::from_fn(|_| rng.gen()),
+            to_move: array::from_fn(|_| rng.next_u64()),
         })
     }
 }
@@ -401,7 +412,7 @@ pub struct Position<const S: usize> {
     moves: Vec<Move<S>>,
     komi: Komi,
     hash: u64,              // Zobrist hash of current position
-    hash_history: Vec<u64>, // Zobrist hashes of previous board states, up to the last irreversible move. Does not include the corrent position
+    hash_history: Vec<u64>, // Zobrist hashes of previous board states, one for each move in `moves`, possibly preceded by hashes from an extended TPS. Cleared by symmetry transformations, so it may be shorter than `moves`. Does not include the current position
 }
 
 impl<const S: usize> Clone for Position<S> {
@@ -453,6 +464,71 @@ impl<const S: usize> PartialEq for Position<S> {
 
 impl<const S: usize> Eq for Position<S> {}
 
+/// The serialized form of a `Position`. The move history is only included if it goes back to the start of the game,
+/// and is replayed when deserializing, to restore the position's repetition history.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SerializedPosition<const S: usize> {
+    tps: String,
+    komi: Komi,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    moves: Vec<Move<S>>,
+}
+
+#[cfg(feature = "serde")]
+impl<const S: usize> Serialize for Position<S> {
+    fn serialize<Ser: serde::Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let moves = if self.moves.len() == self.half_moves_played {
+            self.moves.clone()
+        } else {
+            vec![]
+        };
+        SerializedPosition {
+            tps: self.to_fen(),
+            komi: self.komi,
+            moves,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const S: usize> Deserialize<'de> for Position<S> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let SerializedPosition { tps, komi, moves } =
+            SerializedPosition::deserialize(deserializer)?;
+        let position =
+            Self::from_fen_with_settings(&tps, &Settings { komi }).map_err(D::Error::custom)?;
+        if moves.is_empty() {
+            return Ok(position);
+        }
+
+        let mut replayed_position = Self::start_position_with_komi(komi);
+        let mut legal_moves = vec![];
+        for mv in moves {
+            legal_moves.clear();
+            replayed_position.generate_moves(&mut legal_moves);
+            if replayed_position.game_result().is_some() || !legal_moves.contains(&mv) {
+                return Err(D::Error::custom(format!(
+                    "Illegal move {} in the move history",
+                    mv
+                )));
+            }
+            replayed_position.do_move(mv);
+        }
+        if replayed_position != position {
+            return Err(D::Error::custom(format!(
+                "Move history leads to {}, but the TPS is {}",
+                replayed_position.to_fen(),
+                tps
+            )));
+        }
+        Ok(replayed_position)
+    }
+}
+
 impl<const S: usize> Hash for Position<S> {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.cells.hash(state);
@@ -546,10 +622,28 @@ impl<const S: usize> Position<S> {
         }
     }
 
+    /// Parse a TPS string, using `komi` unless the TPS is extended with its own komi
     pub fn from_fen_with_komi(fen: &str, komi: Komi) -> Result<Self, pgn_traits::Error> {
-        let mut position = Self::from_fen(fen)?;
-        position.komi = komi;
-        Ok(position)
+        Self::from_fen_with_settings(fen, &Settings { komi })
+    }
+
+    /// The position as extended TPS, which also includes komi,
+    /// and the hash history since the last placement if there is one, to preserve repetition detection.
+    ///
+    /// Extended TPS is a regular TPS string followed by `key=value` fields, like
+    /// `x5/x5/x5/x5/x5 1 1 komi=2` or `2,x4/x5/x5/x5/x4,1 1 5 komi=0 history=5a0c11e5b8a02c1e,7fe2c3d1a0b9e84f`.
+    /// The history is the Zobrist hash of each earlier position that can still be repeated, oldest first, in hex.
+    /// The hashes are stable across builds and versions of the crate's dependencies, see `ZobristKeys::new`.
+    /// All TPS parsers in this crate accept both extended and regular TPS.
+    pub fn to_extended_fen(&self) -> String {
+        let mut fen = self.to_fen();
+        write!(fen, " komi={}", self.komi).unwrap();
+        let history = self.repeatable_hash_history();
+        if !history.is_empty() {
+            let hashes: Vec<String> = history.iter().map(|hash| format!("{:x}", hash)).collect();
+            write!(fen, " history={}", hashes.join(",")).unwrap();
+        }
+        fen
     }
 
     pub fn white_reserves_left(&self) -> u8 {
@@ -568,7 +662,6 @@ impl<const S: usize> Position<S> {
         self.black_caps_left
     }
 
-    #[cfg(test)]
     pub fn zobrist_hash(&self) -> u64 {
         self.hash
     }
@@ -591,6 +684,45 @@ impl<const S: usize> Position<S> {
         &self.moves
     }
 
+    /// Zobrist hashes of the positions before each move in `moves`.
+    /// If the position was read from an extended TPS with a history, those hashes come first.
+    /// Positions from `flip_board_x`, `flip_board_y`, `rotate_board` and `flip_colors` start with an empty history,
+    /// so it may be shorter than `moves`. Used to detect threefold repetitions
+    pub fn hash_history(&self) -> &[u64] {
+        &self.hash_history
+    }
+
+    /// The part of the hash history after the last irreversible move.
+    /// Positions before a placement can never be repeated, since the new piece stays on the board.
+    fn repeatable_hash_history(&self) -> &[u64] {
+        // The history is aligned with the end of `moves`. Hashes read from an extended TPS have no corresponding moves,
+        // and moves from before a symmetry transformation have no corresponding hashes
+        let irreversible_moves = self
+            .moves
+            .iter()
+            .rposition(|mv| matches!(mv.expand(), ExpMove::Place(_, _)))
+            .map(|i| (self.hash_history.len() + i + 1).saturating_sub(self.moves.len()))
+            .unwrap_or(0);
+        &self.hash_history[irreversible_moves..]
+    }
+
+    /// Plays a random game of `num_plies` moves from the start position, and returns the resulting position.
+    /// The game stops early if it is decided before then.
+    pub fn random_position<R: Rng>(rng: &mut R, num_plies: usize) -> Self {
+        let mut position = Self::start_position();
+        let mut moves = vec![];
+        for _ in 0..num_plies {
+            if position.game_result().is_some() {
+                break;
+            }
+            moves.clear();
+            position.generate_moves(&mut moves);
+            let mv = moves[rng.gen_range(0..moves.len())];
+            position.do_move(mv);
+        }
+        position
+    }
+
     pub fn null_move(&mut self) {
         self.to_move = !self.to_move;
     }
@@ -641,6 +773,9 @@ impl<const S: usize> Position<S> {
         sum_of_connections.is_winning()
     }
 
+    /// Mirror the board vertically. Like the other symmetries, the new position has no hash history,
+    /// because the hashes of the earlier positions cannot be recomputed.
+    /// Repetitions of positions from before the transformation are not detected
     pub fn flip_board_y(&self) -> Position<S> {
         let mut new_board = self.clone();
         for file in 0..S as u8 {
@@ -649,9 +784,13 @@ impl<const S: usize> Position<S> {
                     self[Square::from_rank_file(S as u8 - rank - 1, file)];
             }
         }
+        new_board.moves = self.moves.iter().map(|mv| mv.flip_board_y()).collect();
+        new_board.hash = new_board.zobrist_hash_from_scratch();
+        new_board.hash_history.clear();
         new_board
     }
 
+    /// Mirror the board horizontally, with no hash history, see `flip_board_y`
     pub fn flip_board_x(&self) -> Position<S> {
         let mut new_board = self.clone();
         for file in 0..S as u8 {
@@ -660,9 +799,13 @@ impl<const S: usize> Position<S> {
                     self[Square::from_rank_file(rank, S as u8 - file - 1)];
             }
         }
+        new_board.moves = self.moves.iter().map(|mv| mv.flip_board_x()).collect();
+        new_board.hash = new_board.zobrist_hash_from_scratch();
+        new_board.hash_history.clear();
         new_board
     }
 
+    /// Rotate the board by 90 degrees, with no hash history, see `flip_board_y`
     pub fn rotate_board(&self) -> Position<S> {
         let mut new_board = self.clone();
         for file in 0..S as u8 {
@@ -673,9 +816,13 @@ impl<const S: usize> Position<S> {
                     self[Square::from_rank_file(new_rank, new_file)];
             }
         }
+        new_board.moves = self.moves.iter().map(|mv| mv.rotate_board()).collect();
+        new_board.hash = new_board.zobrist_hash_from_scratch();
+        new_board.hash_history.clear();
         new_board
     }
 
+    /// Swap the colors of all pieces and the side to move, with no hash history, see `flip_board_y`
     pub fn flip_colors(&self) -> Position<S> {
         let mut new_board = self.clone();
         for square in square::squares_iterator::<S>() {
@@ -693,6 +840,8 @@ impl<const S: usize> Position<S> {
             &mut new_board.black_caps_left,
         );
         new_board.to_move = !new_board.to_move;
+        new_board.hash = new_board.zobrist_hash_from_scratch();
+        new_board.hash_history.clear();
         new_board
     }
 
@@ -718,6 +867,16 @@ impl<const S: usize> Position<S> {
             .collect()
     }
 
+    /// The smallest TPS among the board's 8 symmetries.
+    /// Positions that are rotations or reflections of each other have the same canonical TPS
+    pub fn canonical_tps(&self) -> String {
+        self.symmetries()
+            .iter()
+            .map(|symmetry| symmetry.to_fen())
+            .min()
+            .unwrap()
+    }
+
     fn count_all_pieces(&self) -> u8 {
         squares_iterator::<S>()
             .map(|square| self[square].len())
@@ -783,6 +942,70 @@ impl<const S: usize> Position<S> {
         group_data
     }
 
+    /// Returns all moves that would immediately win the game by road for `color`, as if it was `color`'s turn to move.
+    /// Placement wins are found from the critical squares, while stack movements are simulated.
+    pub fn road_winning_moves(&self, color: Color) -> Vec<Move<S>> {
+        match color {
+            Color::White => self.road_winning_moves_colortr::<WhiteTr, BlackTr>(),
+            Color::Black => self.road_winning_moves_colortr::<BlackTr, WhiteTr>(),
+        }
+    }
+
+    /// Returns the opponent's immediate road wins, if they were allowed to move next.
+    /// The side to move is in "Tak" if this is non-empty.
+    pub fn tak_threats(&self) -> Vec<Move<S>> {
+        self.road_winning_moves(!self.side_to_move())
+    }
+
+    fn road_winning_moves_colortr<Us: ColorTr, Them: ColorTr>(&self) -> Vec<Move<S>> {
+        let mut winning_moves = vec![];
+
+        // In the opening, the players place each other's stones
+        if self.half_moves_played() < 2 {
+            return winning_moves;
+        }
+
+        let group_data = self.group_data();
+
+        for square in Us::critical_squares(&group_data).into_iter::<S>() {
+            if self[square].is_empty() {
+                if Us::stones_left(self) > 0 {
+                    winning_moves.push(Move::placement(Flat, square));
+                }
+                if Us::caps_left(self) > 0 {
+                    winning_moves.push(Move::placement(Cap, square));
+                }
+            }
+        }
+
+        let mut moves = vec![];
+        for square in squares_iterator::<S>() {
+            if self[square].top_stone().is_some_and(Us::piece_is_ours) {
+                self.generate_moves_for_square_colortr::<_, Us, Them>(&mut moves, square);
+            }
+        }
+
+        let mut position = self.clone();
+        if position.side_to_move() != Us::color() {
+            position.null_move();
+        }
+
+        for mv in moves {
+            let reverse_move = position.do_move(mv);
+            let group_data = position.group_data();
+            if Us::road_stones(&group_data).into_iter::<S>().any(|square| {
+                group_data.amount_in_group[group_data.groups[square] as usize]
+                    .1
+                    .is_winning()
+            }) {
+                winning_moves.push(mv);
+            }
+            position.reverse_move(reverse_move);
+        }
+
+        winning_moves
+    }
+
     /// An iterator over the top stones left behind after a stack movement
     pub fn top_stones_left_behind_by_move<'a>(
         &'a self,
@@ -869,7 +1092,7 @@ impl<const S: usize> Position<S> {
 
     fn detailed_game_result(&self, group_data: &GroupData<S>) -> Option<DetailedGameResult> {
         let repetitions = self
-            .hash_history
+            .repeatable_hash_history()
             .iter()
             .filter(|hash| **hash == self.hash)
             .count();
@@ -1168,6 +1391,109 @@ impl<const S: usize> Position<S> {
             }
         }
     }
+
+    /// Returns the perft result for each legal move in the position, at `depth` plies including the move itself.
+    pub fn perft_divide(&mut self, depth: u16) -> Vec<(Move<S>, u64)> {
+        if depth == 0 || self.game_result().is_some() {
+            return vec![];
+        }
+        let mut moves = vec![];
+        self.generate_moves(&mut moves);
+        moves
+            .into_iter()
+            .map(|mv| {
+                let reverse_move = self.do_move(mv);
+                let num_moves = self.bulk_perft(depth - 1);
+                self.reverse_move(reverse_move);
+                (mv, num_moves)
+            })
+            .collect()
+    }
+
+    /// Like `perft_divide`, but searches each root move in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn parallel_perft_divide(&self, depth: u16) -> Vec<(Move<S>, u64)> {
+        if depth == 0 || self.game_result().is_some() {
+            return vec![];
+        }
+        let mut moves = vec![];
+        self.generate_moves(&mut moves);
+        moves
+            .into_par_iter()
+            .map(|mv| {
+                let mut position = self.clone();
+                position.do_move(mv);
+                (mv, position.bulk_perft(depth - 1))
+            })
+            .collect()
+    }
+
+    /// Like `bulk_perft`, but searches each root move in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn parallel_perft(&self, depth: u16) -> u64 {
+        if depth == 0 || self.game_result().is_some() {
+            1
+        } else {
+            self.parallel_perft_divide(depth)
+                .into_iter()
+                .map(|(_, num_moves)| num_moves)
+                .sum()
+        }
+    }
+
+    /// Like `bulk_perft`, but with a transposition table of `table_size` entries, keyed on the zobrist hash.
+    ///
+    /// Because of the threefold repetition rule, the subtree below a position depends on its hash history.
+    /// Therefore, only positions directly after an irreversible move are stored in the table.
+    pub fn hashed_perft(&mut self, depth: u16, table_size: usize) -> u64 {
+        let mut table = vec![PerftEntry::default(); table_size.max(1).next_power_of_two()];
+        self.hashed_perft_with_table(depth, &mut table)
+    }
+
+    fn hashed_perft_with_table(&mut self, depth: u16, table: &mut [PerftEntry]) -> u64 {
+        if depth <= 1 {
+            return self.bulk_perft(depth);
+        }
+        let use_table = self.repeatable_hash_history().is_empty();
+        // Mix in the depth, so that the same position at different depths use different slots
+        let index = (self.hash ^ (depth as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)) as usize
+            & (table.len() - 1);
+        let entry = table[index];
+        if use_table && entry.hash == self.hash && entry.depth == depth {
+            return entry.num_moves;
+        }
+
+        let num_moves = if self.game_result().is_some() {
+            1
+        } else {
+            let mut moves = Vec::with_capacity(S * S * 4);
+            self.generate_moves(&mut moves);
+            moves
+                .into_iter()
+                .map(|mv| {
+                    let reverse_move = self.do_move(mv);
+                    let num_moves = self.hashed_perft_with_table(depth - 1, table);
+                    self.reverse_move(reverse_move);
+                    num_moves
+                })
+                .sum()
+        };
+        if use_table {
+            table[index] = PerftEntry {
+                hash: self.hash,
+                depth,
+                num_moves,
+            };
+        }
+        num_moves
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PerftEntry {
+    hash: u64,
+    depth: u16,
+    num_moves: u64,
 }
 
 impl<const S: usize> PositionTrait for Position<S> {
@@ -1262,7 +1588,6 @@ impl<const S: usize> PositionTrait for Position<S> {
                 }
 
                 self.hash ^= zobrist_top_stones::<S>(to, piece);
-                self.hash_history.clear(); // This move is irreversible, so previous position are never repeated from here
 
                 ReverseMove::Place(to)
             }
@@ -1509,13 +1834,6 @@ impl<const S: usize> pgn_traits::PgnPosition for Position<S> {
                 fen
             )));
         }
-        if fen_words.len() > 3 {
-            return Err(pgn_traits::Error::new_parse_error(format!(
-                "Couldn't parse TPS string \"{}\", unexpected \"{}\"",
-                fen, fen_words[3]
-            )));
-        }
-
         let fen_rows: Vec<&str> = fen_words[0].split('/').collect();
         if fen_rows.len() != S {
             return Err(pgn_traits::Error::new_parse_error(format!(
@@ -1582,6 +1900,48 @@ impl<const S: usize> pgn_traits::PgnPosition for Position<S> {
 
         position.hash = position.zobrist_hash_from_scratch();
 
+        // Optional fields of extended TPS
+        for word in &fen_words[3..] {
+            let Some((key, value)) = word.split_once('=') else {
+                return Err(pgn_traits::Error::new_parse_error(format!(
+                    "Couldn't parse TPS string \"{}\", unexpected \"{}\"",
+                    fen, word
+                )));
+            };
+            match key {
+                "komi" => {
+                    position.komi = value.parse().map_err(|err| {
+                        pgn_traits::Error::new_parse_error(format!(
+                            "Error parsing TPS \"{}\": Got bad komi \"{}\": {}",
+                            fen, value, err
+                        ))
+                    })?
+                }
+                "history" => {
+                    position.hash_history = value
+                        .split(',')
+                        .map(|hash| u64::from_str_radix(hash, 16))
+                        .collect::<Result<_, _>>()
+                        .map_err(|err| {
+                            pgn_traits::Error::new_caused_by(
+                                pgn_traits::ErrorKind::ParseError,
+                                format!(
+                                    "Error parsing TPS \"{}\": Got bad hash history \"{}\"",
+                                    fen, value
+                                ),
+                                err,
+                            )
+                        })?
+                }
+                _ => {
+                    return Err(pgn_traits::Error::new_parse_error(format!(
+                        "Couldn't parse TPS string \"{}\", unknown field \"{}\"",
+                        fen, key
+                    )))
+                }
+            }
+        }
+
         return Ok(position);
 
         fn parse_row<const S: usize>(row_str: &str) -> Result<[Stack; S], pgn_traits::Error> {
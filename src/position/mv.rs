@@ -22,8 +22,8 @@ pub enum ExpMove<const S: usize> {
 }
 
 /// A legal move for a position.
+/// With the `serde` feature, moves are serialized in PTN notation, like `3c3>12`.
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Move<const S: usize> {
     inner: u16,
 }
@@ -56,6 +56,11 @@ impl<const S: usize> Move<S> {
         }
     }
 
+    /// The move's compact 16-bit representation, used in binary training data
+    pub fn to_u16(self) -> u16 {
+        self.inner
+    }
+
     pub fn expand(self) -> ExpMove<S> {
         if self.inner >> 8 == 0 {
             unsafe {
@@ -77,6 +82,48 @@ impl<const S: usize> Move<S> {
         Square::from_u8(self.inner as u8 & 63)
     }
 
+    /// The same move on the board mirrored by `Position::flip_board_x`
+    pub fn flip_board_x(self) -> Self {
+        self.map_board(Square::flip_board_x, Direction::flip_board_x)
+    }
+
+    /// The same move on the board mirrored by `Position::flip_board_y`
+    pub fn flip_board_y(self) -> Self {
+        self.map_board(Square::flip_board_y, Direction::flip_board_y)
+    }
+
+    /// The same move on the board rotated by `Position::rotate_board`
+    pub fn rotate_board(self) -> Self {
+        self.map_board(Square::rotate_board, Direction::rotate_board)
+    }
+
+    /// The move in all 8 symmetries of the board, in the same order as `Position::symmetries`
+    pub fn symmetries(self) -> [Self; 8] {
+        [
+            self,
+            self.flip_board_x(),
+            self.flip_board_y(),
+            self.rotate_board(),
+            self.rotate_board().rotate_board(),
+            self.rotate_board().rotate_board().rotate_board(),
+            self.rotate_board().flip_board_x(),
+            self.rotate_board().flip_board_y(),
+        ]
+    }
+
+    fn map_board(
+        self,
+        map_square: impl Fn(Square<S>) -> Square<S>,
+        map_direction: impl Fn(Direction) -> Direction,
+    ) -> Self {
+        match self.expand() {
+            ExpMove::Place(role, square) => Self::placement(role, map_square(square)),
+            ExpMove::Move(square, direction, stack_movement) => {
+                Self::movement(map_square(square), map_direction(direction), stack_movement)
+            }
+        }
+    }
+
     pub fn from_string(input: &str) -> Result<Self, pgn_traits::Error> {
         // Trim crush notation
         let input = input.trim_end_matches('*');
@@ -171,56 +218,86 @@ impl<const S: usize> Move<S> {
         }
     }
 
+    /// Parse a move in Playtak's server notation, like `P A1 C` or `M A1 A3 1 1`.
+    /// Panics if the move is malformed.
     pub fn from_string_playtak(input: &str) -> Self {
+        Self::try_from_string_playtak(input).unwrap()
+    }
+
+    /// Parse a move in Playtak's server notation, like `P A1 C` or `M A1 A3 1 1`
+    pub fn try_from_string_playtak(input: &str) -> Result<Self, pgn_traits::Error> {
+        let parse_error = || {
+            pgn_traits::Error::new_parse_error(format!("Couldn't parse Playtak move \"{}\"", input))
+        };
         let words: Vec<&str> = input.split_whitespace().collect();
-        if words[0] == "P" {
-            let square = Square::parse_square(&words[1].to_lowercase()).unwrap();
-            let role = match words.get(2) {
-                Some(&"C") => Role::Cap,
-                Some(&"W") => Role::Wall,
-                None => Role::Flat,
-                Some(s) => panic!("Unknown role {} for move {}", s, input),
-            };
-            Self::placement(role, square)
-        } else if words[0] == "M" {
-            let start_square = Square::parse_square(&words[1].to_lowercase()).unwrap();
-            let end_square: Square<S> = Square::parse_square(&words[2].to_lowercase()).unwrap();
-            let pieces_dropped: Vec<u8> = words
-                .iter()
-                .skip(3)
-                .map(|s| u8::from_str(s).unwrap())
-                .collect();
-
-            let num_pieces_taken: u8 = pieces_dropped.iter().sum();
-
-            let mut pieces_held = num_pieces_taken;
-
-            let pieces_taken: StackMovement<S> = StackMovement::from_movements(
-                iter::once(num_pieces_taken)
-                    .chain(pieces_dropped.iter().take(pieces_dropped.len() - 1).map(
-                        |pieces_to_drop| {
-                            pieces_held -= pieces_to_drop;
-                            pieces_held
-                        },
-                    ))
-                    .chain(iter::once(0))
-                    .map(|pieces_to_take| Movement { pieces_to_take }),
-            );
-
-            let direction = match (
-                start_square.rank().cmp(&end_square.rank()),
-                start_square.file().cmp(&end_square.file()),
-            ) {
-                (Ordering::Equal, Ordering::Less) => Direction::East,
-                (Ordering::Equal, Ordering::Greater) => Direction::West,
-                (Ordering::Less, Ordering::Equal) => Direction::South,
-                (Ordering::Greater, Ordering::Equal) => Direction::North,
-                _ => panic!("Diagonal move string {}", input),
-            };
-
-            Self::movement(start_square, direction, pieces_taken)
-        } else {
-            unreachable!()
+        match words.first() {
+            Some(&"P") => {
+                let square =
+                    Square::parse_square(&words.get(1).ok_or_else(parse_error)?.to_lowercase())?;
+                let role = match words.get(2) {
+                    Some(&"C") => Role::Cap,
+                    Some(&"W") => Role::Wall,
+                    None => Role::Flat,
+                    Some(_) => return Err(parse_error()),
+                };
+                if words.len() > 3 {
+                    return Err(parse_error());
+                }
+                Ok(Self::placement(role, square))
+            }
+            Some(&"M") if words.len() >= 4 => {
+                let start_square = Square::parse_square(&words[1].to_lowercase())?;
+                let end_square: Square<S> = Square::parse_square(&words[2].to_lowercase())?;
+                let pieces_dropped: Vec<u8> = words
+                    .iter()
+                    .skip(3)
+                    .map(|s| u8::from_str(s).map_err(|_| parse_error()))
+                    .collect::<Result<_, _>>()?;
+
+                let num_pieces_taken: u8 = pieces_dropped.iter().sum();
+                if pieces_dropped.contains(&0) || num_pieces_taken as usize > S {
+                    return Err(parse_error());
+                }
+
+                let mut pieces_held = num_pieces_taken;
+
+                let pieces_taken: StackMovement<S> = StackMovement::from_movements(
+                    iter::once(num_pieces_taken)
+                        .chain(pieces_dropped.iter().take(pieces_dropped.len() - 1).map(
+                            |pieces_to_drop| {
+                                pieces_held -= pieces_to_drop;
+                                pieces_held
+                            },
+                        ))
+                        .chain(iter::once(0))
+                        .map(|pieces_to_take| Movement { pieces_to_take }),
+                );
+
+                let (direction, distance) = match (
+                    start_square.rank().cmp(&end_square.rank()),
+                    start_square.file().cmp(&end_square.file()),
+                ) {
+                    (Ordering::Equal, Ordering::Less) => {
+                        (Direction::East, end_square.file() - start_square.file())
+                    }
+                    (Ordering::Equal, Ordering::Greater) => {
+                        (Direction::West, start_square.file() - end_square.file())
+                    }
+                    (Ordering::Less, Ordering::Equal) => {
+                        (Direction::South, end_square.rank() - start_square.rank())
+                    }
+                    (Ordering::Greater, Ordering::Equal) => {
+                        (Direction::North, start_square.rank() - end_square.rank())
+                    }
+                    _ => return Err(parse_error()),
+                };
+                if distance as usize != pieces_dropped.len() {
+                    return Err(parse_error());
+                }
+
+                Ok(Self::movement(start_square, direction, pieces_taken))
+            }
+            _ => Err(parse_error()),
         }
     }
 
@@ -235,6 +312,21 @@ impl<const S: usize> fmt::Display for Move<S> {
     }
 }
 
+#[cfg(feature = "serde")]
+impl<const S: usize> Serialize for Move<S> {
+    fn serialize<Ser: serde::Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const S: usize> Deserialize<'de> for Move<S> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        Move::from_string(&input).map_err(serde::de::Error::custom)
+    }
+}
+
 impl<const S: usize> ExpMove<S> {
     pub fn origin_square(&self) -> Square<S> {
         Move::compress(self.clone()).origin_square()
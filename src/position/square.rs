@@ -7,8 +7,8 @@ use serde::{Deserialize, Serialize};
 use super::Direction::{self, *};
 
 /// A location on the board. Can be used to index a `Board`.
+/// With the `serde` feature, squares are serialized by name, like `c3`.
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Square<const S: usize> {
     inner: u8,
 }
@@ -51,6 +51,21 @@ impl<const S: usize> Square<S> {
         self.inner / S as u8
     }
 
+    /// The square's location after `Position::flip_board_x`
+    pub const fn flip_board_x(self) -> Self {
+        Square::from_rank_file(self.rank(), S as u8 - self.file() - 1)
+    }
+
+    /// The square's location after `Position::flip_board_y`
+    pub const fn flip_board_y(self) -> Self {
+        Square::from_rank_file(S as u8 - self.rank() - 1, self.file())
+    }
+
+    /// The square's location after `Position::rotate_board`
+    pub const fn rotate_board(self) -> Self {
+        Square::from_rank_file(self.file(), S as u8 - self.rank() - 1)
+    }
+
     pub fn downcast_size<const N: usize>(self) -> Square<N> {
         if S == N {
             unsafe { mem::transmute(self) }
@@ -163,6 +178,21 @@ impl<const S: usize> fmt::Display for Square<S> {
     }
 }
 
+#[cfg(feature = "serde")]
+impl<const S: usize> Serialize for Square<S> {
+    fn serialize<Ser: serde::Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const S: usize> Deserialize<'de> for Square<S> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input = String::deserialize(deserializer)?;
+        Square::parse_square(&input).map_err(serde::de::Error::custom)
+    }
+}
+
 /// Iterates over all board squares.
 pub fn squares_iterator<const S: usize>() -> impl Iterator<Item = Square<S>> {
     // Safety: `i` must be smaller than `S * S`, which is trivially true here
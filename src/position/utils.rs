@@ -16,7 +16,13 @@ use crate::position::Role::{Cap, Flat, Wall};
 
 use super::{GroupEdgeConnection, Square, SquareCacheEntry};
 
+/// With the `serde` feature, komi is serialized as a number, like `2.5`.
 #[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "f64", into = "f64")
+)]
 pub struct Komi {
     half_komi: i8,
 }
@@ -341,6 +347,31 @@ impl Direction {
         }
     }
 
+    pub(crate) fn flip_board_x(self) -> Direction {
+        match self {
+            West => East,
+            East => West,
+            North | South => self,
+        }
+    }
+
+    pub(crate) fn flip_board_y(self) -> Direction {
+        match self {
+            North => South,
+            South => North,
+            West | East => self,
+        }
+    }
+
+    pub(crate) fn rotate_board(self) -> Direction {
+        match self {
+            North => East,
+            East => South,
+            South => West,
+            West => North,
+        }
+    }
+
     pub(crate) fn orthogonal_directions(self) -> [Direction; 2] {
         match self {
             North | South => [West, East],
@@ -1,16 +1,87 @@
 use board_game_traits::{GameResult, Position};
 use pgn_traits::PgnPosition;
-use std::error;
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use std::str::FromStr;
+use std::{error, fmt};
 
+use crate::position::{Komi, Position as TakPosition, Settings};
+
+pub mod playtak;
 pub mod ptn_parser;
 pub mod ptn_writer;
 
 type ParseError = Box<dyn error::Error + Send + Sync>;
 
+/// Positions that can be set up from a game's PTN tags
+pub trait PtnPosition: PgnPosition {
+    /// Read the game settings from the tags,
+    /// and return an error if the tags describe a game that this position type cannot represent
+    fn settings_from_tags(tags: &[(String, String)]) -> Result<Self::Settings, ParseError>;
+}
+
+impl<const S: usize> PtnPosition for TakPosition<S> {
+    fn settings_from_tags(tags: &[(String, String)]) -> Result<Settings, ParseError> {
+        let mut settings = Settings::default();
+        for (tag, value) in tags {
+            if tag.eq_ignore_ascii_case("Size") {
+                if value.trim().parse::<usize>() != Ok(S) {
+                    return Err(format!("Size tag was \"{}\", expected {}", value, S).into());
+                }
+            } else if tag.eq_ignore_ascii_case("Komi") {
+                settings.komi = Komi::from_str(value.trim())
+                    .map_err(|err| format!("Bad komi \"{}\": {}", value, err))?;
+            } else if tag.eq_ignore_ascii_case("Opening") && !value.eq_ignore_ascii_case("swap") {
+                return Err(
+                    format!("Unsupported opening \"{}\", only swap is supported", value).into(),
+                );
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// An error from parsing a PTN file, with the location where it happened
+#[derive(Debug)]
+pub struct PtnError {
+    /// Index of the failing game in the file, starting at 0
+    pub game_index: usize,
+    /// Line number in the file, starting at 1
+    pub line: usize,
+    /// Column in the line, starting at 1
+    pub column: usize,
+    pub error: ParseError,
+}
+
+impl fmt::Display for PtnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Error in game #{} at line {}, column {}: {}",
+            self.game_index + 1,
+            self.line,
+            self.column,
+            self.error
+        )
+    }
+}
+
+impl error::Error for PtnError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize),
+    serde(bound(serialize = "B: Serialize, B::Move: Serialize"))
+)]
 pub struct Game<B: Position> {
     pub start_position: B,
     pub moves: Vec<PtnMove<B::Move>>,
+    #[cfg_attr(feature = "serde", serde(rename = "result"))]
     pub game_result_str: Option<&'static str>,
     pub tags: Vec<(String, String)>,
 }
@@ -27,9 +98,117 @@ impl<B: PgnPosition> Game<B> {
     }
 }
 
+/// A move in a game, along with any side lines that could have been played instead of it.
+/// Each variation starts from the position before this move, and may contain further variations.
 #[derive(Default, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct PtnMove<Move> {
     pub mv: Move,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
     pub annotations: Vec<&'static str>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "String::is_empty"))]
     pub comment: String,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub variations: Vec<Vec<PtnMove<Move>>>,
+}
+
+// `Game` and `PtnMove` are deserialized through these intermediate types,
+// because the game result and annotations are `&'static str`, and must be looked up
+
+/// Deserialized form of `Game`, before the game result is checked
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "B: DeserializeOwned, B::Move: DeserializeOwned"))]
+struct SerializedGame<B: Position> {
+    start_position: B,
+    moves: Vec<PtnMove<B::Move>>,
+    #[serde(default)]
+    result: Option<String>,
+    #[serde(default)]
+    tags: Vec<(String, String)>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, B: PgnPosition + DeserializeOwned> Deserialize<'de> for Game<B>
+where
+    B::Move: DeserializeOwned,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        SerializedGame::<B>::deserialize(deserializer)?
+            .try_into()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<B: PgnPosition> TryFrom<SerializedGame<B>> for Game<B> {
+    type Error = String;
+
+    fn try_from(game: SerializedGame<B>) -> Result<Self, Self::Error> {
+        let game_result_str = match game.result {
+            Some(result) => Some(
+                B::POSSIBLE_GAME_RESULTS
+                    .iter()
+                    .map(|(result_str, _)| *result_str)
+                    .find(|result_str| *result_str == result)
+                    .ok_or_else(|| format!("Unknown game result \"{}\"", result))?,
+            ),
+            None => None,
+        };
+        Ok(Game {
+            start_position: game.start_position,
+            moves: game.moves,
+            game_result_str,
+            tags: game.tags,
+        })
+    }
+}
+
+/// Deserialized form of `PtnMove`, before the annotations are checked
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "Move: DeserializeOwned"))]
+struct SerializedPtnMove<Move> {
+    mv: Move,
+    #[serde(default)]
+    annotations: Vec<String>,
+    #[serde(default)]
+    comment: String,
+    #[serde(default)]
+    variations: Vec<Vec<PtnMove<Move>>>,
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Move: DeserializeOwned> Deserialize<'de> for PtnMove<Move> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        SerializedPtnMove::<Move>::deserialize(deserializer)?
+            .try_into()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<Move> TryFrom<SerializedPtnMove<Move>> for PtnMove<Move> {
+    type Error = String;
+
+    fn try_from(ptn_move: SerializedPtnMove<Move>) -> Result<Self, Self::Error> {
+        // The possible annotations are the same for all board sizes
+        let annotations = ptn_move
+            .annotations
+            .iter()
+            .map(|annotation| {
+                <TakPosition<5>>::POSSIBLE_MOVE_ANNOTATIONS
+                    .iter()
+                    .copied()
+                    .find(|possible_annotation| possible_annotation == annotation)
+                    .ok_or_else(|| format!("Unknown move annotation \"{}\"", annotation))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(PtnMove {
+            mv: ptn_move.mv,
+            annotations,
+            comment: ptn_move.comment,
+            variations: ptn_move.variations,
+        })
+    }
 }
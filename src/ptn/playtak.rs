@@ -0,0 +1,226 @@
+//! Convert game records from Playtak's game database into PTN.
+//!
+//! Playtak stores each game as a row with the players, time control, komi and result,
+//! along with a comma-separated move list in the server's notation, like `P A1,P E5 C,M A1 A3 1 1`.
+//! As text, a record is the row's columns separated by `|`, in this order:
+//!
+//! `id|date|size|player_white|player_black|notation|result|timertime|timerinc|komi|pieces|capstones`
+//!
+//! This is the output format of `sqlite3` for those columns of the `games` table.
+//! To read the database directly, use the `sqlite` module.
+
+use std::str::FromStr;
+
+use board_game_traits::Position as PositionTrait;
+use pgn_traits::{Error, ErrorKind, PgnPosition};
+
+use crate::position::{starting_capstones, starting_stones, Komi, Move, Position};
+use crate::ptn::{Game, PtnMove};
+
+/// A single game from Playtak's database
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PlaytakGameRecord {
+    pub id: u64,
+    /// Start of the game, in milliseconds since the Unix epoch
+    pub date: i64,
+    pub size: usize,
+    pub player_white: String,
+    pub player_black: String,
+    /// Comma-separated moves in Playtak notation. Space-separated PTN moves are also accepted
+    pub notation: String,
+    /// Playtak's result string. The same as in PTN, except that `0-0` is an aborted game
+    pub result: String,
+    /// Starting time for each player, in seconds
+    pub timer_time: u64,
+    /// Increment, in seconds
+    pub timer_inc: u64,
+    /// Komi in half flats, like Playtak stores it
+    pub half_komi: i8,
+    /// Number of stones for each player. 0 or -1 for the default
+    pub pieces: i32,
+    /// Number of capstones for each player. 0 or -1 for the default
+    pub capstones: i32,
+}
+
+impl FromStr for PlaytakGameRecord {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('|').collect();
+        if fields.len() != 12 {
+            return Err(Error::new_parse_error(format!(
+                "Expected 12 fields in Playtak game record, found {}: \"{}\"",
+                fields.len(),
+                line
+            )));
+        }
+        Ok(PlaytakGameRecord {
+            id: parse_field(fields[0], "id")?,
+            date: parse_field(fields[1], "date")?,
+            size: parse_field(fields[2], "size")?,
+            player_white: fields[3].to_string(),
+            player_black: fields[4].to_string(),
+            notation: fields[5].to_string(),
+            result: fields[6].to_string(),
+            timer_time: parse_field(fields[7], "timertime")?,
+            timer_inc: parse_field(fields[8], "timerinc")?,
+            half_komi: parse_field(fields[9], "komi")?,
+            pieces: parse_field(fields[10], "pieces")?,
+            capstones: parse_field(fields[11], "capstones")?,
+        })
+    }
+}
+
+fn parse_field<T: FromStr>(field: &str, name: &str) -> Result<T, Error> {
+    // Null columns are written as empty fields
+    let field = if field.is_empty() { "0" } else { field };
+    field.parse().map_err(|_| {
+        Error::new_parse_error(format!(
+            "Couldn't parse {} \"{}\" in Playtak game",
+            name, field
+        ))
+    })
+}
+
+impl PlaytakGameRecord {
+    /// Convert the game, checking that all moves are legal. Returns an error if the game is not size `S`.
+    pub fn to_game<const S: usize>(&self) -> Result<Game<Position<S>>, Error> {
+        if self.size != S {
+            return Err(Error::new(
+                ErrorKind::IllegalPosition,
+                format!("Expected a {}s game, got size {}", S, self.size),
+            ));
+        }
+        if !matches!(self.pieces, -1 | 0) && self.pieces != starting_stones(S) as i32
+            || !matches!(self.capstones, -1 | 0) && self.capstones != starting_capstones(S) as i32
+        {
+            return Err(Error::new(
+                ErrorKind::IllegalPosition,
+                format!(
+                    "Custom piece counts of {} stones and {} capstones are not supported",
+                    self.pieces, self.capstones
+                ),
+            ));
+        }
+
+        let komi = Komi::from_half_komi(self.half_komi).ok_or_else(|| {
+            Error::new(
+                ErrorKind::IllegalPosition,
+                format!("Unsupported komi {}", self.half_komi as f64 / 2.0),
+            )
+        })?;
+        let start_position = <Position<S>>::start_position_with_komi(komi);
+
+        let mut position = start_position.clone();
+        let mut moves = vec![];
+        let mut legal_moves = vec![];
+        let is_playtak_notation = self.notation.contains(',')
+            || self.notation.starts_with("P ")
+            || self.notation.starts_with("M ");
+        let move_strings: Vec<&str> = if is_playtak_notation {
+            self.notation.split(',').map(str::trim).collect()
+        } else {
+            self.notation.split_whitespace().collect()
+        };
+        for move_string in move_strings {
+            if move_string.is_empty() {
+                continue;
+            }
+            let mv = if is_playtak_notation {
+                Move::try_from_string_playtak(move_string)?
+            } else {
+                Move::from_string(move_string)?
+            };
+            legal_moves.clear();
+            position.generate_moves(&mut legal_moves);
+            if position.game_result().is_some() || !legal_moves.contains(&mv) {
+                return Err(Error::new(
+                    ErrorKind::IllegalMove,
+                    format!(
+                        "Illegal move {} on move {}",
+                        move_string,
+                        position.half_moves_played() / 2 + 1
+                    ),
+                ));
+            }
+            position.do_move(mv);
+            moves.push(PtnMove {
+                mv,
+                annotations: vec![],
+                comment: String::new(),
+                variations: vec![],
+            });
+        }
+
+        let game_result_str = match self.result.as_str() {
+            "0-0" | "" => None,
+            result => Some(
+                <Position<S>>::POSSIBLE_GAME_RESULTS
+                    .iter()
+                    .map(|(result_str, _)| *result_str)
+                    .find(|result_str| *result_str == result)
+                    .ok_or_else(|| {
+                        Error::new_parse_error(format!("Unknown game result \"{}\"", result))
+                    })?,
+            ),
+        };
+
+        let date = chrono::NaiveDateTime::from_timestamp_millis(self.date).ok_or_else(|| {
+            Error::new_parse_error(format!("Bad date {} in Playtak game", self.date))
+        })?;
+
+        let tags = vec![
+            ("Site".to_string(), "playtak.com".to_string()),
+            ("Event".to_string(), "Online Play".to_string()),
+            ("Player1".to_string(), self.player_white.clone()),
+            ("Player2".to_string(), self.player_black.clone()),
+            ("Date".to_string(), date.format("%Y.%m.%d").to_string()),
+            ("Time".to_string(), date.format("%H:%M:%S").to_string()),
+            ("Size".to_string(), S.to_string()),
+            ("Komi".to_string(), komi.to_string()),
+            ("Flats".to_string(), starting_stones(S).to_string()),
+            ("Caps".to_string(), starting_capstones(S).to_string()),
+            (
+                "Clock".to_string(),
+                format!(
+                    "{}:{:02} +{}",
+                    self.timer_time / 60,
+                    self.timer_time % 60,
+                    self.timer_inc
+                ),
+            ),
+            (
+                "Result".to_string(),
+                game_result_str.unwrap_or("*").to_string(),
+            ),
+        ];
+
+        Ok(Game {
+            start_position,
+            moves,
+            game_result_str,
+            tags,
+        })
+    }
+
+    /// Convert the game, and write it as PTN
+    pub fn to_ptn(&self) -> Result<String, Error> {
+        let mut ptn = vec![];
+        match self.size {
+            3 => self.to_game::<3>()?.game_to_ptn(&mut ptn),
+            4 => self.to_game::<4>()?.game_to_ptn(&mut ptn),
+            5 => self.to_game::<5>()?.game_to_ptn(&mut ptn),
+            6 => self.to_game::<6>()?.game_to_ptn(&mut ptn),
+            7 => self.to_game::<7>()?.game_to_ptn(&mut ptn),
+            8 => self.to_game::<8>()?.game_to_ptn(&mut ptn),
+            size => {
+                return Err(Error::new(
+                    ErrorKind::IllegalPosition,
+                    format!("Unsupported size {}", size),
+                ))
+            }
+        }
+        .map_err(|err| Error::new(ErrorKind::IoError, err))?;
+        Ok(String::from_utf8(ptn).unwrap())
+    }
+}
@@ -1,37 +1,169 @@
-use crate::ptn::{Game, ParseError, PtnMove};
+use crate::ptn::{Game, ParseError, PtnError, PtnMove, PtnPosition};
 use pgn_traits::PgnPosition;
+use std::collections::VecDeque;
+use std::io::BufRead;
 use std::str::FromStr;
 
-pub fn parse_ptn<B: PgnPosition>(input: &str) -> Result<Vec<Game<B>>, ParseError> {
-    let mut parser = ParserData { input };
+/// Parse all games in the input, failing on the first bad game
+pub fn parse_ptn<B: PtnPosition>(input: &str) -> Result<Vec<Game<B>>, PtnError> {
+    let mut parser = ParserData::new(input);
     let mut games = vec![];
-    loop {
-        if parser.input.chars().any(|ch| !ch.is_whitespace()) {
-            match parse_game(&mut parser) {
-                Ok(game) => games.push(game),
+    while parser.has_more_games() {
+        games.push(parse_game_with_location(&mut parser, games.len())?);
+    }
+    Ok(games)
+}
+
+/// Parse all games in the input, skipping any games that fail to parse.
+/// Returns the successfully parsed games, and an error for each skipped game.
+pub fn parse_ptn_skipping_errors<B: PtnPosition>(input: &str) -> (Vec<Game<B>>, Vec<PtnError>) {
+    let mut parser = ParserData::new(input);
+    let mut games = vec![];
+    let mut errors = vec![];
+    let mut game_index = 0;
+    while parser.has_more_games() {
+        match parse_game_with_location(&mut parser, game_index) {
+            Ok(game) => games.push(game),
+            Err(err) => {
+                errors.push(err);
+                parser.skip_to_next_game();
+            }
+        }
+        game_index += 1;
+    }
+    (games, errors)
+}
+
+/// Reads games one at a time from a PTN file or stream, without reading the whole input into memory.
+///
+/// The input is split into games at lines starting with a tag, after at least one line of moves.
+/// A bad game yields an error, and reading continues with the next game.
+pub struct PtnReader<R, B: PtnPosition> {
+    reader: R,
+    /// Lines of the next game, which have already been read
+    next_lines: String,
+    /// Line number of the first line in `next_lines`, starting at 1
+    next_line_number: usize,
+    game_index: usize,
+    /// Games that were found on the same lines as the previous game
+    parsed_games: VecDeque<Result<Game<B>, PtnError>>,
+    finished: bool,
+}
+
+impl<R: BufRead, B: PtnPosition> PtnReader<R, B> {
+    pub fn new(reader: R) -> Self {
+        PtnReader {
+            reader,
+            next_lines: String::new(),
+            next_line_number: 1,
+            game_index: 0,
+            parsed_games: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// Read the lines of the next game. Returns the game's text and its first line number
+    fn read_game_lines(&mut self) -> Result<(String, usize), std::io::Error> {
+        let mut lines = std::mem::take(&mut self.next_lines);
+        let first_line_number = self.next_line_number;
+        let mut num_lines = lines.lines().count();
+        let mut seen_move_text = lines
+            .lines()
+            .any(|line| !line.trim().is_empty() && !line.trim_start().starts_with('['));
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                self.finished = true;
+                break;
+            }
+            let trimmed_line = line.trim_start();
+            if trimmed_line.starts_with('[') {
+                if seen_move_text {
+                    self.next_lines = line.clone();
+                    self.next_line_number = first_line_number + num_lines;
+                    break;
+                }
+            } else if !trimmed_line.is_empty() {
+                seen_move_text = true;
+            }
+            lines.push_str(&line);
+            num_lines += 1;
+        }
+        Ok((lines, first_line_number))
+    }
+}
+
+impl<R: BufRead, B: PtnPosition> Iterator for PtnReader<R, B> {
+    type Item = Result<Game<B>, PtnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.parsed_games.is_empty() {
+            if self.finished && self.next_lines.is_empty() {
+                return None;
+            }
+            let (lines, first_line_number) = match self.read_game_lines() {
+                Ok(lines) => lines,
                 Err(err) => {
-                    eprintln!("Couldn't parse game: {}", err);
-                    if games.is_empty() {
-                        return Err(err);
-                    } else {
-                        return Ok(games);
-                    }
+                    self.finished = true;
+                    self.next_lines.clear();
+                    return Some(Err(PtnError {
+                        game_index: self.game_index,
+                        line: self.next_line_number,
+                        column: 1,
+                        error: Box::new(err),
+                    }));
+                }
+            };
+
+            let mut parser = ParserData::new(&lines);
+            while parser.has_more_games() {
+                let result =
+                    parse_game_with_location(&mut parser, self.game_index).map_err(|mut err| {
+                        err.line += first_line_number - 1;
+                        err
+                    });
+                self.game_index += 1;
+                let is_err = result.is_err();
+                self.parsed_games.push_back(result);
+                if is_err {
+                    parser.skip_to_next_game();
                 }
             }
-        } else {
-            return Ok(games);
         }
+        self.parsed_games.pop_front()
     }
 }
 
-fn parse_game<B: PgnPosition>(input: &mut ParserData) -> Result<Game<B>, ParseError> {
+fn parse_game_with_location<B: PtnPosition>(
+    parser: &mut ParserData,
+    game_index: usize,
+) -> Result<Game<B>, PtnError> {
+    parse_game(parser).map_err(|error| {
+        let (line, column) = parser.token_location();
+        PtnError {
+            game_index,
+            line,
+            column,
+            error,
+        }
+    })
+}
+
+fn parse_game<B: PtnPosition>(input: &mut ParserData) -> Result<Game<B>, ParseError> {
     let mut tags = vec![];
-    input.skip_whitespaces();
+    input.in_move_text = false;
+    input.skip_whitespaces_and_comments()?;
     while input.peek() == Some('[') {
+        input.start_token();
         let (tag, value) = parse_tag(input)?;
-        input.skip_whitespaces();
+        input.skip_whitespaces_and_comments()?;
         tags.push((tag.to_string(), value));
     }
+    input.in_move_text = true;
+    input.start_token();
+    let settings = B::settings_from_tags(&tags)?;
 
     // Thunk to get the game's start position
     // It can't be a regular variable, because there is no `B: Clone` bound
@@ -41,12 +173,12 @@ fn parse_game<B: PgnPosition>(input: &mut ParserData) -> Result<Game<B>, ParseEr
                 .iter()
                 .find(|(name, _)| name.eq_ignore_ascii_case(fen_tag))
             {
-                B::from_fen(tps)
+                B::from_fen_with_settings(tps, &settings)
             } else {
-                Ok(B::start_position())
+                Ok(B::start_position_with_settings(&settings))
             }
         } else {
-            Ok(B::start_position())
+            Ok(B::start_position_with_settings(&settings))
         }
     };
 
@@ -108,12 +240,30 @@ fn parse_tag<'a>(input: &mut ParserData<'a>) -> Result<(&'a str, String), pgn_tr
 fn parse_moves<B: PgnPosition>(
     input: &mut ParserData,
     mut position: B,
+) -> Result<(Vec<PtnMove<B::Move>>, Option<&'static str>), ParseError> {
+    parse_line(input, &mut position, false)
+}
+
+/// Parse the main line of the game, or a variation inside parentheses.
+/// A variation is parsed up to and including its closing ')', and `position` is restored afterwards.
+#[allow(clippy::type_complexity)]
+fn parse_line<B: PgnPosition>(
+    input: &mut ParserData,
+    position: &mut B,
+    is_variation: bool,
 ) -> Result<(Vec<PtnMove<B::Move>>, Option<&'static str>), ParseError> {
     let mut moves: Vec<PtnMove<B::Move>> = vec![];
+    let mut reverse_moves: Vec<B::ReverseMove> = vec![];
     let mut _ply_counter = 0; // Last ply seen
     loop {
-        input.skip_whitespaces();
+        input.skip_whitespaces_and_comments()?;
+        input.start_token();
         if input.peek().is_none() || input.peek() == Some('[') {
+            if is_variation {
+                return Err(Box::new(pgn_traits::Error::new_parse_error(
+                    "Unexpected EOF, expected ')' to close variation.".to_string(),
+                )));
+            }
             // Games without a result aren't allowed by the spec,
             // but try to accept it anyway and return a `None` result
             if !moves.is_empty() {
@@ -124,6 +274,35 @@ fn parse_moves<B: PgnPosition>(
                 "Unexpected EOF, expected a move or a game result.".to_string(),
             )));
         }
+        match input.peek() {
+            Some(')') if is_variation => {
+                input.take();
+                for reverse_move in reverse_moves.into_iter().rev() {
+                    position.reverse_move(reverse_move);
+                }
+                return Ok((moves, None));
+            }
+            Some(')') => {
+                return Err(Box::new(pgn_traits::Error::new_parse_error(
+                    "Unexpected ')' outside of a variation".to_string(),
+                )));
+            }
+            Some('(') => {
+                input.take();
+                // The variation is an alternative to the last move, so play it from the position before that move
+                let Some(last_move) = moves.last_mut() else {
+                    return Err(Box::new(pgn_traits::Error::new_parse_error(
+                        "Found variation before the first move".to_string(),
+                    )));
+                };
+                position.reverse_move(reverse_moves.pop().unwrap());
+                let (variation, _) = parse_line(input, position, true)?;
+                last_move.variations.push(variation);
+                reverse_moves.push(position.do_move(last_move.mv.clone()));
+                continue;
+            }
+            _ => (),
+        }
         let word = input.take_word();
 
         assert!(!word.is_empty());
@@ -138,7 +317,10 @@ fn parse_moves<B: PgnPosition>(
             .iter()
             .find(|(s, _result)| *s == word)
         {
-            return Ok((moves, Some(*result_str)));
+            // A variation may end in a result, but only the main line's result is kept
+            if !is_variation {
+                return Ok((moves, Some(*result_str)));
+            }
         } else {
             let mut move_string = word;
             let mut annotations = vec![];
@@ -158,24 +340,23 @@ fn parse_moves<B: PgnPosition>(
                             word,
                         )));
                     }
-                    position.do_move(mv.clone());
+                    reverse_moves.push(position.do_move(mv.clone()));
+                    // Comments directly after a move belong to that move
+                    let mut comment = String::new();
                     input.skip_whitespaces();
-                    if input.peek() == Some('{') {
-                        input.take();
-                        let comment = input.take_while(|ch| ch != '}');
-                        input.take();
-                        moves.push(PtnMove {
-                            mv,
-                            annotations,
-                            comment: comment.to_string(),
-                        })
-                    } else {
-                        moves.push(PtnMove {
-                            mv,
-                            annotations,
-                            comment: String::new(),
-                        });
+                    while input.peek() == Some('{') {
+                        if !comment.is_empty() {
+                            comment.push(' ');
+                        }
+                        comment.push_str(input.take_comment()?);
+                        input.skip_whitespaces();
                     }
+                    moves.push(PtnMove {
+                        mv,
+                        annotations,
+                        comment,
+                        variations: vec![],
+                    });
                 }
                 Err(err) => {
                     return Err(Box::new(pgn_traits::Error::new_parse_error(format!(
@@ -190,16 +371,94 @@ fn parse_moves<B: PgnPosition>(
 
 struct ParserData<'a> {
     input: &'a str,
+    full_input: &'a str,
+    /// Byte offset of the token currently being parsed, used for error locations
+    token_start: usize,
+    /// Whether the current game's tags have all been read
+    in_move_text: bool,
 }
 
 impl<'a> ParserData<'a> {
+    fn new(input: &'a str) -> Self {
+        ParserData {
+            input,
+            full_input: input,
+            token_start: 0,
+            in_move_text: false,
+        }
+    }
+
+    fn has_more_games(&mut self) -> bool {
+        self.skip_whitespaces();
+        self.peek().is_some()
+    }
+
+    fn offset(&self) -> usize {
+        self.full_input.len() - self.input.len()
+    }
+
+    fn start_token(&mut self) {
+        self.token_start = self.offset();
+    }
+
+    /// Line and column of the current token, both starting at 1
+    fn token_location(&self) -> (usize, usize) {
+        let before_token = &self.full_input[0..self.token_start];
+        let line = before_token.matches('\n').count() + 1;
+        let line_start = before_token.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let column = before_token[line_start..].chars().count() + 1;
+        (line, column)
+    }
+
+    /// After a failed game, skip ahead to the first tag that follows a line of moves
+    fn skip_to_next_game(&mut self) {
+        // If the error happened in the tags, skip the rest of them first
+        let mut seen_move_text = self.in_move_text;
+        loop {
+            let line = self.take_while(|ch| ch != '\n');
+            let trimmed_line = line.trim();
+            if !trimmed_line.is_empty() && !trimmed_line.starts_with('[') {
+                seen_move_text = true;
+            }
+            if self.take().is_none() {
+                return;
+            }
+            if seen_move_text && self.input.trim_start().starts_with('[') {
+                return;
+            }
+        }
+    }
+
     fn skip_whitespaces(&mut self) {
         self.input = self.input.trim_start_matches(char::is_whitespace);
     }
 
+    fn skip_whitespaces_and_comments(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_whitespaces();
+            if self.peek() != Some('{') {
+                return Ok(());
+            }
+            self.start_token();
+            self.take_comment()?;
+        }
+    }
+
+    /// Take a comment in braces, and return its contents
+    fn take_comment(&mut self) -> Result<&'a str, ParseError> {
+        assert_eq!(self.take(), Some('{'));
+        let comment = self.take_while(|ch| ch != '}');
+        if self.take() == Some('}') {
+            Ok(comment)
+        } else {
+            Err("Unexpected EOF, expected '}' to close comment".into())
+        }
+    }
+
+    /// Take a word, which ends at a whitespace or at the start or end of a variation
     fn take_word(&mut self) -> &'a str {
         self.skip_whitespaces();
-        self.take_while(|ch| !ch.is_whitespace())
+        self.take_while(|ch| !ch.is_whitespace() && ch != '(' && ch != ')')
     }
 
     fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> &'a str {
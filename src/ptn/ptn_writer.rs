@@ -40,8 +40,9 @@ impl<B: PgnPosition + Clone> Game<B> {
         }
 
         // Write TPS tag, if starting position is non-standard
+        // Compare the TPS strings, because settings such as komi are written in their own tags
         if let Some(fen_tag) = B::START_POSITION_TAG_NAME {
-            if self.start_position != B::start_position()
+            if self.start_position.to_fen() != B::start_position().to_fen()
                 && !B::REQUIRED_TAGS
                     .iter()
                     .any(|(tag, _)| tag.eq_ignore_ascii_case(fen_tag))
@@ -56,77 +57,118 @@ impl<B: PgnPosition + Clone> Game<B> {
         writeln!(f)?;
 
         let mut position = self.start_position.clone();
-        let mut column_position = 0;
-        let mut buffer = String::new();
-
         let start_move_number = position.full_move_number().unwrap_or(1) as usize;
+        let first_ply = match position.side_to_move() {
+            Color::White => start_move_number * 2 - 2,
+            Color::Black => start_move_number * 2 - 1,
+        };
+
+        let mut chunks = vec![];
+        line_to_chunks(&mut position, &self.moves, first_ply, &mut chunks);
+
+        let game_result = self.game_result_str.unwrap_or("*");
+        match chunks.last_mut() {
+            Some(chunk) => {
+                chunk.push(' ');
+                chunk.push_str(game_result);
+            }
+            None => chunks.push(game_result.to_string()),
+        }
 
-        for (
-            i,
-            PtnMove {
-                mv,
-                comment,
-                annotations,
-            },
-        ) in self.moves.iter().enumerate()
-        {
-            let move_string = position.move_to_san(mv);
-            if i == 0 && position.side_to_move() == Color::Black {
-                write!(buffer, "{}... {}", start_move_number, move_string).unwrap();
-            } else if position.side_to_move() == Color::White {
-                write!(
-                    buffer,
-                    "{}. {}",
-                    (i + 1) / 2 + start_move_number,
-                    move_string
-                )
-                .unwrap();
+        let mut column_position = 0;
+        for chunk in chunks {
+            if column_position == 0 {
+                write!(f, "{}", chunk)?;
+                column_position = chunk.len();
+            } else if column_position + chunk.len() < LINE_WIDTH {
+                write!(f, " {}", chunk)?;
+                column_position += chunk.len() + 1;
             } else {
-                buffer.push_str(&move_string);
+                write!(f, "\n{}", chunk)?;
+                column_position = chunk.len();
             }
+        }
 
-            for annotation in annotations {
-                buffer.push_str(annotation);
-            }
+        writeln!(f)?;
+        writeln!(f)?;
+        Ok(())
+    }
+}
 
-            if !comment.is_empty() {
-                buffer.push_str(" {");
-                buffer.push_str(comment);
-                buffer.push('}');
-            }
+/// Write a line of moves as chunks of text that should not be split across lines, usually one full move each.
+/// Variations are written in parentheses after the move they are alternatives to.
+fn line_to_chunks<B: PgnPosition + Clone>(
+    position: &mut B,
+    moves: &[PtnMove<B::Move>],
+    first_ply: usize,
+    chunks: &mut Vec<String>,
+) {
+    let mut buffer = String::new();
+    let mut reverse_moves = vec![];
+    // Black's moves need a move number at the start of a line, or after a variation
+    let mut needs_move_number = true;
+
+    for (
+        i,
+        PtnMove {
+            mv,
+            annotations,
+            comment,
+            variations,
+        },
+    ) in moves.iter().enumerate()
+    {
+        let ply = first_ply + i;
+        let move_string = position.move_to_san(mv);
+        if position.side_to_move() == Color::White {
+            write!(buffer, "{}. {}", ply / 2 + 1, move_string).unwrap();
+        } else if needs_move_number {
+            write!(buffer, "{}... {}", ply / 2 + 1, move_string).unwrap();
+        } else {
+            buffer.push_str(&move_string);
+        }
+        needs_move_number = false;
 
-            if i == self.moves.len() - 1 {
-                buffer.push(' ');
-                buffer.push_str(self.game_result_str.unwrap_or("*"));
-            }
+        for annotation in annotations {
+            buffer.push_str(annotation);
+        }
 
-            if position.side_to_move() == Color::Black || i == self.moves.len() - 1 {
-                if column_position == 0 {
-                    write!(f, "{}", buffer)?;
-                    column_position = buffer.len();
-                } else if column_position + buffer.len() < LINE_WIDTH {
-                    write!(f, " {}", buffer)?;
-                    column_position += buffer.len() + 1;
+        if !comment.is_empty() {
+            buffer.push_str(" {");
+            buffer.push_str(comment);
+            buffer.push('}');
+        }
+
+        if !variations.is_empty() {
+            chunks.push(buffer.clone());
+            buffer.clear();
+            for variation in variations {
+                let mut variation_chunks = vec![];
+                line_to_chunks(position, variation, ply, &mut variation_chunks);
+                if variation_chunks.is_empty() {
+                    chunks.push("()".to_string());
                 } else {
-                    write!(f, "\n{}", buffer)?;
-                    column_position = buffer.len();
+                    variation_chunks.first_mut().unwrap().insert(0, '(');
+                    variation_chunks.last_mut().unwrap().push(')');
+                    chunks.extend(variation_chunks);
                 }
-                buffer.clear();
-            } else {
-                buffer.push(' ');
             }
-
-            position.do_move(mv.clone());
+            needs_move_number = true;
+        } else if position.side_to_move() == Color::Black {
+            chunks.push(buffer.clone());
+            buffer.clear();
+        } else {
+            buffer.push(' ');
         }
 
-        assert!(
-            buffer.is_empty(),
-            "\"{}\" was not written to the ptn",
-            buffer
-        );
+        reverse_moves.push(position.do_move(mv.clone()));
+    }
 
-        writeln!(f)?;
-        writeln!(f)?;
-        Ok(())
+    if !buffer.is_empty() {
+        chunks.push(buffer.trim_end().to_string());
+    }
+
+    for reverse_move in reverse_moves.into_iter().rev() {
+        position.reverse_move(reverse_move);
     }
 }
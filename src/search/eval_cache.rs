@@ -0,0 +1,147 @@
+use half::f16;
+
+use crate::evaluation::{policy_eval, value_eval};
+use crate::position::Position;
+
+/// A fixed-size, direct-mapped cache of static evaluations and policy priors.
+///
+/// The search has no transposition handling, so the same position is evaluated again every time it shows up in a different branch.
+/// The cache stores the static evaluation from white's perspective, and the normalized prior of every legal move, in move generation order.
+/// They are stored separately, under different keys, because they depend on different parts of the move history, see `value_key` and `priors_key`.
+/// Positions with more than `max_priors::<S>()` legal moves only have their evaluation cached.
+#[derive(Clone, Debug)]
+pub struct EvalCache<const S: usize> {
+    values: Box<[Option<(u64, f32)>]>,
+    /// The key and the number of priors of each entry. The priors themselves are in `priors`
+    prior_entries: Box<[Option<(u64, u16)>]>,
+    priors: Box<[f16]>,
+    hits: u64,
+    lookups: u64,
+}
+
+/// The maximum number of move priors stored for a single position
+pub const fn max_priors<const S: usize>() -> usize {
+    8 * S * S
+}
+
+impl<const S: usize> EvalCache<S> {
+    /// Create a cache with room for `num_entries` positions, rounded up to the nearest power of two.
+    pub fn new(num_entries: usize) -> Self {
+        let num_entries = num_entries.max(1).next_power_of_two();
+        EvalCache {
+            values: vec![None; num_entries].into_boxed_slice(),
+            prior_entries: vec![None; num_entries].into_boxed_slice(),
+            priors: vec![f16::ZERO; num_entries * max_priors::<S>()].into_boxed_slice(),
+            hits: 0,
+            lookups: 0,
+        }
+    }
+
+    /// Memory used by each entry, in bytes
+    pub const fn entry_mem_usage() -> usize {
+        std::mem::size_of::<Option<(u64, f32)>>()
+            + std::mem::size_of::<Option<(u64, u16)>>()
+            + max_priors::<S>() * std::mem::size_of::<f16>()
+    }
+
+    /// The key for the position's static evaluation.
+    /// Apart from the stones on the board and the side to move, the evaluation only depends on
+    /// how far the game has progressed, until the game phase scale factors stop changing.
+    pub fn value_key(position: &Position<S>) -> u64 {
+        let ply = position
+            .half_moves_played()
+            .min(value_eval::MAX_SCALE_FACTOR_PLY) as u64;
+        position.zobrist_hash() ^ (ply + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+
+    /// The key for the position's policy priors.
+    /// Apart from the stones on the board and the side to move, the policy only depends on
+    /// the last move of each side, see `policy_eval::last_moves_key`.
+    pub fn priors_key(position: &Position<S>) -> u64 {
+        position.zobrist_hash()
+            ^ (policy_eval::last_moves_key(position) + 1).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+    }
+
+    /// Returns the cached static evaluation, from white's perspective
+    pub fn get_value(&mut self, key: u64) -> Option<f32> {
+        let index = self.index(key);
+        let value = self.values[index]
+            .filter(|(entry_key, _)| *entry_key == key)
+            .map(|(_, value)| value);
+        self.count_lookup(value.is_some());
+        value
+    }
+
+    pub fn insert_value(&mut self, key: u64, value: f32) {
+        let index = self.index(key);
+        self.values[index] = Some((key, value));
+    }
+
+    /// Returns the cached priors, if the cache has priors for exactly `num_moves` moves.
+    pub fn get_priors(&mut self, key: u64, num_moves: usize) -> Option<&[f16]> {
+        let index = self.index(key);
+        let hit = self.prior_entries[index] == Some((key, num_moves as u16));
+        self.count_lookup(hit);
+        if hit {
+            Some(&self.priors[index * max_priors::<S>()..][..num_moves])
+        } else {
+            None
+        }
+    }
+
+    /// Cache the priors for every legal move, in move generation order
+    pub fn insert_priors(&mut self, key: u64, priors: impl ExactSizeIterator<Item = f16>) {
+        let num_moves = priors.len();
+        if num_moves > max_priors::<S>() {
+            return;
+        }
+        let index = self.index(key);
+        self.prior_entries[index] = Some((key, num_moves as u16));
+        for (entry_prior, prior) in self.priors[index * max_priors::<S>()..]
+            .iter_mut()
+            .zip(priors)
+        {
+            *entry_prior = prior;
+        }
+    }
+
+    fn count_lookup(&mut self, hit: bool) {
+        self.lookups += 1;
+        if hit {
+            self.hits += 1;
+        }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        key as usize & (self.values.len() - 1)
+    }
+
+    pub fn clear(&mut self) {
+        self.values.fill(None);
+        self.prior_entries.fill(None);
+        self.hits = 0;
+        self.lookups = 0;
+    }
+
+    /// The maximum number of positions in the cache
+    pub fn capacity(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn lookups(&self) -> u64 {
+        self.lookups
+    }
+
+    /// The fraction of lookups that were served from the cache
+    pub fn hit_rate(&self) -> f64 {
+        if self.lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.lookups as f64
+        }
+    }
+}
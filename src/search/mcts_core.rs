@@ -9,7 +9,7 @@ use crate::evaluation::parameters::{self, PolicyFeatures};
 use crate::position::Move;
 /// This module contains the core of the MCTS search algorithm
 use crate::position::{GroupData, Position};
-use crate::search::{cp_to_win_percentage, MctsSetting, Score};
+use crate::search::{cp_to_win_percentage, EvalCache, MctsSetting, Score};
 
 use super::{arena, Arena};
 
@@ -38,6 +38,9 @@ pub struct TempVectors<const S: usize> {
     value_scores: Vec<f16>,
     policy_score_sets: Vec<Box<[f16]>>,
     policy_feature_sets: Option<Vec<PolicyFeatures<'static>>>,
+    eval_cache: Option<EvalCache<S>>,
+    #[cfg(feature = "mlp")]
+    mlp_activations: Vec<f32>,
 }
 
 impl<const S: usize> Default for TempVectors<S> {
@@ -49,10 +52,27 @@ impl<const S: usize> Default for TempVectors<S> {
             value_scores: vec![f16::ZERO; parameters::num_value_features::<S>()],
             policy_score_sets: vec![],
             policy_feature_sets: Some(vec![]),
+            eval_cache: None,
+            #[cfg(feature = "mlp")]
+            mlp_activations: vec![],
         }
     }
 }
 
+impl<const S: usize> TempVectors<S> {
+    /// Create temporary vectors, with an evaluation cache if `eval_cache_size` is set
+    pub fn new(eval_cache_size: Option<usize>) -> Self {
+        TempVectors {
+            eval_cache: eval_cache_size.map(EvalCache::new),
+            ..Default::default()
+        }
+    }
+
+    pub fn eval_cache(&self) -> Option<&EvalCache<S>> {
+        self.eval_cache.as_ref()
+    }
+}
+
 impl<const S: usize> TreeEdge<S> {
     pub fn new(mv: Move<S>, heuristic_score: f16, mean_action_value: Score) -> Self {
         TreeEdge {
@@ -211,18 +231,7 @@ impl<const S: usize> Tree<S> {
         temp_vectors: &mut TempVectors<S>,
         arena: &Arena,
     ) -> Option<()> {
-        position.generate_moves_with_params(
-            match settings.policy_params.as_ref() {
-                Some(params) => params,
-                None => <Position<S>>::policy_params(position.komi()),
-            },
-            group_data,
-            &mut temp_vectors.simple_moves,
-            &mut temp_vectors.moves,
-            &mut temp_vectors.fcd_per_move,
-            &mut temp_vectors.policy_score_sets,
-            &mut temp_vectors.policy_feature_sets,
-        );
+        generate_moves_with_policy(position, group_data, settings, temp_vectors);
         // let mut children_vec = arena.add_from_iter(length, source) Vec::with_capacity(temp_vectors.moves.len());
         let policy_sum: f32 = temp_vectors
             .moves
@@ -297,31 +306,14 @@ pub fn rollout<const S: usize>(
 
         (game_result_for_us.score(), true)
     } else if depth == 0 {
-        let static_eval = cp_to_win_percentage(position.static_eval_with_params_and_data(
-            &group_data,
-            match settings.value_params.as_ref() {
-                Some(params) => params,
-                None => <Position<S>>::value_params(position.komi()),
-            },
-            &mut temp_vectors.value_scores,
-        ));
+        let static_eval =
+            cp_to_win_percentage(static_eval(position, &group_data, settings, temp_vectors));
         match position.side_to_move() {
             Color::White => (static_eval, false),
             Color::Black => (1.0 - static_eval, false),
         }
     } else {
-        position.generate_moves_with_probabilities(
-            &group_data,
-            &mut temp_vectors.simple_moves,
-            &mut temp_vectors.moves,
-            &mut temp_vectors.fcd_per_move,
-            &mut temp_vectors.policy_score_sets,
-            match settings.policy_params.as_ref() {
-                Some(params) => params,
-                None => <Position<S>>::policy_params(position.komi()),
-            },
-            &mut temp_vectors.policy_feature_sets,
-        );
+        generate_moves_with_policy(position, &group_data, settings, temp_vectors);
 
         let mut rng = rand::thread_rng();
 
@@ -334,6 +326,122 @@ pub fn rollout<const S: usize>(
     }
 }
 
+/// Static evaluation of the position from white's perspective,
+/// from the evaluation cache if possible
+fn static_eval<const S: usize>(
+    position: &Position<S>,
+    group_data: &GroupData<S>,
+    settings: &MctsSetting<S>,
+    temp_vectors: &mut TempVectors<S>,
+) -> f32 {
+    let Some(eval_cache) = temp_vectors.eval_cache.as_mut() else {
+        return static_eval_uncached(position, group_data, settings, temp_vectors);
+    };
+    let key = EvalCache::value_key(position);
+    if let Some(eval) = eval_cache.get_value(key) {
+        return eval;
+    }
+    let eval = static_eval_uncached(position, group_data, settings, temp_vectors);
+    if let Some(eval_cache) = temp_vectors.eval_cache.as_mut() {
+        eval_cache.insert_value(key, eval);
+    }
+    eval
+}
+
+/// Static evaluation of the position from white's perspective,
+/// with the value network or the value parameters from the settings
+fn static_eval_uncached<const S: usize>(
+    position: &Position<S>,
+    group_data: &GroupData<S>,
+    settings: &MctsSetting<S>,
+    temp_vectors: &mut TempVectors<S>,
+) -> f32 {
+    #[cfg(feature = "mlp")]
+    if let Some(mlp) = settings.value_mlp.as_ref() {
+        return position.static_eval_with_mlp(
+            group_data,
+            mlp,
+            &mut temp_vectors.value_scores,
+            &mut temp_vectors.mlp_activations,
+        );
+    }
+    position.static_eval_with_params_and_data(
+        group_data,
+        match settings.value_params.as_ref() {
+            Some(params) => params,
+            None => <Position<S>>::value_params(position.komi()),
+        },
+        &mut temp_vectors.value_scores,
+    )
+}
+
+/// Generate moves with their probabilities into `temp_vectors.moves`,
+/// from the evaluation cache if possible
+fn generate_moves_with_policy<const S: usize>(
+    position: &Position<S>,
+    group_data: &GroupData<S>,
+    settings: &MctsSetting<S>,
+    temp_vectors: &mut TempVectors<S>,
+) {
+    debug_assert!(temp_vectors.moves.is_empty());
+    let Some(eval_cache) = temp_vectors.eval_cache.as_mut() else {
+        return generate_moves_with_policy_uncached(position, group_data, settings, temp_vectors);
+    };
+    let key = EvalCache::priors_key(position);
+    position.generate_moves(&mut temp_vectors.simple_moves);
+    if let Some(priors) = eval_cache.get_priors(key, temp_vectors.simple_moves.len()) {
+        temp_vectors.moves.extend(
+            temp_vectors
+                .simple_moves
+                .drain(..)
+                .zip(priors.iter().copied()),
+        );
+        return;
+    }
+    temp_vectors.simple_moves.clear();
+
+    generate_moves_with_policy_uncached(position, group_data, settings, temp_vectors);
+    if let Some(eval_cache) = temp_vectors.eval_cache.as_mut() {
+        eval_cache.insert_priors(key, temp_vectors.moves.iter().map(|(_, prior)| *prior));
+    }
+}
+
+/// Generate moves with their probabilities into `temp_vectors.moves`,
+/// with the policy network or the policy parameters from the settings
+fn generate_moves_with_policy_uncached<const S: usize>(
+    position: &Position<S>,
+    group_data: &GroupData<S>,
+    settings: &MctsSetting<S>,
+    temp_vectors: &mut TempVectors<S>,
+) {
+    #[cfg(feature = "mlp")]
+    if let Some(mlp) = settings.policy_mlp.as_ref() {
+        position.generate_moves_with_mlp(
+            mlp,
+            group_data,
+            &mut temp_vectors.simple_moves,
+            &mut temp_vectors.moves,
+            &mut temp_vectors.fcd_per_move,
+            &mut temp_vectors.policy_score_sets,
+            &mut temp_vectors.policy_feature_sets,
+            &mut temp_vectors.mlp_activations,
+        );
+        return;
+    }
+    position.generate_moves_with_params(
+        match settings.policy_params.as_ref() {
+            Some(params) => params,
+            None => <Position<S>>::policy_params(position.komi()),
+        },
+        group_data,
+        &mut temp_vectors.simple_moves,
+        &mut temp_vectors.moves,
+        &mut temp_vectors.fcd_per_move,
+        &mut temp_vectors.policy_score_sets,
+        &mut temp_vectors.policy_feature_sets,
+    );
+}
+
 /// A game result from one side's perspective
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum GameResultForUs {
@@ -6,8 +6,14 @@ use half::f16;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::process;
+#[cfg(feature = "mlp")]
+use std::sync::Arc;
 use std::{mem, time};
 
+#[cfg(feature = "mlp")]
+use crate::evaluation::mlp::Mlp;
+#[cfg(feature = "mlp")]
+use crate::evaluation::parameter_file::ParameterKind;
 use crate::position::Move;
 use crate::position::Position;
 use crate::position::{Role, Square};
@@ -18,10 +24,12 @@ use self::arena::ArenaError;
 use self::mcts_core::Pv;
 
 mod arena;
+mod eval_cache;
 /// This module contains the public-facing convenience API for the search.
 /// The implementation itself in in mcts_core.
 mod mcts_core;
 pub use arena::Arena;
+pub use eval_cache::EvalCache;
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, PartialEq, Clone)]
@@ -30,16 +38,52 @@ pub enum TimeControl {
     Time(time::Duration, time::Duration), // Total time left, increment
 }
 
+/// Constants for deciding how long to search for, when playing with a time control
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeManagement {
+    /// The maximum search time is this share of the time left, plus `increment_share` of the increment
+    pub time_left_share: f32,
+    pub increment_share: f32,
+    /// The search stops early when `(elapsed / max_time)^stop_time_exponent` exceeds
+    /// `stop_visit_ratio` times the second-best move's share of the best move's visits
+    pub stop_time_exponent: f32,
+    pub stop_visit_ratio: f32,
+}
+
+impl Default for TimeManagement {
+    fn default() -> Self {
+        TimeManagement {
+            time_left_share: 0.2,
+            increment_share: 0.5,
+            stop_time_exponent: 2.0,
+            stop_visit_ratio: 0.5,
+        }
+    }
+}
+
+impl TimeManagement {
+    /// The maximum time to search for a move
+    pub fn max_time(&self, time_left: time::Duration, increment: time::Duration) -> time::Duration {
+        time_left.mul_f32(self.time_left_share) + increment.mul_f32(self.increment_share)
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct MctsSetting<const S: usize> {
     arena_size: u32,
     value_params: Option<Box<[f32]>>,
     policy_params: Option<Box<[f32]>>,
+    #[cfg(feature = "mlp")]
+    value_mlp: Option<Arc<Mlp>>,
+    #[cfg(feature = "mlp")]
+    policy_mlp: Option<Arc<Mlp>>,
     search_params: Box<[Score]>,
     dirichlet: Option<f32>,
     excluded_moves: Vec<Move<S>>,
     rollout_depth: u16,
     rollout_temperature: f64,
+    eval_cache_size: Option<usize>,
+    time_management: TimeManagement,
 }
 
 impl<const S: usize> Default for MctsSetting<S> {
@@ -48,11 +92,17 @@ impl<const S: usize> Default for MctsSetting<S> {
             arena_size: 2_u32.pow(29), // Default to 12GB max
             value_params: None,
             policy_params: None,
+            #[cfg(feature = "mlp")]
+            value_mlp: None,
+            #[cfg(feature = "mlp")]
+            policy_mlp: None,
             search_params: vec![1.43, 2800.0, 0.61].into_boxed_slice(),
             dirichlet: None,
             excluded_moves: vec![],
             rollout_depth: 0,
             rollout_temperature: 0.25,
+            eval_cache_size: None,
+            time_management: TimeManagement::default(),
         }
     }
 }
@@ -89,6 +139,22 @@ impl<const S: usize> MctsSetting<S> {
         self
     }
 
+    /// Use a value network for the static evaluation, instead of the linear value parameters
+    #[cfg(feature = "mlp")]
+    pub fn add_value_mlp(mut self, value_mlp: Arc<Mlp>) -> Self {
+        assert_eq!(value_mlp.kind, ParameterKind::Value);
+        self.value_mlp = Some(value_mlp);
+        self
+    }
+
+    /// Use a policy network for the move priors, instead of the linear policy parameters
+    #[cfg(feature = "mlp")]
+    pub fn add_policy_mlp(mut self, policy_mlp: Arc<Mlp>) -> Self {
+        assert_eq!(policy_mlp.kind, ParameterKind::Policy);
+        self.policy_mlp = Some(policy_mlp);
+        self
+    }
+
     pub fn add_search_params(mut self, search_params: Box<[f32]>) -> Self {
         self.search_params = search_params;
         self
@@ -117,6 +183,28 @@ impl<const S: usize> MctsSetting<S> {
         self
     }
 
+    /// Cache the static evaluation and move priors of up to `num_entries` positions, rounded up to the nearest power of two.
+    /// Each entry uses `EvalCache::<S>::entry_mem_usage()` bytes, which is 624 bytes on 6s,
+    /// so `1 << 16` entries use about 40MB. This memory is allocated for each tree, in addition to `arena_size`,
+    /// and is not included in `MonteCarloTree::mem_usage`. Disabled by default
+    pub fn eval_cache_size(mut self, num_entries: usize) -> Self {
+        self.eval_cache_size = Some(num_entries);
+        self
+    }
+
+    pub fn add_time_management(mut self, time_management: TimeManagement) -> Self {
+        self.time_management = time_management;
+        self
+    }
+
+    pub fn search_params(&self) -> &[Score] {
+        &self.search_params
+    }
+
+    pub fn time_management(&self) -> TimeManagement {
+        self.time_management
+    }
+
     pub fn c_puct_init(&self) -> Score {
         self.search_params[0]
     }
@@ -181,7 +269,7 @@ impl<const S: usize> MonteCarloTree<S> {
             }
             Err(err) => panic!("{}", err),
         };
-        let mut temp_vectors = TempVectors::default();
+        let mut temp_vectors = TempVectors::new(settings.eval_cache_size);
         let mut root_edge = TreeEdge {
             child: None,
             mv: Move::placement(Role::Flat, Square::default()),
@@ -296,7 +384,10 @@ impl<const S: usize> MonteCarloTree<S> {
 
             let best_exploration_value = best_edge.exploration_value(visits_sqrt, dynamic_cpuct);
 
-            if time_ratio.powf(2.0) > node_ratio / 2.0 {
+            let time_management = self.settings.time_management;
+            if time_ratio.powf(time_management.stop_time_exponent)
+                > node_ratio * time_management.stop_visit_ratio
+            {
                 callback(self);
                 // Do not stop if any other child nodes have better exploration value
                 if self.children().iter().any(|edge| {
@@ -368,6 +459,16 @@ impl<const S: usize> MonteCarloTree<S> {
             .collect()
     }
 
+    /// Every move at the root, with its number of visits,
+    /// and its score as winning probability for the side to move.
+    /// Panics if no search iterations have been run
+    pub fn root_moves(&self) -> Vec<(Move<S>, u32, Score)> {
+        self.children()
+            .iter()
+            .map(|edge| (edge.mv, edge.visits, 1.0 - edge.mean_action_value))
+            .collect()
+    }
+
     pub fn pv(&self) -> impl Iterator<Item = Move<S>> + '_ {
         Pv::new(&self.edge, &self.arena)
     }
@@ -410,6 +511,12 @@ impl<const S: usize> MonteCarloTree<S> {
     pub fn mean_action_value(&self) -> Score {
         self.edge.mean_action_value
     }
+
+    /// The fraction of static evaluations and move generations that were served from the evaluation cache,
+    /// or `None` if the cache is disabled
+    pub fn eval_cache_hit_rate(&self) -> Option<f64> {
+        self.temp_vectors.eval_cache().map(EvalCache::hit_rate)
+    }
 }
 
 /// The simplest way to use the mcts module. Run Monte Carlo Tree Search for `nodes` nodes, returning the best move, and its estimated winning probability for the side to move.
@@ -456,7 +563,7 @@ pub fn mcts_training<const S: usize>(
             }
         }
         TimeControl::Time(time, increment) => {
-            let max_time = *time / 5 + *increment / 2;
+            let max_time = tree.settings.time_management.max_time(*time, *increment);
             tree.search_for_time(max_time, |_| {});
         }
     }
@@ -0,0 +1,269 @@
+//! Load games from a SQLite copy of Playtak's game database, for analysis and experiments.
+//!
+//! Games are read from the `games` table, with the same columns as Playtak's database.
+//! Only `size` and `notation` are required, missing columns are read as null.
+//! Filtering on a missing column is an error, except for `komi`, which is read as 0.
+
+use board_game_traits::{GameResult, Position as PositionTrait};
+use chrono::NaiveDate;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection};
+
+use crate::position::{Komi, Move, Position};
+use crate::ptn::playtak::PlaytakGameRecord;
+use crate::ptn::{Game, PtnMove};
+
+pub mod reports;
+
+/// Columns of the `games` table, in the order of `PlaytakGameRecord`'s fields
+const COLUMNS: [&str; 12] = [
+    "id",
+    "date",
+    "size",
+    "player_white",
+    "player_black",
+    "notation",
+    "result",
+    "timertime",
+    "timerinc",
+    "komi",
+    "pieces",
+    "capstones",
+];
+
+/// Selects which games to load from the database. By default, all games are loaded.
+///
+/// ```
+/// use tiltak::position::Komi;
+/// use tiltak::sqlite::GameFilter;
+///
+/// let filter = GameFilter::default()
+///     .player("Tiltak_Bot")
+///     .komi(Komi::from_half_komi(4).unwrap())
+///     .min_rating(1600);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameFilter {
+    size: Option<usize>,
+    player: Option<String>,
+    min_rating: Option<i64>,
+    after: Option<NaiveDate>,
+    before: Option<NaiveDate>,
+    komi: Option<Komi>,
+}
+
+impl GameFilter {
+    /// Only games of this size. Loading games with `GameDatabase::games` sets this automatically.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Only games where this player played either color
+    pub fn player(mut self, player: &str) -> Self {
+        self.player = Some(player.to_string());
+        self
+    }
+
+    /// Only games where both players were rated at least this high
+    pub fn min_rating(mut self, rating: i64) -> Self {
+        self.min_rating = Some(rating);
+        self
+    }
+
+    /// Only games played on or after this date (UTC)
+    pub fn after(mut self, date: NaiveDate) -> Self {
+        self.after = Some(date);
+        self
+    }
+
+    /// Only games played before this date (UTC)
+    pub fn before(mut self, date: NaiveDate) -> Self {
+        self.before = Some(date);
+        self
+    }
+
+    pub fn komi(mut self, komi: Komi) -> Self {
+        self.komi = Some(komi);
+        self
+    }
+
+    /// Build the `WHERE` clause for the filter, and its parameters.
+    /// Returns an error if the filter needs a column that is not in `existing_columns`.
+    fn to_sql(&self, existing_columns: &[String]) -> rusqlite::Result<(String, Vec<Value>)> {
+        let has_column = |column: &str| existing_columns.iter().any(|existing| existing == column);
+        let require_columns = |columns: &[&str], filter_name: &str| match columns
+            .iter()
+            .find(|column| !has_column(column))
+        {
+            Some(column) => Err(rusqlite::Error::InvalidColumnName(format!(
+                "{}, required by GameFilter::{}",
+                column, filter_name
+            ))),
+            None => Ok(()),
+        };
+
+        let mut conditions = vec![];
+        let mut params = vec![];
+        if let Some(size) = self.size {
+            conditions.push("size = ?");
+            params.push(Value::Integer(size as i64));
+        }
+        if let Some(player) = &self.player {
+            require_columns(&["player_white", "player_black"], "player")?;
+            conditions.push("(player_white = ? OR player_black = ?)");
+            params.push(Value::Text(player.clone()));
+            params.push(Value::Text(player.clone()));
+        }
+        if let Some(rating) = self.min_rating {
+            require_columns(&["rating_white", "rating_black"], "min_rating")?;
+            conditions.push("rating_white >= ? AND rating_black >= ?");
+            params.push(Value::Integer(rating));
+            params.push(Value::Integer(rating));
+        }
+        if let Some(date) = self.after {
+            require_columns(&["date"], "after")?;
+            conditions.push("date >= ?");
+            params.push(Value::Integer(timestamp_millis(date)));
+        }
+        if let Some(date) = self.before {
+            require_columns(&["date"], "before")?;
+            conditions.push("date < ?");
+            params.push(Value::Integer(timestamp_millis(date)));
+        }
+        if let Some(komi) = self.komi {
+            // Games without a komi column were played without komi
+            if has_column("komi") {
+                conditions.push("IFNULL(komi, 0) = ?");
+            } else {
+                conditions.push("0 = ?");
+            }
+            params.push(Value::Integer(komi.half_komi() as i64));
+        }
+        if conditions.is_empty() {
+            Ok((String::new(), params))
+        } else {
+            Ok((format!(" WHERE {}", conditions.join(" AND ")), params))
+        }
+    }
+}
+
+fn timestamp_millis(date: NaiveDate) -> i64 {
+    date.and_hms_opt(0, 0, 0).unwrap().timestamp_millis()
+}
+
+/// A position from a database game, along with the move that was played in it
+#[derive(Debug, Clone)]
+pub struct DatabasePosition<const S: usize> {
+    pub position: Position<S>,
+    /// The move played in the position, or `None` if this is the last position of the game
+    pub next_move: Option<Move<S>>,
+    /// The final result of the game, if it was finished
+    pub game_result: Option<GameResult>,
+}
+
+pub struct GameDatabase {
+    connection: Connection,
+}
+
+impl GameDatabase {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        Ok(GameDatabase {
+            connection: Connection::open(path)?,
+        })
+    }
+
+    /// Read the raw records of all games that match the filter, ordered by id
+    pub fn records(&self, filter: &GameFilter) -> rusqlite::Result<Vec<PlaytakGameRecord>> {
+        let mut existing_columns = vec![];
+        let mut stmt = self.connection.prepare("PRAGMA table_info(games)")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            existing_columns.push(row.get::<_, String>(1)?);
+        }
+
+        let select_columns: Vec<String> = COLUMNS
+            .iter()
+            .map(|column| {
+                if existing_columns.iter().any(|existing| existing == column) {
+                    column.to_string()
+                } else {
+                    format!("NULL AS {}", column)
+                }
+            })
+            .collect();
+        let order = if existing_columns.iter().any(|column| column == "id") {
+            " ORDER BY id"
+        } else {
+            ""
+        };
+        let (where_clause, params) = filter.to_sql(&existing_columns)?;
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT {} FROM games{}{}",
+            select_columns.join(", "),
+            where_clause,
+            order
+        ))?;
+        let rows = stmt.query_map(params_from_iter(params), |row| {
+            Ok(PlaytakGameRecord {
+                id: row.get::<_, Option<u64>>(0)?.unwrap_or_default(),
+                date: row.get::<_, Option<i64>>(1)?.unwrap_or_default(),
+                size: row.get(2)?,
+                player_white: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                player_black: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                notation: row.get(5)?,
+                result: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                timer_time: row.get::<_, Option<u64>>(7)?.unwrap_or_default(),
+                timer_inc: row.get::<_, Option<u64>>(8)?.unwrap_or_default(),
+                half_komi: row.get::<_, Option<i8>>(9)?.unwrap_or_default(),
+                pieces: row.get::<_, Option<i32>>(10)?.unwrap_or(-1),
+                capstones: row.get::<_, Option<i32>>(11)?.unwrap_or(-1),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Load all games of size `S` that match the filter.
+    /// Games are converted lazily, and games that can't be converted, for example because of illegal moves, yield an error.
+    pub fn games<const S: usize>(
+        &self,
+        filter: &GameFilter,
+    ) -> rusqlite::Result<impl Iterator<Item = Result<Game<Position<S>>, pgn_traits::Error>>> {
+        let records = self.records(&filter.clone().size(S))?;
+        Ok(records.into_iter().map(|record| record.to_game()))
+    }
+
+    /// Every position from all games of size `S` that match the filter.
+    /// Games that can't be converted are skipped.
+    pub fn positions<const S: usize>(
+        &self,
+        filter: &GameFilter,
+    ) -> rusqlite::Result<impl Iterator<Item = DatabasePosition<S>>> {
+        Ok(self
+            .games(filter)?
+            .filter_map(Result::ok)
+            .flat_map(|game| game_positions(&game)))
+    }
+}
+
+/// Every position in a game, from the start position to the final position
+pub fn game_positions<const S: usize>(game: &Game<Position<S>>) -> Vec<DatabasePosition<S>> {
+    let game_result = game.game_result();
+    let mut position = game.start_position.clone();
+    let mut positions = Vec::with_capacity(game.moves.len() + 1);
+    for PtnMove { mv, .. } in game.moves.iter() {
+        positions.push(DatabasePosition {
+            position: position.clone(),
+            next_move: Some(*mv),
+            game_result,
+        });
+        position.do_move(*mv);
+    }
+    positions.push(DatabasePosition {
+        position,
+        next_move: None,
+        game_result,
+    });
+    positions
+}
@@ -0,0 +1,373 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{atomic::AtomicUsize, Mutex},
+};
+
+use board_game_traits::{Color, EvalPosition, GameResult, Position as BoardTrait};
+use half::f16;
+use pgn_traits::PgnPosition;
+use rayon::prelude::*;
+
+use super::DatabasePosition;
+use crate::evaluation::parameters;
+use crate::position::{Move, Position};
+use crate::search;
+
+/// Positions where the side to move may have an immediate win,
+/// along with the result of the winning move if there is one
+fn analysis_positions<const S: usize>(
+    positions: &[DatabasePosition<S>],
+) -> Vec<AnalysisPosition<S>> {
+    let mut result = vec![];
+    'position_loop: for DatabasePosition { position, .. } in positions.iter() {
+        // Skip the start position, as in the original experiment, and finished games
+        if position.half_moves_played() == 0 || position.game_result().is_some() {
+            continue;
+        }
+        let mut position = position.clone();
+        let mut moves = vec![];
+        position.generate_moves(&mut moves);
+
+        for legal_move in moves {
+            let reverse_move = position.do_move(legal_move);
+            let game_result = position.game_result();
+            let ptn_game_result = position.pgn_game_result();
+            position.reverse_move(reverse_move);
+            if position.side_to_move() == Color::White && game_result == Some(GameResult::WhiteWin)
+                || position.side_to_move() == Color::Black
+                    && game_result == Some(GameResult::BlackWin)
+            {
+                result.push(AnalysisPosition {
+                    position: position.clone(),
+                    solution_result: ptn_game_result.map(|s| s.to_string()),
+                });
+                continue 'position_loop;
+            }
+        }
+        result.push(AnalysisPosition {
+            position,
+            solution_result: None,
+        })
+    }
+    result
+}
+
+#[derive(Debug, Clone)]
+struct AnalysisPosition<const S: usize> {
+    position: Position<S>,
+    solution_result: Option<String>,
+}
+
+fn policy_finds_win<const S: usize>(position: &Position<S>) -> Option<Move<S>> {
+    let mut simple_moves = vec![];
+    let mut moves = vec![];
+    let mut fcd_per_move = vec![];
+
+    position.generate_moves_with_probabilities(
+        &position.group_data(),
+        &mut simple_moves,
+        &mut moves,
+        &mut fcd_per_move,
+        &mut vec![],
+        <Position<S>>::policy_params(position.komi()),
+        &mut Some(vec![]),
+    );
+
+    let mut feature_sets =
+        vec![vec![f16::ZERO; parameters::num_policy_features::<S>()]; moves.len()];
+
+    let mut policy_feature_sets: Vec<_> = feature_sets
+        .iter_mut()
+        .map(|feature_set| parameters::PolicyFeatures::new::<S>(feature_set))
+        .collect();
+
+    let simple_moves: Vec<Move<S>> = moves.iter().map(|(mv, _)| *mv).collect();
+
+    position.features_for_moves(
+        &mut policy_feature_sets,
+        &simple_moves,
+        &mut fcd_per_move,
+        &position.group_data(),
+    );
+    if simple_moves
+        .iter()
+        .zip(policy_feature_sets.iter())
+        .any(|(_, score)| score.decline_win[0] != f16::ZERO)
+    {
+        simple_moves
+            .iter()
+            .zip(policy_feature_sets)
+            .find(|(_, score)| score.decline_win[0] == f16::ZERO)
+            .map(|(mv, _)| *mv)
+    } else {
+        None
+    }
+}
+
+/// Test how well the policy's decline-win feature finds immediate wins, and print the results
+pub fn check_immediate_wins<const S: usize>(positions: &[DatabasePosition<S>]) {
+    let mut true_positives = AtomicUsize::new(0);
+    let false_positives = Mutex::new(vec![]);
+    let mut true_negative = AtomicUsize::new(0);
+    let false_negatives = Mutex::new(vec![]);
+    let wrong_groups = Mutex::new(HashMap::new());
+    analysis_positions(positions)
+        .par_iter()
+        .for_each(|win_position| {
+            match (
+                policy_finds_win(&win_position.position),
+                win_position.solution_result.as_ref(),
+            ) {
+                (Some(_), Some(_)) => {
+                    true_positives.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+                (Some(mv), None) => false_positives
+                    .lock()
+                    .unwrap()
+                    .push((win_position.clone(), mv)),
+                (None, Some(solution_result)) => {
+                    *wrong_groups
+                        .lock()
+                        .unwrap()
+                        .entry(solution_result.clone())
+                        .or_insert(0) += 1;
+                    false_negatives.lock().unwrap().push(win_position.clone());
+                }
+                (None, None) => {
+                    true_negative.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                }
+            }
+        });
+
+    let false_positives = false_positives.into_inner().unwrap();
+    let false_negatives = false_negatives.into_inner().unwrap();
+    let wrong_groups = wrong_groups.into_inner().unwrap();
+
+    println!("\nFalse negatives: ");
+    for wrongg in false_negatives.iter() {
+        println!(
+            "{}: {}",
+            wrongg.solution_result.as_ref().unwrap(),
+            wrongg.position.to_fen()
+        );
+    }
+    println!("\nFalse positives: ");
+    for wrongg in false_positives.iter() {
+        println!(
+            "{}, {} komi, {}",
+            wrongg.0.position.to_fen(),
+            wrongg.0.position.komi(),
+            wrongg.1
+        );
+    }
+    println!(
+        "Analyzed {} positions, got {} true positives, {} false positives and {} false negatives",
+        *true_positives.get_mut()
+            + false_positives.len()
+            + *true_negative.get_mut()
+            + false_negatives.len(),
+        true_positives.get_mut(),
+        false_positives.len(),
+        false_negatives.len()
+    );
+    for (result, n) in wrong_groups {
+        println!("Got {} wrong {} times", result, n);
+    }
+}
+
+/// Number of full moves in each game phase of the reports. The last phase contains the rest of the game.
+const MOVES_PER_PHASE: usize = 10;
+const NUM_PHASES: usize = 4;
+
+fn game_phase<const S: usize>(position: &Position<S>) -> usize {
+    (position.half_moves_played() / 2 / MOVES_PER_PHASE).min(NUM_PHASES - 1)
+}
+
+fn phase_name(phase: usize) -> String {
+    if phase == NUM_PHASES - 1 {
+        format!("moves {}+", phase * MOVES_PER_PHASE + 1)
+    } else {
+        format!(
+            "moves {}-{}",
+            phase * MOVES_PER_PHASE + 1,
+            (phase + 1) * MOVES_PER_PHASE
+        )
+    }
+}
+
+/// How often the move with the highest policy score was the move played in the game
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyAccuracyReport {
+    /// Number of positions in each game phase
+    pub positions: [u64; NUM_PHASES],
+    /// Number of positions in each game phase where the policy's top move was played
+    pub top_move_played: [u64; NUM_PHASES],
+}
+
+impl PolicyAccuracyReport {
+    pub fn top1_accuracy(&self) -> f64 {
+        self.top_move_played.iter().sum::<u64>() as f64
+            / self.positions.iter().sum::<u64>().max(1) as f64
+    }
+}
+
+impl fmt::Display for PolicyAccuracyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Policy top-1 accuracy:")?;
+        for phase in 0..NUM_PHASES {
+            writeln!(
+                f,
+                "{:>12}: {:.1}% of {} positions",
+                phase_name(phase),
+                100.0 * self.top_move_played[phase] as f64 / self.positions[phase].max(1) as f64,
+                self.positions[phase]
+            )?;
+        }
+        write!(
+            f,
+            "{:>12}: {:.1}% of {} positions",
+            "total",
+            100.0 * self.top1_accuracy(),
+            self.positions.iter().sum::<u64>()
+        )
+    }
+}
+
+/// Check how often the policy's top move is the move that was played.
+/// Panics if the engine has no policy parameters for the size and komi.
+pub fn policy_accuracy<const S: usize>(positions: &[DatabasePosition<S>]) -> PolicyAccuracyReport {
+    let results: Vec<(usize, bool)> = positions
+        .par_iter()
+        .filter_map(|database_position| {
+            let next_move = database_position.next_move?;
+            let position = &database_position.position;
+
+            let mut simple_moves = vec![];
+            let mut moves = vec![];
+            position.generate_moves_with_probabilities(
+                &position.group_data(),
+                &mut simple_moves,
+                &mut moves,
+                &mut vec![],
+                &mut vec![],
+                <Position<S>>::policy_params(position.komi()),
+                &mut Some(vec![]),
+            );
+            let (top_move, _) = moves
+                .iter()
+                .max_by(|(_, score1), (_, score2)| score1.total_cmp(score2))?;
+            Some((game_phase(position), *top_move == next_move))
+        })
+        .collect();
+
+    let mut report = PolicyAccuracyReport::default();
+    for (phase, top_move_played) in results {
+        report.positions[phase] += 1;
+        if top_move_played {
+            report.top_move_played[phase] += 1;
+        }
+    }
+    report
+}
+
+/// Running totals of predicted winning probabilities against actual results
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CalibrationStats {
+    pub positions: u64,
+    pub predicted_sum: f64,
+    pub result_sum: f64,
+    pub squared_error_sum: f64,
+}
+
+impl CalibrationStats {
+    fn add(&mut self, predicted: f64, result: f64) {
+        self.positions += 1;
+        self.predicted_sum += predicted;
+        self.result_sum += result;
+        self.squared_error_sum += (predicted - result).powi(2);
+    }
+
+    pub fn mean_predicted(&self) -> f64 {
+        self.predicted_sum / self.positions.max(1) as f64
+    }
+
+    pub fn mean_result(&self) -> f64 {
+        self.result_sum / self.positions.max(1) as f64
+    }
+
+    /// Mean squared error of the predictions. Lower is better, and always predicting 50% scores 0.25
+    pub fn brier_score(&self) -> f64 {
+        self.squared_error_sum / self.positions.max(1) as f64
+    }
+}
+
+/// Calibration of the static evaluation against the results of finished games,
+/// with all probabilities from white's perspective
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValueCalibrationReport {
+    pub phases: [CalibrationStats; NUM_PHASES],
+    /// Positions grouped by predicted winning probability, in steps of 10%
+    pub buckets: [CalibrationStats; 10],
+}
+
+impl fmt::Display for ValueCalibrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Value calibration by game phase:")?;
+        for (phase, stats) in self.phases.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>12}: predicted {:.1}%, actual {:.1}%, brier score {:.4}, {} positions",
+                phase_name(phase),
+                100.0 * stats.mean_predicted(),
+                100.0 * stats.mean_result(),
+                stats.brier_score(),
+                stats.positions
+            )?;
+        }
+        write!(f, "Value calibration by predicted score:")?;
+        for (i, stats) in self.buckets.iter().enumerate() {
+            write!(
+                f,
+                "\n{:>9}-{}%: predicted {:.1}%, actual {:.1}%, {} positions",
+                i * 10,
+                (i + 1) * 10,
+                100.0 * stats.mean_predicted(),
+                100.0 * stats.mean_result(),
+                stats.positions
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the static evaluation of each position to the result of the game.
+/// Positions from unfinished games are skipped.
+/// Panics if the engine has no value parameters for the size and komi.
+pub fn value_calibration<const S: usize>(
+    positions: &[DatabasePosition<S>],
+) -> ValueCalibrationReport {
+    let results: Vec<(usize, f64, f64)> = positions
+        .par_iter()
+        .filter_map(|database_position| {
+            let position = &database_position.position;
+            if position.game_result().is_some() {
+                return None;
+            }
+            let result = match database_position.game_result? {
+                GameResult::WhiteWin => 1.0,
+                GameResult::Draw => 0.5,
+                GameResult::BlackWin => 0.0,
+            };
+            let predicted = search::cp_to_win_percentage(position.static_eval()) as f64;
+            Some((game_phase(position), predicted, result))
+        })
+        .collect();
+
+    let mut report = ValueCalibrationReport::default();
+    for (phase, predicted, result) in results {
+        report.phases[phase].add(predicted, result);
+        report.buckets[((predicted * 10.0) as usize).min(9)].add(predicted, result);
+    }
+    report
+}
@@ -0,0 +1,77 @@
+use board_game_traits::Position as PositionTrait;
+use pgn_traits::PgnPosition;
+
+use crate::analysis::{self, AnalysisSettings};
+use crate::position::Position;
+use crate::ptn::{ptn_parser, Game, PtnMove};
+
+fn game_from_moves<const S: usize>(tps: &str, move_strings: &[&str]) -> Game<Position<S>> {
+    let start_position = <Position<S>>::from_fen(tps).unwrap();
+    let mut position = start_position.clone();
+    let mut moves = vec![];
+    for move_string in move_strings {
+        let mv = position.move_from_san(move_string).unwrap();
+        position.do_move(mv);
+        moves.push(PtnMove {
+            mv,
+            annotations: vec![],
+            comment: "0.0".to_string(),
+            variations: vec![],
+        });
+    }
+    Game {
+        start_position,
+        moves,
+        game_result_str: position.pgn_game_result(),
+        tags: vec![],
+    }
+}
+
+#[test]
+fn annotate_missed_win_test() {
+    // White misses the win on e1, and black wins on e5 instead
+    let game = game_from_moves::<5>("2,2,2,2,x/x5/x5/x5/1,1,1,1,x 1 5", &["c3", "e5"]);
+    let annotated = analysis::annotate_game(&game, &AnalysisSettings::nodes(1000)).game;
+
+    assert_eq!(annotated.moves.len(), 2);
+    assert_eq!(annotated.moves[0].annotations, vec!["?", "?"]);
+    assert!(annotated.moves[0]
+        .comment
+        .starts_with("0.0%, best e1 100.0%, pv e1"));
+    assert_eq!(annotated.moves[0].variations.len(), 1);
+    assert_eq!(
+        annotated.moves[0].variations[0][0].mv,
+        game.start_position.move_from_san("e1").unwrap()
+    );
+
+    assert!(annotated.moves[1].annotations.is_empty());
+    assert!(annotated.moves[1].comment.starts_with("100.0%, best e5"));
+    assert_eq!(annotated.game_result_str, Some("0-R"));
+}
+
+#[test]
+fn annotated_game_round_trip_test() {
+    let game = game_from_moves::<5>(
+        "x5/x5/x5/x5/x5 1 1",
+        &["a1", "e5", "c3", "d3", "c4", "d4", "c2", "d2"],
+    );
+    let annotated = analysis::annotate_game(&game, &AnalysisSettings::nodes(200)).game;
+    assert_eq!(annotated.moves.len(), game.moves.len());
+    for ptn_move in annotated.moves.iter() {
+        assert!(ptn_move.comment.contains(", best "), "{}", ptn_move.comment);
+    }
+
+    let mut ptn = vec![];
+    annotated.game_to_ptn(&mut ptn).unwrap();
+    let parsed_games: Vec<Game<Position<5>>> =
+        ptn_parser::parse_ptn(&String::from_utf8(ptn).unwrap()).unwrap();
+    assert_eq!(parsed_games.len(), 1);
+    let parsed_game = &parsed_games[0];
+    assert_eq!(parsed_game.moves.len(), annotated.moves.len());
+    for (parsed_move, annotated_move) in parsed_game.moves.iter().zip(annotated.moves.iter()) {
+        assert_eq!(parsed_move.mv, annotated_move.mv);
+        assert_eq!(parsed_move.comment, annotated_move.comment);
+        assert_eq!(parsed_move.annotations, annotated_move.annotations);
+        assert_eq!(parsed_move.variations, annotated_move.variations);
+    }
+}
@@ -0,0 +1,129 @@
+use board_game_traits::Position as PositionTrait;
+use half::f16;
+use pgn_traits::PgnPosition;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::evaluation::parameters::{NUM_POLICY_FEATURES_5S, NUM_VALUE_FEATURES_5S};
+use crate::position::{Komi, Move, Position};
+use crate::ptn::{Game, PtnMove};
+use crate::tune::training::{
+    policy_training_samples, value_training_samples, Augmentation, MoveScoresForGame,
+};
+use crate::tune::training_data;
+
+fn uniform_move_scores<const S: usize>(position: &Position<S>) -> Vec<(Move<S>, f16)> {
+    let mut moves = vec![];
+    position.generate_moves(&mut moves);
+    let score = f16::from_f32(1.0 / moves.len() as f32);
+    moves.into_iter().map(|mv| (mv, score)).collect()
+}
+
+fn game_with_move_scores() -> (Game<Position<5>>, MoveScoresForGame<5>) {
+    let mut position = <Position<5>>::start_position();
+    let mut moves = vec![];
+    let mut move_scores = vec![];
+    for move_string in ["a1", "e5", "c3", "c2", "b3", "c2<"] {
+        move_scores.push(uniform_move_scores(&position));
+        let mv = position.move_from_san(move_string).unwrap();
+        position.do_move(mv);
+        moves.push(PtnMove {
+            mv,
+            annotations: vec![],
+            comment: String::new(),
+            variations: vec![],
+        });
+    }
+    let game = Game {
+        start_position: <Position<5>>::start_position(),
+        moves,
+        game_result_str: Some("1-0"),
+        tags: vec![],
+    };
+    (game, move_scores)
+}
+
+#[test]
+fn augmentation_from_str_test() {
+    assert_eq!("none".parse::<Augmentation>().unwrap(), Augmentation::None);
+    assert_eq!(
+        "symmetries".parse::<Augmentation>().unwrap(),
+        Augmentation::Symmetries
+    );
+    assert_eq!(
+        "swapped-colors".parse::<Augmentation>().unwrap(),
+        Augmentation::SwappedColors
+    );
+    assert!("all".parse::<Augmentation>().is_err());
+}
+
+#[test]
+fn augment_position_test() {
+    let position = <Position<5>>::from_fen("2,x4/x2,2,x2/x,2,1,1,x/x,2C,1,x2/1,1C,x3 1 7").unwrap();
+    assert_eq!(Augmentation::None.augment(&position).len(), 1);
+
+    let augmented = Augmentation::Symmetries.augment(&position);
+    assert_eq!(augmented.len(), 8);
+    assert_eq!(augmented[0].position, position);
+    assert!(augmented.iter().all(|augmented| !augmented.swapped_colors));
+
+    let augmented = Augmentation::SwappedColors.augment(&position);
+    assert_eq!(augmented.len(), 16);
+    for (i, augmented) in augmented.iter().enumerate() {
+        assert_eq!(augmented.symmetry, i / 2);
+        assert_eq!(augmented.swapped_colors, i % 2 == 1);
+        assert_eq!(
+            augmented.position.side_to_move() != position.side_to_move(),
+            augmented.swapped_colors
+        );
+    }
+
+    // Colors are never swapped in the opening, where players place each other's stones
+    let start_position = <Position<5>>::start_position();
+    assert_eq!(
+        Augmentation::SwappedColors.augment(&start_position).len(),
+        8
+    );
+}
+
+#[test]
+fn augmentation_komi_test() {
+    let komi = Komi::from_half_komi(4).unwrap();
+    assert!(Augmentation::Symmetries.check_komi(komi).is_ok());
+    assert!(Augmentation::SwappedColors.check_komi(komi).is_err());
+    assert!(Augmentation::SwappedColors
+        .check_komi(Komi::default())
+        .is_ok());
+}
+
+#[test]
+fn augmented_training_samples_test() {
+    let (game, move_scores) = game_with_move_scores();
+    let mut rng = StdRng::from_seed([0; 32]);
+    let records = training_data::records_from_game(&game, &move_scores);
+
+    let value_samples = |augmentation| {
+        value_training_samples::<5, NUM_VALUE_FEATURES_5S, _>(
+            &records,
+            augmentation,
+            &mut StdRng::from_seed([0; 32]),
+        )
+    };
+    assert_eq!(value_samples(Augmentation::None).len(), 6);
+    assert_eq!(value_samples(Augmentation::Symmetries).len(), 48);
+    // The first two positions don't get their colors swapped
+    let swapped_samples = value_samples(Augmentation::SwappedColors);
+    assert_eq!(swapped_samples.len(), 2 * 8 + 4 * 16);
+    let white_wins = swapped_samples
+        .iter()
+        .filter(|sample| sample.result == f16::ONE)
+        .count();
+    assert_eq!(white_wins, 2 * 8 + 4 * 8);
+
+    let num_move_scores: usize = move_scores.iter().map(Vec::len).sum();
+    let policy_samples = policy_training_samples::<5, NUM_POLICY_FEATURES_5S, _>(
+        &records,
+        Augmentation::Symmetries,
+        &mut rng,
+    );
+    assert_eq!(policy_samples.len(), 8 * num_move_scores);
+}
@@ -4,6 +4,7 @@ use board_game_traits::{Color, EvalPosition, GameResult::*, Position as Position
 use half::f16;
 use pgn_traits::PgnPosition;
 use rand::seq::SliceRandom;
+use rand::Rng;
 
 use crate::position::{squares_iterator, Role, Square};
 use crate::position::{GroupData, Move};
@@ -199,6 +200,88 @@ fn play_random_games_prop<const S: usize>(num_games: usize) {
     )
 }
 
+#[test]
+fn move_symmetries_4s_test() {
+    move_symmetries_prop::<4>(20)
+}
+
+#[test]
+fn move_symmetries_5s_test() {
+    move_symmetries_prop::<5>(20)
+}
+
+#[test]
+fn move_symmetries_6s_test() {
+    move_symmetries_prop::<6>(20)
+}
+
+/// Moves transformed with `Move::symmetries` are the legal moves in the transformed positions,
+/// lead to the transformed resulting positions, and have the same policy features
+fn move_symmetries_prop<const S: usize>(num_games: usize) {
+    let mut rng = rand::thread_rng();
+    for _ in 0..num_games {
+        let mut position = <Position<S>>::default();
+        let mut moves = vec![];
+
+        while position.game_result().is_none() {
+            moves.clear();
+            position.generate_moves(&mut moves);
+            let move_symmetries: Vec<[Move<S>; 8]> =
+                moves.iter().map(|mv| mv.symmetries()).collect();
+
+            for (i, symmetry) in position.symmetries().into_iter().enumerate() {
+                let symmetric_moves: Vec<Move<S>> = move_symmetries
+                    .iter()
+                    .map(|symmetries| symmetries[i])
+                    .collect();
+                let mut legal_moves = vec![];
+                symmetry.generate_moves(&mut legal_moves);
+                assert_eq!(legal_moves.len(), symmetric_moves.len());
+                for mv in symmetric_moves.iter() {
+                    assert!(
+                        legal_moves.contains(mv),
+                        "{} is not legal in\n{:?}",
+                        mv,
+                        symmetry
+                    );
+                }
+
+                assert_eq!(
+                    policy_features(&position, &moves),
+                    policy_features(&symmetry, &symmetric_moves),
+                    "Policy features changed with symmetry {} in {}",
+                    i,
+                    position.to_fen()
+                );
+            }
+
+            let index = rng.gen_range(0..moves.len());
+            let symmetries = position.symmetries();
+            position.do_move(moves[index]);
+            for (i, mut symmetry) in symmetries.into_iter().enumerate() {
+                symmetry.do_move(move_symmetries[index][i]);
+                assert_eq!(symmetry, position.symmetries()[i]);
+                assert_eq!(
+                    symmetry.zobrist_hash(),
+                    position.symmetries()[i].zobrist_hash()
+                );
+            }
+        }
+    }
+}
+
+fn policy_features<const S: usize>(position: &Position<S>, moves: &[Move<S>]) -> Vec<Vec<f16>> {
+    let group_data = position.group_data();
+    let mut feature_sets =
+        vec![vec![f16::ZERO; parameters::num_policy_features::<S>()]; moves.len()];
+    let mut policy_feature_sets: Vec<PolicyFeatures> = feature_sets
+        .iter_mut()
+        .map(|feature_set| PolicyFeatures::new::<S>(feature_set))
+        .collect();
+    position.features_for_moves(&mut policy_feature_sets, moves, &mut vec![], &group_data);
+    feature_sets
+}
+
 #[test]
 fn go_in_directions_4s_test() {
     go_in_directions_prop::<4>()
@@ -4,9 +4,9 @@ use pgn_traits::PgnPosition;
 
 use crate::position::Direction::*;
 use crate::position::Piece::{BlackCap, BlackFlat, WhiteFlat, WhiteWall};
-use crate::position::Position;
 use crate::position::{squares_iterator, Piece, Role, Square, Stack};
 use crate::position::{ExpMove, Move};
+use crate::position::{Komi, Position};
 use crate::tests::do_moves_and_check_validity;
 use crate::{position as board_mod, search};
 
@@ -454,6 +454,87 @@ fn parse_tps_test() {
     assert_eq!(<Position<5>>::from_fen(tps_string).unwrap(), position);
 }
 
+#[test]
+fn parse_extended_tps_test() {
+    let position = <Position<5>>::from_fen("x5/x5/x5/x5/x5 1 1 komi=2.5").unwrap();
+    assert_eq!(position.komi(), Komi::from_half_komi(5).unwrap());
+    assert_eq!(position.to_extended_fen(), position.to_fen() + " komi=2.5");
+    assert_eq!(
+        position,
+        <Position<5>>::start_position_with_komi(position.komi())
+    );
+
+    // Komi in the TPS takes precedence
+    let position =
+        <Position<5>>::from_fen_with_komi("x5/x5/x5/x5/x5 1 1 komi=-1", Komi::default()).unwrap();
+    assert_eq!(position.komi(), Komi::from_half_komi(-2).unwrap());
+
+    assert!(<Position<5>>::from_fen("x5/x5/x5/x5/x5 1 1 komi=two").is_err());
+    assert!(<Position<5>>::from_fen("x5/x5/x5/x5/x5 1 1 history=xyz").is_err());
+    assert!(<Position<5>>::from_fen("x5/x5/x5/x5/x5 1 1 rules=other").is_err());
+    assert!(<Position<5>>::from_fen("x5/x5/x5/x5/x5 1 1 2").is_err());
+}
+
+#[test]
+fn extended_tps_keeps_repetitions_test() {
+    let mut position = <Position<5>>::start_position();
+    do_moves_and_check_validity(&mut position, &["a1", "e5"]);
+
+    let cycle_move_strings = ["e5-", "a1+", "e4+", "a2-"];
+    do_moves_and_check_validity(&mut position, &cycle_move_strings);
+    let tps = position.to_extended_fen();
+    assert!(tps.contains(" history="));
+
+    let mut parsed_position = <Position<5>>::from_fen(&tps).unwrap();
+    assert_eq!(parsed_position, position);
+    assert_eq!(parsed_position.to_extended_fen(), tps);
+    do_moves_and_check_validity(&mut parsed_position, &cycle_move_strings);
+    assert_eq!(parsed_position.game_result(), Some(GameResult::Draw));
+
+    // Without the history, the repetition is lost
+    let mut plain_position = <Position<5>>::from_fen(&position.to_fen()).unwrap();
+    do_moves_and_check_validity(&mut plain_position, &cycle_move_strings);
+    assert_eq!(plain_position.game_result(), None);
+
+    // Placements make earlier positions unrepeatable
+    do_moves_and_check_validity(&mut position, &["c3"]);
+    assert_eq!(position.to_extended_fen(), position.to_fen() + " komi=0");
+}
+
+#[test]
+fn symmetries_have_no_repetition_history_test() {
+    let mut position = <Position<5>>::start_position();
+    do_moves_and_check_validity(&mut position, &["a1", "e5", "e5-", "a1+", "e4+", "a2-"]);
+    assert!(!position.hash_history().is_empty());
+
+    for symmetry in position.symmetries_with_swapped_colors().iter().skip(1) {
+        assert!(symmetry.hash_history().is_empty());
+        assert_eq!(symmetry.to_extended_fen(), symmetry.to_fen() + " komi=0");
+    }
+
+    // The mirrored cycle repeats the current position once, which is not yet a draw
+    let mut flipped_position = position.flip_board_x();
+    let cycle_move_strings = ["a5-", "e1+", "a4+", "e2-"];
+    do_moves_and_check_validity(&mut flipped_position, &cycle_move_strings);
+    assert_eq!(flipped_position.game_result(), None);
+
+    do_moves_and_check_validity(&mut flipped_position, &cycle_move_strings);
+    assert_eq!(flipped_position.game_result(), Some(GameResult::Draw));
+}
+
+#[test]
+fn extended_tps_hashes_are_stable_test() {
+    // The hashes must not change between builds, or extended TPS strings written earlier lose their repetitions
+    let tps = "x,x,x,x,1/x,x,x,x,x/x,x,x,x,x/x,x,x,x,x/2,x,x,x,x 1 4 komi=0 history=a61605b2da809ad2,379e03f0701d3e40,8f716090121d8802,1ef966d2b8802c90";
+    let mut position = <Position<5>>::start_position();
+    do_moves_and_check_validity(&mut position, &["a1", "e5", "e5-", "a1+", "e4+", "a2-"]);
+    assert_eq!(position.to_extended_fen(), tps);
+
+    let mut parsed_position = <Position<5>>::from_fen(tps).unwrap();
+    do_moves_and_check_validity(&mut parsed_position, &["e5-", "a1+", "e4+", "a2-"]);
+    assert_eq!(parsed_position.game_result(), Some(GameResult::Draw));
+}
+
 #[test]
 fn join_too_many_groups_test() {
     let tps = "1,x,1,x,1,x/21121111S,x4,1/1,x,1,x,1,x/x6/x,2,2,2,2,x/2,x5 1 18";
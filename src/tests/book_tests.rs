@@ -0,0 +1,223 @@
+use board_game_traits::Position as PositionTrait;
+use pgn_traits::PgnPosition;
+use rand::SeedableRng;
+
+use crate::book::{BookMove, BookSelection, BookSettings, OpeningBook};
+use crate::position::{Komi, Move, Position};
+use crate::ptn::{Game, PtnMove};
+use crate::search::MctsSetting;
+
+fn position_after<const S: usize>(move_strings: &[&str]) -> Position<S> {
+    let mut position = <Position<S>>::start_position();
+    for move_string in move_strings {
+        position.do_move(position.move_from_san(move_string).unwrap());
+    }
+    position
+}
+
+fn book_move<const S: usize>(
+    position: &Position<S>,
+    mv: &str,
+    weight: f32,
+    score: f32,
+) -> BookMove<S> {
+    BookMove {
+        mv: position.move_from_san(mv).unwrap(),
+        weight,
+        score,
+    }
+}
+
+fn game_from_moves<const S: usize>(
+    move_strings: &[&str],
+    result: &'static str,
+) -> Game<Position<S>> {
+    let mut position = <Position<S>>::start_position();
+    let mut moves = vec![];
+    for move_string in move_strings {
+        let mv = position.move_from_san(move_string).unwrap();
+        position.do_move(mv);
+        moves.push(PtnMove {
+            mv,
+            annotations: vec![],
+            comment: String::new(),
+            variations: vec![],
+        });
+    }
+    Game {
+        start_position: <Position<S>>::start_position(),
+        moves,
+        game_result_str: Some(result),
+        tags: vec![],
+    }
+}
+
+#[test]
+fn book_lookup_in_symmetric_positions_test() {
+    let position = position_after::<5>(&["a1"]);
+    let mut book = OpeningBook::new(Komi::default());
+    book.insert(
+        &position,
+        &[
+            book_move(&position, "e1", 0.75, 0.5),
+            book_move(&position, "b1", 0.25, 0.4),
+        ],
+    );
+    assert_eq!(book.len(), 1);
+
+    // The position is symmetric along the diagonal, so the moves may come back mirrored
+    let moves = book.moves(&position);
+    assert_eq!(moves.len(), 2);
+    for (mv, expected) in moves.iter().zip(["e1", "b1"]) {
+        let mut child = position.clone();
+        child.do_move(mv.mv);
+        let mut expected_child = position.clone();
+        expected_child.do_move(position.move_from_san(expected).unwrap());
+        assert_eq!(child.canonical_tps(), expected_child.canonical_tps());
+    }
+
+    // The same position, rotated. Moves are transformed to match
+    let rotated = position_after::<5>(&["e5"]);
+    let rotated_moves = book.moves(&rotated);
+    assert_eq!(rotated_moves.len(), 2);
+    for (mv, rotated_mv) in moves.iter().zip(&rotated_moves) {
+        let mut child = position.clone();
+        child.do_move(mv.mv);
+        let mut rotated_child = rotated.clone();
+        rotated_child.do_move(rotated_mv.mv);
+        assert_eq!(child.canonical_tps(), rotated_child.canonical_tps());
+        assert_eq!(mv.weight, rotated_mv.weight);
+    }
+
+    assert!(book.moves(&position_after::<5>(&["c3"])).is_empty());
+    // Different komi than the book
+    let mut komi_position = position.clone();
+    komi_position.set_komi(Komi::from_half_komi(4).unwrap());
+    assert!(book.moves(&komi_position).is_empty());
+}
+
+#[test]
+fn book_read_write_test() {
+    let mut book = OpeningBook::<5>::new(Komi::from_half_komi(4).unwrap());
+    let start_position = <Position<5>>::start_position_with_komi(book.komi());
+    book.insert(
+        &start_position,
+        &[book_move(&start_position, "a1", 1.0, 0.55)],
+    );
+    let mut position = start_position.clone();
+    position.do_move(position.move_from_san("a5").unwrap());
+    book.insert(
+        &position,
+        &[
+            book_move(&position, "e5", 0.5, 0.5),
+            book_move(&position, "c3", 0.5, 0.6),
+        ],
+    );
+
+    let mut output = vec![];
+    book.write(&mut output).unwrap();
+    let text = String::from_utf8(output).unwrap();
+    assert!(text.starts_with("tiltak-book version 1 size 5 komi 2\n"));
+
+    let read_book = OpeningBook::<5>::read(text.as_bytes()).unwrap();
+    assert_eq!(read_book.komi(), book.komi());
+    assert_eq!(read_book.len(), 2);
+    assert_eq!(
+        read_book.moves(&start_position),
+        book.moves(&start_position)
+    );
+    assert_eq!(read_book.moves(&position), book.moves(&position));
+
+    assert!(OpeningBook::<6>::read(text.as_bytes()).is_err());
+    assert!(OpeningBook::<5>::read(&b"tiltak-book version 2 size 5 komi 2\n"[..]).is_err());
+    assert!(OpeningBook::<5>::read(&b""[..]).is_err());
+    assert!(OpeningBook::<5>::read(
+        &b"tiltak-book version 1 size 5 komi 0\nx5/x5/x5/x5/x5 1 1: a1 1\n"[..]
+    )
+    .is_err());
+    // Illegal move for the position
+    assert!(OpeningBook::<5>::read(
+        &b"tiltak-book version 1 size 5 komi 0\nx5/x5/x5/x5/x5 1 1: Ca1 1 0.5\n"[..]
+    )
+    .is_err());
+}
+
+#[test]
+fn book_from_games_test() {
+    let games = vec![
+        game_from_moves::<5>(&["a1", "e5", "c3"], "1-0"),
+        // The same opening, mirrored
+        game_from_moves::<5>(&["e1", "a5", "c3"], "0-1"),
+        game_from_moves::<5>(&["a1", "e5", "b3"], "1-0"),
+        game_from_moves::<5>(&["c3", "a1"], "1/2-1/2"),
+    ];
+    let book = OpeningBook::from_games(&games, Komi::default(), 2, 1);
+    // The start position, and the positions after 1. a1 and 1. c3
+    assert_eq!(book.len(), 3);
+
+    let start_moves = book.moves(&<Position<5>>::start_position());
+    assert_eq!(start_moves.len(), 2);
+    let corner_move = start_moves
+        .iter()
+        .find(|book_move| {
+            book_move.mv == Move::from_string("a1").unwrap()
+                || ["a5", "e1", "e5"].contains(&book_move.mv.to_string().as_str())
+        })
+        .unwrap();
+    assert_eq!(corner_move.weight, 0.75);
+    assert!((corner_move.score - 2.0 / 3.0).abs() < 1e-6);
+
+    let moves = book.moves(&position_after::<5>(&["a1"]));
+    assert_eq!(moves.len(), 1);
+    assert_eq!(moves[0].weight, 1.0);
+
+    // With a higher threshold, only the corner move is played often enough
+    let book = OpeningBook::from_games(&games, Komi::default(), 2, 3);
+    assert_eq!(book.moves(&<Position<5>>::start_position()).len(), 1);
+}
+
+#[test]
+fn choose_book_move_test() {
+    let position = <Position<5>>::start_position();
+    let mut book = OpeningBook::new(Komi::default());
+    book.insert(
+        &position,
+        &[
+            book_move(&position, "a1", 0.0, 0.5),
+            book_move(&position, "b1", 1.0, 0.3),
+        ],
+    );
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+    let best = BookSettings::new(book.clone()).selection(BookSelection::Best);
+    let best_move = best.choose_move(&position, &mut rng).unwrap();
+    assert_eq!(position.move_to_san(&best_move.mv), "a1");
+
+    let weighted = BookSettings::new(book.clone());
+    for _ in 0..10 {
+        let weighted_move = weighted.choose_move(&position, &mut rng).unwrap();
+        assert_eq!(position.move_to_san(&weighted_move.mv), "b1");
+    }
+
+    let no_book_plies = BookSettings::new(book).max_ply(0);
+    assert!(no_book_plies.choose_move(&position, &mut rng).is_none());
+    assert!(weighted
+        .choose_move(&position_after::<5>(&["a1"]), &mut rng)
+        .is_none());
+}
+
+#[test]
+fn book_from_search_test() {
+    let book =
+        OpeningBook::<4>::from_search(Komi::default(), 1000, 2, 0.2, &MctsSetting::default());
+    let start_position = <Position<4>>::start_position();
+    let moves = book.moves(&start_position);
+    assert!(!moves.is_empty());
+    assert!(moves.iter().all(|book_move| book_move.weight >= 0.2));
+    assert!(book.len() >= 2);
+    for book_move in moves {
+        let mut position = start_position.clone();
+        position.do_move(book_move.mv);
+        assert!(!book.moves(&position).is_empty(), "{}", position.to_fen());
+    }
+}
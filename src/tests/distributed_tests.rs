@@ -0,0 +1,187 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use board_game_traits::Position as PositionTrait;
+
+use crate::position::{Komi, Move, Position};
+use crate::search::TimeControl;
+use crate::tune::distributed::{run_worker, Coordinator, GameJob, WorkerParams};
+
+fn test_job(white: usize, black: usize, opening: Vec<Move<4>>) -> GameJob<4> {
+    GameJob {
+        white,
+        black,
+        komi: Komi::default(),
+        time_control: TimeControl::FixedNodes(50),
+        dirichlet_alpha: Some(0.2),
+        temperature: 1.0,
+        opening,
+    }
+}
+
+#[test]
+fn coordinator_and_workers_test() {
+    let coordinator = <Coordinator<4>>::bind("127.0.0.1:0").unwrap();
+    let address = coordinator.local_addr().to_string();
+    let default_params = coordinator.add_params(WorkerParams::default());
+    let linear_params = coordinator.add_params(WorkerParams {
+        value_params: Some(<Position<4>>::value_params(Komi::default()).to_vec()),
+        policy_params: Some(<Position<4>>::policy_params(Komi::default()).to_vec()),
+    });
+
+    let workers: Vec<_> = (0..2)
+        .map(|_| {
+            let address = address.clone();
+            thread::spawn(move || run_worker::<4>(&address, 2).unwrap())
+        })
+        .collect();
+
+    let opening = vec![
+        Move::from_string("a1").unwrap(),
+        Move::from_string("d4").unwrap(),
+    ];
+    let jobs: Vec<_> = (0..6)
+        .map(|i| {
+            if i % 2 == 0 {
+                test_job(default_params, linear_params, opening.clone())
+            } else {
+                test_job(linear_params, default_params, vec![])
+            }
+        })
+        .collect();
+    let games = coordinator.play_games(jobs);
+    assert_eq!(games.len(), 6);
+
+    for (i, (game, move_scores)) in games.iter().enumerate() {
+        assert_eq!(game.start_position.komi(), Komi::default());
+        assert_eq!(game.moves.len(), move_scores.len());
+        let num_opening_moves = if i % 2 == 0 { 2 } else { 0 };
+        for (j, (ptn_move, move_scores)) in game.moves.iter().zip(move_scores).enumerate() {
+            if j < num_opening_moves {
+                assert_eq!(ptn_move.mv, opening[j]);
+                assert!(move_scores.is_empty());
+            } else {
+                assert!(move_scores.iter().any(|(mv, _)| *mv == ptn_move.mv));
+            }
+        }
+        let mut position = game.start_position.clone();
+        for ptn_move in game.moves.iter() {
+            position.do_move(ptn_move.mv);
+        }
+        assert_eq!(position.game_result(), game.game_result());
+    }
+
+    // The workers stop when the coordinator is dropped
+    drop(coordinator);
+    let num_games: u64 = workers
+        .into_iter()
+        .map(|worker| worker.join().unwrap())
+        .sum();
+    assert_eq!(num_games, 6);
+}
+
+#[test]
+fn worker_with_wrong_size_test() {
+    let coordinator = <Coordinator<4>>::bind("127.0.0.1:0").unwrap();
+    let address = coordinator.local_addr().to_string();
+    assert!(run_worker::<5>(&address, 1).is_err());
+}
+
+/// Connect as a worker without playing any games, and take a job
+fn take_job(address: &str) -> (BufReader<TcpStream>, TcpStream) {
+    let stream = TcpStream::connect(address).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    writeln!(writer, "tiltak-worker version 1 size 4").unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line.trim(), "ok");
+    writeln!(writer, "job").unwrap();
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.starts_with("job "));
+    (reader, writer)
+}
+
+#[test]
+fn disconnected_worker_test() {
+    let coordinator = <Coordinator<4>>::bind("127.0.0.1:0").unwrap();
+    let address = coordinator.local_addr().to_string();
+    let params = coordinator.add_params(WorkerParams::default());
+
+    let games = thread::scope(|scope| {
+        let games = scope.spawn(|| coordinator.play_games(vec![test_job(params, params, vec![])]));
+
+        // Take the job, and disconnect without playing it
+        drop(take_job(&address));
+
+        // The game is handed out again
+        let address = address.clone();
+        thread::spawn(move || run_worker::<4>(&address, 1));
+        games.join().unwrap()
+    });
+    assert_eq!(games.len(), 1);
+}
+
+#[test]
+fn unresponsive_worker_test() {
+    let coordinator =
+        <Coordinator<4>>::bind_with_worker_timeout("127.0.0.1:0", Duration::from_secs(1)).unwrap();
+    let address = coordinator.local_addr().to_string();
+    let params = coordinator.add_params(WorkerParams::default());
+
+    let games = thread::scope(|scope| {
+        let games = scope.spawn(|| coordinator.play_games(vec![test_job(params, params, vec![])]));
+
+        // Take the job, and stay connected without sending anything
+        let (mut reader, _writer) = take_job(&address);
+
+        // The coordinator gives up on the worker, and hands the game out again
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        let address = address.clone();
+        thread::spawn(move || run_worker::<4>(&address, 1));
+        games.join().unwrap()
+    });
+    assert_eq!(games.len(), 1);
+}
+
+#[test]
+fn bad_result_test() {
+    let coordinator = <Coordinator<4>>::bind("127.0.0.1:0").unwrap();
+    let address = coordinator.local_addr().to_string();
+    let params = coordinator.add_params(WorkerParams::default());
+    let opening = vec![
+        Move::from_string("a1").unwrap(),
+        Move::from_string("d4").unwrap(),
+    ];
+
+    let games = thread::scope(|scope| {
+        let games =
+            scope.spawn(|| coordinator.play_games(vec![test_job(params, params, opening.clone())]));
+
+        // A result that's too large to be accepted
+        let (mut reader, mut writer) = take_job(&address);
+        writeln!(writer, "result 0 1000000000000 0").unwrap();
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+
+        // A game that ends before its opening does
+        let (mut reader, mut writer) = take_job(&address);
+        let ptn = "[Size \"4\"]\n\n1. a1\n";
+        let move_scores = "a1:\n";
+        writeln!(writer, "result 0 {} {}", ptn.len(), move_scores.len()).unwrap();
+        write!(writer, "{}{}", ptn, move_scores).unwrap();
+        rest.clear();
+        reader.read_to_string(&mut rest).unwrap();
+
+        // Both times, the game is handed out again
+        let address = address.clone();
+        thread::spawn(move || run_worker::<4>(&address, 1));
+        games.join().unwrap()
+    });
+    assert_eq!(games.len(), 1);
+    assert!(games[0].0.moves.len() >= opening.len());
+}
@@ -0,0 +1,123 @@
+use board_game_traits::Position as PositionTrait;
+use half::f16;
+use pgn_traits::PgnPosition;
+
+use crate::position::Position;
+use crate::search::{EvalCache, MctsSetting, MonteCarloTree};
+
+#[test]
+fn eval_cache_value_test() {
+    let mut cache = <EvalCache<5>>::new(100);
+    assert_eq!(cache.capacity(), 128);
+    // The entry size is documented on `MctsSetting::eval_cache_size`
+    assert_eq!(<EvalCache<6>>::entry_mem_usage(), 624);
+    assert_eq!(cache.get_value(17), None);
+    cache.insert_value(17, 0.25);
+    assert_eq!(cache.get_value(17), Some(0.25));
+    // Same index, different position
+    assert_eq!(cache.get_value(17 + 128), None);
+    cache.insert_value(17 + 128, -1.5);
+    assert_eq!(cache.get_value(17 + 128), Some(-1.5));
+    assert_eq!(cache.get_value(17), None);
+
+    assert_eq!(cache.lookups(), 5);
+    assert_eq!(cache.hits(), 2);
+    assert_eq!(cache.hit_rate(), 0.4);
+}
+
+#[test]
+fn eval_cache_priors_test() {
+    let mut cache = <EvalCache<4>>::new(16);
+    let priors: Vec<f16> = (0..10).map(|i| f16::from_f32(i as f32 / 10.0)).collect();
+
+    cache.insert_value(3, 0.5);
+    assert_eq!(cache.get_priors(3, 10), None);
+    cache.insert_priors(3, priors.iter().copied());
+    assert_eq!(cache.get_priors(3, 10), Some(&priors[..]));
+    // The value is kept when the priors are added
+    assert_eq!(cache.get_value(3), Some(0.5));
+
+    // A different number of legal moves means a hash collision
+    assert_eq!(cache.get_priors(3, 9), None);
+
+    // Too many moves to cache
+    cache.insert_priors(5, vec![f16::ONE; 200].into_iter());
+    assert_eq!(cache.get_priors(5, 200), None);
+
+    cache.clear();
+    assert_eq!(cache.get_priors(3, 10), None);
+    assert_eq!(cache.get_value(3), None);
+    assert_eq!(cache.lookups(), 2);
+}
+
+#[test]
+fn eval_cache_keys_test() {
+    let position_after = |moves: &[&str]| {
+        let mut position = <Position<5>>::start_position();
+        for mv in moves {
+            position.do_move(position.move_from_san(mv).unwrap());
+        }
+        position
+    };
+    // The same stones, reached with different move orders
+    let position = position_after(&["a1", "e5", "c3", "b2", "b3", "c2", "d3"]);
+    let transposed = position_after(&["a1", "e5", "c3", "b2", "d3", "c2", "b3"]);
+    let with_same_last_moves = position_after(&["a1", "e5", "b3", "b2", "c3", "c2", "d3"]);
+
+    assert_eq!(position.zobrist_hash(), transposed.zobrist_hash());
+    assert_eq!(
+        EvalCache::value_key(&position),
+        EvalCache::value_key(&transposed)
+    );
+    assert_ne!(
+        EvalCache::priors_key(&position),
+        EvalCache::priors_key(&transposed)
+    );
+    assert_eq!(
+        EvalCache::priors_key(&position),
+        EvalCache::priors_key(&with_same_last_moves)
+    );
+    assert_ne!(
+        EvalCache::value_key(&position),
+        EvalCache::priors_key(&position)
+    );
+
+    // The move number only matters until the game phase scale factors stop changing
+    let at_move = |move_number: usize| {
+        <Position<5>>::from_fen(&format!(
+            "2,x4/x2,2,x2/x,2,1,1,x/x,2C,1,x2/1,1C,x3 1 {}",
+            move_number
+        ))
+        .unwrap()
+    };
+    assert_ne!(
+        EvalCache::value_key(&at_move(10)),
+        EvalCache::value_key(&at_move(12))
+    );
+    assert_eq!(
+        EvalCache::value_key(&at_move(30)),
+        EvalCache::value_key(&at_move(40))
+    );
+}
+
+#[test]
+fn search_with_eval_cache_test() {
+    let position = <Position<5>>::from_fen("2,x4/x2,2,x2/x,2,1,1,x/x,2C,1,x2/1,1C,x3 1 7").unwrap();
+    let settings = MctsSetting::default().arena_size_for_nodes(20_000);
+
+    let mut tree = MonteCarloTree::with_settings(position.clone(), settings.clone());
+    let mut cached_tree =
+        MonteCarloTree::with_settings(position.clone(), settings.eval_cache_size(1 << 14));
+    for _ in 0..20_000 {
+        tree.select().unwrap();
+        cached_tree.select().unwrap();
+    }
+    assert_eq!(tree.eval_cache_hit_rate(), None);
+    assert!(cached_tree.eval_cache_hit_rate().unwrap() > 0.0);
+
+    // Cached evaluations are identical, so the search is too
+    assert_eq!(tree.root_moves(), cached_tree.root_moves());
+    let mut legal_moves = vec![];
+    position.generate_moves(&mut legal_moves);
+    assert!(legal_moves.contains(&cached_tree.best_move().0));
+}
@@ -0,0 +1,81 @@
+use pgn_traits::PgnPosition;
+
+use crate::evaluation::explain::{explain_policy, explain_value};
+use crate::evaluation::parameters;
+use crate::evaluation::policy_eval::sigmoid;
+use crate::position::{Komi, Position};
+
+const TPS: &str = "2,x4/x2,2,x2/x,2,1,1,x/x,2C,1,x2/1,1C,x3 1 7";
+
+#[test]
+fn explain_value_matches_static_eval_test() {
+    let position = <Position<5>>::from_fen(TPS).unwrap();
+    let params = <Position<5>>::value_params(Komi::default());
+    let explanation = explain_value(&position, params);
+
+    let mut features = vec![half::f16::ZERO; parameters::num_value_features::<5>()];
+    let static_eval =
+        position.static_eval_with_params_and_data(&position.group_data(), params, &mut features);
+    assert!((explanation.eval - static_eval).abs() < 1e-4);
+
+    let sum: f32 = explanation
+        .white
+        .iter()
+        .chain(explanation.black.iter())
+        .map(|contribution| contribution.contribution)
+        .sum();
+    assert!((sum - explanation.eval).abs() < 1e-4);
+    assert!(explanation
+        .white
+        .iter()
+        .all(|contribution| contribution.name != "padding"));
+    assert!(explanation
+        .white
+        .iter()
+        .any(|contribution| contribution.name == "flat_psqt" && contribution.contribution != 0.0));
+    assert!(!explanation.largest_contributions().is_empty());
+}
+
+#[test]
+fn explain_policy_matches_move_generation_test() {
+    let position = <Position<5>>::from_fen(TPS).unwrap();
+    let params = <Position<5>>::policy_params(Komi::default());
+    let explanations = explain_policy(&position, params);
+
+    let mut moves = vec![];
+    position.generate_moves_with_probabilities(
+        &position.group_data(),
+        &mut vec![],
+        &mut moves,
+        &mut vec![],
+        &mut vec![],
+        params,
+        &mut Some(vec![]),
+    );
+    assert_eq!(explanations.len(), moves.len());
+    for (mv, probability) in moves {
+        let explanation = explanations
+            .iter()
+            .find(|explanation| explanation.mv == mv)
+            .unwrap();
+        assert_eq!(explanation.probability, probability.to_f32());
+    }
+
+    assert!(explanations
+        .windows(2)
+        .all(|pair| pair[0].probability >= pair[1].probability));
+    for explanation in explanations.iter() {
+        let sum: f32 = explanation
+            .features
+            .iter()
+            .map(|contribution| contribution.contribution)
+            .sum();
+        assert!((sum + explanation.offset - explanation.logit).abs() < 1e-4);
+        for contribution in explanation.features.iter() {
+            assert_eq!(contribution.values.len(), contribution.params.len());
+            let expected_delta =
+                sigmoid(explanation.logit) - sigmoid(explanation.logit - contribution.contribution);
+            assert!((contribution.probability_delta - expected_delta).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs;
+
+use half::f16;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::evaluation::parameter_file::ParameterKind;
+use crate::tune::gradient_descent::{
+    average_error, gradient_descent, sigmoid, GradientDescentSettings, Optimizer, TrainingSample,
+};
+
+const N: usize = 8;
+
+/// Samples where the result only depends on the first two features
+fn samples(rng: &mut StdRng) -> Vec<TrainingSample<N>> {
+    (0..500)
+        .map(|_| {
+            let features: [f16; N] =
+                std::array::from_fn(|_| f16::from_f32(rng.gen_range(0.0..1.0)));
+            let result = sigmoid(4.0 * features[0].to_f32() - 4.0 * features[1].to_f32());
+            TrainingSample {
+                features,
+                offset: 0.0,
+                result: f16::from_f32(result),
+            }
+        })
+        .collect()
+}
+
+fn norm(params: &[f32]) -> f32 {
+    params.iter().map(|param| param * param).sum::<f32>().sqrt()
+}
+
+#[test]
+fn optimizer_from_str_test() {
+    assert_eq!("adam".parse::<Optimizer>().unwrap(), Optimizer::Adam);
+    assert_eq!(
+        "momentum".parse::<Optimizer>().unwrap(),
+        Optimizer::Momentum
+    );
+    assert!("sgd".parse::<Optimizer>().is_err());
+    assert_eq!(
+        Optimizer::Momentum.default_learning_rate(ParameterKind::Value),
+        50.0
+    );
+}
+
+#[test]
+fn momentum_and_adam_reduce_error_test() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let samples = samples(&mut rng);
+    let initial_params = [0.0; N];
+    let initial_error = average_error(&samples, &initial_params);
+
+    for settings in [
+        GradientDescentSettings::new(50.0).patience(5),
+        GradientDescentSettings::new(0.1)
+            .optimizer(Optimizer::Adam)
+            .patience(5),
+    ] {
+        let params = gradient_descent(&samples, &[], &initial_params, &settings, &mut rng).unwrap();
+        assert!(
+            average_error(&samples, &params) < initial_error * 0.2,
+            "{:?} didn't reduce the error enough",
+            settings
+        );
+        assert!(params[0] > 1.0 && params[1] < -1.0, "{:?}", params);
+    }
+}
+
+#[test]
+fn weight_decay_shrinks_params_test() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let samples = samples(&mut rng);
+    let initial_params = [0.0; N];
+    let settings = GradientDescentSettings::new(0.1)
+        .optimizer(Optimizer::Adam)
+        .patience(5);
+
+    let params = gradient_descent(&samples, &[], &initial_params, &settings, &mut rng).unwrap();
+    let l2_params = gradient_descent(
+        &samples,
+        &[],
+        &initial_params,
+        &settings.clone().l2_weight_decay(0.01),
+        &mut rng,
+    )
+    .unwrap();
+    let l1_params = gradient_descent(
+        &samples,
+        &[],
+        &initial_params,
+        &settings.l1_weight_decay(0.01),
+        &mut rng,
+    )
+    .unwrap();
+    assert!(norm(&l2_params) < norm(&params));
+    assert!(norm(&l1_params) < norm(&params));
+}
+
+#[test]
+fn split_validation_test() {
+    let games: Vec<usize> = (0..25).collect();
+    let (training_games, validation_games) =
+        GradientDescentSettings::new(50.0).split_validation(&games);
+    assert_eq!(training_games, games);
+    assert!(validation_games.is_empty());
+
+    let (training_games, validation_games) = GradientDescentSettings::new(50.0)
+        .validation_fraction(0.2)
+        .split_validation(&games);
+    assert_eq!(training_games, &games[..20]);
+    assert_eq!(validation_games, &games[20..]);
+}
+
+#[test]
+fn gradient_descent_log_test() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let samples = samples(&mut rng);
+    let path = std::env::temp_dir().join(format!(
+        "tiltak_gradient_descent_test_{}.csv",
+        std::process::id()
+    ));
+    let settings = GradientDescentSettings::new(50.0)
+        .patience(5)
+        .log_file(&path);
+    let (training_samples, validation_samples) = samples.split_at(samples.len() * 3 / 4);
+    gradient_descent(
+        training_samples,
+        validation_samples,
+        &[0.0; N],
+        &settings,
+        &mut rng,
+    )
+    .unwrap();
+
+    let log = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let mut lines = log.lines();
+    assert_eq!(
+        lines.next(),
+        Some("iteration,seconds,learning_rate,training_error,validation_error")
+    );
+    let rows: Vec<Vec<f64>> = lines
+        .map(|line| {
+            line.split(',')
+                .map(|value| value.parse().unwrap())
+                .collect()
+        })
+        .collect();
+    assert!(rows.len() > 5);
+    for (i, row) in rows.iter().enumerate() {
+        assert_eq!(row.len(), 5);
+        assert_eq!(row[0], i as f64);
+    }
+    // Errors go down
+    assert!(rows.last().unwrap()[3] < rows[0][3]);
+    assert!(rows.last().unwrap()[4] < rows[0][4]);
+}
@@ -0,0 +1,216 @@
+use std::io::Cursor;
+use std::sync::Arc;
+
+use board_game_traits::Position as PositionTrait;
+use half::f16;
+use pgn_traits::PgnPosition;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::evaluation::mlp::{Layer, Mlp};
+use crate::evaluation::parameter_file::ParameterKind;
+use crate::evaluation::parameters;
+use crate::position::{Komi, Position};
+use crate::search::{MctsSetting, MonteCarloTree};
+
+const TPS: &str = "2,x4/x2,2,x2/x,2,1,1,x/x,2C,1,x2/1,1C,x3 1 7";
+
+/// A network without hidden layers, which is the same as the linear evaluation
+fn linear_mlp<const S: usize>(kind: ParameterKind, params: &[f32]) -> Mlp {
+    Mlp {
+        kind,
+        size: S,
+        komi: Komi::default(),
+        layout_hash: kind.layout_hash::<S>(),
+        layers: vec![Layer {
+            inputs: params.len(),
+            outputs: 1,
+            weights: params.to_vec(),
+            biases: vec![0.0],
+        }],
+    }
+}
+
+#[test]
+fn mlp_read_write_test() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let mlp = Mlp::new_random::<5, _>(ParameterKind::Policy, Komi::default(), &[8, 4], &mut rng);
+    assert_eq!(mlp.num_inputs(), parameters::num_policy_features::<5>());
+    let mut output = vec![];
+    mlp.write(&mut output).unwrap();
+
+    let read_mlp = Mlp::read(Cursor::new(&output)).unwrap();
+    assert_eq!(read_mlp, mlp);
+    assert!(read_mlp
+        .check::<5>(ParameterKind::Policy, Komi::default())
+        .is_ok());
+    assert!(read_mlp
+        .check::<5>(ParameterKind::Value, Komi::default())
+        .is_err());
+    assert!(read_mlp
+        .check::<6>(ParameterKind::Policy, Komi::default())
+        .is_err());
+    assert!(read_mlp
+        .check::<5>(ParameterKind::Policy, Komi::from_half_komi(4).unwrap())
+        .is_err());
+
+    let text = String::from_utf8(output).unwrap();
+    let truncated = &text[..text.trim_end().rfind('\n').unwrap()];
+    assert!(Mlp::read(Cursor::new(truncated)).is_err());
+    assert!(Mlp::read(Cursor::new(text.replace("version 1", "version 2"))).is_err());
+}
+
+#[test]
+fn linear_mlp_matches_value_params_test() {
+    let position = <Position<5>>::from_fen(TPS).unwrap();
+    let params = <Position<5>>::value_params(Komi::default());
+    let mlp = linear_mlp::<5>(ParameterKind::Value, params);
+
+    let group_data = position.group_data();
+    let mut features = vec![f16::ZERO; parameters::num_value_features::<5>()];
+    let linear_eval = position.static_eval_with_params_and_data(&group_data, params, &mut features);
+    let mlp_eval = position.static_eval_with_mlp(&group_data, &mlp, &mut features, &mut vec![]);
+    assert!((linear_eval - mlp_eval).abs() < 1e-4);
+    assert!(features.iter().all(|feature| *feature == f16::ZERO));
+}
+
+#[test]
+fn linear_mlp_matches_policy_params_test() {
+    let position = <Position<5>>::from_fen(TPS).unwrap();
+    let params = <Position<5>>::policy_params(Komi::default());
+    let mlp = linear_mlp::<5>(ParameterKind::Policy, params);
+    let group_data = position.group_data();
+
+    let mut linear_moves = vec![];
+    position.generate_moves_with_params(
+        params,
+        &group_data,
+        &mut vec![],
+        &mut linear_moves,
+        &mut vec![],
+        &mut vec![],
+        &mut Some(vec![]),
+    );
+    let mut mlp_moves = vec![];
+    position.generate_moves_with_mlp(
+        &mlp,
+        &group_data,
+        &mut vec![],
+        &mut mlp_moves,
+        &mut vec![],
+        &mut vec![],
+        &mut Some(vec![]),
+        &mut vec![],
+    );
+    assert_eq!(linear_moves.len(), mlp_moves.len());
+    for ((linear_move, linear_score), (mlp_move, mlp_score)) in linear_moves.iter().zip(mlp_moves) {
+        assert_eq!(*linear_move, mlp_move);
+        assert!((linear_score.to_f32() - mlp_score.to_f32()).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn mlp_gradients_test() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let mut mlp = Mlp::new_random::<4, _>(ParameterKind::Value, Komi::default(), &[6, 3], &mut rng);
+    for param in mlp.params_mut() {
+        *param += 0.05;
+    }
+    let position = <Position<4>>::from_fen("2,1,x2/x,2C,1,x/x,1C,2,x/1,x3 2 4").unwrap();
+    let mut features = vec![f16::ZERO; parameters::num_value_features::<4>()];
+    position.static_eval_features(&mut features);
+
+    let mut activations = vec![];
+    mlp.evaluate(&features, &mut activations);
+    let mut gradients = vec![0.0; mlp.num_params()];
+    mlp.add_gradients(
+        &features,
+        &activations,
+        1.0,
+        &mut gradients,
+        &mut vec![],
+        &mut vec![],
+    );
+    assert!(gradients.iter().any(|gradient| *gradient != 0.0));
+
+    // Compare with numerical gradients
+    let epsilon = 1e-2;
+    for (i, gradient) in gradients.iter().enumerate().step_by(7) {
+        let mut higher = mlp.clone();
+        *higher.params_mut().nth(i).unwrap() += epsilon;
+        let mut lower = mlp.clone();
+        *lower.params_mut().nth(i).unwrap() -= epsilon;
+        let numerical = (higher.evaluate(&features, &mut activations)
+            - lower.evaluate(&features, &mut activations))
+            / (2.0 * epsilon);
+        assert!(
+            (numerical - gradient).abs() < 1e-2,
+            "Gradient {} was {}, expected {}",
+            i,
+            gradient,
+            numerical
+        );
+    }
+}
+
+#[test]
+fn search_with_mlp_test() {
+    let mut rng = StdRng::from_seed([0; 32]);
+    let komi = Komi::default();
+    let settings = MctsSetting::default()
+        .arena_size_for_nodes(1000)
+        .add_value_mlp(Arc::new(Mlp::new_random::<5, _>(
+            ParameterKind::Value,
+            komi,
+            &[16],
+            &mut rng,
+        )))
+        .add_policy_mlp(Arc::new(Mlp::new_random::<5, _>(
+            ParameterKind::Policy,
+            komi,
+            &[16],
+            &mut rng,
+        )));
+    let position = <Position<5>>::from_fen(TPS).unwrap();
+    let mut tree = MonteCarloTree::with_settings(position.clone(), settings);
+    for _ in 0..1000 {
+        tree.select().unwrap();
+    }
+    let (mv, _) = tree.best_move();
+    let mut legal_moves = vec![];
+    position.generate_moves(&mut legal_moves);
+    assert!(legal_moves.contains(&mv));
+}
+
+#[cfg(feature = "constant-tuning")]
+#[test]
+fn train_mlp_reduces_error_test() {
+    use crate::tune::gradient_descent::TrainingSample;
+    use crate::tune::mlp_training::{average_error, train_mlp};
+    use rand::Rng;
+
+    const N: usize = parameters::NUM_VALUE_FEATURES_4S;
+    let mut rng = StdRng::from_seed([0; 32]);
+    let mlp = Mlp::new_random::<4, _>(ParameterKind::Value, Komi::default(), &[8], &mut rng);
+
+    // The result only depends on the first two features
+    let samples: Vec<TrainingSample<N>> = (0..2000)
+        .map(|_| {
+            let mut features = [f16::ZERO; N];
+            features[0] = f16::from_f32(rng.gen_range(0.0..1.0));
+            features[1] = f16::from_f32(rng.gen_range(0.0..1.0));
+            let result = if features[0] > features[1] {
+                f16::ONE
+            } else {
+                f16::ZERO
+            };
+            TrainingSample {
+                features,
+                offset: 0.0,
+                result,
+            }
+        })
+        .collect();
+
+    let trained_mlp = train_mlp(&samples, &[], &mlp, 0.5, &mut rng);
+    assert!(average_error(&samples, &trained_mlp) < average_error(&samples, &mlp) * 0.8);
+}
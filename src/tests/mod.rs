@@ -1,15 +1,44 @@
+mod analysis_tests;
 mod arena_tests;
+#[cfg(feature = "constant-tuning")]
+mod augmentation_tests;
 mod blunder_tests;
 mod board_generic_tests;
 mod board_tests;
+mod book_tests;
+#[cfg(feature = "constant-tuning")]
+mod distributed_tests;
+mod eval_cache_tests;
+mod explain_tests;
+#[cfg(feature = "constant-tuning")]
+mod gradient_descent_tests;
 mod komi_policy_tests;
 mod mcts_tests;
+#[cfg(feature = "mlp")]
+mod mlp_tests;
 mod move_gen_5s_tests;
+mod move_gen_fuzz_tests;
 mod move_gen_generic_tests;
+mod parameter_file_tests;
+mod playtak_tests;
 mod policy_tests;
+mod position_builder_tests;
 mod ptn_tests;
+#[cfg(feature = "serde")]
+mod serde_tests;
+#[cfg(feature = "constant-tuning")]
+mod sprt_tests;
+#[cfg(feature = "constant-tuning")]
+mod spsa_tests;
+#[cfg(feature = "sqlite")]
+mod sqlite_tests;
 mod tactics_tests_5s;
 mod tactics_tests_6s;
+mod threats_tests;
+#[cfg(feature = "constant-tuning")]
+mod training_data_tests;
+#[cfg(feature = "constant-tuning")]
+mod training_run_tests;
 
 use crate::evaluation::parameters::{self, PolicyFeatures};
 use crate::position::{Komi, Move, Position};
@@ -0,0 +1,442 @@
+use board_game_traits::Position as PositionTrait;
+use pgn_traits::PgnPosition;
+use rand::{Rng, SeedableRng};
+
+use crate::position::{squares_iterator, Direction, Move, Piece, Position, Role, Square};
+
+#[test]
+fn fuzz_move_gen_3s_test() {
+    fuzz_move_gen_prop::<3>(100, 0)
+}
+
+#[test]
+fn fuzz_move_gen_4s_test() {
+    fuzz_move_gen_prop::<4>(100, 1)
+}
+
+#[test]
+fn fuzz_move_gen_5s_test() {
+    fuzz_move_gen_prop::<5>(100, 2)
+}
+
+#[test]
+fn fuzz_move_gen_6s_test() {
+    fuzz_move_gen_prop::<6>(50, 3)
+}
+
+#[test]
+fn fuzz_move_gen_7s_test() {
+    fuzz_move_gen_prop::<7>(30, 4)
+}
+
+#[test]
+fn fuzz_move_gen_8s_test() {
+    fuzz_move_gen_prop::<8>(30, 5)
+}
+
+#[test]
+#[ignore]
+fn fuzz_move_gen_test_long() {
+    let seed = rand::thread_rng().gen();
+    fuzz_move_gen_prop::<3>(5_000, seed);
+    fuzz_move_gen_prop::<4>(5_000, seed);
+    fuzz_move_gen_prop::<5>(5_000, seed);
+    fuzz_move_gen_prop::<6>(2_000, seed);
+    fuzz_move_gen_prop::<7>(1_000, seed);
+    fuzz_move_gen_prop::<8>(1_000, seed);
+}
+
+#[test]
+fn random_position_test() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    for num_plies in 0..30 {
+        let position = <Position<5>>::random_position(&mut rng, num_plies);
+        assert_eq!(position.half_moves_played(), num_plies);
+        assert_eq!(position.moves().len(), num_plies);
+    }
+    // A 3s game cannot last for 1000 plies without a repetition, so it must have stopped early
+    let position = <Position<3>>::random_position(&mut rng, 1000);
+    assert!(position.game_result().is_some());
+    assert!(position.half_moves_played() < 1000);
+}
+
+#[test]
+fn shrink_failing_game_test() {
+    // Artificial failure: Any stack taller than two pieces
+    let check = |position: &Position<5>| {
+        if squares_iterator::<5>().any(|square| position[square].len() > 2) {
+            Err("Tall stack".to_string())
+        } else {
+            Ok(())
+        }
+    };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let failing_moves = (0..)
+        .map(|_| {
+            <Position<5>>::random_position(&mut rng, 100)
+                .moves()
+                .clone()
+        })
+        .find(|moves| check_game(moves, check).is_err())
+        .unwrap();
+
+    let (minimal_moves, error) = shrink_failing_game(&failing_moves, check);
+    assert_eq!(error, "Tall stack");
+    assert!(minimal_moves.len() <= failing_moves.len());
+    assert!(is_legal_game(&minimal_moves));
+
+    let mut position = <Position<5>>::start_position();
+    for mv in minimal_moves {
+        position.do_move(mv);
+    }
+    let (minimal_tps, error) = shrink_failing_tps(&position.to_fen(), check);
+    assert_eq!(error, "Tall stack");
+    // Only a single stack of three stones should be left
+    let minimal_position = <Position<5>>::from_fen(&minimal_tps).unwrap();
+    let stacks: Vec<_> = squares_iterator::<5>()
+        .map(|square| minimal_position[square])
+        .filter(|stack| !stack.is_empty())
+        .collect();
+    assert_eq!(stacks.len(), 1, "{}", minimal_tps);
+    assert_eq!(stacks[0].len(), 3, "{}", minimal_tps);
+}
+
+/// Generates random games, and checks every position in them.
+/// On failure, the game is shrunk to a minimal failing case.
+fn fuzz_move_gen_prop<const S: usize>(num_games: usize, seed: u64) {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    for _ in 0..num_games {
+        let num_plies = rng.gen_range(0..=S * S * 4);
+        let position = <Position<S>>::random_position(&mut rng, num_plies);
+
+        if check_game(position.moves(), check_position).is_err() {
+            let (minimal_moves, error) = shrink_failing_game(position.moves(), check_position);
+            let mut minimal_position = <Position<S>>::start_position();
+            for mv in minimal_moves.iter() {
+                minimal_position.do_move(*mv);
+            }
+            // If the failure doesn't depend on the move history, shrink the position itself
+            let (tps, error) =
+                match check_position(&<Position<S>>::from_fen(&minimal_position.to_fen()).unwrap())
+                {
+                    Err(_) => {
+                        shrink_failing_tps::<S, _>(&minimal_position.to_fen(), check_position)
+                    }
+                    Ok(()) => (minimal_position.to_fen(), error),
+                };
+            panic!(
+                "{}\nMinimal failing TPS: {}\nMoves: {}",
+                error,
+                tps,
+                minimal_moves
+                    .iter()
+                    .map(|mv| mv.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+        }
+    }
+}
+
+/// Plays through the game, and returns the number of plies before the first failing position
+fn check_game<const S: usize, F: Fn(&Position<S>) -> Result<(), String>>(
+    moves: &[Move<S>],
+    check: F,
+) -> Result<(), (usize, String)> {
+    let mut position = <Position<S>>::start_position();
+    check(&position).map_err(|err| (0, err))?;
+    for (i, mv) in moves.iter().enumerate() {
+        position.do_move(*mv);
+        check(&position).map_err(|err| (i + 1, err))?;
+    }
+    Ok(())
+}
+
+/// Shrinks a failing game, by first cutting it off after the first failure,
+/// and then removing single moves or pairs of moves while the game stays legal and keeps failing.
+fn shrink_failing_game<const S: usize, F: Fn(&Position<S>) -> Result<(), String> + Copy>(
+    moves: &[Move<S>],
+    check: F,
+) -> (Vec<Move<S>>, String) {
+    let (failing_ply, mut error) = check_game(moves, check).unwrap_err();
+    let mut moves = moves[0..failing_ply].to_vec();
+
+    'outer: loop {
+        // Removing a single move changes which player makes the later moves,
+        // so also try removing one move from each player
+        let removals = (0..moves.len()).map(|i| (i, None)).chain(
+            (0..moves.len())
+                .flat_map(|i| (i + 1..moves.len()).step_by(2).map(move |j| (i, Some(j)))),
+        );
+        for (i, j) in removals {
+            let mut candidate = moves.clone();
+            if let Some(j) = j {
+                candidate.remove(j);
+            }
+            candidate.remove(i);
+            if !is_legal_game(&candidate) {
+                continue;
+            }
+            if let Err((failing_ply, candidate_error)) = check_game(&candidate, check) {
+                candidate.truncate(failing_ply);
+                moves = candidate;
+                error = candidate_error;
+                continue 'outer;
+            }
+        }
+        return (moves, error);
+    }
+}
+
+/// Shrinks a failing position, by removing pieces and replacing walls and capstones with flats,
+/// for as long as the position keeps failing
+fn shrink_failing_tps<const S: usize, F: Fn(&Position<S>) -> Result<(), String>>(
+    tps: &str,
+    check: F,
+) -> (String, String) {
+    let mut position = <Position<S>>::from_fen(tps).unwrap();
+    let mut error = check(&position).unwrap_err();
+
+    'outer: loop {
+        let tps = position.to_fen();
+        let (board, suffix) = tps.split_once(' ').unwrap();
+        let cells: Vec<Vec<String>> = board
+            .split('/')
+            .map(|row| row.split(',').map(|cell| cell.to_string()).collect())
+            .collect();
+
+        for (row, column) in (0..S).flat_map(|row| (0..S).map(move |column| (row, column))) {
+            let cell = &cells[row][column];
+            if cell.starts_with('x') {
+                continue;
+            }
+            let mut edits = vec![
+                "x".to_string(),                    // Remove the whole stack
+                cell[1..].to_string(),              // Remove the bottom piece
+                cell[..cell.len() - 1].to_string(), // Remove the top piece, or its role
+            ];
+            if cell.ends_with(['S', 'C']) {
+                // Remove the top piece of a wall or capstone stack
+                edits.push(cell[..cell.len() - 2].to_string());
+            }
+
+            for edit in edits {
+                if edit.is_empty() || edit.starts_with(['S', 'C']) || edit == *cell {
+                    continue;
+                }
+                let mut candidate_cells = cells.clone();
+                candidate_cells[row][column] = edit;
+                let candidate_tps = format!(
+                    "{} {}",
+                    candidate_cells
+                        .iter()
+                        .map(|row| row.join(","))
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    suffix
+                );
+                let Ok(candidate) = <Position<S>>::from_fen(&candidate_tps) else {
+                    continue;
+                };
+                if let Err(candidate_error) = check(&candidate) {
+                    position = candidate;
+                    error = candidate_error;
+                    continue 'outer;
+                }
+            }
+        }
+        return (position.to_fen(), error);
+    }
+}
+
+fn is_legal_game<const S: usize>(moves: &[Move<S>]) -> bool {
+    let mut position = <Position<S>>::start_position();
+    let mut legal_moves = vec![];
+    for mv in moves {
+        legal_moves.clear();
+        position.generate_moves(&mut legal_moves);
+        if position.game_result().is_some() || !legal_moves.contains(mv) {
+            return false;
+        }
+        position.do_move(*mv);
+    }
+    true
+}
+
+/// Checks move generation, `do_move`/`reverse_move` round-trips and TPS round-trips for a single position
+fn check_position<const S: usize>(position: &Position<S>) -> Result<(), String> {
+    let mut moves = vec![];
+    position.generate_moves(&mut moves);
+
+    let mut move_strings: Vec<String> = moves.iter().map(Move::to_string).collect();
+    move_strings.sort();
+    let num_moves = move_strings.len();
+    move_strings.dedup();
+    if move_strings.len() != num_moves {
+        return Err(format!("Duplicate moves generated: {:?}", moves));
+    }
+    let mut reference_moves = reference_move_gen(position);
+    reference_moves.sort();
+    if move_strings != reference_moves {
+        return Err(format!(
+            "Generated moves {:?} differ from reference moves {:?}",
+            move_strings, reference_moves
+        ));
+    }
+
+    let mut position = position.clone();
+    let original_position = position.clone();
+    for mv in moves {
+        if position.move_from_san(&mv.to_string()).ok() != Some(mv) {
+            return Err(format!("Couldn't parse move {}", mv));
+        }
+        let reverse_move = position.do_move(mv);
+
+        if position.zobrist_hash() != position.zobrist_hash_from_scratch() {
+            return Err(format!("Wrong zobrist hash after {}", mv));
+        }
+        if position.hash_history().len() != original_position.hash_history().len() + 1
+            || position.hash_history().last() != Some(&original_position.zobrist_hash())
+        {
+            return Err(format!("Wrong hash history after {}", mv));
+        }
+
+        position.reverse_move(reverse_move);
+        if position != original_position
+            || position.zobrist_hash() != original_position.zobrist_hash()
+            || position.hash_history() != original_position.hash_history()
+            || position.moves() != original_position.moves()
+        {
+            return Err(format!("Position not restored after {}", mv));
+        }
+    }
+
+    let tps = position.to_fen();
+    let parsed_position = <Position<S>>::from_fen(&tps)
+        .map_err(|err| format!("Failed to parse TPS {}: {}", tps, err))?;
+    if parsed_position != position
+        || parsed_position.zobrist_hash() != position.zobrist_hash()
+        || parsed_position.to_fen() != tps
+    {
+        return Err(format!("TPS round-trip failed for {}", tps));
+    }
+    Ok(())
+}
+
+/// A simple, slow reference move generator, which returns the legal moves in PTN
+fn reference_move_gen<const S: usize>(position: &Position<S>) -> Vec<String> {
+    let mut moves = vec![];
+    let us = position.side_to_move();
+
+    if position.half_moves_played() < 2 {
+        for square in squares_iterator::<S>() {
+            if position[square].is_empty() {
+                moves.push(square.to_string());
+            }
+        }
+        return moves;
+    }
+
+    let (stones_left, caps_left) = match us {
+        board_game_traits::Color::White => {
+            (position.white_reserves_left(), position.white_caps_left())
+        }
+        board_game_traits::Color::Black => {
+            (position.black_reserves_left(), position.black_caps_left())
+        }
+    };
+
+    for square in squares_iterator::<S>() {
+        let stack = position[square];
+        match stack.top_stone() {
+            None => {
+                if stones_left > 0 {
+                    moves.push(format!("{}", square));
+                    moves.push(format!("S{}", square));
+                }
+                if caps_left > 0 {
+                    moves.push(format!("C{}", square));
+                }
+            }
+            Some(top_stone) if top_stone.color() == us => {
+                for direction in [
+                    Direction::North,
+                    Direction::West,
+                    Direction::East,
+                    Direction::South,
+                ] {
+                    for pieces_taken in 1..=stack.len().min(S as u8) {
+                        let mut drops = vec![];
+                        reference_spreads(
+                            position,
+                            square,
+                            direction,
+                            top_stone,
+                            pieces_taken,
+                            &mut drops,
+                            &mut |drops| {
+                                let mut mv = if pieces_taken == 1 {
+                                    square.to_string()
+                                } else {
+                                    format!("{}{}", pieces_taken, square)
+                                };
+                                mv.push(match direction {
+                                    Direction::North => '+',
+                                    Direction::West => '<',
+                                    Direction::East => '>',
+                                    Direction::South => '-',
+                                });
+                                if drops.len() > 1 {
+                                    for drop in drops {
+                                        mv.push_str(&drop.to_string());
+                                    }
+                                }
+                                moves.push(mv);
+                            },
+                        );
+                    }
+                }
+            }
+            Some(_) => (),
+        }
+    }
+    moves
+}
+
+/// Calls `f` with the drop counts of every legal spread that continues from `square` with `pieces_held` stones
+fn reference_spreads<const S: usize, F: FnMut(&[u8])>(
+    position: &Position<S>,
+    square: Square<S>,
+    direction: Direction,
+    top_stone: Piece,
+    pieces_held: u8,
+    drops: &mut Vec<u8>,
+    f: &mut F,
+) {
+    let Some(next_square) = square.go_direction(direction) else {
+        return;
+    };
+    let max_drop = match position[next_square].top_stone().map(Piece::role) {
+        None | Some(Role::Flat) => pieces_held,
+        // Only a capstone on its own can flatten a wall
+        Some(Role::Wall) if pieces_held == 1 && top_stone.role() == Role::Cap => 1,
+        Some(Role::Wall) | Some(Role::Cap) => return,
+    };
+    for drop in 1..=max_drop {
+        drops.push(drop);
+        if drop == pieces_held {
+            f(drops);
+        } else {
+            reference_spreads(
+                position,
+                next_square,
+                direction,
+                top_stone,
+                pieces_held - drop,
+                drops,
+                f,
+            );
+        }
+        drops.pop();
+    }
+}
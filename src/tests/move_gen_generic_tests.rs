@@ -1,5 +1,6 @@
 use crate::position::Position;
 use board_game_traits::Position as PositionTrait;
+use pgn_traits::PgnPosition;
 
 #[test]
 fn start_position_move_gen_test() {
@@ -38,3 +39,75 @@ fn start_position_move_gen_prop<const S: usize>() {
         position.reverse_move(reverse_move);
     }
 }
+
+#[test]
+fn start_position_perft_3s_test() {
+    let mut position = <Position<3>>::default();
+    perft_check_answers(&mut position, &[1, 9, 72, 1200, 17_792, 271_812]);
+    perft_implementations_agree_prop(&mut position, 5, 271_812);
+}
+
+#[test]
+fn start_position_perft_4s_test() {
+    let mut position = <Position<4>>::default();
+    perft_check_answers(&mut position, &[1, 16, 240, 7440, 216_464]);
+    perft_implementations_agree_prop(&mut position, 4, 216_464);
+}
+
+#[test]
+fn start_position_perft_6s_test() {
+    let mut position = <Position<6>>::default();
+    perft_check_answers(&mut position, &[1, 36, 1260, 132_720]);
+    perft_implementations_agree_prop(&mut position, 3, 132_720);
+}
+
+#[test]
+fn start_position_perft_7s_test() {
+    let mut position = <Position<7>>::default();
+    perft_check_answers(&mut position, &[1, 49, 2352, 339_696]);
+}
+
+#[test]
+fn start_position_perft_8s_test() {
+    let mut position = <Position<8>>::default();
+    perft_check_answers(&mut position, &[1, 64, 4032, 764_064]);
+}
+
+#[test]
+fn midgame_perft_6s_test() {
+    let mut position =
+        <Position<6>>::from_fen("2,x4,1/x,2,1,12,x2/x,1,21,2,1,x/x2,2,1,x2/x,2,x4/1,x5 1 9")
+            .unwrap();
+    perft_check_answers(&mut position, &[1, 101, 9705]);
+    perft_implementations_agree_prop(&mut position, 3, 919_433);
+}
+
+/// Checks that the bulk, hashed, divide and parallel perft implementations all give the known answer
+fn perft_implementations_agree_prop<const S: usize>(
+    position: &mut Position<S>,
+    depth: u16,
+    answer: u64,
+) {
+    assert_eq!(position.bulk_perft(depth), answer);
+    assert_eq!(position.hashed_perft(depth, 1 << 16), answer);
+    // A tiny table forces constant overwrites
+    assert_eq!(position.hashed_perft(depth, 1), answer);
+
+    let move_counts = position.perft_divide(depth);
+    let mut moves = vec![];
+    position.generate_moves(&mut moves);
+    assert_eq!(move_counts.len(), moves.len());
+    assert_eq!(
+        move_counts
+            .iter()
+            .map(|(_, num_moves)| num_moves)
+            .sum::<u64>(),
+        answer
+    );
+
+    #[cfg(feature = "rayon")]
+    {
+        assert_eq!(position.parallel_perft(depth), answer);
+        assert_eq!(position.parallel_perft_divide(depth), move_counts);
+    }
+}
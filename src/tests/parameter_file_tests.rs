@@ -0,0 +1,109 @@
+use std::io::Cursor;
+
+use board_game_traits::Position as PositionTrait;
+
+use crate::evaluation::parameter_file::{ParameterFile, ParameterKind};
+use crate::position::{Komi, Position};
+use crate::search::{self, MctsSetting, MonteCarloTree};
+
+#[test]
+fn parameter_file_read_write_test() {
+    let komi = Komi::from_half_komi(4).unwrap();
+    let file = ParameterFile::new::<5>(
+        ParameterKind::Value,
+        komi,
+        <Position<5>>::value_params(komi),
+    );
+    let mut output = vec![];
+    file.write(&mut output).unwrap();
+
+    let read_file = ParameterFile::read(Cursor::new(output)).unwrap();
+    assert_eq!(read_file, file);
+    assert!(read_file.check::<5>(ParameterKind::Value, komi).is_ok());
+    assert_eq!(read_file.params, <Position<5>>::value_params(komi));
+}
+
+#[test]
+fn parameter_file_check_test() {
+    let komi = Komi::default();
+    let file = ParameterFile::new::<6>(
+        ParameterKind::Policy,
+        komi,
+        <Position<6>>::policy_params(komi),
+    );
+    assert!(file.check::<6>(ParameterKind::Policy, komi).is_ok());
+    assert!(file.check::<6>(ParameterKind::Value, komi).is_err());
+    assert!(file.check::<5>(ParameterKind::Policy, komi).is_err());
+
+    let mut wrong_layout = file.clone();
+    wrong_layout.layout_hash ^= 1;
+    assert!(wrong_layout
+        .check::<6>(ParameterKind::Policy, komi)
+        .is_err());
+
+    let mut wrong_count = file.clone();
+    wrong_count.params.pop();
+    assert!(wrong_count.check::<6>(ParameterKind::Policy, komi).is_err());
+
+    let wrong_komi = Komi::from_half_komi(4).unwrap();
+    assert!(file.check::<6>(ParameterKind::Policy, wrong_komi).is_err());
+}
+
+#[test]
+fn layout_hash_differs_between_sizes_test() {
+    assert_ne!(
+        ParameterKind::Value.layout_hash::<5>(),
+        ParameterKind::Value.layout_hash::<6>()
+    );
+    assert_ne!(
+        ParameterKind::Value.layout_hash::<5>(),
+        ParameterKind::Policy.layout_hash::<5>()
+    );
+    assert_eq!(
+        ParameterKind::Policy.layout_hash::<4>(),
+        ParameterKind::Policy.layout_hash::<4>()
+    );
+}
+
+#[test]
+fn bad_parameter_file_test() {
+    let header = format!(
+        "tiltak-params version 1 kind value size 4 komi 0 layout {:016x} count 2",
+        ParameterKind::Value.layout_hash::<4>()
+    );
+    assert!(ParameterFile::read(Cursor::new(format!("{}\n1.0\n2.0\n", header))).is_ok());
+    assert!(ParameterFile::read(Cursor::new(format!("{}\n1.0\n", header))).is_err());
+    assert!(ParameterFile::read(Cursor::new(format!("{}\n1.0\nfoo\n", header))).is_err());
+    assert!(ParameterFile::read(Cursor::new(
+        header.replace("version 1", "version 2") + "\n1.0\n2.0\n"
+    ))
+    .is_err());
+    assert!(ParameterFile::read(Cursor::new("")).is_err());
+}
+
+#[test]
+fn search_with_loaded_params_test() {
+    let komi = Komi::default();
+    let file = ParameterFile::new::<5>(
+        ParameterKind::Value,
+        komi,
+        <Position<5>>::value_params(komi),
+    );
+    let mut output = vec![];
+    file.write(&mut output).unwrap();
+    let read_file = ParameterFile::read(Cursor::new(output)).unwrap();
+
+    let settings = MctsSetting::default()
+        .arena_size_for_nodes(1000)
+        .add_value_params(read_file.params.into_boxed_slice());
+    let position = <Position<5>>::start_position();
+    let mut tree = MonteCarloTree::with_settings(position.clone(), settings);
+    for _ in 0..1000 {
+        tree.select().unwrap();
+    }
+    let (mv, _) = tree.best_move();
+    assert_eq!(mv, search::mcts::<5>(position.clone(), 1000).0);
+    let mut legal_moves = vec![];
+    position.generate_moves(&mut legal_moves);
+    assert!(legal_moves.contains(&mv));
+}
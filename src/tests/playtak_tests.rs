@@ -0,0 +1,152 @@
+use board_game_traits::Position as PositionTrait;
+use rand::SeedableRng;
+
+use crate::position::{Komi, Move, Position};
+use crate::ptn::playtak::PlaytakGameRecord;
+use crate::ptn::{ptn_parser, Game};
+
+const RECORD: &str =
+    "12345|1600000000000|5|Alice|Bob|P A1,P E5,P C3 C,P B2 W,M C3 C4 1,P D4|0-R|600|10|4|21|1";
+
+#[test]
+fn playtak_move_test() {
+    let move_strings = [
+        ("P A1", "a1"),
+        ("P C3 C", "Cc3"),
+        ("P E5 W", "Se5"),
+        ("M A1 A3 1 1", "2a1+11"),
+        ("M E3 B3 2 1 1", "4e3<211"),
+        ("M C4 C3 1", "c4-"),
+    ];
+    for (playtak_string, ptn_string) in move_strings {
+        let mv = <Move<5>>::try_from_string_playtak(playtak_string).unwrap();
+        assert_eq!(mv, <Move<5>>::from_string(ptn_string).unwrap());
+        assert_eq!(mv.to_string_playtak(), playtak_string);
+    }
+
+    for bad_string in [
+        "",
+        "P",
+        "P F1",
+        "P A1 X",
+        "M A1 A3 1",
+        "M A1 B2 1",
+        "M A1 A3 0 1",
+        "M A1",
+        "Q A1",
+        "M A1 A2 6",
+    ] {
+        assert!(
+            <Move<5>>::try_from_string_playtak(bad_string).is_err(),
+            "{}",
+            bad_string
+        );
+    }
+}
+
+#[test]
+fn playtak_move_round_trip_test() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut moves = vec![];
+    for num_plies in 0..40 {
+        let position = <Position<6>>::random_position(&mut rng, num_plies);
+        moves.clear();
+        position.generate_moves(&mut moves);
+        for mv in moves.iter() {
+            let playtak_string = mv.to_string_playtak();
+            assert_eq!(
+                <Move<6>>::try_from_string_playtak(&playtak_string).unwrap(),
+                *mv
+            );
+        }
+    }
+}
+
+#[test]
+fn playtak_record_to_game_test() {
+    let record: PlaytakGameRecord = RECORD.parse().unwrap();
+    assert_eq!(record.id, 12345);
+    assert_eq!(record.player_black, "Bob");
+    assert_eq!(record.half_komi, 4);
+
+    let game = record.to_game::<5>().unwrap();
+    assert_eq!(game.moves.len(), 6);
+    assert_eq!(game.moves[2].mv, <Move<5>>::from_string("Cc3").unwrap());
+    assert_eq!(game.game_result_str, Some("0-R"));
+    assert_eq!(game.start_position.komi(), Komi::from_half_komi(4).unwrap());
+
+    let tag = |name: &str| {
+        game.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    };
+    assert_eq!(tag("Player1"), Some("Alice"));
+    assert_eq!(tag("Player2"), Some("Bob"));
+    assert_eq!(tag("Date"), Some("2020.09.13"));
+    assert_eq!(tag("Time"), Some("12:26:40"));
+    assert_eq!(tag("Komi"), Some("2"));
+    assert_eq!(tag("Clock"), Some("10:00 +10"));
+    assert_eq!(tag("Result"), Some("0-R"));
+
+    assert!(record.to_game::<6>().is_err());
+}
+
+#[test]
+fn playtak_clock_tag_test() {
+    for (timer_time, timer_inc, clock) in [
+        ("60", "0", "1:00 +0"),
+        ("900", "30", "15:00 +30"),
+        ("90", "2", "1:30 +2"),
+        ("5", "0", "0:05 +0"),
+    ] {
+        let record: PlaytakGameRecord = RECORD
+            .replace("|600|10|", &format!("|{}|{}|", timer_time, timer_inc))
+            .parse()
+            .unwrap();
+        let game = record.to_game::<5>().unwrap();
+        let tag = game.tags.iter().find(|(tag, _)| tag == "Clock").unwrap();
+        assert_eq!(tag.1, clock);
+    }
+}
+
+#[test]
+fn playtak_record_to_ptn_test() {
+    let record: PlaytakGameRecord = RECORD.parse().unwrap();
+    let ptn = record.to_ptn().unwrap();
+    let games: Vec<Game<Position<5>>> = ptn_parser::parse_ptn(&ptn).unwrap();
+    assert_eq!(games.len(), 1);
+    let game = record.to_game::<5>().unwrap();
+    assert_eq!(games[0].start_position, game.start_position);
+    assert_eq!(games[0].moves, game.moves);
+    assert_eq!(games[0].game_result_str, game.game_result_str);
+    let mut tags = games[0].tags.clone();
+    tags.sort();
+    let mut expected_tags = game.tags.clone();
+    expected_tags.sort();
+    assert_eq!(tags, expected_tags);
+
+    let aborted_record: PlaytakGameRecord = RECORD.replace("0-R", "0-0").parse().unwrap();
+    assert_eq!(aborted_record.to_game::<5>().unwrap().game_result_str, None);
+}
+
+#[test]
+fn bad_playtak_record_test() {
+    assert!("1|2|5|Alice|Bob".parse::<PlaytakGameRecord>().is_err());
+    assert!(RECORD
+        .replace("|600|", "|ten minutes|")
+        .parse::<PlaytakGameRecord>()
+        .is_err());
+
+    let illegal_move: PlaytakGameRecord = RECORD.replace("P D4", "P C4").parse().unwrap();
+    assert!(illegal_move.to_game::<5>().is_err());
+
+    let bad_result: PlaytakGameRecord = RECORD.replace("0-R", "2-0").parse().unwrap();
+    assert!(bad_result.to_game::<5>().is_err());
+
+    let custom_pieces: PlaytakGameRecord = RECORD.replace("|21|1", "|25|1").parse().unwrap();
+    assert!(custom_pieces.to_game::<5>().is_err());
+
+    let unknown_size: PlaytakGameRecord = RECORD.replacen("|5|", "|9|", 1).parse().unwrap();
+    assert!(unknown_size.to_ptn().is_err());
+}
@@ -0,0 +1,216 @@
+use board_game_traits::{Color, Position as PositionTrait};
+use pgn_traits::PgnPosition;
+use rand::SeedableRng;
+
+use crate::position::{Komi, Piece::*, Position, PositionBuilder, Square};
+
+fn square<const S: usize>(name: &str) -> Square<S> {
+    Square::parse_square(name).unwrap()
+}
+
+#[test]
+fn empty_builder_is_start_position_test() {
+    let position = <PositionBuilder<6>>::new().build().unwrap();
+    assert_eq!(position, <Position<6>>::start_position());
+    assert_eq!(
+        position.zobrist_hash(),
+        <Position<6>>::start_position().zobrist_hash()
+    );
+}
+
+#[test]
+fn build_same_position_as_tps_test() {
+    let tps = "2,x4/x2,1,x2/x,12,21C,x2/x2,2S,x2/1,x4 2 5";
+    let position = <PositionBuilder<5>>::new()
+        .stack(square("a5"), &[BlackFlat])
+        .stack(square("c4"), &[WhiteFlat])
+        .stack(square("b3"), &[WhiteFlat, BlackFlat])
+        .push_piece(square("c3"), BlackFlat)
+        .push_piece(square("c3"), WhiteCap)
+        .stack(square("c2"), &[BlackWall])
+        .stack(square("a1"), &[WhiteFlat])
+        .side_to_move(Color::Black)
+        .move_number(5)
+        .build()
+        .unwrap();
+
+    let expected = <Position<5>>::from_fen(tps).unwrap();
+    assert_eq!(position, expected);
+    assert_eq!(position.to_fen(), expected.to_fen());
+    assert_eq!(position.zobrist_hash(), expected.zobrist_hash());
+    assert_eq!(position.white_caps_left(), 0);
+    assert_eq!(position.black_reserves_left(), 21 - 4);
+}
+
+#[test]
+fn from_position_round_trip_test() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    for num_plies in 0..60 {
+        let position = <Position<6>>::random_position(&mut rng, num_plies);
+        let built = PositionBuilder::from_position(&position).build().unwrap();
+        assert_eq!(built, position);
+        assert_eq!(built.zobrist_hash(), position.zobrist_hash());
+    }
+}
+
+#[test]
+fn edit_position_test() {
+    let position = <Position<5>>::from_fen("x5/x5/x2,1,x2/x5/2,x4 1 2").unwrap();
+    let edited = PositionBuilder::from_position(&position)
+        .clear_square(square("c3"))
+        .komi(Komi::from_half_komi(4).unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(
+        edited.to_fen(),
+        <Position<5>>::from_fen("x5/x5/x5/x5/2,x4 1 2")
+            .unwrap()
+            .to_fen()
+    );
+    assert_eq!(edited.komi(), Komi::from_half_komi(4).unwrap());
+    assert_eq!(edited.white_reserves_left(), 21);
+}
+
+#[test]
+fn explicit_reserves_test() {
+    let position = <PositionBuilder<5>>::new()
+        .stack(square("a1"), &[WhiteFlat])
+        .reserves(Color::White, 10, 0)
+        .move_number(2)
+        .build()
+        .unwrap();
+    assert_eq!(position.white_reserves_left(), 10);
+    assert_eq!(position.white_caps_left(), 0);
+    assert_eq!(position.black_reserves_left(), 21);
+    assert_eq!(position.black_caps_left(), 1);
+
+    let error = <PositionBuilder<5>>::new()
+        .stack(square("a1"), &[WhiteFlat])
+        .reserves(Color::White, 21, 1)
+        .move_number(2)
+        .build()
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("Too many white stones in reserve"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn too_many_flats_test() {
+    let error = <PositionBuilder<3>>::new()
+        .stack(square("a1"), &[BlackFlat; 6])
+        .stack(square("b1"), &[BlackFlat; 5])
+        .move_number(10)
+        .build()
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("Too many black stones"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn too_many_caps_test() {
+    let error = <PositionBuilder<4>>::new()
+        .stack(square("a1"), &[WhiteCap])
+        .move_number(2)
+        .build()
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("Too many white capstones"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn buried_capstone_test() {
+    let error = <PositionBuilder<5>>::new()
+        .stack(square("c3"), &[WhiteCap, BlackFlat])
+        .move_number(3)
+        .build()
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("Capstone buried in the stack on c3"),
+        "{}",
+        error
+    );
+
+    let error = <PositionBuilder<5>>::new()
+        .stack(square("c3"), &[WhiteWall, BlackWall])
+        .move_number(3)
+        .build()
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("Wall buried in the stack on c3"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn bad_move_number_test() {
+    assert!(<PositionBuilder<5>>::new().move_number(0).build().is_err());
+    assert!(<PositionBuilder<5>>::new()
+        .stack(square("a1"), &[BlackFlat])
+        .stack(square("e5"), &[WhiteFlat])
+        .side_to_move(Color::Black)
+        .build()
+        .is_err());
+}
+
+#[test]
+fn opening_placements_test() {
+    // White places a black flat on the first move, and black places a white flat
+    assert!(<PositionBuilder<5>>::new()
+        .stack(square("a1"), &[BlackFlat])
+        .side_to_move(Color::Black)
+        .build()
+        .is_ok());
+    assert!(<PositionBuilder<5>>::new()
+        .stack(square("a1"), &[BlackFlat])
+        .stack(square("e5"), &[WhiteFlat])
+        .move_number(2)
+        .build()
+        .is_ok());
+
+    for pieces in [[WhiteFlat], [BlackWall], [BlackCap]] {
+        let error = <PositionBuilder<5>>::new()
+            .stack(square("a1"), &pieces)
+            .side_to_move(Color::Black)
+            .build()
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("after only 1 plies"),
+            "{}",
+            error
+        );
+    }
+
+    for (white_piece, black_piece) in [
+        (WhiteWall, BlackFlat),
+        (WhiteFlat, BlackCap),
+        (BlackFlat, BlackFlat),
+        (WhiteFlat, WhiteFlat),
+    ] {
+        assert!(<PositionBuilder<5>>::new()
+            .stack(square("a1"), &[black_piece])
+            .stack(square("e5"), &[white_piece])
+            .move_number(2)
+            .build()
+            .is_err());
+    }
+
+    assert!(<PositionBuilder<5>>::new()
+        .stack(square("a1"), &[BlackFlat, WhiteFlat])
+        .move_number(2)
+        .build()
+        .is_err());
+}
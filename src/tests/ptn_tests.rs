@@ -1,4 +1,4 @@
-use crate::position::{Move, Position};
+use crate::position::{Komi, Move, Position};
 use crate::ptn::{ptn_parser, Game, PtnMove};
 use crate::tests::do_moves_and_check_validity;
 use board_game_traits::Position as PositionTrait;
@@ -19,6 +19,7 @@ pub fn write_and_read_ptn_test() {
             mv: *mv,
             annotations: vec![],
             comment: "".to_string(),
+            variations: vec![],
         })
         .collect();
 
@@ -58,6 +59,7 @@ pub fn custom_start_position_test() {
         mv: position.move_from_san("e6").unwrap(),
         annotations: vec![],
         comment: String::new(),
+        variations: vec![],
     }];
 
     let mut game: Game<Position<6>> = Game {
@@ -112,3 +114,199 @@ fn parse_ptn_without_result2() {
 fn parse_bad_direction_test() {
     assert!(<Move<6>>::from_string("a1d").is_err())
 }
+
+fn ptn_round_trip<const S: usize>(game: &Game<Position<S>>) -> String {
+    let mut ptn_writer = Cursor::new(vec![]);
+    game.game_to_ptn(&mut ptn_writer).unwrap();
+    let ptn = String::from_utf8(ptn_writer.into_inner()).unwrap();
+
+    let parsed_games: Vec<Game<Position<S>>> = ptn_parser::parse_ptn(&ptn).unwrap();
+    assert_eq!(parsed_games.len(), 1);
+    // The writer fills in any missing required tags, so only compare the moves
+    assert_eq!(parsed_games[0].moves, game.moves, "ptn:\n{}", ptn);
+    assert_eq!(parsed_games[0].game_result_str, game.game_result_str);
+    ptn
+}
+
+#[test]
+fn parse_variations_test() {
+    let ptn = "[Size \"5\"]\n\n1. a1 e5 (1... e1 2. c3 (2. b2 {Passive} c3) 2... d3) 2. c3 d3? (2... Cc4 3. Cd3) 3. b3 1-0";
+    let games: Vec<Game<Position<5>>> = ptn_parser::parse_ptn(ptn).unwrap();
+    assert_eq!(games.len(), 1);
+    let game = &games[0];
+    assert_eq!(game.game_result_str, Some("1-0"));
+
+    let main_line: Vec<String> = game.moves.iter().map(|mv| mv.mv.to_string()).collect();
+    assert_eq!(main_line, ["a1", "e5", "c3", "d3", "b3"]);
+    assert_eq!(game.moves[3].annotations, vec!["?"]);
+
+    // A side line for black's first move, with a nested side line for white's second move
+    assert_eq!(game.moves[1].variations.len(), 1);
+    let variation = &game.moves[1].variations[0];
+    let variation_moves: Vec<String> = variation.iter().map(|mv| mv.mv.to_string()).collect();
+    assert_eq!(variation_moves, ["e1", "c3", "d3"]);
+    assert_eq!(variation[1].variations.len(), 1);
+    assert_eq!(variation[1].variations[0][0].comment, "Passive");
+    assert_eq!(variation[1].variations[0][1].mv.to_string(), "c3");
+
+    assert_eq!(game.moves[3].variations.len(), 1);
+    assert_eq!(game.moves[3].variations[0][0].mv.to_string(), "Cc4");
+    assert!(game.moves[4].variations.is_empty());
+
+    ptn_round_trip(game);
+}
+
+#[test]
+fn multiple_variations_round_trip_test() {
+    let ptn = "1. a1 (1. a5) (1. e5 a1 (1... e1)) 1... e5 2. c3 *";
+    let games: Vec<Game<Position<5>>> = ptn_parser::parse_ptn(ptn).unwrap();
+    assert_eq!(games[0].moves[0].variations.len(), 2);
+    assert_eq!(games[0].moves[0].variations[1][1].variations.len(), 1);
+
+    let written_ptn = ptn_round_trip(&games[0]);
+    assert!(
+        written_ptn.contains("1. a1 (1. a5) (1. e5 a1 (1... e1)) 1... e5 2. c3 *"),
+        "{}",
+        written_ptn
+    );
+}
+
+#[test]
+fn game_without_variations_ptn_test() {
+    let ptn = "1. a1 e5 2. c3 d3 3. b3 1-0";
+    let games: Vec<Game<Position<5>>> = ptn_parser::parse_ptn(ptn).unwrap();
+    let written_ptn = ptn_round_trip(&games[0]);
+    assert!(written_ptn.ends_with("\n\n1. a1 e5 2. c3 d3 3. b3 1-0\n\n"));
+}
+
+#[test]
+fn bad_variations_test() {
+    for ptn in [
+        // Unclosed variation
+        "1. a1 e5 (1... e1 2. c3",
+        // Closing a variation that was never opened
+        "1. a1 e5) 2. c3 *",
+        // Variation before the first move
+        "(1. a1) 1. e5 *",
+        // Illegal move in the variation, since e5 is already taken in the main line
+        "1. a1 e5 2. c3 (2. e5) *",
+    ] {
+        assert!(
+            ptn_parser::parse_ptn::<Position<5>>(ptn).is_err(),
+            "Parsed bad ptn {}",
+            ptn
+        );
+    }
+    // The same variation is legal when played from the position before the move
+    assert!(ptn_parser::parse_ptn::<Position<5>>("1. a1 e5 2. c3 (2. e4) *").is_ok());
+}
+
+#[test]
+fn komi_tag_test() {
+    let ptn = "[Size \"5\"]\n[Komi \"2.5\"]\n\n1. a1 e5 2. c3 *";
+    let games: Vec<Game<Position<5>>> = ptn_parser::parse_ptn(ptn).unwrap();
+    assert_eq!(
+        games[0].start_position.komi(),
+        Komi::from_half_komi(5).unwrap()
+    );
+
+    let ptn = "[TPS \"2,x4/x5/x5/x5/x4,1 1 2\"]\n[Komi \"-1\"]\n\n2. c3 *";
+    let games: Vec<Game<Position<5>>> = ptn_parser::parse_ptn(ptn).unwrap();
+    assert_eq!(
+        games[0].start_position.komi(),
+        Komi::from_half_komi(-2).unwrap()
+    );
+
+    assert!(ptn_parser::parse_ptn::<Position<5>>("[Komi \"two\"]\n\n1. a1 e5 *").is_err());
+}
+
+#[test]
+fn size_tag_test() {
+    let ptn = "[Size \"6\"]\n\n1. a1 e5 *";
+    assert!(ptn_parser::parse_ptn::<Position<6>>(ptn).is_ok());
+    let error = ptn_parser::parse_ptn::<Position<5>>(ptn).unwrap_err();
+    assert!(error.to_string().contains("Size"), "{}", error);
+}
+
+#[test]
+fn opening_tag_test() {
+    assert!(ptn_parser::parse_ptn::<Position<5>>("[Opening \"swap\"]\n\n1. a1 e5 *").is_ok());
+    assert!(ptn_parser::parse_ptn::<Position<5>>("[Opening \"no-swap\"]\n\n1. a1 e5 *").is_err());
+}
+
+#[test]
+fn comments_anywhere_test() {
+    let ptn = "{Before tags}[Player1 \"tiltak\"] {Between tags}\n[Size \"5\"]\n{Before the first move}\n1. {After move number} a1 {First} {Second} e5 2. c3 {Before result} 1-0";
+    let games: Vec<Game<Position<5>>> = ptn_parser::parse_ptn(ptn).unwrap();
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].tags.len(), 2);
+    assert_eq!(games[0].moves.len(), 3);
+    assert_eq!(games[0].moves[0].comment, "First Second");
+    assert_eq!(games[0].moves[2].comment, "Before result");
+    assert_eq!(games[0].game_result_str, Some("1-0"));
+
+    assert!(ptn_parser::parse_ptn::<Position<5>>("1. a1 {Unterminated e5 *").is_err());
+}
+
+#[test]
+fn error_location_test() {
+    let ptn = "[Size \"5\"]\n\n1. a1 e5 *\n\n[Size \"5\"]\n\n1. a1 e5\n2. c3 f9 *\n";
+    let error = ptn_parser::parse_ptn::<Position<5>>(ptn).unwrap_err();
+    assert_eq!(error.game_index, 1);
+    assert_eq!(error.line, 8);
+    assert_eq!(error.column, 7);
+}
+
+#[test]
+fn skip_bad_games_test() {
+    let ptn = "[Size \"5\"]\n\n1. a1 e5 *\n\n[Size \"6\"]\n[Player1 \"?\"]\n\n1. a1 e5 *\n\n[Size \"5\"]\n\n1. a1 e5\n2. c3 f9 *\n\n[Size \"5\"]\n\n1. e1 a5 R-0\n";
+    let (games, errors) = ptn_parser::parse_ptn_skipping_errors::<Position<5>>(ptn);
+    assert_eq!(games.len(), 2);
+    assert_eq!(games[1].game_result_str, Some("R-0"));
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].game_index, 1);
+    assert_eq!(errors[1].game_index, 2);
+    assert_eq!(errors[1].line, 13);
+
+    // Without skipping, the first bad game is an error
+    assert!(ptn_parser::parse_ptn::<Position<5>>(ptn).is_err());
+}
+
+#[test]
+fn ptn_reader_test() {
+    let ptn = "[Size \"5\"]\n\n1. a1 e5 *\n\n[Size \"6\"]\n[Player1 \"?\"]\n\n1. a1 e5 *\n\n[Size \"5\"]\n\n1. a1 e5\n2. c3 f9 *\n\n[Size \"5\"]\n\n1. e1 a5 R-0\n";
+    let results: Vec<_> =
+        ptn_parser::PtnReader::<_, Position<5>>::new(Cursor::new(ptn.as_bytes())).collect();
+    assert_eq!(results.len(), 4);
+    assert!(results[0].is_ok());
+    assert_eq!(results[1].as_ref().unwrap_err().game_index, 1);
+    let error = results[2].as_ref().unwrap_err();
+    assert_eq!(error.game_index, 2);
+    assert_eq!(error.line, 13);
+    assert_eq!(error.column, 7);
+    assert_eq!(results[3].as_ref().unwrap().game_result_str, Some("R-0"));
+
+    // The reader finds the same games as the full parser
+    let (games, errors) = ptn_parser::parse_ptn_skipping_errors::<Position<5>>(ptn);
+    assert_eq!(games.len(), 2);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[1].line, error.line);
+}
+
+#[test]
+fn ptn_reader_games_on_one_line_test() {
+    let ptn = "1. a1 e5 * 1. e1 a5 2. c3 0-R\n\n[Size \"5\"] 1. b2 d4 *";
+    let games: Vec<Game<Position<5>>> = ptn_parser::PtnReader::new(ptn.as_bytes())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(games.len(), 3);
+    assert_eq!(games[1].moves.len(), 3);
+    assert_eq!(games[1].game_result_str, Some("0-R"));
+    assert_eq!(games[2].moves.len(), 2);
+
+    assert!(
+        ptn_parser::PtnReader::<_, Position<5>>::new("\n\n".as_bytes())
+            .next()
+            .is_none()
+    );
+}
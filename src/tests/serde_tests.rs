@@ -0,0 +1,115 @@
+use board_game_traits::Position as PositionTrait;
+use pgn_traits::PgnPosition;
+use rand::SeedableRng;
+
+use crate::position::{Komi, Move, Position, Square};
+use crate::ptn::{ptn_parser, Game};
+
+#[test]
+fn move_serde_test() {
+    let mv = <Move<5>>::from_string("3c3>12").unwrap();
+    assert_eq!(serde_json::to_string(&mv).unwrap(), "\"3c3>12\"");
+    assert_eq!(serde_json::from_str::<Move<5>>("\"3c3>12\"").unwrap(), mv);
+    assert!(serde_json::from_str::<Move<5>>("\"f6\"").is_err());
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut moves = vec![];
+    for num_plies in 0..40 {
+        let position = <Position<6>>::random_position(&mut rng, num_plies);
+        moves.clear();
+        position.generate_moves(&mut moves);
+        for mv in moves.iter() {
+            let json = serde_json::to_string(mv).unwrap();
+            assert_eq!(serde_json::from_str::<Move<6>>(&json).unwrap(), *mv);
+        }
+    }
+}
+
+#[test]
+fn square_serde_test() {
+    let square = <Square<5>>::parse_square("c3").unwrap();
+    assert_eq!(serde_json::to_string(&square).unwrap(), "\"c3\"");
+    assert_eq!(serde_json::from_str::<Square<5>>("\"c3\"").unwrap(), square);
+    assert!(serde_json::from_str::<Square<5>>("\"e6\"").is_err());
+}
+
+#[test]
+fn komi_serde_test() {
+    let komi = Komi::from_half_komi(5).unwrap();
+    assert_eq!(serde_json::to_string(&komi).unwrap(), "2.5");
+    assert_eq!(serde_json::from_str::<Komi>("2.5").unwrap(), komi);
+    assert_eq!(serde_json::from_str::<Komi>("2").unwrap().half_komi(), 4);
+    assert!(serde_json::from_str::<Komi>("2.25").is_err());
+}
+
+#[test]
+fn position_with_history_serde_test() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+    for num_plies in 0..60 {
+        let position = <Position<5>>::random_position(&mut rng, num_plies);
+        let json = serde_json::to_string(&position).unwrap();
+        let deserialized: Position<5> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, position);
+        assert_eq!(deserialized.to_fen(), position.to_fen());
+        assert_eq!(deserialized.moves(), position.moves());
+        assert_eq!(deserialized.hash_history(), position.hash_history());
+        assert_eq!(deserialized.zobrist_hash(), position.zobrist_hash());
+    }
+}
+
+#[test]
+fn position_from_tps_serde_test() {
+    let settings = crate::position::Settings {
+        komi: Komi::from_half_komi(4).unwrap(),
+    };
+    let mut position =
+        <Position<5>>::from_fen_with_settings("x5/x5/x2,1,x2/x5/2,x4 1 2", &settings).unwrap();
+    position.do_move(position.move_from_san("d3").unwrap());
+
+    // The move history doesn't go back to the start of the game, so only the TPS is kept
+    let json = serde_json::to_string(&position).unwrap();
+    assert_eq!(
+        json,
+        format!("{{\"tps\":\"{}\",\"komi\":2.0}}", position.to_fen())
+    );
+    let deserialized: Position<5> = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized, position);
+    assert_eq!(deserialized.komi(), Komi::from_half_komi(4).unwrap());
+    assert!(deserialized.moves().is_empty());
+}
+
+#[test]
+fn bad_position_history_serde_test() {
+    // The history doesn't lead to the position
+    assert!(serde_json::from_str::<Position<5>>(
+        "{\"tps\":\"x5/x5/x5/x5/x5 1 2\",\"komi\":0,\"moves\":[\"a1\",\"e5\"]}"
+    )
+    .is_err());
+    // Illegal move in the history
+    assert!(serde_json::from_str::<Position<5>>(
+        "{\"tps\":\"2,x4/x5/x5/x5/x4,1 1 2\",\"komi\":0,\"moves\":[\"Sa5\",\"e1\"]}"
+    )
+    .is_err());
+    assert!(serde_json::from_str::<Position<5>>(
+        "{\"tps\":\"2,x4/x5/x5/x5/x4,1 1 2\",\"komi\":0,\"moves\":[\"a5\",\"e1\"]}"
+    )
+    .is_ok());
+}
+
+#[test]
+fn game_serde_test() {
+    let ptn = "[Size \"5\"]\n[Komi \"2\"]\n[Player1 \"Alice\"]\n\n1. a1 e5 2. c3 {Center} (2. b2? d4) d4 3. c4'! 0-R\n";
+    let game: Game<Position<5>> = ptn_parser::parse_ptn(ptn).unwrap().pop().unwrap();
+    let json = serde_json::to_string(&game).unwrap();
+    let deserialized: Game<Position<5>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized, game);
+    assert_eq!(deserialized.game_result_str, Some("0-R"));
+    assert_eq!(deserialized.moves[2].comment, "Center");
+    assert_eq!(
+        deserialized.moves[2].variations[0][0].annotations,
+        vec!["?"]
+    );
+    assert_eq!(deserialized.moves[4].annotations, vec!["'", "!"]);
+
+    assert!(serde_json::from_str::<Game<Position<5>>>(&json.replace("0-R", "2-0")).is_err());
+}
@@ -0,0 +1,119 @@
+use crate::position::Komi;
+use crate::search::{MctsSetting, TimeControl};
+use crate::tune::play_match::play_match;
+use crate::tune::sprt::{elo_to_score, score_to_elo, MatchStats, Sprt, SprtResult};
+
+#[test]
+fn elo_conversion_test() {
+    assert_eq!(score_to_elo(0.5), 0.0);
+    assert!((score_to_elo(0.75) - 190.85).abs() < 0.01);
+    assert!((score_to_elo(0.25) + 190.85).abs() < 0.01);
+    for elo in [-300.0, -10.0, 0.0, 5.0, 120.0] {
+        assert!((score_to_elo(elo_to_score(elo)) - elo).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn match_stats_test() {
+    let mut stats = MatchStats::default();
+    assert_eq!(stats.num_pairs(), 0);
+    assert_eq!(stats.los(), 0.5);
+    assert_eq!(stats.llr(0.0, 10.0), 0.0);
+
+    stats.add_pair(2, 0);
+    stats.add_pair(1, 1);
+    stats.add_pair(2, 1);
+    stats.add_pair(0, 1);
+    assert_eq!(stats.pentanomial, [0, 1, 2, 1, 0]);
+    assert_eq!((stats.wins, stats.draws, stats.losses), (2, 4, 2));
+    assert_eq!(stats.num_games(), 8);
+    assert_eq!(stats.score(), 0.5);
+    assert_eq!(stats.elo(), 0.0);
+    assert!((stats.los() - 0.5).abs() < 1e-6);
+    assert!(stats.elo_error() > 0.0);
+
+    stats.add_pair(2, 2);
+    assert!(stats.elo() > 0.0);
+    assert!(stats.los() > 0.5);
+}
+
+#[test]
+fn elo_error_shrinks_test() {
+    let mut stats = MatchStats::default();
+    for _ in 0..10 {
+        stats.add_pair(2, 1);
+        stats.add_pair(1, 0);
+        stats.add_pair(2, 0);
+    }
+    let error = stats.elo_error();
+    for _ in 0..90 {
+        stats.add_pair(2, 1);
+        stats.add_pair(1, 0);
+        stats.add_pair(2, 0);
+    }
+    // 10 times as many games gives about a third of the error
+    assert!((stats.elo_error() * 10.0_f64.sqrt() - error).abs() < 0.05 * error);
+}
+
+#[test]
+fn sprt_test() {
+    let sprt = Sprt::default();
+    let (lower, upper) = sprt.bounds();
+    assert!((lower + 2.944).abs() < 0.001);
+    assert!((upper - 2.944).abs() < 0.001);
+
+    let mut strong = MatchStats::default();
+    let mut weak = MatchStats::default();
+    let mut even = MatchStats::default();
+    for _ in 0..200 {
+        strong.add_pair(2, 1);
+        strong.add_pair(1, 1);
+        weak.add_pair(0, 1);
+        weak.add_pair(1, 1);
+        even.add_pair(2, 1);
+        even.add_pair(0, 1);
+    }
+    assert_eq!(sprt.result(&strong), Some(SprtResult::H1));
+    assert_eq!(sprt.result(&weak), Some(SprtResult::H0));
+    assert!(sprt.llr(&even) < 0.0);
+
+    let mut few_games = MatchStats::default();
+    few_games.add_pair(2, 1);
+    few_games.add_pair(1, 1);
+    assert_eq!(sprt.result(&few_games), None);
+
+    // A single decisive pair is not enough
+    let mut one_pair = MatchStats::default();
+    one_pair.add_pair(2, 2);
+    assert!(sprt.llr(&one_pair) > 0.0);
+    assert_eq!(sprt.result(&one_pair), None);
+
+    // The SPRT still finishes when every pair has the same result
+    let mut draws = MatchStats::default();
+    let mut wins = MatchStats::default();
+    for _ in 0..200 {
+        draws.add_pair(1, 1);
+        wins.add_pair(2, 2);
+    }
+    assert!(sprt.llr(&draws) < 0.0);
+    assert_eq!(sprt.result(&draws), Some(SprtResult::H0));
+    assert_eq!(sprt.result(&wins), Some(SprtResult::H1));
+}
+
+#[test]
+fn play_match_test() {
+    let nodes = 100;
+    let settings = MctsSetting::default().arena_size_for_nodes(nodes as u32);
+    let (stats, result) = play_match::<4>(
+        &settings,
+        &settings,
+        Komi::default(),
+        &TimeControl::FixedNodes(nodes),
+        None,
+        &Sprt::default(),
+        3,
+    );
+    assert_eq!(stats.num_pairs(), 3);
+    assert_eq!(stats.num_games(), 6);
+    assert_eq!(result, None);
+}
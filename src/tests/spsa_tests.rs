@@ -0,0 +1,115 @@
+use std::fs;
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::search::{MctsSetting, TimeControl, TimeManagement};
+use crate::tune::spsa::{self, SpsaParameter, SpsaSettings, SpsaState, Variable};
+
+const VARIABLES: &str = "# name value delta apply-factor min max
+c-puct-init 1.43 0.2 0.005 0.5 5
+c-puct-base 2800 1000 0.005 100 20000
+
+stop-visit-ratio 0.5 0.1 0.005 0.1 1
+";
+
+#[test]
+fn spsa_state_read_write_test() {
+    let state = SpsaState::read(Cursor::new(VARIABLES)).unwrap();
+    assert_eq!(state.iteration, 0);
+    assert_eq!(
+        state.variables[1],
+        Variable {
+            parameter: SpsaParameter::CPuctBase,
+            value: 2800.0,
+            delta: 1000.0,
+            apply_factor: 0.005,
+            min: 100.0,
+            max: 20000.0,
+        }
+    );
+
+    let mut output = vec![];
+    SpsaState {
+        iteration: 17,
+        ..state.clone()
+    }
+    .write(&mut output)
+    .unwrap();
+    let read_state = SpsaState::read(Cursor::new(&output)).unwrap();
+    assert_eq!(read_state.iteration, 17);
+    assert_eq!(read_state.variables, state.variables);
+
+    for parameter in SpsaParameter::ALL {
+        assert_eq!(
+            parameter.name().parse::<SpsaParameter>().unwrap(),
+            parameter
+        );
+    }
+    assert!(SpsaState::read(Cursor::new(VARIABLES.replace("c-puct-init", "c-puct"))).is_err());
+    assert!(SpsaState::read(Cursor::new(VARIABLES.replace("1.43", "6"))).is_err());
+    assert!(SpsaState::read(Cursor::new(VARIABLES.replace(" 0.5 5", " 0.5"))).is_err());
+    assert!(SpsaState::read(Cursor::new(VARIABLES.replace("c-puct-base", "c-puct-init"))).is_err());
+    assert!(SpsaState::read(Cursor::new("iteration 3\n")).is_err());
+    assert!(SpsaState::read(Cursor::new("time-left-share 0.2 0.05 0.005 -0.1 1\n")).is_err());
+    assert!(SpsaState::read(Cursor::new("time-left-share 0.2 0.05 0.005 0 1\n")).is_ok());
+}
+
+#[test]
+fn spsa_apply_test() {
+    let state = SpsaState::read(Cursor::new(VARIABLES)).unwrap();
+    let settings = state.apply(<MctsSetting<5>>::default());
+    assert_eq!(settings.search_params(), &[1.43, 2800.0, 0.61]);
+    assert_eq!(
+        settings.time_management(),
+        TimeManagement {
+            stop_visit_ratio: 0.5,
+            ..TimeManagement::default()
+        }
+    );
+
+    let settings = SpsaParameter::InitialMeanActionValue.apply(settings, 0.55);
+    let settings = SpsaParameter::TimeLeftShare.apply(settings, 0.1);
+    assert_eq!(settings.initial_mean_action_value(), 0.55);
+    assert_eq!(settings.c_puct_init(), 1.43);
+    assert_eq!(
+        settings
+            .time_management()
+            .max_time(Duration::from_secs(60), Duration::from_secs(2)),
+        Duration::from_secs(7)
+    );
+}
+
+#[test]
+fn spsa_tune_and_resume_test() {
+    let directory = std::env::temp_dir().join(format!("tiltak_spsa_test_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    let checkpoint_path = directory.join("checkpoint.txt");
+    let log_path = directory.join("log.csv");
+
+    let mut state = SpsaState::read(Cursor::new(VARIABLES)).unwrap();
+    let settings = <SpsaSettings<4>>::new(TimeControl::FixedNodes(50))
+        .checkpoint_file(&checkpoint_path)
+        .log_file(&log_path)
+        .max_iterations(3);
+    spsa::tune(&mut state, &settings).unwrap();
+    assert_eq!(state.iteration, 3);
+    for variable in state.variables.iter() {
+        assert!((variable.min..=variable.max).contains(&variable.value));
+    }
+    assert_eq!(SpsaState::from_file(&checkpoint_path).unwrap(), state);
+
+    // Resuming continues from the checkpoint, and appends to the log
+    let mut state = SpsaState::from_file(&checkpoint_path).unwrap();
+    spsa::tune(&mut state, &settings.max_iterations(5)).unwrap();
+    assert_eq!(state.iteration, 5);
+
+    let log = fs::read_to_string(&log_path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert!(lines[0].starts_with("iteration,result,c-puct-init,c-puct-base,stop-visit-ratio,"));
+    assert!(lines[5].starts_with("5,"));
+    assert_eq!(lines[5].split(',').count(), 11);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,214 @@
+use std::path::PathBuf;
+
+use board_game_traits::GameResult;
+use chrono::NaiveDate;
+
+use crate::position::{Komi, Position};
+use crate::sqlite::reports;
+use crate::sqlite::{GameDatabase, GameFilter};
+
+/// Create a temporary database with Playtak's schema. The file is deleted when the returned value is dropped
+struct TestDatabase {
+    path: PathBuf,
+}
+
+impl TestDatabase {
+    fn new(name: &str, sql: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("tiltak_{}_test_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(sql).unwrap();
+        TestDatabase { path }
+    }
+
+    fn open(&self) -> GameDatabase {
+        GameDatabase::open(self.path.to_str().unwrap()).unwrap()
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+const PLAYTAK_DATABASE: &str = "CREATE TABLE games (id INTEGER PRIMARY KEY, date INT, size INT, player_white VARCHAR(20), player_black VARCHAR(20), notation TEXT, result VARCHAR(10), timertime INT, timerinc INT, rating_white INT, rating_black INT, unrated INT, tournament INT, komi INT, pieces INT, capstones INT);
+    INSERT INTO games VALUES (12345, 1600000000000, 5, 'Alice', 'Bob', 'P A1,P E5,P C3 C,P B2 W,M C3 C4 1,P D4', '0-R', 600, 10, 1500, 1600, 0, 0, 4, 21, 1);
+    INSERT INTO games VALUES (12346, 1600000000000, 6, 'Bob', 'Alice', 'P A1,P F6', '0-0', 900, 20, 1500, 1600, 0, 0, NULL, NULL, NULL);
+    INSERT INTO games VALUES (12347, 1700000000000, 5, 'Carol', 'Alice', 'a1 e5 a2 e4 a3 e3 a4 e2 a5', 'R-0', 600, 10, 1700, 1700, 0, 0, 0, 21, 1);
+    INSERT INTO games VALUES (12348, 1700000000000, 5, 'Carol', 'Bob', 'P A1,P A1', 'R-0', 600, 10, 1700, 1700, 0, 0, 0, 21, 1);";
+
+#[test]
+fn read_records_test() {
+    let database = TestDatabase::new("records", PLAYTAK_DATABASE);
+    let records = database.open().records(&GameFilter::default()).unwrap();
+
+    assert_eq!(
+        records.iter().map(|record| record.id).collect::<Vec<_>>(),
+        vec![12345, 12346, 12347, 12348]
+    );
+    assert_eq!(records[0].player_white, "Alice");
+    assert_eq!(records[0].half_komi, 4);
+    assert_eq!(records[0].result, "0-R");
+    assert_eq!(records[1].half_komi, 0);
+    assert_eq!(records[1].pieces, -1);
+    assert_eq!(records[1].to_game::<6>().unwrap().moves.len(), 2);
+}
+
+#[test]
+fn missing_columns_test() {
+    let database = TestDatabase::new(
+        "missing_columns",
+        "CREATE TABLE games (size INT, notation TEXT);
+        INSERT INTO games VALUES (5, 'P A1,P E5,P C3');",
+    );
+    let records = database.open().records(&GameFilter::default()).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].id, 0);
+    assert_eq!(records[0].player_white, "");
+
+    let games: Vec<_> = database
+        .open()
+        .games::<5>(&GameFilter::default())
+        .unwrap()
+        .collect();
+    assert_eq!(games.len(), 1);
+    let game = games[0].as_ref().unwrap();
+    assert_eq!(game.moves.len(), 3);
+    assert_eq!(game.game_result(), None);
+
+    // Filtering on missing columns is an error, except for komi, which defaults to 0
+    let error = database
+        .open()
+        .records(&GameFilter::default().min_rating(1600))
+        .unwrap_err();
+    assert!(error.to_string().contains("rating_white"), "{}", error);
+    assert!(database
+        .open()
+        .records(&GameFilter::default().player("Alice"))
+        .is_err());
+    assert_eq!(
+        database
+            .open()
+            .records(&GameFilter::default().komi(Komi::default()))
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(database
+        .open()
+        .records(&GameFilter::default().komi(Komi::from_half_komi(4).unwrap()))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn game_filter_test() {
+    let database = TestDatabase::new("filter", PLAYTAK_DATABASE);
+    let db = database.open();
+    let ids = |filter: GameFilter| -> Vec<u64> {
+        db.records(&filter)
+            .unwrap()
+            .iter()
+            .map(|record| record.id)
+            .collect()
+    };
+
+    assert_eq!(ids(GameFilter::default().size(6)), vec![12346]);
+    assert_eq!(
+        ids(GameFilter::default().player("Bob")),
+        vec![12345, 12346, 12348]
+    );
+    assert_eq!(
+        ids(GameFilter::default().min_rating(1600)),
+        vec![12347, 12348]
+    );
+    assert_eq!(
+        ids(GameFilter::default().after(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap())),
+        vec![12347, 12348]
+    );
+    assert_eq!(
+        ids(GameFilter::default().before(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap())),
+        vec![12345, 12346]
+    );
+    assert_eq!(
+        ids(GameFilter::default().komi(Komi::default())),
+        vec![12346, 12347, 12348]
+    );
+    assert_eq!(
+        ids(GameFilter::default()
+            .size(5)
+            .player("Alice")
+            .komi(Komi::from_half_komi(4).unwrap())),
+        vec![12345]
+    );
+}
+
+#[test]
+fn games_and_positions_test() {
+    let database = TestDatabase::new("games", PLAYTAK_DATABASE);
+    let db = database.open();
+
+    let games: Vec<_> = db.games::<5>(&GameFilter::default()).unwrap().collect();
+    assert_eq!(games.len(), 3);
+    let first_game = games[0].as_ref().unwrap();
+    assert_eq!(first_game.moves.len(), 6);
+    assert_eq!(
+        first_game.start_position.komi(),
+        Komi::from_half_komi(4).unwrap()
+    );
+    assert_eq!(first_game.game_result(), Some(GameResult::BlackWin));
+    let second_game = games[1].as_ref().unwrap();
+    assert_eq!(second_game.moves.len(), 9);
+    assert_eq!(second_game.game_result(), Some(GameResult::WhiteWin));
+    // The last game has an illegal move
+    assert!(games[2].is_err());
+
+    let positions: Vec<_> = db.positions::<5>(&GameFilter::default()).unwrap().collect();
+    assert_eq!(positions.len(), 7 + 10);
+    assert_eq!(
+        positions[0].position,
+        <Position<5>>::start_position_with_komi(Komi::from_half_komi(4).unwrap())
+    );
+    assert_eq!(positions[0].next_move, Some(first_game.moves[0].mv));
+    assert!(positions[6].next_move.is_none());
+    assert!(positions.iter().all(
+        |position| position.game_result == Some(GameResult::BlackWin)
+            || position.game_result == Some(GameResult::WhiteWin)
+    ));
+}
+
+#[test]
+fn database_reports_test() {
+    let database = TestDatabase::new("reports", PLAYTAK_DATABASE);
+    let positions: Vec<_> = database
+        .open()
+        .positions::<5>(&GameFilter::default())
+        .unwrap()
+        .collect();
+
+    let policy_report = reports::policy_accuracy(&positions);
+    // Every position except the last one of each game
+    assert_eq!(policy_report.positions.iter().sum::<u64>(), 15);
+    assert!((0.0..=1.0).contains(&policy_report.top1_accuracy()));
+
+    let value_report = reports::value_calibration(&positions);
+    // Neither game ends on the board, so all their positions are evaluated
+    assert_eq!(value_report.phases[0].positions, 17);
+    assert_eq!(
+        value_report
+            .buckets
+            .iter()
+            .map(|stats| stats.positions)
+            .sum::<u64>(),
+        17
+    );
+    let mean_result = value_report.phases[0].mean_result();
+    assert!((mean_result - 10.0 / 17.0).abs() < 1e-9);
+    assert!((0.0..=1.0).contains(&value_report.phases[0].brier_score()));
+
+    reports::check_immediate_wins(&positions);
+    assert!(!policy_report.to_string().is_empty());
+    assert!(!value_report.to_string().is_empty());
+}
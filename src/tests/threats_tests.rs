@@ -0,0 +1,85 @@
+use board_game_traits::{Color, Position as PositionTrait};
+use pgn_traits::PgnPosition;
+
+use crate::position::{Move, Position};
+
+/// Checks that `color` has exactly the expected immediate road wins, in all symmetries of the position
+fn road_winning_moves_prop<const S: usize>(tps: &str, color: Color, expected_moves: &[&str]) {
+    let position = <Position<S>>::from_fen(tps).unwrap();
+    let mut winning_moves: Vec<String> = position
+        .road_winning_moves(color)
+        .iter()
+        .map(Move::to_string)
+        .collect();
+    winning_moves.sort();
+
+    let mut expected_moves: Vec<String> = expected_moves.iter().map(|s| s.to_string()).collect();
+    expected_moves.sort();
+
+    assert_eq!(
+        winning_moves, expected_moves,
+        "Wrong road wins for\n{:?}",
+        position
+    );
+
+    for symmetry in position.symmetries() {
+        assert_eq!(
+            symmetry.road_winning_moves(color).len(),
+            expected_moves.len(),
+            "Wrong number of road wins for symmetry\n{:?}",
+            symmetry
+        );
+    }
+}
+
+#[test]
+fn placement_threat_test() {
+    let tps = "1,1,1,1,x/2,2,2,x2/x5/x5/x5 1 4";
+    road_winning_moves_prop::<5>(tps, Color::White, &["e5", "Ce5"]);
+    road_winning_moves_prop::<5>(tps, Color::Black, &[]);
+}
+
+#[test]
+fn tak_threats_test() {
+    let white_to_move = <Position<5>>::from_fen("1,1,1,1,x/2,2,2,x2/x5/x5/x5 1 4").unwrap();
+    assert!(white_to_move.tak_threats().is_empty());
+
+    let black_to_move = <Position<5>>::from_fen("1,1,1,1,x/2,2,2,x2/x5/x5/x5 2 4").unwrap();
+    assert_eq!(black_to_move.tak_threats().len(), 2);
+}
+
+#[test]
+fn no_threats_in_opening_test() {
+    let mut position = <Position<5>>::start_position();
+    assert!(position.tak_threats().is_empty());
+    position.do_move(position.move_from_san("a1").unwrap());
+    assert!(position.tak_threats().is_empty());
+    assert!(position.road_winning_moves(Color::Black).is_empty());
+}
+
+#[test]
+fn single_spread_threat_test() {
+    let tps = "1,1,1,2,1/x3,1,x/2,2,2,x2/x5/x5 1 5";
+    road_winning_moves_prop::<5>(tps, Color::White, &["d4+"]);
+}
+
+#[test]
+fn long_spread_threat_test() {
+    let tps = "x5/1,21111,x3/x5/x5/2,2,2,x2 1 6";
+    road_winning_moves_prop::<5>(tps, Color::White, &["3b4>111"]);
+}
+
+#[test]
+fn double_threat_test() {
+    let tps = "1,1,1,1,x/1,2,2,2,x/1,x4/1,2,x3/x5 2 6";
+    road_winning_moves_prop::<5>(tps, Color::White, &["e5", "Ce5", "a1", "Ca1"]);
+
+    let position = <Position<5>>::from_fen(tps).unwrap();
+    let mut threatened_squares: Vec<_> = position
+        .tak_threats()
+        .iter()
+        .map(|mv| mv.origin_square())
+        .collect();
+    threatened_squares.dedup();
+    assert_eq!(threatened_squares.len(), 2);
+}
@@ -0,0 +1,287 @@
+use std::io;
+
+use board_game_traits::{GameResult, Position as PositionTrait};
+use half::f16;
+use pgn_traits::PgnPosition;
+
+use crate::position::{Komi, Move, Piece, Position, PositionBuilder, Square};
+use crate::ptn::{Game, PtnMove};
+use crate::tune::training_data::{
+    self, TrainingDataReader, TrainingDataWriter, TrainingRecord, TRAINING_DATA_VERSION,
+};
+
+fn uniform_move_scores<const S: usize>(position: &Position<S>) -> Vec<(Move<S>, f16)> {
+    let mut moves = vec![];
+    position.generate_moves(&mut moves);
+    let score = f16::from_f32(1.0 / moves.len() as f32);
+    moves.into_iter().map(|mv| (mv, score)).collect()
+}
+
+fn record<const S: usize>(position: Position<S>, game_result: GameResult) -> TrainingRecord<S> {
+    TrainingRecord {
+        move_scores: uniform_move_scores(&position),
+        position,
+        result: training_data::result_score(game_result),
+    }
+}
+
+fn write_records<const S: usize>(komi: Komi, records: &[TrainingRecord<S>]) -> Vec<u8> {
+    let mut writer = TrainingDataWriter::<_, S>::new(vec![], komi).unwrap();
+    for record in records {
+        writer.write_record(record).unwrap();
+    }
+    writer.into_inner()
+}
+
+fn read_records<const S: usize>(bytes: &[u8]) -> io::Result<Vec<TrainingRecord<S>>> {
+    TrainingDataReader::<_, S>::new(bytes)?.collect()
+}
+
+#[test]
+fn training_data_round_trip_test() {
+    let komi = Komi::from_half_komi(4).unwrap();
+    let position =
+        <Position<5>>::from_fen_with_komi("2,1,x3/x,12S,x3/x2,2C,x2/x,1,x3/x5 1 5", komi).unwrap();
+    // A stack taller than 8 pieces, to test the buried pieces' colors over several bytes
+    let tall_stack = [
+        Piece::WhiteFlat,
+        Piece::BlackFlat,
+        Piece::BlackFlat,
+        Piece::WhiteFlat,
+        Piece::WhiteFlat,
+        Piece::BlackFlat,
+        Piece::WhiteFlat,
+        Piece::BlackFlat,
+        Piece::BlackFlat,
+        Piece::WhiteFlat,
+        Piece::BlackCap,
+    ];
+    let tall_position = PositionBuilder::<5>::new()
+        .komi(komi)
+        .stack(Square::parse_square("c3").unwrap(), &tall_stack)
+        .move_number(12)
+        .build()
+        .unwrap();
+
+    let records = vec![
+        record(
+            <Position<5>>::start_position_with_komi(komi),
+            GameResult::Draw,
+        ),
+        record(position, GameResult::WhiteWin),
+        record(tall_position, GameResult::BlackWin),
+    ];
+    let bytes = write_records(komi, &records);
+
+    let reader = TrainingDataReader::<_, 5>::new(&bytes[..]).unwrap();
+    assert_eq!(reader.komi(), komi);
+    assert_eq!(reader.version(), TRAINING_DATA_VERSION);
+    let read_records: Vec<TrainingRecord<5>> = reader.collect::<io::Result<_>>().unwrap();
+
+    assert_eq!(read_records, records);
+    for (read_record, record) in read_records.iter().zip(&records) {
+        assert_eq!(read_record.position.to_fen(), record.position.to_fen());
+        assert_eq!(
+            read_record.position.zobrist_hash(),
+            record.position.zobrist_hash()
+        );
+    }
+}
+
+#[test]
+fn bad_training_data_test() {
+    let komi = Komi::default();
+    let records = vec![record(
+        <Position<4>>::start_position_with_komi(komi),
+        GameResult::Draw,
+    )];
+    let bytes = write_records(komi, &records);
+    assert_eq!(read_records::<4>(&bytes).unwrap(), records);
+
+    // Wrong size
+    assert!(TrainingDataReader::<_, 5>::new(&bytes[..]).is_err());
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = b'X';
+    assert!(TrainingDataReader::<_, 4>::new(&bad_magic[..]).is_err());
+
+    let mut future_version = bytes.clone();
+    future_version[4..6].copy_from_slice(&(TRAINING_DATA_VERSION + 1).to_le_bytes());
+    assert!(TrainingDataReader::<_, 4>::new(&future_version[..]).is_err());
+
+    // Truncated in the middle of a record
+    assert!(read_records::<4>(&bytes[..bytes.len() - 1]).is_err());
+
+    // A move that's not legal in the position, a wall placement on a1 in the first ply
+    let mut illegal_move = write_records(
+        komi,
+        &[TrainingRecord {
+            position: <Position<4>>::start_position_with_komi(komi),
+            result: training_data::result_score(GameResult::Draw),
+            move_scores: vec![(Move::from_string("a1").unwrap(), f16::ONE)],
+        }],
+    );
+    let len = illegal_move.len();
+    illegal_move[len - 4..len - 2].copy_from_slice(
+        &Move::<4>::from_string("Sa1")
+            .unwrap()
+            .to_u16()
+            .to_le_bytes(),
+    );
+    assert!(read_records::<4>(&illegal_move).is_err());
+
+    // Writing a position with different komi than the file
+    let mut writer =
+        TrainingDataWriter::<_, 4>::new(vec![], Komi::from_half_komi(4).unwrap()).unwrap();
+    assert!(writer.write_record(&records[0]).is_err());
+}
+
+#[test]
+fn records_from_games_test() {
+    let mut position = <Position<5>>::start_position();
+    let mut moves = vec![];
+    let mut move_scores = vec![];
+    for move_string in ["a1", "e5", "c3", "c2"] {
+        move_scores.push(uniform_move_scores(&position));
+        let mv = position.move_from_san(move_string).unwrap();
+        position.do_move(mv);
+        moves.push(PtnMove {
+            mv,
+            annotations: vec![],
+            comment: String::new(),
+            variations: vec![],
+        });
+    }
+    let game = Game {
+        start_position: <Position<5>>::start_position(),
+        moves,
+        game_result_str: Some("0-1"),
+        tags: vec![],
+    };
+
+    let records = training_data::records_from_games(&[game], &[move_scores.clone()]);
+    assert_eq!(records.len(), 4);
+    assert!(records.iter().all(|record| record.result == f16::ZERO));
+    assert_eq!(records[0].position, <Position<5>>::start_position());
+    assert_eq!(records[3].move_scores, move_scores[3]);
+}
+
+#[test]
+fn training_data_games_test() {
+    let komi = Komi::default();
+    let mut position = <Position<4>>::start_position();
+    let mut game = vec![];
+    for move_string in ["a1", "d4", "b2"] {
+        game.push(record(position.clone(), GameResult::WhiteWin));
+        position.do_move(position.move_from_san(move_string).unwrap());
+    }
+    let single_record = record(position, GameResult::BlackWin);
+
+    let mut writer = TrainingDataWriter::<_, 4>::new(vec![], komi).unwrap();
+    writer.write_game(&game).unwrap();
+    writer.write_game(&[]).unwrap();
+    writer.write_record(&single_record).unwrap();
+    let bytes = writer.into_inner();
+
+    let mut reader = TrainingDataReader::<_, 4>::new(&bytes[..]).unwrap();
+    assert_eq!(reader.read_record().unwrap().as_ref(), Some(&game[0]));
+    // Reads the rest of the partially read game
+    assert_eq!(reader.read_game().unwrap().unwrap(), game[1..]);
+    assert_eq!(reader.read_game().unwrap().unwrap(), vec![]);
+    assert_eq!(
+        reader.read_game().unwrap().unwrap(),
+        vec![single_record.clone()]
+    );
+    assert!(reader.read_game().unwrap().is_none());
+
+    // Empty games are skipped when reading records
+    let mut all_records = game.clone();
+    all_records.push(single_record);
+    assert_eq!(read_records::<4>(&bytes).unwrap(), all_records);
+}
+
+#[test]
+fn read_version_1_test() {
+    // Version 1 has no games, and the result is a single byte
+    let mut bytes = b"TKTD".to_vec();
+    bytes.extend_from_slice(&1_u16.to_le_bytes());
+    bytes.extend_from_slice(&[4, 0]);
+    for result_byte in [0, 2] {
+        bytes.extend_from_slice(&0_u16.to_le_bytes());
+        bytes.extend_from_slice(&[15, 0, 15, 0]);
+        bytes.extend_from_slice(&[0; 16]);
+        bytes.push(result_byte);
+        bytes.extend_from_slice(&1_u16.to_le_bytes());
+        bytes.extend_from_slice(&Move::<4>::from_string("a1").unwrap().to_u16().to_le_bytes());
+        bytes.extend_from_slice(&f16::ONE.to_le_bytes());
+    }
+
+    let mut reader = TrainingDataReader::<_, 4>::new(&bytes[..]).unwrap();
+    assert_eq!(reader.version(), 1);
+    let game = reader.read_game().unwrap().unwrap();
+    assert_eq!(game.len(), 1);
+    assert_eq!(game[0].position, <Position<4>>::start_position());
+    assert_eq!(game[0].result, f16::ONE);
+    let records: Vec<TrainingRecord<4>> = reader.collect::<io::Result<_>>().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].result, f16::ZERO);
+}
+
+#[test]
+fn deduplicate_symmetries_test() {
+    let mut position_a1 = <Position<5>>::start_position();
+    position_a1.do_move(position_a1.move_from_san("a1").unwrap());
+    let mut position_e5 = <Position<5>>::start_position();
+    position_e5.do_move(position_e5.move_from_san("e5").unwrap());
+    let mut position_c3 = <Position<5>>::start_position();
+    position_c3.do_move(position_c3.move_from_san("c3").unwrap());
+
+    let one_move = |position: &Position<5>, san: &str| TrainingRecord {
+        position: position.clone(),
+        result: f16::ONE,
+        move_scores: vec![(position.move_from_san(san).unwrap(), f16::ONE)],
+    };
+    let records = vec![
+        record(position_a1.clone(), GameResult::WhiteWin),
+        record(position_c3.clone(), GameResult::WhiteWin),
+        record(position_e5.clone(), GameResult::BlackWin),
+        record(position_c3.clone(), GameResult::Draw),
+        record(position_a1.flip_board_x(), GameResult::Draw),
+    ];
+    let deduplicated = training_data::deduplicate(records);
+    assert_eq!(deduplicated.len(), 2);
+    assert_eq!(
+        deduplicated[0].position.to_fen(),
+        position_a1.canonical_tps()
+    );
+    assert_eq!(deduplicated[1].position.to_fen(), position_c3.to_fen());
+    // The results are averaged
+    assert_eq!(deduplicated[0].result, f16::from_f32(0.5));
+    assert_eq!(deduplicated[1].result, f16::from_f32(0.75));
+    // Uniform move scores stay uniform, since the moves are mapped into the canonical orientation
+    assert_eq!(deduplicated[0].move_scores.len(), 24);
+    assert!(deduplicated[0]
+        .move_scores
+        .iter()
+        .all(|(_, score)| *score == f16::from_f32(1.0 / 24.0)));
+    let mut moves = vec![];
+    deduplicated[0].position.generate_moves(&mut moves);
+    for (mv, _) in &deduplicated[0].move_scores {
+        assert!(moves.contains(mv));
+    }
+
+    // A move played from each orientation of the same position is merged into a single move,
+    // and the scores for moves only some of them played are averaged over all of them
+    let deduplicated = training_data::deduplicate(vec![
+        one_move(&position_a1, "e1"),
+        one_move(&position_e5, "a5"),
+        one_move(&position_a1.flip_board_x(), "b1"),
+    ]);
+    assert_eq!(deduplicated.len(), 1);
+    let mut move_scores = deduplicated[0].move_scores.clone();
+    move_scores.sort_by(|(_, score1), (_, score2)| score1.partial_cmp(score2).unwrap());
+    assert_eq!(move_scores.len(), 2);
+    assert_eq!(move_scores[0].1, f16::from_f32(1.0 / 3.0));
+    assert_eq!(move_scores[1].1, f16::from_f32(2.0 / 3.0));
+    assert_eq!(deduplicated[0].result, f16::ONE);
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use crate::evaluation::parameters;
+use crate::position::{Komi, Position};
+use crate::tune::training::{self, LinearParams, SelfPlayParams};
+use crate::tune::training_run::{BatchResult, Manifest, TrainingConfig, MANIFEST_FILE_NAME};
+
+const N: usize = parameters::NUM_VALUE_FEATURES_4S;
+const M: usize = parameters::NUM_POLICY_FEATURES_4S;
+
+fn test_manifest() -> Manifest {
+    Manifest {
+        size: 4,
+        kind: "linear".to_string(),
+        config: TrainingConfig {
+            komi: Komi::from_half_komi(4).unwrap(),
+            batch_size: 100,
+            batches_for_training: 10,
+            nodes: 1000,
+            dirichlet_alpha: 0.25,
+        },
+        batch_id: 2,
+        num_games: 200,
+        params_files: vec![
+            "value_params_batch1.txt".to_string(),
+            "policy_params_batch1.txt".to_string(),
+        ],
+        last_params_files: vec![
+            "value_params_batch0.txt".to_string(),
+            "policy_params_batch0.txt".to_string(),
+        ],
+        history: vec![
+            BatchResult {
+                batch_id: 0,
+                wins: 40,
+                losses: 50,
+                draws: 10,
+            },
+            BatchResult {
+                batch_id: 1,
+                wins: 55,
+                losses: 40,
+                draws: 5,
+            },
+        ],
+    }
+}
+
+fn test_directory(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("tiltak_{}_test_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+}
+
+#[test]
+fn manifest_read_write_test() {
+    let manifest = test_manifest();
+    let mut output = vec![];
+    manifest.write(&mut output).unwrap();
+    assert_eq!(Manifest::read(Cursor::new(&output)).unwrap(), manifest);
+
+    let text = String::from_utf8(output).unwrap();
+    assert!(Manifest::read(Cursor::new(text.replace("version 1", "version 2"))).is_err());
+    assert!(Manifest::read(Cursor::new(text.replace("nodes 1000\n", ""))).is_err());
+    assert!(Manifest::read(Cursor::new(text.replace("batch 2", "batch two"))).is_err());
+    assert!(Manifest::read(Cursor::new(text.replace("result 1 55 40 5", "result 1 55"))).is_err());
+    assert!(Manifest::read(Cursor::new("")).is_err());
+}
+
+#[test]
+fn manifest_write_to_dir_test() {
+    let directory = test_directory("manifest");
+    fs::create_dir(&directory).unwrap();
+
+    let mut manifest = test_manifest();
+    manifest.write_to_dir(&directory).unwrap();
+    manifest.batch_id += 1;
+    manifest.write_to_dir(&directory).unwrap();
+    assert_eq!(Manifest::from_dir(&directory).unwrap(), manifest);
+
+    // Only the manifest itself is left behind
+    let file_names: Vec<_> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(file_names, [MANIFEST_FILE_NAME]);
+
+    fs::remove_dir_all(&directory).unwrap();
+    assert!(Manifest::from_dir(&directory).is_err());
+}
+
+#[test]
+fn linear_params_write_read_test() {
+    let directory = test_directory("linear_params");
+    fs::create_dir(&directory).unwrap();
+
+    let komi = Komi::default();
+    let params = LinearParams::<N, M> {
+        value_params: <Position<4>>::value_params(komi).try_into().unwrap(),
+        policy_params: <Position<4>>::policy_params(komi).try_into().unwrap(),
+    };
+    let file_names = SelfPlayParams::<4>::write(&params, &directory, "batch3", komi).unwrap();
+    assert_eq!(
+        file_names,
+        ["value_params_batch3.txt", "policy_params_batch3.txt"]
+    );
+    let read_params: LinearParams<N, M> =
+        SelfPlayParams::<4>::read(&directory, &file_names, komi).unwrap();
+    assert_eq!(read_params, params);
+
+    // 5s parameters can't be read as 4s parameters
+    assert!(
+        <LinearParams<N, M> as SelfPlayParams<5>>::read(&directory, &file_names, komi).is_err()
+    );
+    // Nor can parameters tuned for a different komi
+    assert!(
+        SelfPlayParams::<4>::read(&directory, &file_names, Komi::from_half_komi(4).unwrap())
+            .map(|_: LinearParams<N, M>| ())
+            .is_err()
+    );
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn resume_wrong_training_run_test() {
+    let directory = test_directory("resume");
+    fs::create_dir(&directory).unwrap();
+    test_manifest().write_to_dir(&directory).unwrap();
+
+    // The manifest is for a 4s run
+    assert!(training::continue_training::<
+        5,
+        { parameters::NUM_VALUE_FEATURES_5S },
+        { parameters::NUM_POLICY_FEATURES_5S },
+    >(&directory, None, None)
+    .is_err());
+
+    let mut manifest = test_manifest();
+    manifest.kind = "unknown".to_string();
+    manifest.write_to_dir(&directory).unwrap();
+    assert!(training::continue_training::<4, N, M>(&directory, None, None).is_err());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
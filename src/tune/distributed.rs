@@ -0,0 +1,683 @@
+//! Self-play on several machines: A coordinator hands out games to workers over TCP, and collects the results.
+//!
+//! The protocol is line-based text. A worker connects and sends `tiltak-worker version 1 size 5`,
+//! and the coordinator answers `ok`, or `error` and a reason before closing the connection.
+//! The worker then asks for one game at a time, and the coordinator answers with a job:
+//!
+//! ```text
+//! > job
+//! < job 17
+//! < komi 2
+//! < time-control nodes 50000
+//! < dirichlet-alpha 0.2
+//! < temperature 1
+//! < white 0
+//! < black 1
+//! < opening e1 a5
+//! < end
+//! ```
+//!
+//! `time-control` may also be `time` followed by the time and the increment in milliseconds, and `dirichlet-alpha` is optional.
+//! `white` and `black` are parameter sets. The first time a worker sees a parameter set, it asks for it:
+//!
+//! ```text
+//! > params 1
+//! < params 1
+//! < value 0.2 -0.53 0.1 ...
+//! < policy 0.96 -1.2 ...
+//! < end
+//! ```
+//!
+//! An empty `value` or `policy` line means the built-in parameters.
+//! When the game is finished, the worker sends `result 17 <ptn bytes> <move scores bytes>`,
+//! followed by the game as PTN, and its move scores in the same format as the training files.
+//! If the coordinator is shutting down, it answers `stop` instead of a job.
+//!
+//! While it's playing a game, the worker sends a `heartbeat` line every few seconds.
+//! If a worker disconnects in the middle of a game, or the coordinator doesn't hear from it for too long,
+//! the game is handed to the next worker that asks for one.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::position::{Komi, Move, Position};
+use crate::ptn::ptn_parser::parse_ptn;
+use crate::ptn::Game;
+use crate::search::{MctsSetting, TimeControl};
+use crate::tune::play_match::play_game;
+use crate::tune::training::MoveScoresForGame;
+use crate::tune::training::{parse_move_scores_for_game, write_move_scores_for_game};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long the coordinator waits for a message from a worker that is playing a game,
+/// before giving up on the worker and handing its game to another one
+pub const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often a worker sends a heartbeat while it's playing a game
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The longest line or result that is accepted from the other side, in bytes
+const MAX_MESSAGE_SIZE: usize = 16 << 20;
+
+/// Evaluation parameters that can be sent to workers. `None` means the built-in parameters
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorkerParams {
+    pub value_params: Option<Vec<f32>>,
+    pub policy_params: Option<Vec<f32>>,
+}
+
+impl WorkerParams {
+    pub fn mcts_settings<const S: usize>(&self) -> MctsSetting<S> {
+        let mut settings = MctsSetting::default();
+        if let Some(value_params) = &self.value_params {
+            settings = settings.add_value_params(value_params.clone().into_boxed_slice());
+        }
+        if let Some(policy_params) = &self.policy_params {
+            settings = settings.add_policy_params(policy_params.clone().into_boxed_slice());
+        }
+        settings
+    }
+}
+
+/// A game for a worker to play
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameJob<const S: usize> {
+    /// Parameter set for white, as returned by `Coordinator::add_params`
+    pub white: usize,
+    pub black: usize,
+    pub komi: Komi,
+    pub time_control: TimeControl,
+    pub dirichlet_alpha: Option<f32>,
+    pub temperature: f64,
+    pub opening: Vec<Move<S>>,
+}
+
+#[derive(Debug)]
+struct Shared<const S: usize> {
+    state: Mutex<State<S>>,
+    condvar: Condvar,
+}
+
+#[derive(Debug)]
+struct State<const S: usize> {
+    params: Vec<WorkerParams>,
+    queue: VecDeque<(u64, GameJob<S>)>,
+    results: HashMap<u64, (Game<Position<S>>, MoveScoresForGame<S>)>,
+    next_job_id: u64,
+    shutdown: bool,
+}
+
+/// Listens for workers in a background thread, and hands out games to them.
+/// The workers are told to stop when the coordinator is dropped.
+#[derive(Debug)]
+pub struct Coordinator<const S: usize> {
+    shared: Arc<Shared<S>>,
+    local_addr: SocketAddr,
+}
+
+impl<const S: usize> Coordinator<S> {
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::bind_with_worker_timeout(address, DEFAULT_WORKER_TIMEOUT)
+    }
+
+    /// Like `bind`, but with a different time to wait for unresponsive workers than `DEFAULT_WORKER_TIMEOUT`
+    pub fn bind_with_worker_timeout(
+        address: impl ToSocketAddrs,
+        worker_timeout: Duration,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                params: vec![],
+                queue: VecDeque::new(),
+                results: HashMap::new(),
+                next_job_id: 0,
+                shutdown: false,
+            }),
+            condvar: Condvar::new(),
+        });
+
+        let listener_shared = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if listener_shared.state.lock().unwrap().shutdown {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        eprintln!("Failed to accept worker: {}", err);
+                        continue;
+                    }
+                };
+                let shared = listener_shared.clone();
+                thread::spawn(move || {
+                    let peer = stream
+                        .peer_addr()
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_default();
+                    match handle_worker(stream, &shared, worker_timeout) {
+                        Ok(()) => println!("Worker {} disconnected", peer),
+                        Err(err) => eprintln!("Worker {} disconnected: {}", peer, err),
+                    }
+                });
+            }
+        });
+
+        Ok(Coordinator { shared, local_addr })
+    }
+
+    /// The address the coordinator is listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Make a parameter set available to workers. Returns its id, for use in `GameJob`
+    pub fn add_params(&self, params: WorkerParams) -> usize {
+        let mut state = self.shared.state.lock().unwrap();
+        state.params.push(params);
+        state.params.len() - 1
+    }
+
+    /// Let the workers play the games, and wait until all of them are finished.
+    /// Returns the games and their move scores, in the same order as the jobs.
+    pub fn play_games(
+        &self,
+        jobs: Vec<GameJob<S>>,
+    ) -> Vec<(Game<Position<S>>, MoveScoresForGame<S>)> {
+        let mut state = self.shared.state.lock().unwrap();
+        let first_job_id = state.next_job_id;
+        let num_jobs = jobs.len() as u64;
+        for job in jobs {
+            assert!(job.white < state.params.len() && job.black < state.params.len());
+            let job_id = state.next_job_id;
+            state.queue.push_back((job_id, job));
+            state.next_job_id += 1;
+        }
+        self.shared.condvar.notify_all();
+
+        let job_ids = first_job_id..first_job_id + num_jobs;
+        while !job_ids
+            .clone()
+            .all(|job_id| state.results.contains_key(&job_id))
+        {
+            state = self.shared.condvar.wait(state).unwrap();
+        }
+        job_ids
+            .map(|job_id| state.results.remove(&job_id).unwrap())
+            .collect()
+    }
+}
+
+impl<const S: usize> Drop for Coordinator<S> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.condvar.notify_all();
+        // Wake up the listener thread, so that it sees the shutdown
+        let _ = TcpStream::connect_timeout(&self.local_addr, Duration::from_secs(1));
+    }
+}
+
+/// Talk to a single worker, until it disconnects, times out or the coordinator shuts down.
+/// If that happens in the middle of a game, the game is put back in the queue
+fn handle_worker<const S: usize>(
+    stream: TcpStream,
+    shared: &Shared<S>,
+    worker_timeout: Duration,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(worker_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut in_flight: Option<(u64, GameJob<S>)> = None;
+
+    let result = serve_worker(&mut reader, &mut writer, shared, &mut in_flight).map_err(|err| {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("No message for {}s", worker_timeout.as_secs_f32()),
+            ),
+            _ => err,
+        }
+    });
+
+    if let Some(job) = in_flight {
+        shared.state.lock().unwrap().queue.push_front(job);
+        shared.condvar.notify_all();
+    }
+    result
+}
+
+fn serve_worker<const S: usize>(
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+    shared: &Shared<S>,
+    in_flight: &mut Option<(u64, GameJob<S>)>,
+) -> io::Result<()> {
+    let hello = read_line(reader)?;
+    let expected_hello = format!("tiltak-worker version {} size {}", PROTOCOL_VERSION, S);
+    if hello != expected_hello {
+        writeln!(writer, "error expected \"{}\"", expected_hello)?;
+        writer.flush()?;
+        return Err(invalid_data(format!("Bad hello \"{}\"", hello)));
+    }
+    writeln!(writer, "ok")?;
+    writer.flush()?;
+
+    loop {
+        let line = match read_line(reader) {
+            Ok(line) => line,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && in_flight.is_none() => {
+                return Ok(())
+            }
+            Err(err) => return Err(err),
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["job"] => {
+                let mut state = shared.state.lock().unwrap();
+                while state.queue.is_empty() && !state.shutdown {
+                    state = shared.condvar.wait(state).unwrap();
+                }
+                if state.shutdown {
+                    drop(state);
+                    writeln!(writer, "stop")?;
+                    writer.flush()?;
+                    return Ok(());
+                }
+                let (job_id, job) = state.queue.pop_front().unwrap();
+                drop(state);
+                write_job(writer, job_id, &job)?;
+                *in_flight = Some((job_id, job));
+            }
+            ["params", params_id] => {
+                let params_id: usize = parse(params_id, "parameter set")?;
+                let params = shared.state.lock().unwrap().params.get(params_id).cloned();
+                let params = params
+                    .ok_or_else(|| invalid_data(format!("Unknown parameter set {}", params_id)))?;
+                write_params(writer, params_id, &params)?;
+            }
+            ["heartbeat"] => (),
+            ["result", job_id, ptn_length, move_scores_length] => {
+                let job_id: u64 = parse(job_id, "job")?;
+                let ptn = read_bytes(reader, parse(ptn_length, "length")?)?;
+                let move_scores = read_bytes(reader, parse(move_scores_length, "length")?)?;
+                let Some((in_flight_id, job)) = in_flight.take() else {
+                    return Err(invalid_data(format!(
+                        "Unexpected result for job {}",
+                        job_id
+                    )));
+                };
+                if in_flight_id != job_id {
+                    *in_flight = Some((in_flight_id, job));
+                    return Err(invalid_data(format!(
+                        "Got result for job {}, expected {}",
+                        job_id, in_flight_id
+                    )));
+                }
+                let game_result = parse_result::<S>(&ptn, &move_scores, &job);
+                let game_result = match game_result {
+                    Ok(game_result) => game_result,
+                    Err(err) => {
+                        *in_flight = Some((in_flight_id, job));
+                        return Err(err);
+                    }
+                };
+                shared
+                    .state
+                    .lock()
+                    .unwrap()
+                    .results
+                    .insert(job_id, game_result);
+                shared.condvar.notify_all();
+            }
+            _ => return Err(invalid_data(format!("Bad request \"{}\"", line))),
+        }
+    }
+}
+
+fn parse_result<const S: usize>(
+    ptn: &str,
+    move_scores: &str,
+    job: &GameJob<S>,
+) -> io::Result<(Game<Position<S>>, MoveScoresForGame<S>)> {
+    let mut games = parse_ptn::<Position<S>>(ptn).map_err(|err| invalid_data(err.to_string()))?;
+    let move_scores = parse_move_scores_for_game::<S>(move_scores)
+        .map_err(|err| invalid_data(err.to_string()))?;
+    let (Some(game), true) = (games.pop(), games.is_empty()) else {
+        return Err(invalid_data("Expected a single game in result"));
+    };
+    if game.start_position.komi() != job.komi {
+        return Err(invalid_data(format!(
+            "Game has komi {}, expected {}",
+            game.start_position.komi(),
+            job.komi
+        )));
+    }
+    if game.moves.len() != move_scores.len()
+        || game.moves.len() < job.opening.len()
+        || !game
+            .moves
+            .iter()
+            .zip(job.opening.iter())
+            .all(|(ptn_move, mv)| ptn_move.mv == *mv)
+    {
+        return Err(invalid_data("Game doesn't match its job"));
+    }
+    Ok((game, move_scores))
+}
+
+/// Connect to a coordinator with `threads` connections, each playing one game at a time,
+/// until the coordinator tells them to stop. Returns the number of games played
+pub fn run_worker<const S: usize>(address: &str, threads: usize) -> io::Result<u64> {
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let address = address.to_string();
+            thread::spawn(move || worker_connection::<S>(&address))
+        })
+        .collect();
+    let mut num_games = 0;
+    for handle in handles {
+        num_games += handle.join().unwrap()?;
+    }
+    Ok(num_games)
+}
+
+fn worker_connection<const S: usize>(address: &str) -> io::Result<u64> {
+    let stream = TcpStream::connect(address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    writeln!(
+        writer,
+        "tiltak-worker version {} size {}",
+        PROTOCOL_VERSION, S
+    )?;
+    writer.flush()?;
+    let answer = read_line(&mut reader)?;
+    if answer != "ok" {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("Coordinator refused connection: {}", answer),
+        ));
+    }
+
+    let mut params_cache: HashMap<usize, WorkerParams> = HashMap::new();
+    let mut num_games = 0;
+    loop {
+        writeln!(writer, "job")?;
+        writer.flush()?;
+        let Some((job_id, job)) = read_job::<S>(&mut reader)? else {
+            return Ok(num_games);
+        };
+
+        for params_id in [job.white, job.black] {
+            if let Entry::Vacant(entry) = params_cache.entry(params_id) {
+                writeln!(writer, "params {}", params_id)?;
+                writer.flush()?;
+                entry.insert(read_params(&mut reader, params_id)?);
+            }
+        }
+        let player_settings = |params_id: usize| {
+            let settings = params_cache[&params_id].mcts_settings::<S>();
+            let settings = match job.time_control {
+                TimeControl::FixedNodes(nodes) => settings.arena_size_for_nodes(nodes as u32),
+                TimeControl::Time(_, _) => settings,
+            };
+            match job.dirichlet_alpha {
+                Some(alpha) => settings.add_dirichlet(alpha),
+                None => settings,
+            }
+        };
+
+        let (game, move_scores) = with_heartbeats(writer.get_ref(), || {
+            play_game::<S>(
+                &player_settings(job.white),
+                &player_settings(job.black),
+                job.komi,
+                &job.opening,
+                None,
+                job.temperature,
+                &job.time_control,
+            )
+        })?;
+
+        let mut ptn = vec![];
+        game.game_to_ptn(&mut ptn)?;
+        let mut move_scores_text = vec![];
+        write_move_scores_for_game(&mut move_scores_text, &game, &move_scores)?;
+        writeln!(
+            writer,
+            "result {} {} {}",
+            job_id,
+            ptn.len(),
+            move_scores_text.len()
+        )?;
+        writer.write_all(&ptn)?;
+        writer.write_all(&move_scores_text)?;
+        writer.flush()?;
+        num_games += 1;
+    }
+}
+
+/// Run `f`, while sending a heartbeat to the coordinator every `HEARTBEAT_INTERVAL` from another thread.
+/// Nothing else may be written to the stream in the meantime
+fn with_heartbeats<T>(stream: &TcpStream, f: impl FnOnce() -> T) -> io::Result<T> {
+    let (done_sender, done_receiver) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        let heartbeats = scope.spawn(move || -> io::Result<()> {
+            let mut stream = stream;
+            while let Err(RecvTimeoutError::Timeout) =
+                done_receiver.recv_timeout(HEARTBEAT_INTERVAL)
+            {
+                stream.write_all(b"heartbeat\n")?;
+            }
+            Ok(())
+        });
+        let result = f();
+        drop(done_sender);
+        heartbeats.join().unwrap()?;
+        Ok(result)
+    })
+}
+
+fn write_job<const S: usize>(
+    writer: &mut impl Write,
+    job_id: u64,
+    job: &GameJob<S>,
+) -> io::Result<()> {
+    writeln!(writer, "job {}", job_id)?;
+    writeln!(writer, "komi {}", job.komi)?;
+    match job.time_control {
+        TimeControl::FixedNodes(nodes) => writeln!(writer, "time-control nodes {}", nodes)?,
+        TimeControl::Time(time, increment) => writeln!(
+            writer,
+            "time-control time {} {}",
+            time.as_millis(),
+            increment.as_millis()
+        )?,
+    }
+    if let Some(alpha) = job.dirichlet_alpha {
+        writeln!(writer, "dirichlet-alpha {}", alpha)?;
+    }
+    writeln!(writer, "temperature {}", job.temperature)?;
+    writeln!(writer, "white {}", job.white)?;
+    writeln!(writer, "black {}", job.black)?;
+    write!(writer, "opening")?;
+    for mv in job.opening.iter() {
+        write!(writer, " {}", mv)?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "end")?;
+    writer.flush()
+}
+
+/// Read a job sent by `write_job`, or `None` if the coordinator said stop
+fn read_job<const S: usize>(reader: &mut impl BufRead) -> io::Result<Option<(u64, GameJob<S>)>> {
+    let first_line = read_line(reader)?;
+    let job_id = match first_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["stop"] => return Ok(None),
+        ["job", job_id] => parse(job_id, "job")?,
+        _ => {
+            return Err(invalid_data(format!(
+                "Expected job, got \"{}\"",
+                first_line
+            )))
+        }
+    };
+
+    let mut komi = None;
+    let mut time_control = None;
+    let mut dirichlet_alpha = None;
+    let mut temperature = None;
+    let mut white = None;
+    let mut black = None;
+    let mut opening = None;
+    loop {
+        let line = read_line(reader)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["end"] => break,
+            ["komi", value] => komi = Some(parse(value, "komi")?),
+            ["time-control", "nodes", nodes] => {
+                time_control = Some(TimeControl::FixedNodes(parse(nodes, "nodes")?))
+            }
+            ["time-control", "time", time, increment] => {
+                time_control = Some(TimeControl::Time(
+                    Duration::from_millis(parse(time, "time")?),
+                    Duration::from_millis(parse(increment, "increment")?),
+                ))
+            }
+            ["dirichlet-alpha", value] => dirichlet_alpha = Some(parse(value, "dirichlet alpha")?),
+            ["temperature", value] => temperature = Some(parse(value, "temperature")?),
+            ["white", value] => white = Some(parse(value, "parameter set")?),
+            ["black", value] => black = Some(parse(value, "parameter set")?),
+            ["opening", ref moves @ ..] => {
+                opening = Some(
+                    moves
+                        .iter()
+                        .map(|mv| {
+                            Move::from_string(mv).map_err(|err| invalid_data(err.to_string()))
+                        })
+                        .collect::<io::Result<Vec<Move<S>>>>()?,
+                )
+            }
+            _ => return Err(invalid_data(format!("Bad job line \"{}\"", line))),
+        }
+    }
+    let missing = |name: &str| invalid_data(format!("Job is missing {}", name));
+    Ok(Some((
+        job_id,
+        GameJob {
+            white: white.ok_or_else(|| missing("white"))?,
+            black: black.ok_or_else(|| missing("black"))?,
+            komi: komi.ok_or_else(|| missing("komi"))?,
+            time_control: time_control.ok_or_else(|| missing("time-control"))?,
+            dirichlet_alpha,
+            temperature: temperature.ok_or_else(|| missing("temperature"))?,
+            opening: opening.ok_or_else(|| missing("opening"))?,
+        },
+    )))
+}
+
+fn write_params(
+    writer: &mut impl Write,
+    params_id: usize,
+    params: &WorkerParams,
+) -> io::Result<()> {
+    writeln!(writer, "params {}", params_id)?;
+    for (name, values) in [
+        ("value", &params.value_params),
+        ("policy", &params.policy_params),
+    ] {
+        write!(writer, "{}", name)?;
+        for value in values.iter().flatten() {
+            write!(writer, " {}", value)?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "end")?;
+    writer.flush()
+}
+
+fn read_params(reader: &mut impl BufRead, params_id: usize) -> io::Result<WorkerParams> {
+    let first_line = read_line(reader)?;
+    if first_line != format!("params {}", params_id) {
+        return Err(invalid_data(format!(
+            "Expected parameter set {}, got \"{}\"",
+            params_id, first_line
+        )));
+    }
+    let mut params = WorkerParams::default();
+    loop {
+        let line = read_line(reader)?;
+        let mut words = line.split_whitespace();
+        let values = match words.next() {
+            Some("end") => return Ok(params),
+            Some("value") => &mut params.value_params,
+            Some("policy") => &mut params.policy_params,
+            _ => return Err(invalid_data(format!("Bad parameters line \"{}\"", line))),
+        };
+        let parsed = words
+            .map(|value| parse(value, "parameter"))
+            .collect::<io::Result<Vec<f32>>>()?;
+        *values = if parsed.is_empty() {
+            None
+        } else {
+            Some(parsed)
+        };
+    }
+}
+
+/// Read a line, without the line ending. Fails if the connection was closed, or the line is longer than `MAX_MESSAGE_SIZE`
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    if reader
+        .take(MAX_MESSAGE_SIZE as u64 + 1)
+        .read_line(&mut line)?
+        == 0
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed",
+        ));
+    }
+    if line.len() > MAX_MESSAGE_SIZE {
+        return Err(invalid_data(format!(
+            "Line is longer than {} bytes",
+            MAX_MESSAGE_SIZE
+        )));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+/// Read exactly `length` bytes of text. Fails without reading anything if `length` is larger than `MAX_MESSAGE_SIZE`
+fn read_bytes(reader: &mut impl Read, length: usize) -> io::Result<String> {
+    if length > MAX_MESSAGE_SIZE {
+        return Err(invalid_data(format!(
+            "Message of {} bytes is longer than {} bytes",
+            length, MAX_MESSAGE_SIZE
+        )));
+    }
+    let mut bytes = vec![0; length];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|err| invalid_data(err.to_string()))
+}
+
+fn parse<T: FromStr>(value: &str, name: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid_data(format!("Bad {} \"{}\"", name, value)))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
@@ -1,7 +1,13 @@
 use half::f16;
 use log::trace;
+use pgn_traits::Error;
 use rayon::prelude::*;
-use std::{array, time::Instant};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::{array, str, time::Instant};
+
+use crate::evaluation::parameter_file::ParameterKind;
 
 pub struct TrainingSample<const N: usize> {
     pub features: [f16; N],
@@ -9,23 +15,236 @@ pub struct TrainingSample<const N: usize> {
     pub result: f16,
 }
 
+/// How the parameters are updated from the gradients of each minibatch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Optimizer {
+    /// Gradient descent with momentum
+    Momentum,
+    /// Adam, which scales the learning rate of each parameter by the size of its recent gradients
+    Adam,
+}
+
+impl Optimizer {
+    /// Learning rate that works well for tuning the engine's parameters with this optimizer
+    pub fn default_learning_rate(self, kind: ParameterKind) -> f32 {
+        match (self, kind) {
+            (Optimizer::Momentum, ParameterKind::Value) => 50.0,
+            (Optimizer::Momentum, ParameterKind::Policy) => 500.0,
+            (Optimizer::Adam, _) => 0.01,
+        }
+    }
+}
+
+impl str::FromStr for Optimizer {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "momentum" => Ok(Optimizer::Momentum),
+            "adam" => Ok(Optimizer::Adam),
+            _ => Err(Error::new_parse_error(format!(
+                "Optimizer must be \"momentum\" or \"adam\", got \"{}\"",
+                input
+            ))),
+        }
+    }
+}
+
+/// Settings for `gradient_descent`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradientDescentSettings {
+    optimizer: Optimizer,
+    learning_rate: f32,
+    l1_weight_decay: f32,
+    l2_weight_decay: f32,
+    validation_fraction: f32,
+    patience: usize,
+    log_file: Option<PathBuf>,
+}
+
+impl GradientDescentSettings {
+    /// Gradient descent with momentum and no weight decay, without a validation set
+    pub fn new(learning_rate: f32) -> Self {
+        GradientDescentSettings {
+            optimizer: Optimizer::Momentum,
+            learning_rate,
+            l1_weight_decay: 0.0,
+            l2_weight_decay: 0.0,
+            validation_fraction: 0.0,
+            patience: 50,
+            log_file: None,
+        }
+    }
+
+    pub fn learning_rate(&self) -> f32 {
+        self.learning_rate
+    }
+
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    /// Penalize the absolute value of each parameter, which pushes unimportant parameters to zero
+    pub fn l1_weight_decay(mut self, l1_weight_decay: f32) -> Self {
+        self.l1_weight_decay = l1_weight_decay;
+        self
+    }
+
+    /// Penalize the squared value of each parameter
+    pub fn l2_weight_decay(mut self, l2_weight_decay: f32) -> Self {
+        self.l2_weight_decay = l2_weight_decay;
+        self
+    }
+
+    /// The fraction of games that are held out from training, and only used to measure the error, see `split_validation`
+    pub fn validation_fraction(mut self, validation_fraction: f32) -> Self {
+        assert!((0.0..1.0).contains(&validation_fraction));
+        self.validation_fraction = validation_fraction;
+        self
+    }
+
+    /// If the validation error is not reduced this number of iterations, reduce the learning rate,
+    /// or stop if it is already low
+    pub fn patience(mut self, patience: usize) -> Self {
+        self.patience = patience;
+        self
+    }
+
+    /// Write the training and validation error after every iteration to a CSV file
+    pub fn log_file(mut self, log_file: impl AsRef<Path>) -> Self {
+        self.log_file = Some(log_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Split the games into training and validation games, holding out the last `validation_fraction` of them.
+    /// Positions from the same game are closely related, so the split is by game, not by position.
+    pub fn split_validation<'a, T>(&self, games: &'a [T]) -> (&'a [T], &'a [T]) {
+        let num_validation_games = (games.len() as f32 * self.validation_fraction) as usize;
+        games.split_at(games.len() - num_validation_games)
+    }
+
+    fn add_weight_decay(&self, slopes: &mut [f32], params: &[f32]) {
+        for (slope, param) in slopes.iter_mut().zip(params) {
+            *slope += self.l2_weight_decay * param;
+            if *param != 0.0 {
+                *slope += self.l1_weight_decay * param.signum();
+            }
+        }
+    }
+}
+
+/// The running averages the optimizer keeps between minibatches
+enum OptimizerState<const N: usize> {
+    Momentum {
+        gradients: [f32; N],
+    },
+    Adam {
+        first_moments: [f32; N],
+        second_moments: [f32; N],
+        steps: i32,
+    },
+}
+
+impl<const N: usize> OptimizerState<N> {
+    const MOMENTUM_BETA: f32 = 0.98;
+    const ADAM_BETA1: f32 = 0.9;
+    const ADAM_BETA2: f32 = 0.999;
+    const ADAM_EPSILON: f32 = 1e-8;
+
+    fn new(optimizer: Optimizer) -> Self {
+        match optimizer {
+            Optimizer::Momentum => OptimizerState::Momentum {
+                gradients: [0.0; N],
+            },
+            Optimizer::Adam => OptimizerState::Adam {
+                first_moments: [0.0; N],
+                second_moments: [0.0; N],
+                steps: 0,
+            },
+        }
+    }
+
+    fn step(&mut self, params: &mut [f32; N], slopes: &[f32; N], eta: f32) {
+        match self {
+            OptimizerState::Momentum { gradients } => {
+                let beta = Self::MOMENTUM_BETA;
+                for ((param, gradient), slope) in
+                    params.iter_mut().zip(gradients.iter_mut()).zip(slopes)
+                {
+                    *gradient = beta * *gradient + (1.0 - beta) * slope;
+                    *param -= *gradient * eta;
+                }
+                trace!("Gradients: {:?}", gradients);
+            }
+            OptimizerState::Adam {
+                first_moments,
+                second_moments,
+                steps,
+            } => {
+                let (beta1, beta2) = (Self::ADAM_BETA1, Self::ADAM_BETA2);
+                *steps += 1;
+                let first_correction = 1.0 - beta1.powi(*steps);
+                let second_correction = 1.0 - beta2.powi(*steps);
+                for (((param, first_moment), second_moment), slope) in params
+                    .iter_mut()
+                    .zip(first_moments)
+                    .zip(second_moments)
+                    .zip(slopes)
+                {
+                    *first_moment = beta1 * *first_moment + (1.0 - beta1) * slope;
+                    *second_moment = beta2 * *second_moment + (1.0 - beta2) * slope * slope;
+                    *param -= eta * (*first_moment / first_correction)
+                        / ((*second_moment / second_correction).sqrt() + Self::ADAM_EPSILON);
+                }
+            }
+        }
+    }
+}
+
+/// Tune the parameters on the training samples, starting from `params`.
+/// The parameters with the lowest error on the validation samples are returned,
+/// or on the training samples if there are no validation samples.
+/// The learning rate is reduced when the validation error stops improving.
+/// Returns an error if the log file cannot be written.
 pub fn gradient_descent<R: rand::Rng, const N: usize>(
-    samples: &[TrainingSample<N>],
+    training_samples: &[TrainingSample<N>],
+    validation_samples: &[TrainingSample<N>],
     params: &[f32; N],
-    initial_learning_rate: f32,
+    settings: &GradientDescentSettings,
     rng: &mut R,
-) -> [f32; N] {
+) -> io::Result<[f32; N]> {
     let start_time = Instant::now();
-    let beta = 0.98;
 
-    // If error is not reduced this number of times, reduce eta, or abort if eta is already low
-    const MAX_TRIES: usize = 50;
+    // If error is not reduced by at least this ratio, count it as an insignificant improvement
     const ERROR_THRESHOLD: f64 = 1.000_000_1;
     const MINIBATCH_SIZE: usize = 10000;
 
-    let initial_error = average_error(samples, params);
-    let num_minibatches = samples.len().div_ceil(MINIBATCH_SIZE);
-    println!("Running gradient descent on {} positions", samples.len(),);
+    let mut log = match &settings.log_file {
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            writeln!(
+                writer,
+                "iteration,seconds,learning_rate,training_error,validation_error"
+            )?;
+            Some(writer)
+        }
+        None => None,
+    };
+
+    // Without a validation set, fall back to measuring error on the training set
+    let initial_error = if validation_samples.is_empty() {
+        average_error(training_samples, params)
+    } else {
+        average_error(validation_samples, params)
+    };
+    let num_minibatches = training_samples.len().div_ceil(MINIBATCH_SIZE);
+    println!(
+        "Running gradient descent with {:?} on {} positions, holding out {} for validation",
+        settings.optimizer,
+        training_samples.len(),
+        validation_samples.len()
+    );
     trace!("Initial parameters: {:?}", params);
     println!("Initial error: {}", initial_error);
 
@@ -34,52 +253,60 @@ pub fn gradient_descent<R: rand::Rng, const N: usize>(
     let mut i = 0;
 
     'eta_loop: for eta in [
-        initial_learning_rate,
-        initial_learning_rate / 10.0,
-        initial_learning_rate / 100.0,
-        initial_learning_rate / 1000.0,
+        settings.learning_rate,
+        settings.learning_rate / 10.0,
+        settings.learning_rate / 100.0,
+        settings.learning_rate / 1000.0,
     ]
     .iter()
     {
         trace!("\nTuning with eta = {}\n", eta);
         let mut parameter_set = best_parameter_set;
-        let mut gradients = [0.0; N];
+        let mut optimizer_state = OptimizerState::new(settings.optimizer);
 
         let mut iterations_since_improvement = 0;
         let mut iterations_since_large_improvement = 0;
         'minibatch_loop: loop {
             for _ in 0..num_minibatches {
-                let minibatch_samples = if samples.len() <= MINIBATCH_SIZE {
-                    samples
+                let minibatch_samples = if training_samples.len() <= MINIBATCH_SIZE {
+                    training_samples
                 } else {
-                    let start_index = rng.gen_range(0..(samples.len() - MINIBATCH_SIZE));
-                    &samples[start_index..(start_index + MINIBATCH_SIZE)]
+                    let start_index = rng.gen_range(0..(training_samples.len() - MINIBATCH_SIZE));
+                    &training_samples[start_index..(start_index + MINIBATCH_SIZE)]
                 };
-                let slopes = calc_slope(minibatch_samples, &parameter_set);
+                let mut slopes = calc_slope(minibatch_samples, &parameter_set);
+                settings.add_weight_decay(&mut slopes, &parameter_set);
                 trace!("Slopes: {:?}", slopes);
-                gradients
-                    .iter_mut()
-                    .zip(slopes.iter())
-                    .for_each(|(gradient, slope)| {
-                        *gradient = beta * *gradient + (1.0 - beta) * slope
-                    });
-                trace!("Gradients: {:?}", gradients);
-
-                parameter_set
-                    .iter_mut()
-                    .zip(gradients.iter())
-                    .for_each(|(param, gradient)| *param -= gradient * eta);
+                optimizer_state.step(&mut parameter_set, &slopes, *eta);
             }
             trace!("New parameters: {:?}", parameter_set);
 
-            let error = average_error(samples, &parameter_set);
+            let training_error = average_error(training_samples, &parameter_set);
+            let error = if validation_samples.is_empty() {
+                training_error
+            } else {
+                average_error(validation_samples, &parameter_set)
+            };
+
+            if let Some(log) = log.as_mut() {
+                writeln!(
+                    log,
+                    "{},{:.3},{},{},{}",
+                    i,
+                    start_time.elapsed().as_secs_f32(),
+                    eta,
+                    training_error,
+                    error
+                )?;
+            }
 
             if i % 50 == 0 {
                 println!(
-                    "\n{:04} iterations in {:.1}s: Error {:.8}, eta={:.4}, {} ({}) iterations since (large) improvement, {:.8} error ratio, {} minibatches\n",
+                    "\n{:04} iterations in {:.1}s: Error {:.8}, training error {:.8}, eta={:.4}, {} ({}) iterations since (large) improvement, {:.8} error ratio, {} minibatches\n",
                     i,
                     start_time.elapsed().as_secs_f32(),
                     error,
+                    training_error,
                     eta,
                     iterations_since_improvement,
                     iterations_since_large_improvement,
@@ -91,10 +318,11 @@ pub fn gradient_descent<R: rand::Rng, const N: usize>(
                 }
             } else {
                 trace!(
-                    "\n{:04} iterations in {:.1}s: Error {:.8}, eta={:.4}, {} ({}) iterations since (large) improvement, {:.8} error ratio, {} minibatches\n",
+                    "\n{:04} iterations in {:.1}s: Error {:.8}, training error {:.8}, eta={:.4}, {} ({}) iterations since (large) improvement, {:.8} error ratio, {} minibatches\n",
                     i,
                     start_time.elapsed().as_secs_f32(),
                     error,
+                    training_error,
                     eta,
                     iterations_since_improvement,
                     iterations_since_large_improvement,
@@ -111,7 +339,7 @@ pub fn gradient_descent<R: rand::Rng, const N: usize>(
                     iterations_since_large_improvement = 0;
                 } else {
                     iterations_since_large_improvement += 1;
-                    if iterations_since_large_improvement >= MAX_TRIES {
+                    if iterations_since_large_improvement >= settings.patience {
                         println!(
                             "\n{:04} iterations in {:.1}s, lowest error {:.8}. Reducing eta because improvements have been insignificant for {} iterations\n",
                             i,
@@ -129,7 +357,7 @@ pub fn gradient_descent<R: rand::Rng, const N: usize>(
             } else {
                 iterations_since_improvement += 1;
                 iterations_since_large_improvement += 1;
-                if iterations_since_improvement >= MAX_TRIES {
+                if iterations_since_improvement >= settings.patience {
                     println!(
                         "\n{:04} iterations in {:.1}s, lowest error {:.8}. Reducing eta because no improvements were seen for {} iterations\n",
                         i,
@@ -143,15 +371,19 @@ pub fn gradient_descent<R: rand::Rng, const N: usize>(
         }
     }
 
+    if let Some(mut log) = log {
+        log.flush()?;
+    }
+
     let elapsed = start_time.elapsed();
 
     println!(
-        "Finished gradient descent in {:.1}s, error is {:.8}. Parameters:\n{:?}",
+        "Finished gradient descent in {:.1}s, validation error is {:.8}. Parameters:\n{:?}",
         elapsed.as_secs_f64(),
         lowest_error,
         best_parameter_set.to_vec()
     );
-    best_parameter_set
+    Ok(best_parameter_set)
 }
 
 /// For each parameter, calculate the slope for that dimension
@@ -192,7 +424,7 @@ fn calc_slope<const N: usize>(samples: &[TrainingSample<N>], params: &[f32; N])
 }
 
 /// Mean squared error of the parameter set, measured against given results and positions
-fn average_error<const N: usize>(samples: &[TrainingSample<N>], params: &[f32; N]) -> f64 {
+pub fn average_error<const N: usize>(samples: &[TrainingSample<N>], params: &[f32; N]) -> f64 {
     samples
         .par_iter()
         .map(
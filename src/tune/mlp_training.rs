@@ -0,0 +1,302 @@
+//! Train value and policy networks with gradient descent,
+//! from the same training samples and in the same self-play loop as the linear parameters.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use crate::book::BookSettings;
+use crate::evaluation::mlp::{load_policy_mlp, load_value_mlp, Mlp};
+use crate::evaluation::parameter_file::ParameterKind;
+use crate::position::Komi;
+use crate::search::MctsSetting;
+use crate::tune::gradient_descent::{sigmoid, GradientDescentSettings, TrainingSample};
+use crate::tune::training::{
+    self, merge_duplicates, policy_training_samples, value_training_samples, Augmentation,
+    DynError, SelfPlayParams,
+};
+use crate::tune::training_data::TrainingRecord;
+use crate::tune::training_run::TrainingConfig;
+
+/// Value and policy networks, taking `N` value features and `M` policy features as input
+#[derive(Clone, Debug, PartialEq)]
+pub struct MlpParams<const N: usize, const M: usize> {
+    pub value_mlp: Arc<Mlp>,
+    pub policy_mlp: Arc<Mlp>,
+}
+
+impl<const S: usize, const N: usize, const M: usize> SelfPlayParams<S> for MlpParams<N, M> {
+    const KIND: &'static str = "mlp";
+
+    fn mcts_settings(&self) -> MctsSetting<S> {
+        MctsSetting::default()
+            .add_value_mlp(self.value_mlp.clone())
+            .add_policy_mlp(self.policy_mlp.clone())
+    }
+
+    fn tune(&self, games: &[Vec<TrainingRecord<S>>], _komi: Komi) -> Result<Self, DynError> {
+        let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
+
+        // Hold out a tenth of the games, so that training stops when the error on them stops improving
+        let value_settings = GradientDescentSettings::new(0.5).validation_fraction(0.1);
+        let policy_settings = GradientDescentSettings::new(5.0).validation_fraction(0.1);
+
+        let mut games: Vec<&Vec<TrainingRecord<S>>> = games.iter().collect();
+        games.shuffle(&mut rng);
+
+        let (training_games, validation_games) = value_settings.split_validation(&games);
+        let value_mlp = train_mlp(
+            &value_training_samples::<S, N, _>(
+                &merge_duplicates(training_games),
+                Augmentation::None,
+                &mut rng,
+            ),
+            &value_training_samples::<S, N, _>(
+                &merge_duplicates(validation_games),
+                Augmentation::None,
+                &mut rng,
+            ),
+            &self.value_mlp,
+            value_settings.learning_rate(),
+            &mut rng,
+        );
+
+        let (training_games, validation_games) = policy_settings.split_validation(&games);
+        let policy_mlp = train_mlp(
+            &policy_training_samples::<S, M, _>(
+                &merge_duplicates(training_games),
+                Augmentation::None,
+                &mut rng,
+            ),
+            &policy_training_samples::<S, M, _>(
+                &merge_duplicates(validation_games),
+                Augmentation::None,
+                &mut rng,
+            ),
+            &self.policy_mlp,
+            policy_settings.learning_rate(),
+            &mut rng,
+        );
+
+        Ok(MlpParams {
+            value_mlp: Arc::new(value_mlp),
+            policy_mlp: Arc::new(policy_mlp),
+        })
+    }
+
+    fn write(&self, directory: &Path, name: &str, _komi: Komi) -> Result<Vec<String>, DynError> {
+        let value_file_name = format!("value_mlp_{}.txt", name);
+        let policy_file_name = format!("policy_mlp_{}.txt", name);
+        self.value_mlp
+            .to_file(&directory.join(&value_file_name).to_string_lossy())?;
+        self.policy_mlp
+            .to_file(&directory.join(&policy_file_name).to_string_lossy())?;
+        Ok(vec![value_file_name, policy_file_name])
+    }
+
+    fn read(directory: &Path, file_names: &[String], komi: Komi) -> Result<Self, DynError> {
+        let [value_file_name, policy_file_name] = file_names else {
+            return Err(format!("Expected 2 network files, got {:?}", file_names).into());
+        };
+        let value_mlp =
+            load_value_mlp::<S>(&directory.join(value_file_name).to_string_lossy(), komi)?;
+        let policy_mlp =
+            load_policy_mlp::<S>(&directory.join(policy_file_name).to_string_lossy(), komi)?;
+        Ok(MlpParams {
+            value_mlp: Arc::new(value_mlp),
+            policy_mlp: Arc::new(policy_mlp),
+        })
+    }
+}
+
+/// Start a new training run in `run_directory` from randomly initialized networks with the given hidden layers
+pub fn train_mlp_from_scratch<const S: usize, const N: usize, const M: usize>(
+    run_directory: &Path,
+    config: &TrainingConfig,
+    hidden_layers: &[usize],
+    book: Option<&BookSettings<S>>,
+) -> Result<(), DynError> {
+    let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
+    let value_mlp =
+        Mlp::new_random::<S, _>(ParameterKind::Value, config.komi, hidden_layers, &mut rng);
+    let policy_mlp =
+        Mlp::new_random::<S, _>(ParameterKind::Policy, config.komi, hidden_layers, &mut rng);
+    train_mlp_perpetually::<S, N, M>(run_directory, config, value_mlp, policy_mlp, book)
+}
+
+/// Start a new training run in `run_directory`, which plays batches of self-play games with the networks,
+/// and trains new networks from them, indefinitely.
+/// Works like `training::train_perpetually`, but writes networks instead of parameter files.
+pub fn train_mlp_perpetually<const S: usize, const N: usize, const M: usize>(
+    run_directory: &Path,
+    config: &TrainingConfig,
+    value_mlp: Mlp,
+    policy_mlp: Mlp,
+    book: Option<&BookSettings<S>>,
+) -> Result<(), DynError> {
+    value_mlp.check::<S>(ParameterKind::Value, config.komi)?;
+    policy_mlp.check::<S>(ParameterKind::Policy, config.komi)?;
+    assert_eq!(value_mlp.num_inputs(), N);
+    assert_eq!(policy_mlp.num_inputs(), M);
+
+    training::start_training_run(
+        run_directory,
+        config,
+        MlpParams::<N, M> {
+            value_mlp: Arc::new(value_mlp),
+            policy_mlp: Arc::new(policy_mlp),
+        },
+        book,
+        None,
+    )
+}
+
+/// Resume a training run of networks from its run directory
+pub fn continue_mlp_training<const S: usize, const N: usize, const M: usize>(
+    run_directory: &Path,
+    book: Option<&BookSettings<S>>,
+) -> Result<(), DynError> {
+    training::resume_training_run::<S, MlpParams<N, M>>(run_directory, book, None)
+}
+
+/// Train a network with minibatch gradient descent with momentum, starting from `mlp`.
+/// Training stops when the error on the validation samples stops improving.
+/// Without validation samples, the error is measured on the training samples.
+pub fn train_mlp<R: Rng, const N: usize>(
+    training_samples: &[TrainingSample<N>],
+    validation_samples: &[TrainingSample<N>],
+    mlp: &Mlp,
+    initial_learning_rate: f32,
+    rng: &mut R,
+) -> Mlp {
+    const MINIBATCH_SIZE: usize = 1000;
+    // If the validation error is not reduced this number of epochs, reduce eta
+    const MAX_TRIES: usize = 3;
+    let beta = 0.9;
+
+    let start_time = Instant::now();
+    let validation_samples = if validation_samples.is_empty() {
+        training_samples
+    } else {
+        validation_samples
+    };
+    let num_minibatches = training_samples.len().div_ceil(MINIBATCH_SIZE);
+
+    let mut lowest_error = average_error(validation_samples, mlp);
+    let mut best_mlp = mlp.clone();
+    println!(
+        "Training {} network with {} weights on {} samples, initial error {:.8}",
+        mlp.kind,
+        mlp.num_params(),
+        training_samples.len(),
+        lowest_error
+    );
+
+    let mut epoch = 0;
+    for eta in [
+        initial_learning_rate,
+        initial_learning_rate / 10.0,
+        initial_learning_rate / 100.0,
+    ] {
+        let mut mlp = best_mlp.clone();
+        let mut momentum = vec![0.0; mlp.num_params()];
+        let mut epochs_since_improvement = 0;
+
+        while epochs_since_improvement < MAX_TRIES {
+            for _ in 0..num_minibatches {
+                let minibatch_samples = if training_samples.len() <= MINIBATCH_SIZE {
+                    training_samples
+                } else {
+                    let start_index = rng.gen_range(0..(training_samples.len() - MINIBATCH_SIZE));
+                    &training_samples[start_index..(start_index + MINIBATCH_SIZE)]
+                };
+                let gradients = calc_gradients(minibatch_samples, &mlp);
+                for ((param, velocity), gradient) in
+                    mlp.params_mut().zip(momentum.iter_mut()).zip(gradients)
+                {
+                    *velocity = beta * *velocity + (1.0 - beta) * gradient;
+                    *param -= eta * *velocity;
+                }
+            }
+            epoch += 1;
+
+            let error = average_error(validation_samples, &mlp);
+            println!(
+                "Epoch {} in {:.1}s: error {:.8}, eta={}",
+                epoch,
+                start_time.elapsed().as_secs_f32(),
+                error,
+                eta
+            );
+            if error < lowest_error {
+                lowest_error = error;
+                best_mlp = mlp.clone();
+                epochs_since_improvement = 0;
+            } else {
+                epochs_since_improvement += 1;
+            }
+        }
+    }
+
+    println!(
+        "Finished training in {:.1}s, error is {:.8}",
+        start_time.elapsed().as_secs_f32(),
+        lowest_error
+    );
+    best_mlp
+}
+
+/// The gradient of the mean squared error for every weight and bias, in the order of `Mlp::params`
+fn calc_gradients<const N: usize>(samples: &[TrainingSample<N>], mlp: &Mlp) -> Vec<f32> {
+    let num_params = mlp.num_params();
+    let mut gradients = samples
+        .par_iter()
+        .fold(
+            || (vec![0.0; num_params], vec![], vec![], vec![]),
+            |(mut gradients, mut activations, mut deltas, mut previous_deltas), sample| {
+                let estimate =
+                    sigmoid(mlp.evaluate(&sample.features, &mut activations) + sample.offset);
+                let output_gradient =
+                    (estimate - sample.result.to_f32()) * estimate * (1.0 - estimate);
+                mlp.add_gradients(
+                    &sample.features,
+                    &activations,
+                    output_gradient,
+                    &mut gradients,
+                    &mut deltas,
+                    &mut previous_deltas,
+                );
+                (gradients, activations, deltas, previous_deltas)
+            },
+        )
+        .map(|(gradients, _, _, _)| gradients)
+        .reduce(
+            || vec![0.0; num_params],
+            |mut a, b| {
+                for (a, b) in a.iter_mut().zip(b) {
+                    *a += b;
+                }
+                a
+            },
+        );
+
+    for gradient in gradients.iter_mut() {
+        *gradient /= samples.len() as f32;
+    }
+    gradients
+}
+
+/// Mean squared error of the network, measured against the given results
+pub fn average_error<const N: usize>(samples: &[TrainingSample<N>], mlp: &Mlp) -> f64 {
+    samples
+        .par_iter()
+        .map_init(Vec::new, |activations, sample| {
+            let estimate = sigmoid(mlp.evaluate(&sample.features, activations) + sample.offset);
+            (estimate - sample.result.to_f32()).powi(2) as f64
+        })
+        .sum::<f64>()
+        / (samples.len() as f64)
+}
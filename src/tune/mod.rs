@@ -1,5 +1,11 @@
+pub mod distributed;
 pub mod gradient_descent;
+#[cfg(feature = "mlp")]
+pub mod mlp_training;
 mod openings;
 pub mod play_match;
+pub mod sprt;
 pub mod spsa;
 pub mod training;
+pub mod training_data;
+pub mod training_run;
@@ -1,6 +1,7 @@
 use std::fs;
 use std::io;
 use std::io::BufRead;
+use std::path::Path;
 
 use board_game_traits::Position as PositionTrait;
 use pgn_traits::PgnPosition;
@@ -8,7 +9,7 @@ use pgn_traits::PgnPosition;
 use crate::position::Move;
 use crate::position::Position;
 
-pub fn openings_from_file<const S: usize>(path: &str) -> io::Result<Vec<Vec<Move<S>>>> {
+pub fn openings_from_file<const S: usize>(path: impl AsRef<Path>) -> io::Result<Vec<Vec<Move<S>>>> {
     let reader = io::BufReader::new(fs::File::open(path)?);
     let mut openings = vec![];
 
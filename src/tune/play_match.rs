@@ -1,12 +1,17 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
 
-use board_game_traits::{Color, Position as PositionTrait};
+use board_game_traits::{Color, GameResult, Position as PositionTrait};
 use chrono::Datelike;
 use half::f16;
 use pgn_traits::PgnPosition;
 use rand::seq::SliceRandom;
+use rand::Rng;
+use rayon::prelude::*;
 
+use crate::book::BookSettings;
 use crate::position::ExpMove;
 use crate::position::Komi;
 use crate::position::Move;
@@ -16,13 +21,119 @@ use crate::ptn::{Game, PtnMove};
 use crate::search;
 use crate::search::MctsSetting;
 use crate::search::TimeControl;
+use crate::tune::sprt::{MatchStats, Sprt, SprtResult};
 
-/// Play a single training game between two parameter sets
+/// Games in a match are played with a low temperature, so that the players don't repeat the same games.
+/// Most of the variety comes from the openings.
+const MATCH_TEMPERATURE: f64 = 0.1;
+
+/// Play a match between two players in parallel, until the SPRT accepts one of its hypotheses,
+/// or `max_pairs` game pairs have been played.
+/// Each pair starts from the same opening, with each player playing white once.
+/// Openings are taken from the book if there is one, and are filled in with random flatstone placements
+/// up to the third ply, where `play_game` would otherwise pick random moves for each game.
+/// Games that reach the move limit are scored as draws.
+///
+/// Prints the match statistics after every pair. Returns the final statistics,
+/// and the SPRT result at the time it was reached, if any. Pairs that were already being played when
+/// the SPRT stopped the match are included in the statistics.
+pub fn play_match<const S: usize>(
+    player1: &MctsSetting<S>,
+    player2: &MctsSetting<S>,
+    komi: Komi,
+    time_control: &TimeControl,
+    book: Option<&BookSettings<S>>,
+    sprt: &Sprt,
+    max_pairs: u64,
+) -> (MatchStats, Option<SprtResult>) {
+    let stopped = AtomicBool::new(false);
+    let state: Mutex<(MatchStats, Option<SprtResult>)> = Mutex::new((MatchStats::default(), None));
+    let (lower_bound, upper_bound) = sprt.bounds();
+
+    (0..max_pairs).into_par_iter().for_each(|_| {
+        if stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let opening = match_opening(komi, book, &mut rand::thread_rng());
+        let mut half_points = [0; 2];
+        for (i, (white, black)) in [(player1, player2), (player2, player1)]
+            .into_iter()
+            .enumerate()
+        {
+            let (game, _) = play_game(
+                white,
+                black,
+                komi,
+                &opening,
+                None,
+                MATCH_TEMPERATURE,
+                time_control,
+            );
+            half_points[i] = match (game.game_result(), i) {
+                (Some(GameResult::WhiteWin), 0) | (Some(GameResult::BlackWin), 1) => 2,
+                (Some(GameResult::WhiteWin), 1) | (Some(GameResult::BlackWin), 0) => 0,
+                _ => 1,
+            };
+        }
+
+        let mut state = state.lock().unwrap();
+        let (stats, result) = &mut *state;
+        stats.add_pair(half_points[0], half_points[1]);
+        if result.is_none() {
+            *result = sprt.result(stats);
+            if result.is_some() {
+                stopped.store(true, Ordering::Relaxed);
+            }
+        }
+        println!(
+            "{}, LLR {:.2} ({:.2}, {:.2})",
+            stats,
+            sprt.llr(stats),
+            lower_bound,
+            upper_bound
+        );
+    });
+
+    state.into_inner().unwrap()
+}
+
+/// The opening for a game pair: A line from the book, extended with random flatstone placements to at least 3 plies
+fn match_opening<const S: usize, R: Rng>(
+    komi: Komi,
+    book: Option<&BookSettings<S>>,
+    rng: &mut R,
+) -> Vec<Move<S>> {
+    let mut position = Position::start_position_with_komi(komi);
+    let opening = book.map(|book| book.choose_line(position.clone(), rng));
+    let mut opening = opening.unwrap_or_default();
+    for mv in opening.iter() {
+        position.do_move(*mv);
+    }
+    let mut legal_moves = vec![];
+    while opening.len() < 3 && position.game_result().is_none() {
+        legal_moves.clear();
+        position.generate_moves(&mut legal_moves);
+        let mv = **legal_moves
+            .iter()
+            .filter(|mv| matches!(mv.expand(), ExpMove::Place(Role::Flat, _)))
+            .collect::<Vec<_>>()
+            .choose(rng)
+            .unwrap();
+        position.do_move(mv);
+        opening.push(mv);
+    }
+    opening
+}
+
+/// Play a single training game between two parameter sets.
+/// After the opening, both players play from the book while it has moves. Like the opening moves,
+/// book moves are played instantly, and get no move scores.
 pub fn play_game<const S: usize>(
     white_settings: &MctsSetting<S>,
     black_settings: &MctsSetting<S>,
     komi: Komi,
     opening: &[Move<S>],
+    book: Option<&BookSettings<S>>,
     temperature: f64,
     time_control: &TimeControl,
 ) -> (Game<Position<S>>, Vec<Vec<(Move<S>, f16)>>) {
@@ -45,6 +156,13 @@ pub fn play_game<const S: usize>(
             break;
         }
 
+        if let Some(book_move) = book.and_then(|book| book.choose_move(&position, &mut rng)) {
+            position.do_move(book_move.mv);
+            game_moves.push(book_move.mv);
+            move_scores.push(vec![]);
+            continue;
+        }
+
         let start_time = Instant::now();
 
         let moves_scores = match (time_control, position.side_to_move()) {
@@ -62,7 +180,7 @@ pub fn play_game<const S: usize>(
             (TimeControl::Time(_, _), Color::Black) => search::mcts_training::<S>(
                 position.clone(),
                 &TimeControl::Time(black_time_left, increment),
-                white_settings.clone(),
+                black_settings.clone(),
             ),
         };
 
@@ -125,6 +243,7 @@ pub fn play_game<const S: usize>(
                     mv,
                     annotations: vec![],
                     comment: String::new(),
+                    variations: vec![],
                 })
                 .collect::<Vec<_>>(),
             game_result_str: position.pgn_game_result(),
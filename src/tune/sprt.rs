@@ -0,0 +1,256 @@
+//! Statistics for engine matches, and a sequential probability ratio test (SPRT) to decide when to stop them.
+//!
+//! Games are played in pairs, with the same opening and the colors swapped.
+//! The results of a pair are much less noisy than two independent games, so the statistics are calculated
+//! from the pentanomial distribution: The number of pairs where the first player scored 0, 0.5, 1, 1.5 or 2 points.
+//! The SPRT uses the generalized log-likelihood ratio for the pentanomial model,
+//! as in [fishtest](https://github.com/official-stockfish/fishtest).
+
+use std::fmt;
+
+/// Results of a match, from the first player's perspective
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchStats {
+    pub wins: u64,
+    pub draws: u64,
+    pub losses: u64,
+    /// Number of game pairs where the first player scored 0, 0.5, 1, 1.5 and 2 points
+    pub pentanomial: [u64; 5],
+}
+
+impl MatchStats {
+    /// Add the results of a game pair, given as the first player's score in half points in each game.
+    /// 2 is a win, 1 is a draw and 0 is a loss
+    pub fn add_pair(&mut self, first_half_points: u8, second_half_points: u8) {
+        for half_points in [first_half_points, second_half_points] {
+            match half_points {
+                0 => self.losses += 1,
+                1 => self.draws += 1,
+                2 => self.wins += 1,
+                _ => panic!("Invalid game score of {} half points", half_points),
+            }
+        }
+        self.pentanomial[(first_half_points + second_half_points) as usize] += 1;
+    }
+
+    pub fn num_pairs(&self) -> u64 {
+        self.pentanomial.iter().sum()
+    }
+
+    pub fn num_games(&self) -> u64 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// The first player's average score per game, between 0 and 1
+    pub fn score(&self) -> f64 {
+        self.mean_and_variance().0
+    }
+
+    /// Average score per game, and the variance of the average score of a single pair
+    fn mean_and_variance(&self) -> (f64, f64) {
+        let num_pairs = self.num_pairs() as f64;
+        if num_pairs == 0.0 {
+            return (0.5, 0.0);
+        }
+        let mean = self
+            .pentanomial
+            .iter()
+            .enumerate()
+            .map(|(i, count)| *count as f64 * pair_score(i))
+            .sum::<f64>()
+            / num_pairs;
+        let variance = self
+            .pentanomial
+            .iter()
+            .enumerate()
+            .map(|(i, count)| *count as f64 * (pair_score(i) - mean).powi(2))
+            .sum::<f64>()
+            / num_pairs;
+        (mean, variance)
+    }
+
+    /// Estimated Elo difference between the first and the second player
+    pub fn elo(&self) -> f64 {
+        score_to_elo(self.score())
+    }
+
+    /// Half the width of the 95% confidence interval of the Elo difference
+    pub fn elo_error(&self) -> f64 {
+        let (mean, variance) = self.mean_and_variance();
+        let deviation = 1.96 * (variance / self.num_pairs().max(1) as f64).sqrt();
+        let lower = score_to_elo((mean - deviation).max(0.0));
+        let upper = score_to_elo((mean + deviation).min(1.0));
+        (upper - lower) / 2.0
+    }
+
+    /// Likelihood of superiority: The probability that the first player is stronger than the second
+    pub fn los(&self) -> f64 {
+        let (mean, variance) = self.mean_and_variance();
+        if variance == 0.0 {
+            return match mean.total_cmp(&0.5) {
+                std::cmp::Ordering::Less => 0.0,
+                std::cmp::Ordering::Equal => 0.5,
+                std::cmp::Ordering::Greater => 1.0,
+            };
+        }
+        let z = (mean - 0.5) / (variance / self.num_pairs() as f64).sqrt();
+        0.5 * (1.0 + erf(z / 2.0_f64.sqrt()))
+    }
+
+    /// Generalized log-likelihood ratio of the Elo difference being `elo1` rather than `elo0`.
+    /// Like in fishtest, empty pentanomial buckets are given a tiny count first,
+    /// so that the ratio is still meaningful when every pair so far had the same result.
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.num_pairs() == 0 {
+            return 0.0;
+        }
+        let counts = self.pentanomial.map(|count| {
+            if count == 0 {
+                REGULARIZATION_COUNT
+            } else {
+                count as f64
+            }
+        });
+        let num_pairs = counts.iter().sum::<f64>();
+        let pdf = counts.map(|count| count / num_pairs);
+        num_pairs
+            * (max_log_likelihood(&pdf, elo_to_score(elo1))
+                - max_log_likelihood(&pdf, elo_to_score(elo0)))
+    }
+}
+
+impl fmt::Display for MatchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} games: +{}-{}={}, Elo {:.1} ± {:.1}, LOS {:.1}%, pentanomial {:?}",
+            self.num_games(),
+            self.wins,
+            self.losses,
+            self.draws,
+            self.elo(),
+            self.elo_error(),
+            self.los() * 100.0,
+            self.pentanomial
+        )
+    }
+}
+
+/// Settings for a sequential probability ratio test, which tests whether the Elo difference is `elo0` or `elo1`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    /// Probability of accepting `elo1`, when the Elo difference is really `elo0`
+    pub alpha: f64,
+    /// Probability of accepting `elo0`, when the Elo difference is really `elo1`
+    pub beta: f64,
+}
+
+/// Which hypothesis the SPRT accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SprtResult {
+    /// The Elo difference is `elo0` or less
+    H0,
+    /// The Elo difference is `elo1` or more
+    H1,
+}
+
+impl Default for Sprt {
+    fn default() -> Self {
+        Sprt {
+            elo0: 0.0,
+            elo1: 10.0,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+}
+
+impl Sprt {
+    /// The lower and upper bounds for the log-likelihood ratio
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    pub fn llr(&self, stats: &MatchStats) -> f64 {
+        stats.llr(self.elo0, self.elo1)
+    }
+
+    /// Returns the accepted hypothesis, or `None` if more games are needed
+    pub fn result(&self, stats: &MatchStats) -> Option<SprtResult> {
+        let (lower, upper) = self.bounds();
+        let llr = self.llr(stats);
+        if llr <= lower {
+            Some(SprtResult::H0)
+        } else if llr >= upper {
+            Some(SprtResult::H1)
+        } else {
+            None
+        }
+    }
+}
+
+/// The count given to empty pentanomial buckets when calculating the log-likelihood ratio
+const REGULARIZATION_COUNT: f64 = 1e-3;
+
+/// The score of a game pair in pentanomial bucket `i`, as the average score per game
+fn pair_score(i: usize) -> f64 {
+    i as f64 / 4.0
+}
+
+/// The log-likelihood of the observed pair results, relative to their own frequencies `pdf`,
+/// under the most likely pentanomial distribution that has an expected score of `score`.
+///
+/// That distribution is `pdf[i] / (1 + lambda * (pair_score(i) - score))`, where `lambda` is found by bisection.
+/// Every bucket must have a non-zero frequency, and `score` must be strictly between 0 and 1.
+fn max_log_likelihood(pdf: &[f64; 5], score: f64) -> f64 {
+    // The expected score of the distribution for `lambda`, minus `score`. It's decreasing in `lambda`
+    let score_error = |lambda: f64| {
+        pdf.iter()
+            .enumerate()
+            .map(|(i, p)| p * (pair_score(i) - score) / (1.0 + lambda * (pair_score(i) - score)))
+            .sum::<f64>()
+    };
+    // Every probability must stay positive
+    let mut low = -1.0 / (1.0 - score);
+    let mut high = 1.0 / score;
+    for _ in 0..100 {
+        let middle = (low + high) / 2.0;
+        if score_error(middle) > 0.0 {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    let lambda = (low + high) / 2.0;
+    -pdf.iter()
+        .enumerate()
+        .map(|(i, p)| p * (1.0 + lambda * (pair_score(i) - score)).ln())
+        .sum::<f64>()
+}
+
+/// Elo difference for an expected score between 0 and 1
+pub fn score_to_elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/// Expected score for an Elo difference
+pub fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10.0_f64.powf(-elo / 400.0))
+}
+
+/// The error function, with a maximum error of 1.5e-7.
+/// From Abramowitz and Stegun, formula 7.1.26
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let polynomial = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let y = 1.0 - polynomial * (-x * x).exp();
+    y.copysign(x)
+}
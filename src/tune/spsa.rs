@@ -1,20 +1,352 @@
-use crate::position::{Komi, Move};
-use crate::search::{MctsSetting, TimeControl};
-use crate::tune::openings::openings_from_file;
-/// Tune search variable using a version of SPSA (Simultaneous perturbation stochastic approximation),
-/// similar to [Stockfish's tuning method](https://www.chessprogramming.org/Stockfish%27s_Tuning_Method)
-use crate::tune::play_match::play_game;
+//! Tune search variables using a version of SPSA (Simultaneous perturbation stochastic approximation),
+//! similar to [Stockfish's tuning method](https://www.chessprogramming.org/Stockfish%27s_Tuning_Method)
+//!
+//! The variables and the number of iterations played so far are stored in a small text file.
+//! Each variable is a parameter name, followed by its value, delta, apply factor, and the bounds of the value:
+//!
+//! ```text
+//! # name value delta apply-factor min max
+//! iteration 0
+//! c-puct-init 1.43 0.2 0.005 0.5 5
+//! c-puct-base 2800 1000 0.005 100 20000
+//! initial-mean-action-value 0.61 0.05 0.005 0 1
+//! ```
+//!
+//! The tuner writes the file back as a checkpoint after every iteration, so tuning can be resumed from it.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Mutex;
+
 use board_game_traits::GameResult;
+use pgn_traits::{Error, ErrorKind};
 use rand::SeedableRng;
 use rayon::prelude::*;
-use std::sync::Mutex;
-use std::time::Duration;
+
+use crate::position::{Komi, Move};
+use crate::search::{MctsSetting, TimeControl, TimeManagement};
+use crate::tune::openings::openings_from_file;
+use crate::tune::play_match::play_game;
+
+/// A value in `MctsSetting` that can be tuned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpsaParameter {
+    CPuctInit,
+    CPuctBase,
+    InitialMeanActionValue,
+    /// Rounded to the nearest integer when applied
+    RolloutDepth,
+    RolloutTemperature,
+    TimeLeftShare,
+    IncrementShare,
+    StopTimeExponent,
+    StopVisitRatio,
+}
+
+impl SpsaParameter {
+    pub const ALL: [SpsaParameter; 9] = [
+        SpsaParameter::CPuctInit,
+        SpsaParameter::CPuctBase,
+        SpsaParameter::InitialMeanActionValue,
+        SpsaParameter::RolloutDepth,
+        SpsaParameter::RolloutTemperature,
+        SpsaParameter::TimeLeftShare,
+        SpsaParameter::IncrementShare,
+        SpsaParameter::StopTimeExponent,
+        SpsaParameter::StopVisitRatio,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SpsaParameter::CPuctInit => "c-puct-init",
+            SpsaParameter::CPuctBase => "c-puct-base",
+            SpsaParameter::InitialMeanActionValue => "initial-mean-action-value",
+            SpsaParameter::RolloutDepth => "rollout-depth",
+            SpsaParameter::RolloutTemperature => "rollout-temperature",
+            SpsaParameter::TimeLeftShare => "time-left-share",
+            SpsaParameter::IncrementShare => "increment-share",
+            SpsaParameter::StopTimeExponent => "stop-time-exponent",
+            SpsaParameter::StopVisitRatio => "stop-visit-ratio",
+        }
+    }
+
+    /// Whether the parameter must not be negative. Negative time shares would panic in `TimeManagement::max_time`
+    pub fn is_non_negative(self) -> bool {
+        matches!(
+            self,
+            SpsaParameter::TimeLeftShare | SpsaParameter::IncrementShare
+        )
+    }
+
+    /// Set the parameter to `value` in the settings
+    pub fn apply<const S: usize>(self, settings: MctsSetting<S>, value: f32) -> MctsSetting<S> {
+        let with_search_param = |settings: MctsSetting<S>, index: usize| {
+            let mut search_params = settings.search_params().to_vec();
+            search_params[index] = value;
+            settings.add_search_params(search_params.into_boxed_slice())
+        };
+        let time_management = settings.time_management();
+        match self {
+            SpsaParameter::CPuctInit => with_search_param(settings, 0),
+            SpsaParameter::CPuctBase => with_search_param(settings, 1),
+            SpsaParameter::InitialMeanActionValue => with_search_param(settings, 2),
+            SpsaParameter::RolloutDepth => {
+                settings.add_rollout_depth(value.round().max(0.0) as u16)
+            }
+            SpsaParameter::RolloutTemperature => settings.add_rollout_temperature(value as f64),
+            SpsaParameter::TimeLeftShare => settings.add_time_management(TimeManagement {
+                time_left_share: value,
+                ..time_management
+            }),
+            SpsaParameter::IncrementShare => settings.add_time_management(TimeManagement {
+                increment_share: value,
+                ..time_management
+            }),
+            SpsaParameter::StopTimeExponent => settings.add_time_management(TimeManagement {
+                stop_time_exponent: value,
+                ..time_management
+            }),
+            SpsaParameter::StopVisitRatio => settings.add_time_management(TimeManagement {
+                stop_visit_ratio: value,
+                ..time_management
+            }),
+        }
+    }
+}
+
+impl fmt::Display for SpsaParameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl str::FromStr for SpsaParameter {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        SpsaParameter::ALL
+            .into_iter()
+            .find(|parameter| parameter.name() == input)
+            .ok_or_else(|| {
+                Error::new_parse_error(format!(
+                    "SPSA parameter must be one of {}, got \"{}\"",
+                    SpsaParameter::ALL
+                        .iter()
+                        .map(|parameter| format!("\"{}\"", parameter))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    input
+                ))
+            })
+    }
+}
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Variable {
+    pub parameter: SpsaParameter,
     pub value: f32,
+    /// How far the value is moved up and down for the two players in each game
     pub delta: f32,
+    /// The value is moved `delta * apply_factor` towards the winner's value after each game
     pub apply_factor: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Variable {
+    fn clamp(&self, value: f32) -> f32 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+/// The variables being tuned, and the number of iterations played so far
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpsaState {
+    pub iteration: u64,
+    pub variables: Vec<Variable>,
+}
+
+impl SpsaState {
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut iteration = 0;
+        let mut variables: Vec<Variable> = vec![];
+        for line in reader.lines() {
+            let line = line.map_err(|err| Error::new(ErrorKind::IoError, err))?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [] => continue,
+                [word, ..] if word.starts_with('#') => continue,
+                ["iteration", value] => iteration = parse(value, "iteration")?,
+                [name, value, delta, apply_factor, min, max] => {
+                    let variable = Variable {
+                        parameter: name.parse()?,
+                        value: parse(value, "value")?,
+                        delta: parse(delta, "delta")?,
+                        apply_factor: parse(apply_factor, "apply factor")?,
+                        min: parse(min, "min")?,
+                        max: parse(max, "max")?,
+                    };
+                    if variables
+                        .iter()
+                        .any(|other| other.parameter == variable.parameter)
+                    {
+                        return Err(Error::new_parse_error(format!(
+                            "Variable {} is given twice",
+                            variable.parameter
+                        )));
+                    }
+                    if variable.parameter.is_non_negative() && variable.min < 0.0 {
+                        return Err(Error::new_parse_error(format!(
+                            "Lower bound {} of {} must not be negative",
+                            variable.min, variable.parameter
+                        )));
+                    }
+                    if !(variable.min..=variable.max).contains(&variable.value) {
+                        return Err(Error::new_parse_error(format!(
+                            "Value {} of {} is outside its bounds {} to {}",
+                            variable.value, variable.parameter, variable.min, variable.max
+                        )));
+                    }
+                    variables.push(variable);
+                }
+                _ => {
+                    return Err(Error::new_parse_error(format!(
+                        "Bad SPSA variable line \"{}\"",
+                        line
+                    )))
+                }
+            }
+        }
+        if variables.is_empty() {
+            return Err(Error::new_parse_error("No SPSA variables given"));
+        }
+        Ok(SpsaState {
+            iteration,
+            variables,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "# name value delta apply-factor min max")?;
+        writeln!(writer, "iteration {}", self.iteration)?;
+        for variable in self.variables.iter() {
+            writeln!(
+                writer,
+                "{} {} {} {} {} {}",
+                variable.parameter,
+                variable.value,
+                variable.delta,
+                variable.apply_factor,
+                variable.min,
+                variable.max
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = fs::File::open(path).map_err(|err| {
+            Error::new_caused_by(
+                ErrorKind::IoError,
+                format!("Couldn't open {}", path.display()),
+                err,
+            )
+        })?;
+        Self::read(io::BufReader::new(file))
+    }
+
+    /// Write the state to a file, replacing the old file atomically
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let file = fs::File::create(&temp_path)?;
+        let mut writer = io::BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(temp_path, path)
+    }
+
+    /// The settings for searching with the variables' current values
+    pub fn apply<const S: usize>(&self, settings: MctsSetting<S>) -> MctsSetting<S> {
+        self.variables.iter().fold(settings, |settings, variable| {
+            variable.parameter.apply(settings, variable.value)
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpsaSettings<const S: usize> {
+    base_settings: MctsSetting<S>,
+    time_control: TimeControl,
+    komi: Komi,
+    book_path: Option<PathBuf>,
+    checkpoint_file: Option<PathBuf>,
+    log_file: Option<PathBuf>,
+    max_iterations: u64,
+}
+
+impl<const S: usize> SpsaSettings<S> {
+    /// Play games with the given time control and no komi, and tune indefinitely
+    pub fn new(time_control: TimeControl) -> Self {
+        let base_settings = match time_control {
+            TimeControl::FixedNodes(nodes) => {
+                MctsSetting::default().arena_size_for_nodes(nodes as u32)
+            }
+            TimeControl::Time(_, _) => MctsSetting::default(),
+        };
+        SpsaSettings {
+            base_settings,
+            time_control,
+            komi: Komi::default(),
+            book_path: None,
+            checkpoint_file: None,
+            log_file: None,
+            max_iterations: u64::MAX,
+        }
+    }
+
+    /// The settings the tuned variables are applied to
+    pub fn base_settings(mut self, base_settings: MctsSetting<S>) -> Self {
+        self.base_settings = base_settings;
+        self
+    }
+
+    pub fn komi(mut self, komi: Komi) -> Self {
+        self.komi = komi;
+        self
+    }
+
+    /// Start the games from the openings in this file, with one opening per line
+    pub fn book(mut self, book_path: impl AsRef<Path>) -> Self {
+        self.book_path = Some(book_path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Write the state to this file after every iteration
+    pub fn checkpoint_file(mut self, checkpoint_file: impl AsRef<Path>) -> Self {
+        self.checkpoint_file = Some(checkpoint_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Append the result and the variables of every iteration to a CSV file
+    pub fn log_file(mut self, log_file: impl AsRef<Path>) -> Self {
+        self.log_file = Some(log_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Stop when the state has reached this many iterations, including those played before resuming
+    pub fn max_iterations(mut self, max_iterations: u64) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
 }
 
 /// In each iteration of SPSA, each variable can be increased, decreased or left unchanged.
@@ -25,58 +357,107 @@ enum SpsaDirection {
     NoChange,
 }
 
-/// Tune the variables indefinitely
-pub fn tune<const S: usize>(variables: &mut [Variable], book_path: Option<&str>) {
-    let openings = if let Some(path) = book_path {
-        openings_from_file::<S>(path).unwrap()
+/// Tune the variables, until `max_iterations` is reached
+pub fn tune<const S: usize>(state: &mut SpsaState, settings: &SpsaSettings<S>) -> io::Result<()> {
+    let openings = if let Some(path) = &settings.book_path {
+        openings_from_file::<S>(path)?
     } else {
         vec![vec![]]
     };
-    let mutex_variables = Mutex::new(variables);
-
-    (1..usize::max_value()).into_par_iter().for_each(|i| {
-        let cloned_variables = (*mutex_variables.lock().unwrap()).to_vec();
-        let mut rng = rand::rngs::StdRng::from_entropy();
 
-        let result =
-            tuning_iteration::<_, S>(&cloned_variables, &mut rng, &openings[i % openings.len()]);
-        {
-            let mut mut_variables = mutex_variables.lock().unwrap();
-            for (variable, result) in (*mut_variables).iter_mut().zip(&result) {
-                match result {
-                    SpsaDirection::Increase => {
-                        variable.value += variable.delta * variable.apply_factor
-                    }
-                    SpsaDirection::Decrease => {
-                        variable.value -= variable.delta * variable.apply_factor
-                    }
-                    SpsaDirection::NoChange => (),
+    let log = match &settings.log_file {
+        Some(path) => {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            if file.metadata()?.len() == 0 {
+                let mut columns = vec!["iteration".to_string(), "result".to_string()];
+                for suffix in ["", "_white", "_black"] {
+                    columns.extend(
+                        state
+                            .variables
+                            .iter()
+                            .map(|variable| format!("{}{}", variable.parameter, suffix)),
+                    );
                 }
+                writeln!(file, "{}", columns.join(","))?;
             }
+            Some(io::BufWriter::new(file))
+        }
+        None => None,
+    };
+
+    let first_iteration = state.iteration;
+    let remaining_iterations = settings.max_iterations.saturating_sub(first_iteration);
+    let shared = Mutex::new((state, log));
+
+    (0..remaining_iterations).into_par_iter().try_for_each(|i| {
+        let variables = shared.lock().unwrap().0.variables.clone();
+        let mut rng = rand::rngs::StdRng::from_entropy();
+        let opening = &openings[((first_iteration + i) % openings.len() as u64) as usize];
+
+        let (directions, white_values, black_values, game_result) =
+            tuning_iteration::<_, S>(&variables, &mut rng, opening, settings);
+
+        let mut guard = shared.lock().unwrap();
+        let (state, log) = &mut *guard;
+        for (variable, direction) in state.variables.iter_mut().zip(&directions) {
+            let step = variable.delta * variable.apply_factor;
+            variable.value = match direction {
+                SpsaDirection::Increase => variable.clamp(variable.value + step),
+                SpsaDirection::Decrease => variable.clamp(variable.value - step),
+                SpsaDirection::NoChange => variable.value,
+            };
+        }
+        state.iteration += 1;
+
+        if let Some(log) = log {
+            let result = match game_result {
+                Some(GameResult::WhiteWin) => "1-0",
+                Some(GameResult::BlackWin) => "0-1",
+                Some(GameResult::Draw) => "1/2-1/2",
+                None => "*",
+            };
+            let values = state
+                .variables
+                .iter()
+                .map(|variable| variable.value)
+                .chain(white_values)
+                .chain(black_values)
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>();
+            writeln!(log, "{},{},{}", state.iteration, result, values.join(","))?;
+            log.flush()?;
+        }
+        if let Some(path) = &settings.checkpoint_file {
+            state.write_to_file(path)?;
         }
 
-        if i % 29 == 0 {
+        if state.iteration % 29 == 0 {
             println!(
                 "{}: Variables: {:?}",
-                i,
-                mutex_variables
-                    .lock()
-                    .unwrap()
+                state.iteration,
+                state
+                    .variables
                     .iter()
                     .map(|variable| variable.value)
                     .collect::<Vec<_>>()
             );
         }
+        Ok(())
     })
 }
 
-/// Run one iteration of the SPSA algorithm
+/// Run one iteration of the SPSA algorithm, by playing a game between two perturbed sets of variables.
+/// Returns the direction each variable should move in, the values of the white and black player, and the game result
+#[allow(clippy::type_complexity)]
 fn tuning_iteration<R: rand::Rng, const S: usize>(
     variables: &[Variable],
     rng: &mut R,
     opening: &[Move<S>],
-) -> Vec<SpsaDirection> {
-    #[allow(clippy::type_complexity)]
+    settings: &SpsaSettings<S>,
+) -> (Vec<SpsaDirection>, Vec<f32>, Vec<f32>, Option<GameResult>) {
     let (player1_variables, player2_variables): (
         Vec<(SpsaDirection, f32)>,
         Vec<(SpsaDirection, f32)>,
@@ -84,29 +465,52 @@ fn tuning_iteration<R: rand::Rng, const S: usize>(
         .iter()
         .map(|variable| {
             (
-                (SpsaDirection::Increase, variable.value + variable.delta),
-                (SpsaDirection::Decrease, variable.value - variable.delta),
+                (
+                    SpsaDirection::Increase,
+                    variable.clamp(variable.value + variable.delta),
+                ),
+                (
+                    SpsaDirection::Decrease,
+                    variable.clamp(variable.value - variable.delta),
+                ),
             )
         })
         .map(|(a, b)| if rng.gen() { (a, b) } else { (b, a) })
         .unzip();
 
-    let player1_settings = <MctsSetting<S>>::default()
-        .add_search_params(player1_variables.iter().map(|(_, a)| *a).collect());
-    let player2_settings = <MctsSetting<S>>::default()
-        .add_search_params(player2_variables.iter().map(|(_, a)| *a).collect());
+    let player_settings = |player_variables: &[(SpsaDirection, f32)]| {
+        variables.iter().zip(player_variables).fold(
+            settings.base_settings.clone(),
+            |player_settings, (variable, (_, value))| {
+                variable.parameter.apply(player_settings, *value)
+            },
+        )
+    };
 
     let (game, _) = play_game::<S>(
-        &player1_settings,
-        &player2_settings,
-        Komi::default(),
+        &player_settings(&player1_variables),
+        &player_settings(&player2_variables),
+        settings.komi,
         opening,
+        None,
         0.2,
-        &TimeControl::Time(Duration::from_secs(20), Duration::from_millis(200)),
+        &settings.time_control,
     );
-    match game.game_result() {
+    let directions = match game.game_result() {
         Some(GameResult::WhiteWin) => player1_variables.iter().map(|(a, _)| *a).collect(),
         Some(GameResult::BlackWin) => player2_variables.iter().map(|(a, _)| *a).collect(),
         None | Some(GameResult::Draw) => vec![SpsaDirection::NoChange; variables.len()],
-    }
+    };
+    (
+        directions,
+        player1_variables.iter().map(|(_, value)| *value).collect(),
+        player2_variables.iter().map(|(_, value)| *value).collect(),
+        game.game_result(),
+    )
+}
+
+fn parse<T: str::FromStr>(value: &str, name: &str) -> Result<T, Error> {
+    value.parse().map_err(|_| {
+        Error::new_parse_error(format!("Bad {} \"{}\" in SPSA variables", name, value))
+    })
 }
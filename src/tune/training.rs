@@ -1,12 +1,14 @@
-use std::convert::TryFrom;
-use std::io::Read;
 use std::io::Write;
 use std::mem;
-use std::str::FromStr;
+use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time;
-use std::{error, fs, io};
+use std::{error, fs, io, iter, str};
 
+use crate::book::BookSettings;
+use crate::evaluation::parameter_file::{
+    load_policy_params, load_value_params, ParameterFile, ParameterKind,
+};
 use crate::evaluation::parameters::PolicyFeatures;
 use crate::position::Komi;
 use crate::search::TimeControl;
@@ -19,22 +21,198 @@ use rayon::prelude::*;
 use crate::evaluation::policy_eval::inverse_sigmoid;
 use crate::position::Move;
 use crate::position::Position;
+use crate::ptn::ptn_parser::PtnReader;
 use crate::ptn::Game;
-use crate::ptn::{ptn_parser, PtnMove};
+use crate::ptn::PtnMove;
 use crate::search::MctsSetting;
+use crate::tune::distributed::{Coordinator, GameJob, WorkerParams};
 use crate::tune::gradient_descent;
-use crate::tune::gradient_descent::TrainingSample;
+use crate::tune::gradient_descent::{GradientDescentSettings, TrainingSample};
 use crate::tune::play_match::play_game;
+use crate::tune::training_data::{self, TrainingRecord};
+use crate::tune::training_run::{
+    games_path, training_data_path, BatchResult, Manifest, TrainingConfig,
+};
 
 // The score, or probability of being played, for a given move
-type MoveScore<const S: usize> = (Move<S>, f16);
+pub type MoveScore<const S: usize> = (Move<S>, f16);
 
 // The probability of each possible move being played, through a whole game.
-type MoveScoresForGame<const S: usize> = Vec<Vec<MoveScore<S>>>;
+pub type MoveScoresForGame<const S: usize> = Vec<Vec<MoveScore<S>>>;
+
+/// Extra training samples generated from each position, by applying symmetries of the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Augmentation {
+    /// Only train on the positions that were played
+    #[default]
+    None,
+    /// Add all 8 rotations and reflections of the board
+    Symmetries,
+    /// Add the 8 symmetries, and each of them with the colors swapped and the result flipped.
+    /// This is only a symmetry without komi
+    SwappedColors,
+}
+
+impl str::FromStr for Augmentation {
+    type Err = pgn_traits::Error;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "none" => Ok(Augmentation::None),
+            "symmetries" => Ok(Augmentation::Symmetries),
+            "swapped-colors" => Ok(Augmentation::SwappedColors),
+            _ => Err(pgn_traits::Error::new_parse_error(format!(
+                "Augmentation must be \"none\", \"symmetries\" or \"swapped-colors\", got \"{}\"",
+                input
+            ))),
+        }
+    }
+}
+
+/// A position generated by `Augmentation::augment`
+pub struct AugmentedPosition<const S: usize> {
+    pub position: Position<S>,
+    /// Index of the symmetry in `Position::symmetries` and `Move::symmetries`
+    pub symmetry: usize,
+    pub swapped_colors: bool,
+}
+
+impl Augmentation {
+    /// Returns an error if the augmentation is not a symmetry with the komi
+    pub fn check_komi(self, komi: Komi) -> Result<(), DynError> {
+        if self == Augmentation::SwappedColors && komi.half_komi() != 0 {
+            Err(format!(
+                "Cannot swap colors of training positions with {} komi",
+                komi
+            )
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The augmented versions of the position, starting with the position itself.
+    /// Colors are not swapped during the first two plies, where players place their opponent's stones.
+    pub fn augment<const S: usize>(self, position: &Position<S>) -> Vec<AugmentedPosition<S>> {
+        let symmetries = match self {
+            Augmentation::None => vec![position.clone()],
+            Augmentation::Symmetries | Augmentation::SwappedColors => position.symmetries(),
+        };
+        let swap_colors = self == Augmentation::SwappedColors && position.half_moves_played() >= 2;
+        symmetries
+            .into_iter()
+            .enumerate()
+            .flat_map(|(symmetry, position)| {
+                let swapped = swap_colors.then(|| AugmentedPosition {
+                    position: position.flip_colors(),
+                    symmetry,
+                    swapped_colors: true,
+                });
+                iter::once(AugmentedPosition {
+                    position,
+                    symmetry,
+                    swapped_colors: false,
+                })
+                .chain(swapped)
+            })
+            .collect()
+    }
+}
+
+/// The evaluation parameters that are improved by self-play training
+pub trait SelfPlayParams<const S: usize>: Clone + Send + Sync + Sized {
+    /// The kind of parameters, as stored in the training run's manifest
+    const KIND: &'static str;
+
+    /// Search settings for playing with these parameters
+    fn mcts_settings(&self) -> MctsSetting<S>;
 
+    /// Tune new parameters from the training records of each game, starting from these parameters
+    fn tune(&self, games: &[Vec<TrainingRecord<S>>], komi: Komi) -> Result<Self, DynError>;
+
+    /// Write the parameters to files in `directory`, with names ending in `name`.
+    /// Returns the file names, relative to the directory
+    fn write(&self, directory: &Path, name: &str, komi: Komi) -> Result<Vec<String>, DynError>;
+
+    /// Read parameters written by `write`, for a training run with the given komi
+    fn read(directory: &Path, file_names: &[String], komi: Komi) -> Result<Self, DynError>;
+
+    /// The parameters in the form they're sent to self-play workers, or `None` if they can only be played locally
+    fn worker_params(&self) -> Option<WorkerParams> {
+        None
+    }
+}
+
+/// Linear value and policy parameters
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearParams<const N: usize, const M: usize> {
+    pub value_params: [f32; N],
+    pub policy_params: [f32; M],
+}
+
+impl<const S: usize, const N: usize, const M: usize> SelfPlayParams<S> for LinearParams<N, M> {
+    const KIND: &'static str = "linear";
+
+    fn mcts_settings(&self) -> MctsSetting<S> {
+        MctsSetting::default()
+            .add_value_params(self.value_params.into())
+            .add_policy_params(self.policy_params.into())
+    }
+
+    fn tune(&self, games: &[Vec<TrainingRecord<S>>], komi: Komi) -> Result<Self, DynError> {
+        let (value_params, policy_params) = tune_value_and_policy(
+            games,
+            komi,
+            &self.value_params,
+            &self.policy_params,
+            Augmentation::None,
+            &GradientDescentSettings::new(50.0),
+            &GradientDescentSettings::new(500.0),
+        )?;
+        Ok(LinearParams {
+            value_params,
+            policy_params,
+        })
+    }
+
+    fn write(&self, directory: &Path, name: &str, komi: Komi) -> Result<Vec<String>, DynError> {
+        let value_file_name = format!("value_params_{}.txt", name);
+        let policy_file_name = format!("policy_params_{}.txt", name);
+        ParameterFile::new::<S>(ParameterKind::Value, komi, &self.value_params)
+            .to_file(&directory.join(&value_file_name).to_string_lossy())?;
+        ParameterFile::new::<S>(ParameterKind::Policy, komi, &self.policy_params)
+            .to_file(&directory.join(&policy_file_name).to_string_lossy())?;
+        Ok(vec![value_file_name, policy_file_name])
+    }
+
+    fn read(directory: &Path, file_names: &[String], komi: Komi) -> Result<Self, DynError> {
+        let [value_file_name, policy_file_name] = file_names else {
+            return Err(format!("Expected 2 parameter files, got {:?}", file_names).into());
+        };
+        let value_file =
+            load_value_params::<S>(&directory.join(value_file_name).to_string_lossy(), komi)?;
+        let policy_file =
+            load_policy_params::<S>(&directory.join(policy_file_name).to_string_lossy(), komi)?;
+        Ok(LinearParams {
+            value_params: value_file.params.try_into().unwrap(),
+            policy_params: policy_file.params.try_into().unwrap(),
+        })
+    }
+
+    fn worker_params(&self) -> Option<WorkerParams> {
+        Some(WorkerParams {
+            value_params: Some(self.value_params.to_vec()),
+            policy_params: Some(self.policy_params.to_vec()),
+        })
+    }
+}
+
+/// Start a new training run in `run_directory` from randomly initialized parameters
 pub fn train_from_scratch<const S: usize, const N: usize, const M: usize>(
-    training_id: usize,
-    komi: Komi,
+    run_directory: &Path,
+    config: &TrainingConfig,
+    book: Option<&BookSettings<S>>,
+    coordinator: Option<&Coordinator<S>>,
 ) -> Result<(), DynError> {
     let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
 
@@ -43,213 +221,285 @@ pub fn train_from_scratch<const S: usize, const N: usize, const M: usize>(
     let initial_policy_params: [f32; M] = array_from_fn(|| rng.gen_range(-0.01..0.01));
 
     train_perpetually::<S, N, M>(
-        training_id,
-        komi,
+        run_directory,
+        config,
         &initial_value_params,
         &initial_policy_params,
-        vec![],
-        vec![],
-        0,
+        book,
+        coordinator,
     )
 }
 
+/// Resume a training run of linear parameters from its run directory
 pub fn continue_training<const S: usize, const N: usize, const M: usize>(
-    training_id: usize,
-    komi: Komi,
+    run_directory: &Path,
+    book: Option<&BookSettings<S>>,
+    coordinator: Option<&Coordinator<S>>,
+) -> Result<(), DynError> {
+    resume_training_run::<S, LinearParams<N, M>>(run_directory, book, coordinator)
+}
+
+/// Start a new training run in `run_directory`, which plays batches of self-play games,
+/// and tunes new parameters from them, indefinitely.
+/// If a book is given, the games start with moves from the book.
+/// If a coordinator is given, the games are played by its workers instead of on this machine.
+pub fn train_perpetually<const S: usize, const N: usize, const M: usize>(
+    run_directory: &Path,
+    config: &TrainingConfig,
+    initial_value_params: &[f32; N],
+    initial_policy_params: &[f32; M],
+    book: Option<&BookSettings<S>>,
+    coordinator: Option<&Coordinator<S>>,
 ) -> Result<(), DynError> {
+    start_training_run(
+        run_directory,
+        config,
+        LinearParams {
+            value_params: *initial_value_params,
+            policy_params: *initial_policy_params,
+        },
+        book,
+        coordinator,
+    )
+}
+
+/// Create a new run directory with a manifest, and start self-play training from the given parameters.
+/// Fails if the directory already exists.
+pub fn start_training_run<const S: usize, P: SelfPlayParams<S>>(
+    run_directory: &Path,
+    config: &TrainingConfig,
+    initial_params: P,
+    book: Option<&BookSettings<S>>,
+    coordinator: Option<&Coordinator<S>>,
+) -> Result<(), DynError> {
+    fs::create_dir(run_directory)?;
+    let params_files = initial_params.write(run_directory, "initial", config.komi)?;
+    let manifest = Manifest {
+        size: S,
+        kind: P::KIND.to_string(),
+        config: config.clone(),
+        batch_id: 0,
+        num_games: 0,
+        params_files: params_files.clone(),
+        last_params_files: params_files,
+        history: vec![],
+    };
+    manifest.write_to_dir(run_directory)?;
+    println!("Started training run in {}", run_directory.display());
+
+    self_play_perpetually(
+        run_directory,
+        manifest,
+        initial_params.clone(),
+        initial_params,
+        vec![],
+        book,
+        coordinator,
+    )
+}
+
+/// Resume self-play training from the manifest in a run directory
+pub fn resume_training_run<const S: usize, P: SelfPlayParams<S>>(
+    run_directory: &Path,
+    book: Option<&BookSettings<S>>,
+    coordinator: Option<&Coordinator<S>>,
+) -> Result<(), DynError> {
+    let manifest = Manifest::from_dir(run_directory)?;
+    if manifest.size != S {
+        return Err(format!("Expected a {}s training run, got {}s", S, manifest.size).into());
+    }
+    if manifest.kind != P::KIND {
+        return Err(format!(
+            "Expected a training run of {} parameters, got {}",
+            P::KIND,
+            manifest.kind
+        )
+        .into());
+    }
+    let params = P::read(run_directory, &manifest.params_files, manifest.config.komi)?;
+    let last_params = P::read(
+        run_directory,
+        &manifest.last_params_files,
+        manifest.config.komi,
+    )?;
+
+    // Only the most recent batches are used for training
     let mut games = vec![];
-    let mut move_scores = vec![];
-    let mut batch_id = 0;
-    loop {
-        match read_games_from_file::<S>(
-            &format!("games{}_{}s_batch{}.ptn", training_id, S, batch_id),
-            komi,
-        ) {
-            Ok(mut game_batch) => {
-                games.append(&mut game_batch);
-            }
-            Err(error) => {
-                let io_error = error.downcast::<io::Error>()?;
-                if io_error.kind() == io::ErrorKind::NotFound {
-                    break;
-                } else {
-                    return Err(io_error);
-                }
-            }
-        }
-        let mut move_scores_batch = read_move_scores_from_file::<S>(&format!(
-            "move_scores{}_{}s_batch{}.ptn",
-            training_id, S, batch_id
-        ))?;
-        move_scores.append(&mut move_scores_batch);
-        batch_id += 1;
+    for batch_id in manifest
+        .batch_id
+        .saturating_sub(manifest.config.batches_for_training)..manifest.batch_id
+    {
+        games.append(&mut read_batch_training_data::<S>(
+            run_directory,
+            batch_id,
+            manifest.config.komi,
+        )?);
     }
-
-    assert_eq!(games.len(), move_scores.len());
     println!(
-        "Resumed training with {} games and {} moves",
+        "Resumed training at batch {} with {} games and {} positions",
+        manifest.batch_id,
         games.len(),
-        move_scores.iter().map(Vec::len).sum::<usize>()
+        games.iter().map(Vec::len).sum::<usize>()
     );
 
-    let value_params = <Position<S>>::value_params(komi);
-
-    let policy_params = <Position<S>>::policy_params(komi);
-
-    train_perpetually::<S, N, M>(
-        training_id,
-        komi,
-        &<[f32; N]>::try_from(value_params).unwrap(),
-        &<[f32; M]>::try_from(policy_params).unwrap(),
+    self_play_perpetually(
+        run_directory,
+        manifest,
+        params,
+        last_params,
         games,
-        move_scores,
-        batch_id,
+        book,
+        coordinator,
     )
 }
 
-pub fn train_perpetually<const S: usize, const N: usize, const M: usize>(
-    training_id: usize,
+/// Read the training records of each game in a batch
+fn read_batch_training_data<const S: usize>(
+    run_directory: &Path,
+    batch_id: usize,
     komi: Komi,
-    initial_value_params: &[f32; N],
-    initial_policy_params: &[f32; M],
-    mut all_games: Vec<Game<Position<S>>>,
-    mut all_move_scores: Vec<MoveScoresForGame<S>>,
-    mut batch_id: usize,
-) -> Result<(), DynError> {
-    const BATCH_SIZE: usize = 500;
-    // Only train from the last n batches
-    const BATCHES_FOR_TRAINING: usize = 25;
-
-    let mut last_value_params = *initial_value_params;
-    let mut last_policy_params = *initial_policy_params;
+) -> Result<Vec<Vec<TrainingRecord<S>>>, DynError> {
+    let path = training_data_path(run_directory, batch_id);
+    let file_name = path.to_string_lossy();
+    let (file_komi, games) = training_data::read_training_data_games_file::<S>(&file_name)?;
+    if file_komi != komi {
+        return Err(format!(
+            "{} has komi {}, but the training run has komi {}",
+            file_name, file_komi, komi
+        )
+        .into());
+    }
+    Ok(games)
+}
 
-    let mut value_params = *initial_value_params;
-    let mut policy_params = *initial_policy_params;
+/// The self-play loop. After every batch, the games, training data and new parameters are written to the run directory,
+/// followed by the updated manifest.
+fn self_play_perpetually<const S: usize, P: SelfPlayParams<S>>(
+    run_directory: &Path,
+    mut manifest: Manifest,
+    mut params: P,
+    mut last_params: P,
+    mut recent_games: Vec<Vec<TrainingRecord<S>>>,
+    book: Option<&BookSettings<S>>,
+    coordinator: Option<&Coordinator<S>>,
+) -> Result<(), DynError> {
+    let config = manifest.config.clone();
+    let max_recent_games = config.batch_size * config.batches_for_training;
 
     let start_time = time::Instant::now();
     let mut playing_time = time::Duration::default();
     let mut tuning_time = time::Duration::default();
 
     loop {
+        let batch_id = manifest.batch_id;
         let current_params_wins: AtomicU64 = AtomicU64::new(0);
         let last_params_wins: AtomicU64 = AtomicU64::new(0);
 
+        // Each search gets its own arena, so only allocate enough for the nodes that are searched
+        let settings = params
+            .mcts_settings()
+            .arena_size_for_nodes(config.nodes as u32)
+            .add_dirichlet(config.dirichlet_alpha);
+        let last_settings = last_params
+            .mcts_settings()
+            .arena_size_for_nodes(config.nodes as u32)
+            .add_dirichlet(config.dirichlet_alpha);
+
         let playing_start_time = time::Instant::now();
-        let (games, move_scores): (Vec<_>, Vec<_>) = (0..BATCH_SIZE)
-            .into_par_iter()
-            .map(|i| {
-                play_game_pair::<S>(
-                    komi,
-                    &last_value_params,
-                    &last_policy_params,
-                    &value_params,
-                    &policy_params,
-                    &current_params_wins,
-                    &last_params_wins,
-                    i,
-                )
-            })
-            .unzip();
+        let (games, move_scores): (Vec<_>, Vec<_>) = match coordinator {
+            Some(coordinator) => play_batch_on_workers(
+                coordinator,
+                &config,
+                &last_params,
+                &params,
+                &current_params_wins,
+                &last_params_wins,
+                book,
+            )?
+            .into_iter()
+            .unzip(),
+            None => (0..config.batch_size)
+                .into_par_iter()
+                .map(|i| {
+                    play_game_pair::<S>(
+                        &config,
+                        &last_settings,
+                        &settings,
+                        &current_params_wins,
+                        &last_params_wins,
+                        book,
+                        i,
+                    )
+                })
+                .unzip(),
+        };
         playing_time += playing_start_time.elapsed();
 
-        all_move_scores.extend_from_slice(&move_scores[..]);
-        all_games.extend_from_slice(&games[..]);
-
-        let file_name = format!("games{}_{}s_batch{}.ptn", training_id, S, batch_id);
-
-        let outfile = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(file_name)
-            .unwrap();
-
-        let mut writer = io::BufWriter::new(outfile);
+        // If an earlier attempt at this batch was interrupted, overwrite its files
+        let mut writer = io::BufWriter::new(fs::File::create(games_path(run_directory, batch_id))?);
 
         for game in games.iter() {
             game.game_to_ptn(&mut writer)?;
         }
-
-        let games_and_move_scores_outfile = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(format!(
-                "move_scores{}_{}s_batch{}.ptn",
-                training_id, S, batch_id
-            ))
-            .unwrap();
         writer.flush()?;
 
-        let mut writer = io::BufWriter::new(games_and_move_scores_outfile);
-
-        for (game, move_scores) in games.iter().zip(move_scores) {
-            for (mv, move_scores) in game
-                .moves
-                .iter()
-                .map(|PtnMove { mv, .. }| mv)
-                .zip(move_scores)
-            {
-                write!(writer, "{}: ", mv)?;
-                for (mv, score) in move_scores {
-                    write!(writer, "{} {}, ", mv, score)?;
-                }
-                writeln!(writer)?;
-            }
-            writeln!(writer)?;
-        }
-        writer.flush()?;
+        let training_games: Vec<Vec<TrainingRecord<S>>> = games
+            .iter()
+            .zip(&move_scores)
+            .map(|(game, move_scores)| training_data::records_from_game(game, move_scores))
+            .collect();
+        training_data::write_training_data_games_file(
+            &training_data_path(run_directory, batch_id).to_string_lossy(),
+            config.komi,
+            &training_games,
+        )?;
 
         let game_stats = GameStats::from_games(&games);
 
         let wins = current_params_wins.into_inner();
         let losses = last_params_wins.into_inner();
-        let draws = BATCH_SIZE as u64 - wins - losses;
+        let draws = config.batch_size as u64 - wins - losses;
+        manifest.num_games += games.len();
 
         println!("Finished playing batch of {} games. {} games played in total. {} white wins, {} draws, {} black wins, {} aborted. New vs old parameters was +{}-{}={}.",
-            games.len(), all_games.len(), game_stats.white_wins, game_stats.draws, game_stats.black_wins, game_stats.aborted, wins, losses, draws
+            games.len(), manifest.num_games, game_stats.white_wins, game_stats.draws, game_stats.black_wins, game_stats.aborted, wins, losses, draws
         );
 
-        // Only take the most recent half of the games, to avoid training on bad, old games
-        let max_training_games = all_games.len() / 2;
+        recent_games.extend(training_games);
+        let num_old_games = recent_games.len().saturating_sub(max_recent_games);
+        recent_games.drain(..num_old_games);
 
-        let games_in_training_batch = all_games
-            .iter()
-            .cloned()
-            .rev()
-            .take(usize::min(
-                max_training_games,
-                BATCH_SIZE * BATCHES_FOR_TRAINING,
-            ))
-            .collect::<Vec<_>>();
+        // Only take the most recent half of the games, to avoid training on bad, old games
+        let max_training_games = manifest.num_games / 2;
 
-        let move_scores_in_training_batch = all_move_scores
+        let games_in_training_batch = recent_games
             .iter()
             .cloned()
             .rev()
-            .take(usize::min(
-                max_training_games,
-                BATCH_SIZE * BATCHES_FOR_TRAINING,
-            ))
+            .take(usize::min(max_training_games, max_recent_games))
             .collect::<Vec<_>>();
 
         let value_tuning_start_time = time::Instant::now();
 
-        let (new_value_params, new_policy_params): ([f32; N], [f32; M]) = tune_value_and_policy(
-            &games_in_training_batch,
-            &move_scores_in_training_batch,
-            komi,
-            &value_params,
-            &policy_params,
-        )?;
+        let new_params = params.tune(&games_in_training_batch, config.komi)?;
 
-        last_value_params = value_params;
-        last_policy_params = policy_params;
+        last_params = mem::replace(&mut params, new_params);
 
-        value_params = new_value_params;
-        policy_params = new_policy_params;
+        let params_files =
+            params.write(run_directory, &format!("batch{}", batch_id), config.komi)?;
 
         tuning_time += value_tuning_start_time.elapsed();
 
-        batch_id += 1;
+        manifest.last_params_files = mem::replace(&mut manifest.params_files, params_files);
+        manifest.history.push(BatchResult {
+            batch_id,
+            wins,
+            losses,
+            draws,
+        });
+        manifest.batch_id += 1;
+        manifest.write_to_dir(run_directory)?;
+
         println!(
             "{}s elapsed. Time use breakdown: {:.2}% playing games, {:.2}% tuning parameters.",
             start_time.elapsed().as_secs(),
@@ -259,33 +509,25 @@ pub fn train_perpetually<const S: usize, const N: usize, const M: usize>(
     }
 }
 
-#[allow(clippy::too_many_arguments)]
 fn play_game_pair<const S: usize>(
-    komi: Komi,
-    last_value_params: &[f32],
-    last_policy_params: &[f32],
-    value_params: &[f32],
-    policy_params: &[f32],
+    config: &TrainingConfig,
+    last_settings: &MctsSetting<S>,
+    settings: &MctsSetting<S>,
     current_params_wins: &AtomicU64,
     last_params_wins: &AtomicU64,
+    book: Option<&BookSettings<S>>,
     i: usize,
 ) -> (Game<Position<S>>, MoveScoresForGame<S>) {
-    let settings = MctsSetting::default()
-        .add_value_params(value_params.into())
-        .add_policy_params(policy_params.into())
-        .add_dirichlet(0.2);
-    let last_settings = MctsSetting::default()
-        .add_value_params(last_value_params.into())
-        .add_policy_params(last_policy_params.into())
-        .add_dirichlet(0.2);
+    let time_control = TimeControl::FixedNodes(config.nodes);
     if i % 2 == 0 {
         let game = play_game::<S>(
-            &settings,
-            &last_settings,
-            komi,
+            settings,
+            last_settings,
+            config.komi,
             &[],
+            book,
             1.0,
-            &TimeControl::FixedNodes(50_000),
+            &time_control,
         );
         match game.0.game_result() {
             Some(GameResult::WhiteWin) => {
@@ -299,12 +541,13 @@ fn play_game_pair<const S: usize>(
         game
     } else {
         let game = play_game::<S>(
-            &last_settings,
-            &settings,
-            komi,
+            last_settings,
+            settings,
+            config.komi,
             &[],
+            book,
             1.0,
-            &TimeControl::FixedNodes(50_000),
+            &time_control,
         );
         match game.0.game_result() {
             Some(GameResult::BlackWin) => {
@@ -319,6 +562,64 @@ fn play_game_pair<const S: usize>(
     }
 }
 
+/// Play a batch of games on the coordinator's workers. Like `play_game_pair`, the current parameters
+/// play white in every other game, and the book moves are chosen before the games are handed out
+fn play_batch_on_workers<const S: usize, P: SelfPlayParams<S>>(
+    coordinator: &Coordinator<S>,
+    config: &TrainingConfig,
+    last_params: &P,
+    params: &P,
+    current_params_wins: &AtomicU64,
+    last_params_wins: &AtomicU64,
+    book: Option<&BookSettings<S>>,
+) -> Result<Vec<(Game<Position<S>>, MoveScoresForGame<S>)>, DynError> {
+    let worker_params = |params: &P| {
+        params
+            .worker_params()
+            .ok_or_else(|| format!("Self-play workers can't play with {} parameters", P::KIND))
+    };
+    let current_id = coordinator.add_params(worker_params(params)?);
+    let last_id = coordinator.add_params(worker_params(last_params)?);
+
+    let mut rng = rand::thread_rng();
+    let jobs = (0..config.batch_size)
+        .map(|i| {
+            let (white, black) = if i % 2 == 0 {
+                (current_id, last_id)
+            } else {
+                (last_id, current_id)
+            };
+            GameJob {
+                white,
+                black,
+                komi: config.komi,
+                time_control: TimeControl::FixedNodes(config.nodes),
+                dirichlet_alpha: Some(config.dirichlet_alpha),
+                temperature: 1.0,
+                opening: book
+                    .map(|book| {
+                        book.choose_line(Position::start_position_with_komi(config.komi), &mut rng)
+                    })
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    let games = coordinator.play_games(jobs);
+    for (i, (game, _)) in games.iter().enumerate() {
+        match (game.game_result(), i % 2 == 0) {
+            (Some(GameResult::WhiteWin), true) | (Some(GameResult::BlackWin), false) => {
+                current_params_wins.fetch_add(1, Ordering::Relaxed);
+            }
+            (Some(GameResult::WhiteWin), false) | (Some(GameResult::BlackWin), true) => {
+                last_params_wins.fetch_add(1, Ordering::Relaxed);
+            }
+            (Some(GameResult::Draw) | None, _) => (),
+        }
+    }
+    Ok(games)
+}
+
 #[derive(Clone, PartialEq, Eq, Debug, Default)]
 pub struct GameStats {
     pub white_wins: u64,
@@ -342,25 +643,24 @@ impl GameStats {
     }
 }
 
+/// Lazily read the games in a PTN file, one at a time
+pub fn games_from_file<const S: usize>(
+    file_name: &str,
+) -> Result<PtnReader<io::BufReader<fs::File>, Position<S>>, DynError> {
+    let file = fs::File::open(file_name)?;
+    Ok(PtnReader::new(io::BufReader::new(file)))
+}
+
 pub fn read_games_from_file<const S: usize>(
     file_name: &str,
     komi: Komi,
 ) -> Result<Vec<Game<Position<S>>>, DynError> {
     let start_time = time::Instant::now();
-    let mut file = fs::File::open(file_name)?;
-    let mut input = String::new();
-    file.read_to_string(&mut input)?;
-    let mut games = ptn_parser::parse_ptn::<Position<S>>(&input)?;
-    for game in games.iter_mut() {
-        if let Some((_, komi_str)) = game
-            .tags
-            .iter()
-            .find(|(tag, _)| tag.eq_ignore_ascii_case("Komi"))
-        {
-            game.start_position
-                .set_komi(Komi::from_str(komi_str).unwrap());
-        }
-        assert_eq!(komi, game.start_position.komi());
+    let mut games = vec![];
+    for game in games_from_file::<S>(file_name)? {
+        let game = game?;
+        check_komi(&game, komi, file_name, games.len())?;
+        games.push(game);
     }
     println!(
         "Read {} games from PTN in {:.1}s",
@@ -370,43 +670,46 @@ pub fn read_games_from_file<const S: usize>(
     Ok(games)
 }
 
+fn check_komi<const S: usize>(
+    game: &Game<Position<S>>,
+    komi: Komi,
+    file_name: &str,
+    game_index: usize,
+) -> Result<(), DynError> {
+    if game.start_position.komi() != komi {
+        return Err(format!(
+            "Game #{} in {} has komi {}, expected {}",
+            game_index + 1,
+            file_name,
+            game.start_position.komi(),
+            komi
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Tune value parameters from randomly initialized values, using the games in a PTN file.
+/// A random selection of the games is held out for validation
 pub fn tune_value_from_file<const S: usize, const N: usize>(
     file_name: &str,
     komi: Komi,
+    augmentation: Augmentation,
+    settings: &GradientDescentSettings,
 ) -> Result<[f32; N], DynError> {
-    let games = read_games_from_file::<S>(file_name, komi)?;
-
+    augmentation.check_komi(komi)?;
+    // Only keep the positions in memory, not the games
     let start_time = time::Instant::now();
-    let (positions, results) = positions_and_results_from_games(&games, komi);
-    println!(
-        "Extracted {} positions in {:.1}s",
-        positions.len(),
-        start_time.elapsed().as_secs_f32()
-    );
-
-    let start_time = time::Instant::now();
-    let mut samples = positions
-        .par_iter()
-        .zip(results)
-        .map(|(position, game_result)| {
-            let mut features = [f16::ZERO; N];
-            position.static_eval_features(&mut features);
-            let result = match game_result {
-                GameResult::WhiteWin => f16::ONE,
-                GameResult::Draw => f16::ONE / (f16::ONE + f16::ONE),
-                GameResult::BlackWin => f16::ZERO,
-            };
-            TrainingSample {
-                features,
-                offset: 0.0,
-                result,
-            }
-        })
-        .collect::<Vec<_>>();
-
+    let mut games = vec![];
+    for game in games_from_file::<S>(file_name)? {
+        let game = game?;
+        check_komi(&game, komi, file_name, games.len())?;
+        games.push(positions_and_results_from_game(&game, komi));
+    }
     println!(
-        "Vectorized {} training samples in {:.1}s",
-        samples.len(),
+        "Extracted {} positions from {} games in {:.1}s",
+        games.iter().map(Vec::len).sum::<usize>(),
+        games.len(),
         start_time.elapsed().as_secs_f32()
     );
 
@@ -417,46 +720,135 @@ pub fn tune_value_from_file<const S: usize, const N: usize>(
         *param = rng.gen_range(-0.01..0.01)
     }
 
-    samples.shuffle(&mut rng);
+    games.shuffle(&mut rng);
+    let (training_games, validation_games) = settings.split_validation(&games);
+
+    let start_time = time::Instant::now();
+    let mut samples_from_games = |games: &[Vec<(Position<S>, GameResult)>]| {
+        let mut samples = games
+            .par_iter()
+            .flatten()
+            .flat_map_iter(|(position, game_result)| {
+                value_training_samples_for_position(
+                    position,
+                    training_data::result_score(*game_result),
+                    augmentation,
+                )
+            })
+            .collect::<Vec<_>>();
+        samples.shuffle(&mut rng);
+        samples
+    };
+    let training_samples = samples_from_games(training_games);
+    let validation_samples = samples_from_games(validation_games);
+
+    println!(
+        "Vectorized {} training samples and {} validation samples in {:.1}s",
+        training_samples.len(),
+        validation_samples.len(),
+        start_time.elapsed().as_secs_f32()
+    );
 
-    let tuned_parameters =
-        gradient_descent::gradient_descent(&samples, &initial_params, 50.0, &mut rng);
+    let tuned_parameters = gradient_descent::gradient_descent(
+        &training_samples,
+        &validation_samples,
+        &initial_params,
+        settings,
+        &mut rng,
+    )?;
 
     Ok(tuned_parameters)
 }
 
+#[allow(clippy::too_many_arguments)]
+/// Tune value and policy parameters from the training records of each game.
+/// The validation games are held out before duplicate positions are merged, as in `training_data::deduplicate`
 pub fn tune_value_and_policy<const S: usize, const N: usize, const M: usize>(
-    games: &[Game<Position<S>>],
-    move_scoress: &[MoveScoresForGame<S>],
+    games: &[Vec<TrainingRecord<S>>],
     komi: Komi,
     initial_value_params: &[f32; N],
     initial_policy_params: &[f32; M],
+    augmentation: Augmentation,
+    value_settings: &GradientDescentSettings,
+    policy_settings: &GradientDescentSettings,
 ) -> Result<([f32; N], [f32; M]), DynError> {
+    augmentation.check_komi(komi)?;
     let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
 
-    let (positions, results) = positions_and_results_from_games(games, komi);
+    // Shuffle the games, so that the held out games are a random selection
+    let mut games: Vec<&Vec<TrainingRecord<S>>> = games.iter().collect();
+    games.shuffle(&mut rng);
+
+    let (training_games, validation_games) = value_settings.split_validation(&games);
+    let tuned_value_parameters = gradient_descent::gradient_descent(
+        &value_training_samples::<S, N, _>(
+            &merge_duplicates(training_games),
+            augmentation,
+            &mut rng,
+        ),
+        &value_training_samples::<S, N, _>(
+            &merge_duplicates(validation_games),
+            augmentation,
+            &mut rng,
+        ),
+        initial_value_params,
+        value_settings,
+        &mut rng,
+    )?;
+
+    let (training_games, validation_games) = policy_settings.split_validation(&games);
+    let tuned_policy_parameters = gradient_descent::gradient_descent(
+        &policy_training_samples::<S, M, _>(
+            &merge_duplicates(training_games),
+            augmentation,
+            &mut rng,
+        ),
+        &policy_training_samples::<S, M, _>(
+            &merge_duplicates(validation_games),
+            augmentation,
+            &mut rng,
+        ),
+        initial_policy_params,
+        policy_settings,
+        &mut rng,
+    )?;
+
+    Ok((tuned_value_parameters, tuned_policy_parameters))
+}
+
+/// Merge the duplicate positions in all the games, as in `training_data::deduplicate`
+pub fn merge_duplicates<const S: usize>(
+    games: &[&Vec<TrainingRecord<S>>],
+) -> Vec<TrainingRecord<S>> {
+    let start_time = time::Instant::now();
+    let num_records = games.iter().map(|game| game.len()).sum::<usize>();
+    let records =
+        training_data::deduplicate(games.iter().flat_map(|game| game.iter().cloned()).collect());
+    println!(
+        "Merged {} positions from {} games into {} unique positions in {:.1}s",
+        num_records,
+        games.len(),
+        records.len(),
+        start_time.elapsed().as_secs_f32()
+    );
+    records
+}
 
+/// Value features and results for every record, and their augmented versions, in random order
+pub fn value_training_samples<const S: usize, const N: usize, R: Rng>(
+    records: &[TrainingRecord<S>],
+    augmentation: Augmentation,
+    rng: &mut R,
+) -> Vec<TrainingSample<N>> {
     let start_time = time::Instant::now();
-    let mut value_training_samples = positions
+    let mut value_training_samples = records
         .par_iter()
-        .zip(results)
-        .map(|(position, game_result)| {
-            let mut features = [f16::ZERO; N];
-            position.static_eval_features(&mut features);
-            let result = match game_result {
-                GameResult::WhiteWin => f16::ONE,
-                GameResult::Draw => f16::ONE / (f16::ONE + f16::ONE),
-                GameResult::BlackWin => f16::ZERO,
-            };
-            TrainingSample {
-                features,
-                offset: 0.0,
-                result,
-            }
+        .flat_map_iter(|record| {
+            value_training_samples_for_position(&record.position, record.result, augmentation)
         })
         .collect::<Vec<_>>();
 
-    value_training_samples.shuffle(&mut rng);
+    value_training_samples.shuffle(rng);
 
     println!(
         "Generated {} value training samples in {:.1}s, {:.2}GiB total",
@@ -465,59 +857,105 @@ pub fn tune_value_and_policy<const S: usize, const N: usize, const M: usize>(
         (value_training_samples.len() * mem::size_of::<TrainingSample<N>>()) as f32
             / f32::powf(2.0, 30.0),
     );
+    value_training_samples
+}
+
+fn value_training_samples_for_position<const S: usize, const N: usize>(
+    position: &Position<S>,
+    result: f16,
+    augmentation: Augmentation,
+) -> impl Iterator<Item = TrainingSample<N>> {
+    augmentation.augment(position).into_iter().map(
+        move |AugmentedPosition {
+                  position,
+                  swapped_colors,
+                  ..
+              }| {
+            let mut features = [f16::ZERO; N];
+            position.static_eval_features(&mut features);
+            let result = if swapped_colors {
+                f16::ONE - result
+            } else {
+                result
+            };
+            TrainingSample {
+                features,
+                offset: 0.0,
+                result,
+            }
+        },
+    )
+}
 
-    let number_of_feature_sets = move_scoress.iter().flatten().flatten().count();
+/// Policy features and search results for every move in the records, and in their augmented positions, in random order
+pub fn policy_training_samples<const S: usize, const M: usize, R: Rng>(
+    records: &[TrainingRecord<S>],
+    augmentation: Augmentation,
+    rng: &mut R,
+) -> Vec<TrainingSample<M>> {
+    let number_of_feature_sets = records
+        .iter()
+        .map(|record| record.move_scores.len())
+        .sum::<usize>()
+        * match augmentation {
+            Augmentation::None => 1,
+            Augmentation::Symmetries => 8,
+            Augmentation::SwappedColors => 16,
+        };
 
     let start_time: time::Instant = time::Instant::now();
 
     let mut policy_training_samples: Vec<TrainingSample<M>> =
         Vec::with_capacity(number_of_feature_sets);
 
-    policy_training_samples.extend(games.iter().zip(move_scoress.iter()).flat_map(
-        |(game, move_scores)| {
-            let mut position = game.start_position.clone();
-
-            game.moves
-                .iter()
-                .map(|PtnMove { mv, .. }| mv)
-                .zip(move_scores.iter())
-                .flat_map(move |(mv, move_scores)| {
-                    let group_data = position.group_data();
-
-                    let mut feature_sets = vec![[f16::ZERO; M]; move_scores.len()];
-                    let mut policy_feature_sets: Vec<PolicyFeatures> = feature_sets
-                        .iter_mut()
-                        .map(|feature_set| PolicyFeatures::new::<S>(feature_set))
-                        .collect();
-                    let moves: Vec<Move<S>> = move_scores.iter().map(|(mv, _score)| *mv).collect();
-
-                    position.features_for_moves(
-                        &mut policy_feature_sets,
-                        &moves,
-                        &mut Vec::with_capacity(moves.len()),
-                        &group_data,
-                    );
-
-                    position.do_move(*mv);
-
-                    move_scores
-                        .iter()
-                        .zip(feature_sets)
-                        .map(|((_, result), features)| {
-                            let offset = inverse_sigmoid(1.0 / move_scores.len().max(2) as f32);
-                            {
-                                TrainingSample {
-                                    features,
-                                    offset,
-                                    result: *result,
-                                }
+    policy_training_samples.extend(records.iter().flat_map(|record| {
+        let move_scores = &record.move_scores;
+        let move_symmetries: Vec<[Move<S>; 8]> = move_scores
+            .iter()
+            .map(|(mv, _score)| mv.symmetries())
+            .collect();
+
+        augmentation.augment(&record.position).into_iter().flat_map(
+            move |AugmentedPosition {
+                      position, symmetry, ..
+                  }| {
+                let group_data = position.group_data();
+
+                let mut feature_sets = vec![[f16::ZERO; M]; move_scores.len()];
+                let mut policy_feature_sets: Vec<PolicyFeatures> = feature_sets
+                    .iter_mut()
+                    .map(|feature_set| PolicyFeatures::new::<S>(feature_set))
+                    .collect();
+                let moves: Vec<Move<S>> = move_symmetries
+                    .iter()
+                    .map(|symmetries| symmetries[symmetry])
+                    .collect();
+
+                position.features_for_moves(
+                    &mut policy_feature_sets,
+                    &moves,
+                    &mut Vec::with_capacity(moves.len()),
+                    &group_data,
+                );
+
+                move_scores
+                    .iter()
+                    .zip(feature_sets)
+                    .map(|((_, result), features)| {
+                        let offset = inverse_sigmoid(1.0 / move_scores.len().max(2) as f32);
+                        {
+                            TrainingSample {
+                                features,
+                                offset,
+                                result: *result,
                             }
-                        })
-                })
-        },
-    ));
+                        }
+                    })
+            },
+        )
+    }));
 
-    policy_training_samples.shuffle(&mut rng);
+    policy_training_samples.shuffle(rng);
     println!(
         "Generated {} policy training samples in {:.1}s, {:.2}GiB total",
         policy_training_samples.len(),
@@ -525,31 +963,59 @@ pub fn tune_value_and_policy<const S: usize, const N: usize, const M: usize>(
         (policy_training_samples.len() * mem::size_of::<TrainingSample<M>>()) as f32
             / f32::powf(2.0, 30.0),
     );
-
-    let tuned_value_parameters = gradient_descent::gradient_descent(
-        &value_training_samples,
-        initial_value_params,
-        50.0,
-        &mut rng,
-    );
-
-    let tuned_policy_parameters = gradient_descent::gradient_descent(
-        &policy_training_samples,
-        initial_policy_params,
-        500.0,
-        &mut rng,
-    );
-
-    Ok((tuned_value_parameters, tuned_policy_parameters))
+    policy_training_samples
 }
 
 pub fn tune_value_and_policy_from_file<const S: usize, const N: usize, const M: usize>(
     value_file_name: &str,
     policy_file_name: &str,
     komi: Komi,
+    augmentation: Augmentation,
+    value_settings: &GradientDescentSettings,
+    policy_settings: &GradientDescentSettings,
 ) -> Result<([f32; N], [f32; M]), DynError> {
     let (games, move_scoress) =
         games_and_move_scoress_from_file::<S>(value_file_name, policy_file_name, komi)?;
+    let games: Vec<Vec<TrainingRecord<S>>> = games
+        .iter()
+        .zip(&move_scoress)
+        .map(|(game, move_scores)| training_data::records_from_game(game, move_scores))
+        .collect();
+
+    tune_value_and_policy_from_scratch(&games, komi, augmentation, value_settings, policy_settings)
+}
+
+/// Tune value and policy parameters from randomly initialized values, using a binary training data file
+pub fn tune_value_and_policy_from_training_data_file<
+    const S: usize,
+    const N: usize,
+    const M: usize,
+>(
+    file_name: &str,
+    komi: Komi,
+    augmentation: Augmentation,
+    value_settings: &GradientDescentSettings,
+    policy_settings: &GradientDescentSettings,
+) -> Result<([f32; N], [f32; M]), DynError> {
+    let (file_komi, games) = training_data::read_training_data_games_file::<S>(file_name)?;
+    if file_komi != komi {
+        return Err(format!(
+            "{} has komi {}, but komi {} was requested",
+            file_name, file_komi, komi
+        )
+        .into());
+    }
+
+    tune_value_and_policy_from_scratch(&games, komi, augmentation, value_settings, policy_settings)
+}
+
+fn tune_value_and_policy_from_scratch<const S: usize, const N: usize, const M: usize>(
+    games: &[Vec<TrainingRecord<S>>],
+    komi: Komi,
+    augmentation: Augmentation,
+    value_settings: &GradientDescentSettings,
+    policy_settings: &GradientDescentSettings,
+) -> Result<([f32; N], [f32; M]), DynError> {
     let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
 
     let initial_value_params: [f32; N] = array_from_fn(|| rng.gen_range(-0.01..0.01));
@@ -557,15 +1023,17 @@ pub fn tune_value_and_policy_from_file<const S: usize, const N: usize, const M:
     let initial_policy_params: [f32; M] = array_from_fn(|| rng.gen_range(-0.01..0.01));
 
     tune_value_and_policy(
-        &games,
-        &move_scoress,
+        games,
         komi,
         &initial_value_params,
         &initial_policy_params,
+        augmentation,
+        value_settings,
+        policy_settings,
     )
 }
 
-type DynError = Box<dyn error::Error + Send + Sync>;
+pub type DynError = Box<dyn error::Error + Send + Sync>;
 
 pub fn games_and_move_scoress_from_file<const S: usize>(
     value_file_name: &str,
@@ -635,29 +1103,7 @@ pub fn read_move_scores_from_file<const S: usize>(
 
     let mut move_scoress: Vec<MoveScoresForGame<S>> = games
         .into_par_iter()
-        .map(|line_group| {
-            line_group
-                .lines()
-                .map(|line| {
-                    let mut scores_for_this_move =
-                        Vec::with_capacity(line.chars().filter(|ch| *ch == ',').count());
-                    let _played_move = line.split(':').next().unwrap();
-                    let possible_moves = line.split(':').nth(1).unwrap();
-                    for move_score_string in possible_moves.split(',') {
-                        if move_score_string.len() < 3 {
-                            continue;
-                        }
-                        let mut words = move_score_string.split_whitespace();
-                        let mv = Move::from_string(words.next().unwrap()).unwrap();
-                        let score = str::parse::<f16>(words.next().unwrap()).unwrap();
-                        scores_for_this_move.push((mv, score));
-                    }
-                    // This assert is only a performance check
-                    assert_eq!(scores_for_this_move.len(), scores_for_this_move.capacity());
-                    scores_for_this_move
-                })
-                .collect()
-        })
+        .map(|line_group| parse_move_scores_for_game(line_group).unwrap())
         .collect();
 
     // Extra empty lines may be interpreted as empty games, remove them
@@ -672,37 +1118,84 @@ pub fn read_move_scores_from_file<const S: usize>(
     Ok(move_scoress)
 }
 
+/// Write the move scores for a game, with one line per move, followed by an empty line
+pub fn write_move_scores_for_game<W: Write, const S: usize>(
+    writer: &mut W,
+    game: &Game<Position<S>>,
+    move_scores: &MoveScoresForGame<S>,
+) -> io::Result<()> {
+    for (mv, move_scores) in game
+        .moves
+        .iter()
+        .map(|PtnMove { mv, .. }| mv)
+        .zip(move_scores)
+    {
+        write!(writer, "{}: ", mv)?;
+        for (mv, score) in move_scores {
+            write!(writer, "{} {}, ", mv, score)?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer)
+}
+
+/// Parse the move scores for a game, as written by `write_move_scores_for_game`
+pub fn parse_move_scores_for_game<const S: usize>(
+    input: &str,
+) -> Result<MoveScoresForGame<S>, DynError> {
+    input
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut scores_for_this_move =
+                Vec::with_capacity(line.chars().filter(|ch| *ch == ',').count());
+            let (_played_move, possible_moves) = line
+                .split_once(':')
+                .ok_or_else(|| format!("Bad move scores line \"{}\"", line))?;
+            for move_score_string in possible_moves.split(',') {
+                if move_score_string.len() < 3 {
+                    continue;
+                }
+                let mut words = move_score_string.split_whitespace();
+                let (Some(mv), Some(score)) = (words.next(), words.next()) else {
+                    return Err(format!("Bad move score \"{}\"", move_score_string).into());
+                };
+                scores_for_this_move.push((Move::from_string(mv)?, str::parse::<f16>(score)?));
+            }
+            Ok(scores_for_this_move)
+        })
+        .collect()
+}
+
 pub fn positions_and_results_from_games<const S: usize>(
     games: &[Game<Position<S>>],
     komi: Komi,
 ) -> (Vec<Position<S>>, Vec<GameResult>) {
     games
         .into_par_iter()
-        .flat_map_iter(|game| {
-            let game_result = game.game_result();
-            let mut position = game.start_position.clone();
-            if let Some((_, komi_str)) = game
-                .tags
-                .iter()
-                .find(|(tag, _)| tag.eq_ignore_ascii_case("Komi"))
-            {
-                position.set_komi(Komi::from_str(komi_str).unwrap());
-            }
-            assert_eq!(komi, position.komi());
-            let mut output: Vec<(Position<S>, GameResult)> = Vec::with_capacity(200);
-            for PtnMove { mv, .. } in game.moves.iter() {
-                if position.game_result().is_some() {
-                    break;
-                }
-                output.push((position.clone(), game_result.unwrap_or(GameResult::Draw)));
-                position.do_move(*mv);
-                // Deliberately skip the final position
-            }
-            output
-        })
+        .flat_map_iter(|game| positions_and_results_from_game(game, komi))
         .unzip()
 }
 
+fn positions_and_results_from_game<const S: usize>(
+    game: &Game<Position<S>>,
+    komi: Komi,
+) -> Vec<(Position<S>, GameResult)> {
+    let game_result = game.game_result();
+    let mut position = game.start_position.clone();
+    assert_eq!(komi, position.komi());
+    let mut output: Vec<(Position<S>, GameResult)> = Vec::with_capacity(200);
+    for PtnMove { mv, .. } in game.moves.iter() {
+        if position.game_result().is_some() {
+            break;
+        }
+        output.push((position.clone(), game_result.unwrap_or(GameResult::Draw)));
+        position.do_move(*mv);
+        // Deliberately skip the final position
+    }
+    output
+}
+
 fn array_from_fn<F, T, const N: usize>(mut f: F) -> [T; N]
 where
     F: FnMut() -> T,
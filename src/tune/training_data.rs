@@ -0,0 +1,562 @@
+//! A compact binary format for training samples from self-play.
+//!
+//! Each sample is a position, the final result of its game, and the search's score for every move in the position.
+//! This replaces reading a PTN file and a `move_scores` text file side by side, which is slow and takes a lot of space.
+//!
+//! A file starts with a header:
+//!
+//! | Bytes | Content                          |
+//! |-------|----------------------------------|
+//! | 4     | Magic bytes `TKTD`               |
+//! | 2     | Format version, little-endian    |
+//! | 1     | Board size                       |
+//! | 1     | Komi, in half flats              |
+//!
+//! followed by games until the end of the file. Each game is the number of records in it, as 2 bytes,
+//! followed by the records. Records from the same game are kept together when the data is split for validation.
+//! Records that don't belong to a game, like merged duplicates, are written as games with a single record.
+//! All integers are little-endian.
+//!
+//! | Bytes          | Content                                                             |
+//! |----------------|---------------------------------------------------------------------|
+//! | 2              | Number of plies played in the position                              |
+//! | 4              | White stones, white caps, black stones and black caps left         |
+//! | varies         | Each square, from `a1` along the rank and up. See below             |
+//! | 2              | White's score from the game as an `f16`, see `TrainingRecord`       |
+//! | 2              | Number of scored moves                                              |
+//! | 4 per move     | The move, as in `Move::to_u16`, and its score as an `f16`           |
+//!
+//! A square is a single byte with the stack height. Non-empty stacks are followed by the top piece,
+//! as in `Piece as u8`, and then one bit per buried flat with its color, from the bottom up, with 1 for black.
+//!
+//! Version 1 files have no games, only records, and the result is a single byte:
+//! 0 for a white win, 1 for a draw, 2 for a black win. They are read as games with a single record each.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::{error, fs, slice};
+
+use board_game_traits::{Color, GameResult, Position as PositionTrait};
+use half::f16;
+use pgn_traits::PgnPosition;
+
+use crate::position::{squares_iterator, Komi, Move, Piece, Position, PositionBuilder};
+use crate::ptn::{Game, PtnMove};
+use crate::tune::training;
+
+const MAGIC: [u8; 4] = *b"TKTD";
+pub const TRAINING_DATA_VERSION: u16 = 2;
+
+/// A single training sample
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingRecord<const S: usize> {
+    pub position: Position<S>,
+    /// White's score from the game the position was taken from: 1 for a white win, 0.5 for a draw and 0 for a black win.
+    /// Unfinished games count as draws. For merged duplicates, this is the average over all of them
+    pub result: f16,
+    /// Every legal move in the position, with its share of the search's visits
+    pub move_scores: Vec<(Move<S>, f16)>,
+}
+
+/// White's score for a game result, as in `TrainingRecord::result`
+pub fn result_score(game_result: GameResult) -> f16 {
+    match game_result {
+        GameResult::WhiteWin => f16::ONE,
+        GameResult::Draw => f16::from_f32(0.5),
+        GameResult::BlackWin => f16::ZERO,
+    }
+}
+
+/// Create training records from a self-play game, along with the move scores for each move in the game.
+/// The final position of the game is skipped, since it has no moves.
+pub fn records_from_game<const S: usize>(
+    game: &Game<Position<S>>,
+    move_scores: &[Vec<(Move<S>, f16)>],
+) -> Vec<TrainingRecord<S>> {
+    let result = result_score(game.game_result().unwrap_or(GameResult::Draw));
+    let mut records = vec![];
+    let mut position = game.start_position.clone();
+    for (PtnMove { mv, .. }, move_scores) in game.moves.iter().zip(move_scores) {
+        if position.game_result().is_some() {
+            break;
+        }
+        records.push(TrainingRecord {
+            position: position.clone(),
+            result,
+            move_scores: move_scores.clone(),
+        });
+        position.do_move(*mv);
+    }
+    records
+}
+
+/// Create training records from several self-play games, as in `records_from_game`
+pub fn records_from_games<const S: usize>(
+    games: &[Game<Position<S>>],
+    move_scoress: &[Vec<Vec<(Move<S>, f16)>>],
+) -> Vec<TrainingRecord<S>> {
+    games
+        .iter()
+        .zip(move_scoress)
+        .flat_map(|(game, move_scores)| records_from_game(game, move_scores))
+        .collect()
+}
+
+/// Merge records whose positions are identical, or identical after rotating or mirroring the board.
+///
+/// Each merged record has the position's canonical orientation, the one with the smallest TPS,
+/// as in `Position::canonical_tps`. Its result is the average of the duplicates' results,
+/// and its move scores are the duplicates' scores for each move averaged, after mapping the moves into the canonical orientation.
+/// Since each score is a share of the search's visits, this sums the visits, assuming every search had the same number of them.
+/// Records are returned in the order their positions first appear.
+pub fn deduplicate<const S: usize>(records: Vec<TrainingRecord<S>>) -> Vec<TrainingRecord<S>> {
+    struct MergedRecord<const S: usize> {
+        position: Position<S>,
+        count: u32,
+        result_sum: f32,
+        move_score_sums: Vec<(Move<S>, f32)>,
+    }
+
+    let mut indices: HashMap<String, usize> = HashMap::with_capacity(records.len());
+    let mut merged_records: Vec<MergedRecord<S>> = Vec::with_capacity(records.len());
+
+    for record in records {
+        let (symmetry, tps, canonical_position) = canonical_symmetry(&record.position);
+        let move_scores = record
+            .move_scores
+            .iter()
+            .map(|(mv, score)| (mv.symmetries()[symmetry], score.to_f32()));
+
+        match indices.entry(tps) {
+            Entry::Vacant(entry) => {
+                entry.insert(merged_records.len());
+                merged_records.push(MergedRecord {
+                    position: canonical_position,
+                    count: 1,
+                    result_sum: record.result.to_f32(),
+                    move_score_sums: move_scores.collect(),
+                });
+            }
+            Entry::Occupied(entry) => {
+                let merged = &mut merged_records[*entry.get()];
+                merged.count += 1;
+                merged.result_sum += record.result.to_f32();
+                for (mv, score) in move_scores {
+                    match merged
+                        .move_score_sums
+                        .iter_mut()
+                        .find(|(merged_move, _)| *merged_move == mv)
+                    {
+                        Some((_, score_sum)) => *score_sum += score,
+                        None => merged.move_score_sums.push((mv, score)),
+                    }
+                }
+            }
+        }
+    }
+
+    merged_records
+        .into_iter()
+        .map(|merged| {
+            let count = merged.count as f32;
+            TrainingRecord {
+                position: merged.position,
+                result: f16::from_f32(merged.result_sum / count),
+                move_scores: merged
+                    .move_score_sums
+                    .into_iter()
+                    .map(|(mv, score_sum)| (mv, f16::from_f32(score_sum / count)))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+/// The index of the position's canonical orientation in `Position::symmetries`, along with its TPS and the position itself
+fn canonical_symmetry<const S: usize>(position: &Position<S>) -> (usize, String, Position<S>) {
+    position
+        .symmetries()
+        .into_iter()
+        .enumerate()
+        .map(|(i, symmetry)| (i, symmetry.to_fen(), symmetry))
+        .min_by(|(_, tps1, _), (_, tps2, _)| tps1.cmp(tps2))
+        .unwrap()
+}
+
+pub struct TrainingDataWriter<W: Write, const S: usize> {
+    writer: W,
+    komi: Komi,
+}
+
+impl<W: Write, const S: usize> TrainingDataWriter<W, S> {
+    /// Write the header. All records must have positions with this komi
+    pub fn new(mut writer: W, komi: Komi) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&TRAINING_DATA_VERSION.to_le_bytes())?;
+        writer.write_all(&[S as u8, komi.half_komi() as u8])?;
+        Ok(TrainingDataWriter { writer, komi })
+    }
+
+    /// Write the records from a single game
+    pub fn write_game(&mut self, records: &[TrainingRecord<S>]) -> io::Result<()> {
+        let num_records = u16::try_from(records.len()).map_err(|_| {
+            invalid_data(format!(
+                "Game has {} records, the maximum is {}",
+                records.len(),
+                u16::MAX
+            ))
+        })?;
+        self.writer.write_all(&num_records.to_le_bytes())?;
+        for record in records {
+            self.write_record_in_game(record)?;
+        }
+        Ok(())
+    }
+
+    /// Write a record that doesn't belong to a game, as a game with a single record
+    pub fn write_record(&mut self, record: &TrainingRecord<S>) -> io::Result<()> {
+        self.write_game(slice::from_ref(record))
+    }
+
+    fn write_record_in_game(&mut self, record: &TrainingRecord<S>) -> io::Result<()> {
+        let position = &record.position;
+        if position.komi() != self.komi {
+            return Err(invalid_data(format!(
+                "Position has komi {}, but the file has komi {}",
+                position.komi(),
+                self.komi
+            )));
+        }
+        let mut bytes = Vec::with_capacity(16 + S * S + 4 * record.move_scores.len());
+        bytes.extend_from_slice(&(position.half_moves_played() as u16).to_le_bytes());
+        bytes.extend_from_slice(&[
+            position.white_reserves_left(),
+            position.white_caps_left(),
+            position.black_reserves_left(),
+            position.black_caps_left(),
+        ]);
+        for square in squares_iterator::<S>() {
+            let stack = position[square];
+            bytes.push(stack.len());
+            if let Some(top_stone) = stack.top_stone() {
+                bytes.push(top_stone as u8);
+                let mut color_bits = vec![0; (stack.len() as usize - 1).div_ceil(8)];
+                for (i, piece) in stack.into_iter().take(stack.len() as usize - 1).enumerate() {
+                    if piece.color() == Color::Black {
+                        color_bits[i / 8] |= 1 << (i % 8);
+                    }
+                }
+                bytes.extend_from_slice(&color_bits);
+            }
+        }
+        bytes.extend_from_slice(&record.result.to_le_bytes());
+        bytes.extend_from_slice(&(record.move_scores.len() as u16).to_le_bytes());
+        for (mv, score) in record.move_scores.iter() {
+            bytes.extend_from_slice(&mv.to_u16().to_le_bytes());
+            bytes.extend_from_slice(&score.to_le_bytes());
+        }
+        self.writer.write_all(&bytes)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads records one at a time, or a game at a time. Every record is validated, including that all its moves are legal
+pub struct TrainingDataReader<R: Read, const S: usize> {
+    reader: R,
+    komi: Komi,
+    version: u16,
+    /// Records left to read in the current game
+    records_left_in_game: u16,
+    /// The first byte of the next record, in version 1 files, which was read while checking for the end of the file
+    pending_byte: Option<u8>,
+}
+
+impl<R: Read, const S: usize> TrainingDataReader<R, S> {
+    /// Read and check the header
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        if header[0..4] != MAGIC {
+            return Err(invalid_data("Not a training data file".to_string()));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version == 0 || version > TRAINING_DATA_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported training data version {}, expected at most {}",
+                version, TRAINING_DATA_VERSION
+            )));
+        }
+        if header[6] as usize != S {
+            return Err(invalid_data(format!(
+                "Expected {}s training data, got {}s",
+                S, header[6]
+            )));
+        }
+        let komi = Komi::from_half_komi(header[7] as i8)
+            .ok_or_else(|| invalid_data(format!("Unsupported half komi {}", header[7] as i8)))?;
+        Ok(TrainingDataReader {
+            reader,
+            komi,
+            version,
+            records_left_in_game: 0,
+            pending_byte: None,
+        })
+    }
+
+    pub fn komi(&self) -> Komi {
+        self.komi
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Read the rest of the current game, or the next game if the current one has been read.
+    /// Returns `None` at the end of the file
+    pub fn read_game(&mut self) -> io::Result<Option<Vec<TrainingRecord<S>>>> {
+        if self.records_left_in_game == 0 && !self.start_game()? {
+            return Ok(None);
+        }
+        let mut records = Vec::with_capacity(self.records_left_in_game as usize);
+        while self.records_left_in_game > 0 {
+            records.push(self.read_record_in_game()?);
+        }
+        Ok(Some(records))
+    }
+
+    /// Read the next record, or `None` at the end of the file
+    pub fn read_record(&mut self) -> io::Result<Option<TrainingRecord<S>>> {
+        while self.records_left_in_game == 0 {
+            if !self.start_game()? {
+                return Ok(None);
+            }
+        }
+        self.read_record_in_game().map(Some)
+    }
+
+    /// Read the number of records in the next game, or return false at the end of the file
+    fn start_game(&mut self) -> io::Result<bool> {
+        let mut bytes = [0; 2];
+        // Check for a clean end of file, before the first byte of a game
+        if self.reader.read(&mut bytes[..1])? == 0 {
+            return Ok(false);
+        }
+        if self.version == 1 {
+            // Version 1 has no games, so this was the first byte of a record
+            self.records_left_in_game = 1;
+            self.pending_byte = Some(bytes[0]);
+        } else {
+            self.reader.read_exact(&mut bytes[1..])?;
+            self.records_left_in_game = u16::from_le_bytes(bytes);
+        }
+        Ok(true)
+    }
+
+    fn read_record_in_game(&mut self) -> io::Result<TrainingRecord<S>> {
+        self.records_left_in_game -= 1;
+        let mut half_moves_bytes = [0; 2];
+        match self.pending_byte.take() {
+            Some(byte) => {
+                half_moves_bytes[0] = byte;
+                self.reader.read_exact(&mut half_moves_bytes[1..])?;
+            }
+            None => self.reader.read_exact(&mut half_moves_bytes)?,
+        }
+        let half_moves_played = u16::from_le_bytes(half_moves_bytes) as usize;
+        let reserves = self.read_bytes(4)?;
+
+        let mut builder = PositionBuilder::<S>::new()
+            .komi(self.komi)
+            .side_to_move([Color::White, Color::Black][half_moves_played % 2])
+            .move_number(half_moves_played / 2 + 1)
+            .reserves(Color::White, reserves[0], reserves[1])
+            .reserves(Color::Black, reserves[2], reserves[3]);
+        for square in squares_iterator::<S>() {
+            let height = self.read_bytes(1)?[0] as usize;
+            if height == 0 {
+                continue;
+            }
+            let top_stone = piece_from_u8(self.read_bytes(1)?[0])?;
+            let color_bits = self.read_bytes((height - 1).div_ceil(8))?;
+            let mut pieces: Vec<Piece> = (0..height - 1)
+                .map(|i| {
+                    if color_bits[i / 8] & (1 << (i % 8)) == 0 {
+                        Piece::WhiteFlat
+                    } else {
+                        Piece::BlackFlat
+                    }
+                })
+                .collect();
+            pieces.push(top_stone);
+            builder = builder.stack(square, &pieces);
+        }
+        let position = builder
+            .build()
+            .map_err(|err| invalid_data(err.to_string()))?;
+
+        let result = if self.version == 1 {
+            match self.read_bytes(1)?[0] {
+                0 => result_score(GameResult::WhiteWin),
+                1 => result_score(GameResult::Draw),
+                2 => result_score(GameResult::BlackWin),
+                byte => return Err(invalid_data(format!("Bad game result {}", byte))),
+            }
+        } else {
+            let result_bytes = self.read_bytes(2)?;
+            let result = f16::from_le_bytes([result_bytes[0], result_bytes[1]]);
+            if !(f16::ZERO..=f16::ONE).contains(&result) {
+                return Err(invalid_data(format!("Bad result {}", result)));
+            }
+            result
+        };
+
+        let num_moves_bytes = self.read_bytes(2)?;
+        let num_moves = u16::from_le_bytes([num_moves_bytes[0], num_moves_bytes[1]]) as usize;
+        let mut legal_moves = vec![];
+        position.generate_moves(&mut legal_moves);
+        let move_bytes = self.read_bytes(4 * num_moves)?;
+        let move_scores = move_bytes
+            .chunks_exact(4)
+            .map(|chunk| {
+                let inner = u16::from_le_bytes([chunk[0], chunk[1]]);
+                let score = f16::from_le_bytes([chunk[2], chunk[3]]);
+                legal_moves
+                    .iter()
+                    .find(|mv| mv.to_u16() == inner)
+                    .map(|mv| (*mv, score))
+                    .ok_or_else(|| {
+                        invalid_data(format!(
+                            "Illegal move {:#06x} in position {}",
+                            inner,
+                            position.to_fen()
+                        ))
+                    })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(TrainingRecord {
+            position,
+            result,
+            move_scores,
+        })
+    }
+
+    fn read_bytes(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0; n];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl<R: Read, const S: usize> Iterator for TrainingDataReader<R, S> {
+    type Item = io::Result<TrainingRecord<S>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn piece_from_u8(byte: u8) -> io::Result<Piece> {
+    match byte {
+        0 => Ok(Piece::WhiteFlat),
+        1 => Ok(Piece::BlackFlat),
+        2 => Ok(Piece::WhiteWall),
+        3 => Ok(Piece::BlackWall),
+        4 => Ok(Piece::WhiteCap),
+        5 => Ok(Piece::BlackCap),
+        _ => Err(invalid_data(format!("Bad piece {}", byte))),
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Write records that don't belong to games to a new file, overwriting it if it exists
+pub fn write_training_data_file<const S: usize>(
+    file_name: &str,
+    komi: Komi,
+    records: &[TrainingRecord<S>],
+) -> io::Result<()> {
+    let file = fs::File::create(file_name)?;
+    let mut writer = TrainingDataWriter::<_, S>::new(BufWriter::new(file), komi)?;
+    for record in records {
+        writer.write_record(record)?;
+    }
+    writer.flush()
+}
+
+/// Write the records of each game to a new file, overwriting it if it exists
+pub fn write_training_data_games_file<const S: usize>(
+    file_name: &str,
+    komi: Komi,
+    games: &[Vec<TrainingRecord<S>>],
+) -> io::Result<()> {
+    let file = fs::File::create(file_name)?;
+    let mut writer = TrainingDataWriter::<_, S>::new(BufWriter::new(file), komi)?;
+    for records in games {
+        writer.write_game(records)?;
+    }
+    writer.flush()
+}
+
+/// Read all records from a file, and return them with the file's komi
+pub fn read_training_data_file<const S: usize>(
+    file_name: &str,
+) -> io::Result<(Komi, Vec<TrainingRecord<S>>)> {
+    let file = fs::File::open(file_name)?;
+    let reader = TrainingDataReader::<_, S>::new(BufReader::new(file))?;
+    let komi = reader.komi();
+    let records = reader.collect::<io::Result<Vec<_>>>()?;
+    Ok((komi, records))
+}
+
+/// Read the records of each game in a file, and return them with the file's komi
+pub fn read_training_data_games_file<const S: usize>(
+    file_name: &str,
+) -> io::Result<(Komi, Vec<Vec<TrainingRecord<S>>>)> {
+    let file = fs::File::open(file_name)?;
+    let mut reader = TrainingDataReader::<_, S>::new(BufReader::new(file))?;
+    let mut games = vec![];
+    while let Some(records) = reader.read_game()? {
+        games.push(records);
+    }
+    Ok((reader.komi(), games))
+}
+
+type DynError = Box<dyn error::Error + Send + Sync>;
+
+/// Convert a PTN file of self-play games and its matching `move_scores` text file to the binary format,
+/// merging duplicate positions. Returns the number of records before and after deduplication.
+pub fn convert_text_training_data<const S: usize>(
+    games_file_name: &str,
+    move_scores_file_name: &str,
+    output_file_name: &str,
+    komi: Komi,
+) -> Result<(usize, usize), DynError> {
+    let games = training::read_games_from_file::<S>(games_file_name, komi)?;
+    let move_scoress = training::read_move_scores_from_file::<S>(move_scores_file_name)?;
+    if games.len() != move_scoress.len() {
+        return Err(format!(
+            "{} has {} games, but {} has move scores for {} games",
+            games_file_name,
+            games.len(),
+            move_scores_file_name,
+            move_scoress.len()
+        )
+        .into());
+    }
+    let records = records_from_games(&games, &move_scoress);
+    let num_records = records.len();
+    let records = deduplicate(records);
+    write_training_data_file(output_file_name, komi, &records)?;
+    Ok((num_records, records.len()))
+}
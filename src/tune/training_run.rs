@@ -0,0 +1,246 @@
+//! A training run directory, which holds everything needed to resume self-play training:
+//! The games and training data from every batch, the parameters after every batch, and a manifest.
+//!
+//! The manifest is a small text file, which is replaced atomically after every batch:
+//!
+//! ```text
+//! tiltak-training version 1
+//! size 5
+//! kind linear
+//! komi 2
+//! batch-size 500
+//! batches-for-training 25
+//! nodes 50000
+//! dirichlet-alpha 0.2
+//! batch 2
+//! games 1000
+//! params value_params_batch1.txt policy_params_batch1.txt
+//! last-params value_params_batch0.txt policy_params_batch0.txt
+//! result 0 260 190 50
+//! result 1 241 207 52
+//! ```
+//!
+//! `batch` is the next batch to be played, and each `result` line is the batch, followed by
+//! the wins, losses and draws of the parameters that played the batch against the ones before them.
+
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::{fs, str};
+
+use pgn_traits::{Error, ErrorKind};
+
+use crate::position::Komi;
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.txt";
+
+/// Settings for self-play training, which are fixed for the whole run
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrainingConfig {
+    pub komi: Komi,
+    /// Number of games played in each batch
+    pub batch_size: usize,
+    /// Only train from the games in this many recent batches
+    pub batches_for_training: usize,
+    /// Nodes searched for each move
+    pub nodes: u64,
+    pub dirichlet_alpha: f32,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        TrainingConfig {
+            komi: Komi::default(),
+            batch_size: 500,
+            batches_for_training: 25,
+            nodes: 50_000,
+            dirichlet_alpha: 0.2,
+        }
+    }
+}
+
+/// Results of the parameters that played a batch, against the parameters before them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchResult {
+    pub batch_id: usize,
+    pub wins: u64,
+    pub losses: u64,
+    pub draws: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub size: usize,
+    /// The kind of parameters being trained, like `linear`
+    pub kind: String,
+    pub config: TrainingConfig,
+    /// The next batch to be played
+    pub batch_id: usize,
+    /// The total number of games played
+    pub num_games: usize,
+    /// The parameter files for the current parameters, relative to the run directory
+    pub params_files: Vec<String>,
+    /// The parameter files for the parameters before the current ones
+    pub last_params_files: Vec<String>,
+    pub history: Vec<BatchResult>,
+}
+
+impl Manifest {
+    pub fn read<R: BufRead>(reader: R) -> Result<Self, Error> {
+        let mut lines = reader.lines();
+        let header = lines
+            .next()
+            .ok_or_else(|| Error::new_parse_error("Empty manifest"))?
+            .map_err(|err| Error::new(ErrorKind::IoError, err))?;
+        if header.split_whitespace().collect::<Vec<_>>()
+            != ["tiltak-training", "version", &MANIFEST_VERSION.to_string()]
+        {
+            return Err(Error::new_parse_error(format!(
+                "Bad manifest header \"{}\", expected version {}",
+                header, MANIFEST_VERSION
+            )));
+        }
+
+        let mut size = None;
+        let mut kind = None;
+        let mut komi = None;
+        let mut batch_size = None;
+        let mut batches_for_training = None;
+        let mut nodes = None;
+        let mut dirichlet_alpha = None;
+        let mut batch_id = None;
+        let mut num_games = None;
+        let mut params_files = None;
+        let mut last_params_files = None;
+        let mut history = vec![];
+
+        for line in lines {
+            let line = line.map_err(|err| Error::new(ErrorKind::IoError, err))?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                [] => continue,
+                ["size", value] => size = Some(parse(value, "size")?),
+                ["kind", value] => kind = Some(value.to_string()),
+                ["komi", value] => komi = Some(parse(value, "komi")?),
+                ["batch-size", value] => batch_size = Some(parse(value, "batch size")?),
+                ["batches-for-training", value] => {
+                    batches_for_training = Some(parse(value, "batches for training")?)
+                }
+                ["nodes", value] => nodes = Some(parse(value, "nodes")?),
+                ["dirichlet-alpha", value] => {
+                    dirichlet_alpha = Some(parse(value, "dirichlet alpha")?)
+                }
+                ["batch", value] => batch_id = Some(parse(value, "batch")?),
+                ["games", value] => num_games = Some(parse(value, "games")?),
+                ["params", ref files @ ..] => {
+                    params_files = Some(files.iter().map(|file| file.to_string()).collect())
+                }
+                ["last-params", ref files @ ..] => {
+                    last_params_files = Some(files.iter().map(|file| file.to_string()).collect())
+                }
+                ["result", batch_id, wins, losses, draws] => history.push(BatchResult {
+                    batch_id: parse(batch_id, "batch")?,
+                    wins: parse(wins, "wins")?,
+                    losses: parse(losses, "losses")?,
+                    draws: parse(draws, "draws")?,
+                }),
+                _ => {
+                    return Err(Error::new_parse_error(format!(
+                        "Bad manifest line \"{}\"",
+                        line
+                    )))
+                }
+            }
+        }
+
+        let missing = |name: &str| Error::new_parse_error(format!("Manifest is missing {}", name));
+        Ok(Manifest {
+            size: size.ok_or_else(|| missing("size"))?,
+            kind: kind.ok_or_else(|| missing("kind"))?,
+            config: TrainingConfig {
+                komi: komi.ok_or_else(|| missing("komi"))?,
+                batch_size: batch_size.ok_or_else(|| missing("batch-size"))?,
+                batches_for_training: batches_for_training
+                    .ok_or_else(|| missing("batches-for-training"))?,
+                nodes: nodes.ok_or_else(|| missing("nodes"))?,
+                dirichlet_alpha: dirichlet_alpha.ok_or_else(|| missing("dirichlet-alpha"))?,
+            },
+            batch_id: batch_id.ok_or_else(|| missing("batch"))?,
+            num_games: num_games.ok_or_else(|| missing("games"))?,
+            params_files: params_files.ok_or_else(|| missing("params"))?,
+            last_params_files: last_params_files.ok_or_else(|| missing("last-params"))?,
+            history,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "tiltak-training version {}", MANIFEST_VERSION)?;
+        writeln!(writer, "size {}", self.size)?;
+        writeln!(writer, "kind {}", self.kind)?;
+        writeln!(writer, "komi {}", self.config.komi)?;
+        writeln!(writer, "batch-size {}", self.config.batch_size)?;
+        writeln!(
+            writer,
+            "batches-for-training {}",
+            self.config.batches_for_training
+        )?;
+        writeln!(writer, "nodes {}", self.config.nodes)?;
+        writeln!(writer, "dirichlet-alpha {}", self.config.dirichlet_alpha)?;
+        writeln!(writer, "batch {}", self.batch_id)?;
+        writeln!(writer, "games {}", self.num_games)?;
+        writeln!(writer, "params {}", self.params_files.join(" "))?;
+        writeln!(writer, "last-params {}", self.last_params_files.join(" "))?;
+        for result in self.history.iter() {
+            writeln!(
+                writer,
+                "result {} {} {} {}",
+                result.batch_id, result.wins, result.losses, result.draws
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Read the manifest in a run directory
+    pub fn from_dir(run_directory: &Path) -> Result<Self, Error> {
+        let path = run_directory.join(MANIFEST_FILE_NAME);
+        let file = fs::File::open(&path).map_err(|err| {
+            Error::new_caused_by(
+                ErrorKind::IoError,
+                format!("Couldn't open {}", path.display()),
+                err,
+            )
+        })?;
+        Self::read(io::BufReader::new(file))
+    }
+
+    /// Write the manifest to a run directory. The old manifest is replaced atomically,
+    /// so that the directory always holds a complete manifest, even if training is interrupted.
+    pub fn write_to_dir(&self, run_directory: &Path) -> io::Result<()> {
+        let temp_path = run_directory.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+        let file = fs::File::create(&temp_path)?;
+        let mut writer = io::BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(temp_path, run_directory.join(MANIFEST_FILE_NAME))
+    }
+}
+
+/// The games file for a batch in a run directory
+pub fn games_path(run_directory: &Path, batch_id: usize) -> PathBuf {
+    run_directory.join(format!("games_batch{}.ptn", batch_id))
+}
+
+/// The training data file for a batch in a run directory, in the format of `training_data`
+pub fn training_data_path(run_directory: &Path, batch_id: usize) -> PathBuf {
+    run_directory.join(format!("training_data_batch{}.tktd", batch_id))
+}
+
+fn parse<T: str::FromStr>(value: &str, name: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::new_parse_error(format!("Bad {} \"{}\" in manifest", name, value)))
+}